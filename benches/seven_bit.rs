@@ -0,0 +1,88 @@
+//! Iterator vs. slice 7-bit conversion over a full sample memory worth of data.
+//!
+//! Run with `cargo bench --bench seven_bit`; the report shows throughput of both variants
+//! side by side for each direction.
+//!
+//! Both variants here produce the whole payload at once, which is what decoding a dump does.
+//! Uploads encode sample data in blocks of 224 samples straight into the chunks sent to the
+//! device, so their memory use stays at about one block and one 256 byte chunk on top of the
+//! samples themselves, instead of the 7-bit payload (8/7 of the sample size) plus a copy of it.
+
+use std::hint::black_box;
+use std::ops;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+// The crate only builds a binary, so the converter module is compiled in directly.
+#[path = "../src/seven_bit.rs"]
+#[allow(dead_code, unused_imports)]
+mod seven_bit;
+
+mod util {
+    use super::ops;
+
+    // Mirrors `crate::util::Array`, which `seven_bit` relies on.
+    pub trait Array:
+        AsRef<[Self::ArrayItem]>
+        + ops::IndexMut<usize, Output = Self::ArrayItem>
+        + IntoIterator<Item = Self::ArrayItem>
+        + Sized
+    {
+        type ArrayItem: Clone + Sized;
+        const LEN: usize;
+    }
+
+    impl<const N: usize, T: Clone + Sized> Array for [T; N] {
+        type ArrayItem = T;
+        const LEN: usize = N;
+    }
+}
+
+use seven_bit::{FromKorgData, IntoKorgData, U7ToU8, U8ToU7};
+
+/// Size of the Volca Sample 2 sample memory.
+const PAYLOAD_LEN: usize = 4 * 1024 * 1024;
+
+fn payload() -> Vec<u8> {
+    (0..PAYLOAD_LEN).map(|idx| (idx * 31 % 251) as u8).collect()
+}
+
+fn encode(c: &mut Criterion) {
+    let data = payload();
+    let mut group = c.benchmark_group("u8_to_u7");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+
+    group.bench_function("iterator", |b| {
+        b.iter(|| IntoKorgData::new(black_box(&data).iter().copied()).collect::<Vec<_>>())
+    });
+    group.bench_function("slice", |b| {
+        b.iter(|| {
+            let mut out = Vec::new();
+            U8ToU7::convert_slice(black_box(&data), &mut out);
+            out
+        })
+    });
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let mut encoded = Vec::new();
+    U8ToU7::convert_slice(&payload(), &mut encoded);
+    let mut group = c.benchmark_group("u7_to_u8");
+    group.throughput(Throughput::Bytes(encoded.len() as u64));
+
+    group.bench_function("iterator", |b| {
+        b.iter(|| FromKorgData::new(black_box(&encoded).iter().copied()).collect::<Vec<_>>())
+    });
+    group.bench_function("slice", |b| {
+        b.iter(|| {
+            let mut out = Vec::new();
+            U7ToU8::convert_slice(black_box(&encoded), &mut out);
+            out
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encode, decode);
+criterion_main!(benches);
@@ -1,5 +1,5 @@
 use std::fs;
-use std::io;
+use std::io::{self, Read};
 use std::path::Path;
 use std::time::Duration;
 
@@ -10,27 +10,55 @@ use bytemuck::Zeroable;
 use clap::ValueEnum;
 use derive_more::Display;
 use hound::{Result as WavResult, SampleFormat, WavReader, WavSpec, WavWriter};
-use rubato::{FftFixedIn, Resampler};
+use rubato::{
+    FftFixedIn, InterpolationParameters, InterpolationType, Resampler, SincFixedIn, WindowFunction,
+};
+use serde::Deserialize;
 use thiserror::Error;
 
+mod decode;
+mod filter;
+mod limiter;
+mod loudness;
+
+use decode::{decode_file, Compressed, Decoded};
+pub use filter::{apply_filter, Pass};
+pub use limiter::apply_limiter;
+pub use loudness::{normalize_loudness, LoudnessTarget, Measurement};
+
 pub const VOLCA_SAMPLERATE: u32 = 31250;
+/// Sample rate used to save device memory. Such samples are played back at a higher speed.
+pub const ECONOMY_SAMPLERATE: u32 = VOLCA_SAMPLERATE / 2;
 
 #[derive(Debug, Error)]
 pub enum AudioError {
     #[error("unsupported format {1}bit {0:?}")]
     Format(SampleFormat, u16),
+    #[error("unsupported codec {0}")]
+    Codec(String),
+    #[cfg(feature = "decode")]
+    #[error("decode error: {0}")]
+    Decode(String),
+    #[error("cutoff frequency {0}Hz must be below {1}Hz, half of the source sample rate")]
+    Cutoff(u32, u32),
     #[error("read WAV error: {0}")]
     Hound(#[from] hound::Error),
     #[error("could not build resampler: {0}")]
     ResamplerBuild(#[from] rubato::ResamplerConstructionError),
     #[error("resample error: {0}")]
     Resample(#[from] rubato::ResampleError),
+    #[error("write error: {0}")]
+    Io(#[from] io::Error),
+    #[cfg(feature = "flac")]
+    #[error("FLAC encode error: {0}")]
+    FlacEncode(String),
 }
 
 pub type Result<T> = std::result::Result<T, AudioError>;
 pub type AudioItem = WavResult<f64>;
 
-#[derive(Debug, Display, Clone, ValueEnum, Default)]
+#[derive(Debug, Display, Clone, ValueEnum, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum MonoMode {
     Left,
     Right,
@@ -40,11 +68,114 @@ pub enum MonoMode {
     // Channel(u16),
 }
 
-pub fn write_sample_to_file(sample_data: &[i16], path: &Path) -> WavResult<()> {
+/// Resampling algorithm.
+#[derive(Debug, Display, Clone, Copy, ValueEnum, Default, PartialEq, Eq)]
+pub enum ResampleMode {
+    /// FFT based resampler over the whole file.
+    #[default]
+    Fft,
+    /// Windowed sinc interpolation, configured by `SincQuality`.
+    Sinc,
+    /// Linear interpolation without anti-aliasing. Fastest, but aliases when downsampling.
+    Linear,
+}
+
+/// Trade-off between speed and quality of the sinc resampler.
+#[derive(Debug, Display, Clone, Copy, ValueEnum, Default, PartialEq, Eq)]
+pub enum SincQuality {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl SincQuality {
+    fn parameters(self) -> InterpolationParameters {
+        let (sinc_len, f_cutoff, oversampling_factor, interpolation, window) = match self {
+            Self::Low => (
+                64,
+                0.9,
+                128,
+                InterpolationType::Linear,
+                WindowFunction::Hann2,
+            ),
+            Self::Medium => (
+                128,
+                0.925,
+                256,
+                InterpolationType::Cubic,
+                WindowFunction::Blackman2,
+            ),
+            Self::High => (
+                256,
+                0.95,
+                256,
+                InterpolationType::Cubic,
+                WindowFunction::BlackmanHarris2,
+            ),
+        };
+        InterpolationParameters {
+            sinc_len,
+            f_cutoff,
+            oversampling_factor,
+            interpolation,
+            window,
+        }
+    }
+}
+
+/// How a signal is converted to another sample rate.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Resampling {
+    pub mode: ResampleMode,
+    /// Only used by the sinc resampler.
+    pub quality: SincQuality,
+}
+
+/// Audio file format for saving samples.
+#[derive(Debug, Display, Clone, Copy, ValueEnum, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Wav,
+    #[cfg(feature = "flac")]
+    Flac,
+    /// AIFF-C with uncompressed 16 bit big-endian PCM.
+    Aiff,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            #[cfg(feature = "flac")]
+            Self::Flac => "flac",
+            Self::Aiff => "aiff",
+        }
+    }
+}
+
+/// Writes 16 bit mono audio to a file in the given format.
+pub fn write_sample(
+    sample_data: &[i16],
+    path: &Path,
+    format: OutputFormat,
+    sample_rate: u32,
+) -> Result<()> {
+    match format {
+        OutputFormat::Wav => {
+            write_sample_to_file(sample_data, path, sample_rate).map_err(Into::into)
+        }
+        #[cfg(feature = "flac")]
+        OutputFormat::Flac => write_flac_to_file(sample_data, path, sample_rate),
+        OutputFormat::Aiff => write_aiff_to_file(sample_data, path, sample_rate),
+    }
+}
+
+pub fn write_sample_to_file(sample_data: &[i16], path: &Path, sample_rate: u32) -> WavResult<()> {
     let length = sample_data.len() as u32;
     let header = WavSpec {
         channels: 1,
-        sample_rate: VOLCA_SAMPLERATE,
+        sample_rate,
         bits_per_sample: 16,
         sample_format: SampleFormat::Int,
     };
@@ -62,6 +193,118 @@ pub fn write_sample_to_file(sample_data: &[i16], path: &Path) -> WavResult<()> {
     writer.flush()
 }
 
+pub fn write_aiff_to_file(sample_data: &[i16], path: &Path, sample_rate: u32) -> Result<()> {
+    let file = fs::File::create(path)?;
+    let mut writer = io::BufWriter::new(file);
+    write_aiff(sample_data, sample_rate, &mut writer)?;
+    io::Write::flush(&mut writer)?;
+    Ok(())
+}
+
+/// Writes AIFF-C stream with uncompressed 16 bit mono audio.
+fn write_aiff(sample_data: &[i16], sample_rate: u32, mut dest: impl io::Write) -> io::Result<()> {
+    /// AIFF-C version 1 timestamp.
+    const AIFC_VERSION: u32 = 0xA280_5140;
+    /// Compression name as a padded Pascal string.
+    const COMPRESSION_NAME: &[u8; 16] = b"\x0enot compressed\0";
+    const COMM_SIZE: u32 = 2 + 4 + 2 + 10 + 4 + COMPRESSION_NAME.len() as u32;
+
+    let data_size = u32::try_from(sample_data.len() * 2)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "sample is too long"))?;
+    let ssnd_size = 8 + data_size;
+    let form_size = 4 + (8 + 4) + (8 + COMM_SIZE) + (8 + ssnd_size);
+
+    dest.write_all(b"FORM")?;
+    dest.write_all(&form_size.to_be_bytes())?;
+    dest.write_all(b"AIFC")?;
+
+    dest.write_all(b"FVER")?;
+    dest.write_all(&4u32.to_be_bytes())?;
+    dest.write_all(&AIFC_VERSION.to_be_bytes())?;
+
+    dest.write_all(b"COMM")?;
+    dest.write_all(&COMM_SIZE.to_be_bytes())?;
+    dest.write_all(&1i16.to_be_bytes())?; // channels
+    dest.write_all(&(sample_data.len() as u32).to_be_bytes())?; // frames
+    dest.write_all(&16i16.to_be_bytes())?; // bits per sample
+    dest.write_all(&u32_to_extended(sample_rate))?;
+    dest.write_all(b"NONE")?;
+    dest.write_all(COMPRESSION_NAME)?;
+
+    dest.write_all(b"SSND")?;
+    dest.write_all(&ssnd_size.to_be_bytes())?;
+    dest.write_all(&0u32.to_be_bytes())?; // offset
+    dest.write_all(&0u32.to_be_bytes())?; // block size
+    for sample in sample_data {
+        dest.write_all(&sample.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+/// Converts integer to 80 bit IEEE 754 extended precision float used in AIFF headers.
+fn u32_to_extended(value: u32) -> [u8; 10] {
+    let mut output = [0; 10];
+    if value == 0 {
+        return output;
+    }
+
+    let exponent = 31 - value.leading_zeros();
+    let biased_exponent = 16383 + exponent as u16;
+    let mantissa = (value as u64) << (63 - exponent);
+    output[..2].copy_from_slice(&biased_exponent.to_be_bytes());
+    output[2..].copy_from_slice(&mantissa.to_be_bytes());
+    output
+}
+
+#[cfg(feature = "flac")]
+pub fn write_flac_to_file(sample_data: &[i16], path: &Path, sample_rate: u32) -> Result<()> {
+    fs::write(path, encode_flac(sample_data, sample_rate)?)?;
+    Ok(())
+}
+
+#[cfg(feature = "flac")]
+fn encode_flac(sample_data: &[i16], sample_rate: u32) -> Result<Vec<u8>> {
+    let samples = sample_data
+        .iter()
+        .copied()
+        .map(i32::from)
+        .collect::<Vec<_>>();
+    let block_size = flacenc::config::Encoder::default().block_size;
+    encode_flac_stream(&samples, 1, 16, sample_rate as usize, block_size)
+}
+
+/// Encodes interleaved samples as FLAC with a fixed block size.
+#[cfg(feature = "flac")]
+fn encode_flac_stream(
+    samples: &[i32],
+    channels: usize,
+    bits_per_sample: usize,
+    sample_rate: usize,
+    block_size: usize,
+) -> Result<Vec<u8>> {
+    use flacenc::component::BitRepr;
+    use flacenc::error::Verify;
+
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, err)| AudioError::FlacEncode(err.to_string()))?;
+    let source =
+        flacenc::source::MemSource::from_samples(samples, channels, bits_per_sample, sample_rate);
+    let mut stream = flacenc::encode_with_fixed_block_size(&config, source, block_size)
+        .map_err(|err| AudioError::FlacEncode(err.to_string()))?;
+    // The minimum block size excludes the last block, which is usually shorter. flacenc counts it
+    // and decoders take the stream for one with variable block sizes then.
+    let info = stream.stream_info_mut();
+    info.set_block_sizes(info.max_block_size(), info.max_block_size())
+        .map_err(|err| AudioError::FlacEncode(err.to_string()))?;
+
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream
+        .write(&mut sink)
+        .map_err(|err| AudioError::FlacEncode(err.to_string()))?;
+    Ok(sink.into_inner())
+}
+
 pub struct AudioReader<'a, I> {
     reader: I,
     spec: WavSpec,
@@ -70,16 +313,35 @@ pub struct AudioReader<'a, I> {
 }
 
 impl AudioReader<'_, ()> {
+    /// Opens a WAV file, or a compressed one that is decoded at once.
     #[auto_enum]
     pub fn open_file(path: &Path) -> Result<AudioReader<'_, impl Iterator<Item = AudioItem>>> {
-        let reader = WavReader::open(path)?;
-        let spec = reader.spec();
-        let duration = reader.duration();
-        let reader = into_samples_f64(reader)?;
+        let format = Compressed::detect(path)?;
+        let decoded = match format {
+            Some(format) => Some(decode_file(path, format)?),
+            None => read_float64_wav(path)?,
+        };
+        let spec;
+        let duration;
+        #[auto_enum(Iterator)]
+        let reader = match decoded {
+            None => {
+                let reader = WavReader::open(path)?;
+                spec = reader.spec();
+                duration = reader.duration();
+                into_samples_f64(reader)?
+            }
+            Some(decoded) => {
+                spec = decoded.spec;
+                duration = decoded.duration();
+                decoded.samples.into_iter().map(Ok)
+            }
+        };
         let duration_secs = Duration::from_secs_f64(duration as f64 / spec.sample_rate as f64);
 
         tracing::debug!(
             ?path,
+            format = %format.map_or("WAV".to_owned(), |format| format.to_string()),
             sample_rate = spec.sample_rate,
             num_channels = spec.channels,
             sample_format = ?spec.sample_format,
@@ -164,34 +426,113 @@ where
         self.lr_transform(|l, r| (l - r) / 2.)
     }
 
-    pub fn resample_to_volca(self) -> Result<Vec<i16>> {
-        if self.spec.sample_rate == VOLCA_SAMPLERATE {
-            // TODO: optimize this
+    /// Reads all samples of the file.
+    pub fn into_signal(self) -> Result<Signal> {
+        Ok(Signal {
+            samples: self.reader.collect::<WavResult<Vec<_>>>()?,
+            sample_rate: self.spec.sample_rate,
+        })
+    }
+}
+
+/// Mono audio at the sample rate of the source file, before it is converted for the device.
+pub struct Signal {
+    pub samples: Vec<f64>,
+    pub sample_rate: u32,
+}
+
+impl Signal {
+    /// Converts the signal to another sample rate.
+    pub fn resample(self, sample_rate: u32, resampling: Resampling) -> Result<Self> {
+        if self.sample_rate == sample_rate {
             tracing::debug!("skipping resampling");
-            self.reader
-                .map(|result| result.map(float_to_i16))
-                .collect::<WavResult<Vec<_>>>()
-                .map_err(Into::into)
-        } else {
-            let original = self.reader.collect::<WavResult<Vec<_>>>()?;
-            let mut resampler = FftFixedIn::new(
-                self.spec.sample_rate as usize,
-                VOLCA_SAMPLERATE as usize,
-                self.duration as usize,
-                self.duration as usize,
-                1,
-            )?;
-            let result = resampler.process(&[original], None)?.pop().unwrap();
-            Ok(result
-                .into_iter()
-                .map(|sample| (sample * i16::MAX as f64).round() as i16)
-                .collect())
+            return Ok(self);
         }
+        tracing::debug!(
+            ?resampling,
+            from = self.sample_rate,
+            to = sample_rate,
+            "resampling"
+        );
+        Ok(Self {
+            samples: resample(self.samples, self.sample_rate, sample_rate, resampling)?,
+            sample_rate,
+        })
+    }
+
+    /// Converts the signal to 16 bit samples, clipping them at full scale.
+    pub fn quantize(&self) -> Vec<i16> {
+        self.samples.iter().copied().map(float_to_i16).collect()
     }
 }
 
+/// Resamples 16 bit audio.
+pub fn resample_i16(data: &[i16], from: u32, to: u32) -> Result<Vec<i16>> {
+    if from == to {
+        return Ok(data.to_vec());
+    }
+    let original = data
+        .iter()
+        .map(|&sample| sample as f64 / i16::MAX as f64)
+        .collect();
+    Ok(resample(original, from, to, Resampling::default())?
+        .into_iter()
+        .map(float_to_i16)
+        .collect())
+}
+
+fn resample(original: Vec<f64>, from: u32, to: u32, resampling: Resampling) -> Result<Vec<f64>> {
+    let len = original.len();
+    match resampling.mode {
+        ResampleMode::Fft => {
+            let mut resampler = FftFixedIn::new(from as usize, to as usize, len, len, 1)?;
+            Ok(resampler.process(&[original], None)?.pop().unwrap())
+        }
+        ResampleMode::Sinc => resample_sinc(original, from, to, resampling.quality),
+        ResampleMode::Linear => Ok(resample_linear(&original, from, to)),
+    }
+}
+
+/// Length of a signal of `len` samples after resampling.
+fn resampled_len(len: usize, from: u32, to: u32) -> usize {
+    (len as f64 * to as f64 / from as f64).round() as usize
+}
+
+fn resample_sinc(
+    mut original: Vec<f64>,
+    from: u32,
+    to: u32,
+    quality: SincQuality,
+) -> Result<Vec<f64>> {
+    let len = resampled_len(original.len(), from, to);
+    let ratio = to as f64 / from as f64;
+    let parameters = quality.parameters();
+    // The resampler stops short of the end of a chunk by the filter length
+    let padding = parameters.sinc_len + 2 * (1. / ratio).ceil() as usize;
+    original.resize(original.len() + padding, 0.);
+
+    let mut resampler = SincFixedIn::new(ratio, 1., parameters, original.len(), 1)?;
+    let mut resampled = resampler.process(&[original], None)?.pop().unwrap();
+    resampled.resize(len, 0.);
+    Ok(resampled)
+}
+
+fn resample_linear(original: &[f64], from: u32, to: u32) -> Vec<f64> {
+    let step = from as f64 / to as f64;
+    (0..resampled_len(original.len(), from, to))
+        .map(|idx| {
+            let position = idx as f64 * step;
+            let left = position.floor() as usize;
+            let a = original.get(left).copied().unwrap_or_default();
+            let b = original.get(left + 1).copied().unwrap_or(a);
+            a + (b - a) * position.fract()
+        })
+        .collect()
+}
+
+/// Quantizes a sample, clipping it at full scale.
 fn float_to_i16(sample: f64) -> i16 {
-    (sample * i16::MAX as f64).round() as i16
+    (sample.clamp(-1., 1.) * i16::MAX as f64).round() as i16
 }
 
 /// Scan function that applies binary operation to left and right channel for each frame.
@@ -211,6 +552,65 @@ where
     }
 }
 
+/// Reads a WAV file with 64-bit float samples, which hound does not support. Returns `None` for
+/// other files, they are left to hound.
+fn read_float64_wav(path: &Path) -> Result<Option<Decoded>> {
+    const IEEE_FLOAT: [u8; 2] = [0x03, 0x00];
+    const EXTENSIBLE: [u8; 2] = [0xFE, 0xFF];
+
+    let mut file = io::BufReader::new(fs::File::open(path)?);
+    let mut riff = [0; 12];
+    if file.read_exact(&mut riff).is_err() || &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+        return Ok(None);
+    }
+
+    let mut spec = None;
+    loop {
+        let mut header = [0; 8];
+        if file.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+        let (id, len) = header.split_at(4);
+        let len = u32::from_le_bytes(len.try_into().expect("split at 4"));
+        let mut chunk = Vec::new();
+        file.by_ref().take(len.into()).read_to_end(&mut chunk)?;
+
+        match id {
+            b"fmt " if chunk.len() >= 16 => {
+                let tag = [chunk[0], chunk[1]];
+                // Extensible format keeps the actual tag at the start of the sub-format GUID
+                let is_float = tag == IEEE_FLOAT
+                    || (tag == EXTENSIBLE && chunk.get(24..26) == Some(&IEEE_FLOAT[..]));
+                let bits_per_sample = u16::from_le_bytes([chunk[14], chunk[15]]);
+                if !is_float || bits_per_sample != 64 {
+                    return Ok(None);
+                }
+                spec = Some(WavSpec {
+                    channels: u16::from_le_bytes([chunk[2], chunk[3]]),
+                    sample_rate: u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]),
+                    bits_per_sample,
+                    sample_format: SampleFormat::Float,
+                });
+            }
+            b"data" => {
+                let Some(spec) = spec else {
+                    return Ok(None);
+                };
+                let samples = chunk
+                    .chunks_exact(8)
+                    .map(|bytes| f64::from_le_bytes(bytes.try_into().expect("chunks of 8")))
+                    .collect();
+                return Ok(Some(Decoded { spec, samples }));
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        if len % 2 == 1 {
+            file.read_exact(&mut [0])?;
+        }
+    }
+}
+
 #[auto_enum]
 fn into_samples_f64<R>(reader: WavReader<R>) -> Result<impl Iterator<Item = WavResult<f64>>>
 where
@@ -220,12 +620,14 @@ where
 
     #[auto_enum(Iterator)]
     let iter = match (spec.sample_format, spec.bits_per_sample) {
+        // 8-bit samples are unsigned, hound recenters them around zero
         (SampleFormat::Int, 8) => reader
             .into_samples::<i8>()
             .map(|res| res.map(IntSample::normalize_to_f64)),
         (SampleFormat::Int, 16) => reader
             .into_samples::<i16>()
             .map(|res| res.map(IntSample::normalize_to_f64)),
+        // 24-bit samples are not shifted to the range of `i32`
         (SampleFormat::Int, 24) => reader
             .into_samples::<i32>()
             .map(|res| res.map(I24))
@@ -269,3 +671,229 @@ macro_rules! impl_int_sample {
     )*}
 }
 impl_int_sample![i8, i16, i32, I24];
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn resample_length() {
+        let samples = vec![1000; 1000];
+        assert_eq!(resample_i16(&samples, 31250, 31250).unwrap(), samples);
+        let resampled = resample_i16(&samples, ECONOMY_SAMPLERATE, VOLCA_SAMPLERATE).unwrap();
+        assert_eq!(resampled.len(), 2000);
+    }
+
+    #[test]
+    fn resampler_modes() {
+        let sine = |sample_rate: u32| {
+            (0..sample_rate)
+                .map(|idx| 0.5 * (2. * PI * 440. * idx as f64 / sample_rate as f64).sin())
+                .collect::<Vec<_>>()
+        };
+        let rms = |samples: &[f64]| {
+            (samples.iter().map(|sample| sample * sample).sum::<f64>() / samples.len() as f64)
+                .sqrt()
+        };
+
+        for (from, to) in [
+            (44100, VOLCA_SAMPLERATE),
+            (48000, ECONOMY_SAMPLERATE),
+            (22050, 31250),
+        ] {
+            let original = sine(from);
+            for &mode in ResampleMode::value_variants() {
+                for &quality in SincQuality::value_variants() {
+                    let signal = Signal {
+                        samples: original.clone(),
+                        sample_rate: from,
+                    };
+                    let resampled = signal.resample(to, Resampling { mode, quality }).unwrap();
+                    assert_eq!(resampled.sample_rate, to);
+                    // One second in, one second out
+                    assert_eq!(
+                        resampled.samples.len(),
+                        to as usize,
+                        "{mode} {from} -> {to}"
+                    );
+                    let error = rms(&resampled.samples) - rms(&original);
+                    assert!(
+                        error.abs() < 0.01,
+                        "{mode} {quality} {from} -> {to}: {error}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn float64_wav() {
+        let path = Path::new("test_data/float64.wav");
+        let reader = AudioReader::open_file(path).unwrap();
+        assert_eq!(reader.channels(), 2);
+        assert_eq!(reader.spec.bits_per_sample, 64);
+        let left = reader
+            .take_channel(0)
+            .into_signal()
+            .unwrap()
+            .resample(44100, Resampling::default())
+            .unwrap()
+            .quantize();
+        // Full scale and beyond is clipped, not wrapped
+        let max = i16::MAX;
+        assert_eq!(left, [0, 16384, -16384, max, -max, max, -max, max, -max, 0]);
+
+        let reader = AudioReader::open_file(path).unwrap();
+        let right = reader
+            .take_channel(1)
+            .into_signal()
+            .unwrap()
+            .resample(44100, Resampling::default())
+            .unwrap()
+            .quantize();
+        assert!(left.iter().zip(&right).all(|(l, r)| *l == -r));
+
+        // Other WAV files are read by hound
+        let path = std::env::temp_dir().join(format!("volsa2-{}-int.wav", std::process::id()));
+        write_sample_to_file(&[1, 2, 3], &path, VOLCA_SAMPLERATE).unwrap();
+        assert!(read_float64_wav(&path).unwrap().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unsigned_8bit_wav() {
+        // Ramp over all unsigned values, 128 is silence
+        let reader = AudioReader::open_file(Path::new("test_data/ramp8.wav")).unwrap();
+        let samples = reader.reader.collect::<WavResult<Vec<_>>>().unwrap();
+        assert_eq!(samples.len(), 256);
+        for (raw, sample) in samples.iter().enumerate() {
+            assert_eq!(*sample, (raw as f64 - 128.) / 127.);
+        }
+        assert_eq!(samples[128], 0.);
+        assert_eq!(samples[255], 1.);
+
+        let reader = AudioReader::open_file(Path::new("test_data/ramp8.wav")).unwrap();
+        let quantized = reader
+            .take_channel(0)
+            .into_signal()
+            .unwrap()
+            .resample(8000, Resampling::default())
+            .unwrap()
+            .quantize();
+        assert_eq!(quantized[..2], [-i16::MAX, -i16::MAX]);
+        assert_eq!(quantized[128], 0);
+        assert_eq!(quantized[255], i16::MAX);
+    }
+
+    #[test]
+    fn pcm_24bit_wav() {
+        let reader = AudioReader::open_file(Path::new("test_data/pcm24.wav")).unwrap();
+        assert_eq!(reader.spec.bits_per_sample, 24);
+        let samples = reader.reader.collect::<WavResult<Vec<_>>>().unwrap();
+        // Normalized by the 24-bit maximum, not by `i32::MAX`
+        let max = f64::from((1 << 23) - 1);
+        let expected = [0, (1 << 23) - 1, -(1 << 23), 1 << 22, -(1 << 22), 1];
+        assert_eq!(samples, expected.map(|raw| f64::from(raw) / max));
+
+        let reader = AudioReader::open_file(Path::new("test_data/pcm24.wav")).unwrap();
+        let quantized = reader
+            .take_channel(0)
+            .into_signal()
+            .unwrap()
+            .resample(48000, Resampling::default())
+            .unwrap()
+            .quantize();
+        assert_eq!(quantized, [0, i16::MAX, -i16::MAX, 16384, -16384, 0]);
+    }
+
+    #[test]
+    fn quantize_clipping() {
+        assert_eq!(float_to_i16(1.0), i16::MAX);
+        assert_eq!(float_to_i16(1.0001), i16::MAX);
+        assert_eq!(float_to_i16(100.), i16::MAX);
+        assert_eq!(float_to_i16(-1.0), -i16::MAX);
+        assert_eq!(float_to_i16(-3.5), -i16::MAX);
+        assert_eq!(float_to_i16(f64::NAN), 0);
+    }
+
+    #[test]
+    fn extended_float() {
+        assert_eq!(u32_to_extended(0), [0; 10]);
+        assert_eq!(
+            u32_to_extended(1),
+            hex_literal::hex!("3F FF 80 00 00 00 00 00 00 00")
+        );
+        assert_eq!(
+            u32_to_extended(44100),
+            hex_literal::hex!("40 0E AC 44 00 00 00 00 00 00")
+        );
+        assert_eq!(
+            u32_to_extended(VOLCA_SAMPLERATE),
+            hex_literal::hex!("40 0D F4 24 00 00 00 00 00 00")
+        );
+    }
+
+    #[test]
+    fn aiff_header() {
+        let samples = [0x0102, -2, i16::MAX];
+        let mut aiff = Vec::new();
+        write_aiff(&samples, VOLCA_SAMPLERATE, &mut aiff).unwrap();
+
+        assert_eq!(&aiff[..4], b"FORM");
+        assert_eq!(
+            u32::from_be_bytes(aiff[4..8].try_into().unwrap()) as usize,
+            aiff.len() - 8
+        );
+        assert_eq!(&aiff[8..12], b"AIFC");
+        assert_eq!(&aiff[12..16], b"FVER");
+
+        let comm = &aiff[24..];
+        assert_eq!(&comm[..4], b"COMM");
+        assert_eq!(u32::from_be_bytes(comm[4..8].try_into().unwrap()), 38);
+        assert_eq!(i16::from_be_bytes([comm[8], comm[9]]), 1);
+        assert_eq!(u32::from_be_bytes(comm[10..14].try_into().unwrap()), 3);
+        assert_eq!(i16::from_be_bytes([comm[14], comm[15]]), 16);
+        assert_eq!(comm[16..26], u32_to_extended(VOLCA_SAMPLERATE));
+        assert_eq!(&comm[26..30], b"NONE");
+
+        let ssnd = &comm[8 + 38..];
+        assert_eq!(&ssnd[..4], b"SSND");
+        assert_eq!(u32::from_be_bytes(ssnd[4..8].try_into().unwrap()), 8 + 6);
+        assert_eq!(&ssnd[16..], &[0x01, 0x02, 0xFF, 0xFE, 0x7F, 0xFF]);
+    }
+
+    #[cfg(feature = "flac")]
+    #[test]
+    fn flac_stream_info() {
+        let samples = (0..10_000)
+            .map(|idx| ((idx as f64 / 20.).sin() * 10_000.) as i16)
+            .collect::<Vec<_>>();
+        let flac = encode_flac(&samples, VOLCA_SAMPLERATE).unwrap();
+
+        assert_eq!(&flac[..4], b"fLaC");
+        // STREAMINFO block follows the magic and a 4 byte block header
+        let stream_info = &flac[8..];
+        let sample_rate =
+            u32::from_be_bytes([0, stream_info[10], stream_info[11], stream_info[12]]) >> 4;
+        let channels = ((stream_info[12] >> 1) & 0b111) + 1;
+        let bits_per_sample = (((stream_info[12] & 1) << 4) | (stream_info[13] >> 4)) + 1;
+        let total_samples = u64::from_be_bytes([
+            0,
+            0,
+            0,
+            stream_info[13] & 0b1111,
+            stream_info[14],
+            stream_info[15],
+            stream_info[16],
+            stream_info[17],
+        ]);
+        assert_eq!(sample_rate, VOLCA_SAMPLERATE);
+        assert_eq!(channels, 1);
+        assert_eq!(bits_per_sample, 16);
+        assert_eq!(total_samples, samples.len() as u64);
+        // Fixed block size, the short last block does not count
+        assert_eq!(stream_info[..2], stream_info[2..4]);
+    }
+}
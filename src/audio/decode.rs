@@ -0,0 +1,406 @@
+//! Decoding of compressed input files.
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use derive_more::Display;
+use hound::WavSpec;
+
+use super::{AudioError, Result};
+
+/// Compressed input formats. Everything else is read as WAV.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum Compressed {
+    #[display(fmt = "FLAC")]
+    Flac,
+    #[display(fmt = "MP3")]
+    Mp3,
+    #[display(fmt = "Ogg Vorbis")]
+    Vorbis,
+}
+
+impl Compressed {
+    /// Detects a compressed file by its magic bytes, or by its extension if they are not known.
+    pub fn detect(path: &Path) -> Result<Option<Self>> {
+        let mut magic = Vec::with_capacity(4);
+        fs::File::open(path)?.take(4).read_to_end(&mut magic)?;
+        let detected = match magic.as_slice() {
+            b"fLaC" => Some(Self::Flac),
+            b"OggS" => Some(Self::Vorbis),
+            // ID3v2 tag or an MPEG audio frame sync
+            [b'I', b'D', b'3', ..] => Some(Self::Mp3),
+            [0xFF, second, ..] if second & 0xE0 == 0xE0 => Some(Self::Mp3),
+            _ => None,
+        };
+        if detected.is_some() {
+            return Ok(detected);
+        }
+
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        Ok(match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("flac") => Some(Self::Flac),
+            Some("mp3") => Some(Self::Mp3),
+            Some("ogg" | "oga") => Some(Self::Vorbis),
+            _ => None,
+        })
+    }
+
+    /// Cargo feature that enables decoding of the format.
+    fn feature(self) -> &'static str {
+        match self {
+            Self::Flac => "flac",
+            Self::Mp3 => "mp3",
+            Self::Vorbis => "vorbis",
+        }
+    }
+
+    #[cfg(feature = "decode")]
+    fn is_enabled(self) -> bool {
+        match self {
+            Self::Flac => cfg!(feature = "flac"),
+            Self::Mp3 => cfg!(feature = "mp3"),
+            Self::Vorbis => cfg!(feature = "vorbis"),
+        }
+    }
+
+    fn disabled(self) -> AudioError {
+        AudioError::Codec(format!(
+            "{self}, built without the `{}` feature",
+            self.feature()
+        ))
+    }
+
+    #[cfg(feature = "decode")]
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Flac => "flac",
+            Self::Mp3 => "mp3",
+            Self::Vorbis => "ogg",
+        }
+    }
+}
+
+/// Interleaved audio, decoded as a whole.
+pub struct Decoded {
+    /// Stream parameters as if it was read from a WAV file.
+    pub spec: WavSpec,
+    pub samples: Vec<f64>,
+}
+
+impl Decoded {
+    /// Length in frames.
+    pub fn duration(&self) -> u32 {
+        (self.samples.len() / usize::from(self.spec.channels.max(1))) as u32
+    }
+}
+
+#[cfg(feature = "decode")]
+pub fn decode_file(path: &Path, format: Compressed) -> Result<Decoded> {
+    if !format.is_enabled() {
+        return Err(format.disabled());
+    }
+    decode(Box::new(fs::File::open(path)?), format)
+}
+
+#[cfg(not(feature = "decode"))]
+pub fn decode_file(_: &Path, format: Compressed) -> Result<Decoded> {
+    Err(format.disabled())
+}
+
+#[cfg(feature = "decode")]
+fn decode(
+    source: Box<dyn symphonia::core::io::MediaSource>,
+    format: Compressed,
+) -> Result<Decoded> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+    use symphonia::core::errors::Error as SymphoniaError;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let stream = MediaSourceStream::new(source, Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(format.extension());
+    let mut reader = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            // Trims encoder delay and padding, e.g. of MP3 files with a LAME tag
+            &FormatOptions {
+                enable_gapless: true,
+                ..Default::default()
+            },
+            &MetadataOptions::default(),
+        )
+        .map_err(decode_error)?
+        .format;
+
+    // Multiplexed Ogg streams, e.g. several audio streams played at once
+    let streams = reader
+        .tracks()
+        .iter()
+        .filter(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .count();
+    if streams > 1 {
+        return Err(AudioError::Decode(format!(
+            "{format} file has {streams} streams, only files with a single stream are supported"
+        )));
+    }
+
+    let track = reader
+        .default_track()
+        .ok_or_else(|| AudioError::Decode(format!("{format} stream has no audio track")))?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+
+    let codecs = symphonia::default::get_codecs();
+    let mut decoder =
+        codecs
+            .make(&params, &DecoderOptions::default())
+            .map_err(|err| match err {
+                SymphoniaError::Unsupported(_) => AudioError::Codec(codec_name(params.codec)),
+                err => decode_error(err),
+            })?;
+
+    let mut spec = WavSpec {
+        channels: params
+            .channels
+            .map_or(0, |channels| channels.count() as u16),
+        sample_rate: params.sample_rate.unwrap_or_default(),
+        bits_per_sample: params.bits_per_sample.map_or(16, |bits| bits as u16),
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut samples = Vec::new();
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            // The end of the stream is reported as an error
+            Err(SymphoniaError::IoError(err))
+                if err.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                break
+            }
+            Err(err) => return Err(decode_error(err)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Players skip corrupt packets too
+            Err(SymphoniaError::DecodeError(err)) => {
+                tracing::warn!(err, "skipping corrupt packet");
+                continue;
+            }
+            Err(err) => return Err(decode_error(err)),
+        };
+        let signal = *decoded.spec();
+        spec.channels = signal.channels.count() as u16;
+        spec.sample_rate = signal.rate;
+
+        let mut buf = SampleBuffer::<f64>::new(decoded.capacity() as u64, signal);
+        buf.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(buf.samples());
+    }
+
+    Ok(Decoded { spec, samples })
+}
+
+#[cfg(feature = "decode")]
+fn decode_error(err: symphonia::core::errors::Error) -> AudioError {
+    use symphonia::core::errors::Error as SymphoniaError;
+
+    match err {
+        SymphoniaError::IoError(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+            AudioError::Decode("stream ends unexpectedly".to_owned())
+        }
+        SymphoniaError::IoError(err) => AudioError::Io(err),
+        // A new stream starts where the previous one ends, as in chained Ogg files
+        SymphoniaError::ResetRequired => {
+            AudioError::Decode("chained streams are not supported".to_owned())
+        }
+        err => AudioError::Decode(err.to_string()),
+    }
+}
+
+/// Name of a codec that may be found in a container but can not be decoded.
+#[cfg(feature = "decode")]
+fn codec_name(codec: symphonia::core::codecs::CodecType) -> String {
+    use symphonia::core::codecs::{
+        CODEC_TYPE_AAC, CODEC_TYPE_ALAC, CODEC_TYPE_FLAC, CODEC_TYPE_MP3, CODEC_TYPE_OPUS,
+        CODEC_TYPE_VORBIS,
+    };
+
+    const NAMES: &[(symphonia::core::codecs::CodecType, &str)] = &[
+        (CODEC_TYPE_AAC, "AAC"),
+        (CODEC_TYPE_ALAC, "ALAC"),
+        (CODEC_TYPE_FLAC, "FLAC"),
+        (CODEC_TYPE_MP3, "MP3"),
+        (CODEC_TYPE_OPUS, "Opus"),
+        (CODEC_TYPE_VORBIS, "Vorbis"),
+    ];
+    NAMES
+        .iter()
+        .find(|(known, _)| *known == codec)
+        .map_or_else(|| format!("{codec:?}"), |(_, name)| name.to_string())
+}
+
+#[cfg(all(test, feature = "decode"))]
+mod tests {
+    use super::*;
+    #[cfg(feature = "flac")]
+    use crate::audio::{encode_flac_stream, AudioReader, Resampling, VOLCA_SAMPLERATE};
+
+    #[cfg(feature = "flac")]
+    /// Interleaved test signal, a different sine on each channel, at full scale for `bits`.
+    fn signal(frames: usize, channels: usize, bits: u32) -> Vec<i32> {
+        let amplitude = f64::from((1 << (bits - 1)) - 1);
+        (0..frames * channels)
+            .map(|idx| {
+                let (frame, channel) = (idx / channels, idx % channels);
+                let phase = frame as f64 / (10. + channel as f64 * 7.);
+                (phase.sin() * amplitude) as i32
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "flac")]
+    fn decode_flac(samples: &[i32], channels: usize, bits: u32, block_size: usize) -> Decoded {
+        let flac = encode_flac_stream(samples, channels, bits as usize, 44100, block_size).unwrap();
+        decode(Box::new(std::io::Cursor::new(flac)), Compressed::Flac).unwrap()
+    }
+
+    #[cfg(feature = "flac")]
+    fn assert_decoded(decoded: &Decoded, samples: &[i32], bits: u32) {
+        let scale = f64::from(1 << (bits - 1));
+        assert_eq!(decoded.samples.len(), samples.len());
+        for (decoded, &sample) in decoded.samples.iter().zip(samples) {
+            assert!((decoded - f64::from(sample) / scale).abs() < 1e-9);
+        }
+    }
+
+    #[cfg(feature = "flac")]
+    #[test]
+    fn flac_channels_and_depth() {
+        for (channels, bits) in [(1, 16), (2, 16), (2, 24), (3, 24), (6, 8)] {
+            let samples = signal(5000, channels, bits);
+            let decoded = decode_flac(&samples, channels, bits, 4096);
+            assert_eq!(decoded.spec.channels, channels as u16);
+            assert_eq!(decoded.spec.bits_per_sample, bits as u16);
+            assert_eq!(decoded.spec.sample_rate, 44100);
+            assert_eq!(decoded.duration(), 5000);
+            assert_decoded(&decoded, &samples, bits);
+        }
+    }
+
+    #[cfg(feature = "flac")]
+    #[test]
+    fn flac_odd_block_sizes() {
+        // Blocks that do not divide the stream, and a short last block
+        for block_size in [32, 1001, 4607] {
+            let samples = signal(9999, 2, 16);
+            let decoded = decode_flac(&samples, 2, 16, block_size);
+            assert_decoded(&decoded, &samples, 16);
+        }
+    }
+
+    #[cfg(feature = "flac")]
+    #[test]
+    fn flac_upload_path() {
+        let samples = signal(3000, 2, 24);
+        let flac = encode_flac_stream(&samples, 2, 24, VOLCA_SAMPLERATE as usize, 4096).unwrap();
+        // No extension, the format is sniffed
+        let path = std::env::temp_dir().join(format!("volsa2-{}-stereo", std::process::id()));
+        fs::write(&path, flac).unwrap();
+
+        assert_eq!(Compressed::detect(&path).unwrap(), Some(Compressed::Flac));
+        let reader = AudioReader::open_file(&path).unwrap();
+        assert_eq!(reader.channels(), 2);
+        let right = reader
+            .take_channel(1)
+            .into_signal()
+            .unwrap()
+            .resample(VOLCA_SAMPLERATE, Resampling::default())
+            .unwrap()
+            .quantize();
+        fs::remove_file(&path).unwrap();
+
+        let expected = samples
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .map(|&sample| (f64::from(sample) / f64::from(1 << 23) * f64::from(i16::MAX)).round())
+            .map(|sample| sample as i16);
+        assert!(right
+            .iter()
+            .zip(expected)
+            .all(|(&sample, expected)| sample.abs_diff(expected) <= 1));
+        assert_eq!(right.len(), 3000);
+    }
+
+    #[cfg(feature = "mp3")]
+    #[test]
+    fn mp3_fixtures() {
+        // 20 frames of silence behind an ID3 tag. The LAME tag gives 576 samples of encoder delay
+        // and 1200 samples of padding, decoders trim 529 more at the start and as many less at the
+        // end. The tag is ignored if its CRC does not match.
+        for (path, channels, sample_rate) in [
+            ("test_data/silence_cbr.mp3", 2, 48000),
+            ("test_data/silence_vbr.mp3", 1, 32000),
+        ] {
+            let path = Path::new(path);
+            assert_eq!(Compressed::detect(path).unwrap(), Some(Compressed::Mp3));
+            let decoded = decode_file(path, Compressed::Mp3).unwrap();
+            assert_eq!(decoded.spec.channels, channels, "{path:?}");
+            assert_eq!(decoded.spec.sample_rate, sample_rate, "{path:?}");
+            assert_eq!(decoded.duration(), 20 * 1152 - 576 - 1200, "{path:?}");
+            assert!(decoded.samples.iter().all(|sample| *sample == 0.));
+        }
+    }
+
+    #[cfg(feature = "vorbis")]
+    #[test]
+    fn ogg_fixtures() {
+        let path = Path::new("test_data/corrupt.ogg");
+        assert_eq!(Compressed::detect(path).unwrap(), Some(Compressed::Vorbis));
+        let err = decode_file(path, Compressed::Vorbis).err().unwrap();
+        assert!(matches!(err, AudioError::Decode(_)), "{err}");
+
+        // Opus has its own Ogg mapping, but no decoder
+        let path = Path::new("test_data/opus.ogg");
+        let err = decode_file(path, Compressed::Vorbis).err().unwrap();
+        assert_eq!(err.to_string(), "unsupported codec Opus");
+    }
+
+    #[test]
+    fn detect_formats() {
+        let path = std::env::temp_dir().join(format!("volsa2-{}-detect", std::process::id()));
+        for (magic, expected) in [
+            (&b"fLaC\0\0"[..], Some(Compressed::Flac)),
+            (&b"ID3\x04"[..], Some(Compressed::Mp3)),
+            (&b"OggS\0\x02"[..], Some(Compressed::Vorbis)),
+            (&[0xFF, 0xFB, 0x90, 0x64][..], Some(Compressed::Mp3)),
+            (&b"RIFF\0\0\0\0WAVE"[..], None),
+            (&b""[..], None),
+        ] {
+            fs::write(&path, magic).unwrap();
+            assert_eq!(Compressed::detect(&path).unwrap(), expected, "{magic:02X?}");
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn codec_names() {
+        use symphonia::core::codecs::CODEC_TYPE_OPUS;
+
+        assert_eq!(codec_name(CODEC_TYPE_OPUS), "Opus");
+        assert_eq!(
+            AudioError::Codec(codec_name(CODEC_TYPE_OPUS)).to_string(),
+            "unsupported codec Opus"
+        );
+    }
+}
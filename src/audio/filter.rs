@@ -0,0 +1,132 @@
+//! Filters applied to the signal before it is converted for the device.
+
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+use super::{AudioError, Result, Signal};
+
+/// Frequencies a filter lets through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    High,
+    Low,
+}
+
+/// Second order IIR filter section with coefficients normalized by `a0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Biquad {
+    pub b: [f64; 3],
+    pub a: [f64; 2],
+}
+
+impl Biquad {
+    /// Second order Butterworth filter, 3dB down at the cutoff frequency.
+    pub fn butterworth(pass: Pass, cutoff: f64, sample_rate: f64) -> Self {
+        let (sin, cos) = (2. * PI * cutoff / sample_rate).sin_cos();
+        let alpha = sin / (2. * FRAC_1_SQRT_2);
+        let a0 = 1. + alpha;
+        let b = match pass {
+            Pass::High => [(1. + cos) / 2., -(1. + cos), (1. + cos) / 2.],
+            Pass::Low => [(1. - cos) / 2., 1. - cos, (1. - cos) / 2.],
+        };
+        Self {
+            b: b.map(|b| b / a0),
+            a: [-2. * cos / a0, (1. - alpha) / a0],
+        }
+    }
+
+    /// Filters samples in place, starting from silence.
+    pub fn apply(&self, samples: &mut [f64]) {
+        let [b0, b1, b2] = self.b;
+        let [a1, a2] = self.a;
+        // Transposed direct form II
+        let (mut z1, mut z2) = (0., 0.);
+        for sample in samples {
+            let x = *sample;
+            let y = b0 * x + z1;
+            z1 = b1 * x - a1 * y + z2;
+            z2 = b2 * x - a2 * y;
+            *sample = y;
+        }
+    }
+}
+
+/// Filters the signal with a Butterworth filter forward and then backward. Phase shifts cancel
+/// out and attenuation doubles, so the cutoff frequency is 6dB down and the slope is 24dB per
+/// octave.
+pub fn apply_filter(signal: &mut Signal, pass: Pass, cutoff: u32) -> Result<()> {
+    if 2 * u64::from(cutoff) >= u64::from(signal.sample_rate) {
+        return Err(AudioError::Cutoff(cutoff, signal.sample_rate / 2));
+    }
+    tracing::debug!(?pass, cutoff, "filtering");
+
+    let filter = Biquad::butterworth(pass, cutoff.into(), signal.sample_rate.into());
+    filter.apply(&mut signal.samples);
+    signal.samples.reverse();
+    filter.apply(&mut signal.samples);
+    signal.samples.reverse();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Level of a filtered sine in dB, measured away from the edges.
+    fn response(pass: Pass, cutoff: u32, frequency: f64, sample_rate: u32) -> f64 {
+        let samples = (0..sample_rate)
+            .map(|idx| (2. * PI * frequency * idx as f64 / sample_rate as f64).sin())
+            .collect();
+        let mut signal = Signal {
+            samples,
+            sample_rate,
+        };
+        apply_filter(&mut signal, pass, cutoff).unwrap();
+
+        let len = signal.samples.len();
+        let middle = &signal.samples[len / 4..len * 3 / 4];
+        let power = middle.iter().map(|sample| sample * sample).sum::<f64>() / middle.len() as f64;
+        // Power of a full scale sine is 1/2
+        10. * (power * 2.).log10()
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn low_pass() {
+        for sample_rate in [44100, 48000] {
+            assert_close(response(Pass::Low, 1000, 250., sample_rate), 0., 0.1);
+            assert_close(response(Pass::Low, 1000, 1000., sample_rate), -6.02, 0.1);
+            assert_close(response(Pass::Low, 1000, 2000., sample_rate), -24.6, 0.3);
+            assert_close(response(Pass::Low, 1000, 4000., sample_rate), -49., 0.5);
+        }
+    }
+
+    #[test]
+    fn high_pass() {
+        for sample_rate in [44100, 48000] {
+            assert_close(response(Pass::High, 1000, 4000., sample_rate), 0., 0.1);
+            assert_close(response(Pass::High, 1000, 1000., sample_rate), -6.02, 0.1);
+            assert_close(response(Pass::High, 1000, 500., sample_rate), -24.6, 0.3);
+            assert_close(response(Pass::High, 1000, 250., sample_rate), -48.2, 0.5);
+        }
+    }
+
+    #[test]
+    fn cutoff_above_nyquist() {
+        let mut signal = Signal {
+            samples: vec![0.; 100],
+            sample_rate: 48000,
+        };
+        assert!(matches!(
+            apply_filter(&mut signal, Pass::Low, 24000),
+            Err(AudioError::Cutoff(24000, 24000))
+        ));
+        assert!(apply_filter(&mut signal, Pass::High, 30000).is_err());
+        assert!(apply_filter(&mut signal, Pass::Low, 23999).is_ok());
+    }
+}
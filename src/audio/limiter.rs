@@ -0,0 +1,130 @@
+//! Look-ahead peak limiter.
+
+use std::collections::VecDeque;
+
+use super::Signal;
+
+/// Time the gain takes to go down before a peak, in seconds.
+const ATTACK: f64 = 0.005;
+/// Time constant of the gain recovering after a peak, in seconds.
+const RELEASE: f64 = 0.05;
+
+/// Keeps peaks of the signal under the ceiling in dBFS, smoothly reducing gain ahead of them.
+/// Returns the largest gain reduction in dB.
+pub fn apply_limiter(signal: &mut Signal, ceiling: f64) -> f64 {
+    let ceiling = 10f64.powf(ceiling / 20.);
+    let attack = ((ATTACK * signal.sample_rate as f64).round() as usize).max(1);
+    let release = 1. - (-1. / (RELEASE * signal.sample_rate as f64)).exp();
+
+    // Gain each sample needs to stay under the ceiling
+    let required = signal
+        .samples
+        .iter()
+        .map(|sample| (ceiling / sample.abs()).min(1.))
+        .collect::<Vec<_>>();
+
+    // Gain for a sample is held at the lowest gain required within the attack window ahead of
+    // it, recovering slowly afterwards. Averaging it over the window then ramps the gain down
+    // without ever going over what any sample in the window requires.
+    let mut window = VecDeque::<(usize, f64)>::new();
+    let mut held = Vec::with_capacity(required.len() + attack);
+    let mut envelope = 1f64;
+    for idx in 0..required.len() + attack - 1 {
+        // Past the end there is nothing to limit
+        let gain = required.get(idx).copied().unwrap_or(1.);
+        while window.back().is_some_and(|&(_, back)| back >= gain) {
+            window.pop_back();
+        }
+        window.push_back((idx, gain));
+        while window
+            .front()
+            .is_some_and(|&(front, _)| front + attack <= idx)
+        {
+            window.pop_front();
+        }
+        let min = window.front().map_or(1., |&(_, min)| min);
+        envelope = min.min(envelope + (1. - envelope) * release);
+        held.push(envelope);
+    }
+
+    let mut reduction = 1f64;
+    let mut sum = held[..attack - 1].iter().sum::<f64>();
+    for (idx, sample) in signal.samples.iter_mut().enumerate() {
+        sum += held[idx + attack - 1];
+        let gain = sum / attack as f64;
+        sum -= held[idx];
+        reduction = reduction.min(gain);
+        // Rounding errors of the running sum must not let a sample through
+        *sample = (*sample * gain).clamp(-ceiling, ceiling);
+    }
+    -20. * reduction.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(samples: Vec<f64>) -> Signal {
+        Signal {
+            samples,
+            sample_rate: 31250,
+        }
+    }
+
+    #[test]
+    fn overdriven() {
+        // A tone driven 12dB over full scale
+        let samples = (0..31250)
+            .map(|idx| 4. * (2. * std::f64::consts::PI * 440. * idx as f64 / 31250.).sin())
+            .collect();
+        let mut signal = signal(samples);
+        let reduction = apply_limiter(&mut signal, -0.1);
+
+        let ceiling = 10f64.powf(-0.1 / 20.);
+        assert!(signal.samples.iter().all(|sample| sample.abs() <= ceiling));
+        assert!((reduction - 12.1).abs() < 0.1, "{reduction}");
+        // Peaks are brought down to the ceiling, not squashed far below it
+        let peak = signal
+            .samples
+            .iter()
+            .fold(0f64, |peak, sample| peak.max(sample.abs()));
+        assert!(peak > 0.9 * ceiling);
+    }
+
+    #[test]
+    fn single_peak() {
+        let mut samples = vec![0.5; 20000];
+        samples[1000] = 2.;
+        let mut signal = signal(samples.clone());
+        let reduction = apply_limiter(&mut signal, 0.);
+        assert!((reduction - 6.02).abs() < 0.01, "{reduction}");
+        assert_eq!(signal.samples[1000], 1.);
+
+        // Gain goes down ahead of the peak and recovers after it
+        assert_eq!(signal.samples[0], 0.5);
+        assert!(signal.samples[900] < 0.5);
+        assert!(signal.samples[1100] < 0.5);
+        assert!((signal.samples[19999] - 0.5).abs() < 1e-3);
+        // without sudden jumps
+        let gains = signal
+            .samples
+            .iter()
+            .zip(&samples)
+            .map(|(limited, sample)| limited / sample)
+            .collect::<Vec<_>>();
+        assert!(gains
+            .windows(2)
+            .all(|pair| (pair[1] - pair[0]).abs() < 0.01));
+    }
+
+    #[test]
+    fn quiet_signal() {
+        let samples = vec![0.1, -0.5, 0.9, -0.9, 0.];
+        let mut signal = signal(samples.clone());
+        assert_eq!(apply_limiter(&mut signal, -0.1), 0.);
+        assert_eq!(signal.samples, samples);
+
+        let mut empty = self::signal(Vec::new());
+        assert_eq!(apply_limiter(&mut empty, -0.1), 0.);
+    }
+}
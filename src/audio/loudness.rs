@@ -0,0 +1,302 @@
+//! Loudness measurement and normalization per ITU-R BS.1770.
+
+use std::f64::consts::PI;
+
+use super::filter::Biquad;
+use super::Signal;
+
+/// Duration of a gating block in seconds.
+const BLOCK: f64 = 0.4;
+/// Blocks overlap by 75%.
+const BLOCK_STEP: f64 = BLOCK / 4.;
+const ABSOLUTE_GATE: f64 = -70.;
+const RELATIVE_GATE: f64 = -10.;
+/// Oversampling factor of the true peak meter.
+const OVERSAMPLING: usize = 4;
+/// Interpolation filter taps on each side of an oversampled point.
+const TAPS: isize = 12;
+
+/// Loudness to normalize a sample to.
+#[derive(Debug, Clone, Copy)]
+pub struct LoudnessTarget {
+    /// Integrated loudness in LUFS.
+    pub integrated: f64,
+    /// Maximum true peak level in dBTP. Takes precedence over the loudness.
+    pub true_peak: f64,
+}
+
+/// How the loudness of a signal was measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Measurement {
+    /// Gated integrated loudness.
+    Integrated,
+    /// Momentary loudness over the whole signal, which is shorter than a single gating block.
+    Momentary,
+}
+
+/// Result of loudness normalization.
+#[derive(Debug, Clone, Copy)]
+pub struct Normalization {
+    /// Loudness of the source in LUFS.
+    pub measured: f64,
+    pub measurement: Measurement,
+    /// True peak level of the source in dBTP.
+    pub true_peak: f64,
+    /// Applied gain in dB.
+    pub gain: f64,
+    /// Whether the gain was reduced to keep the true peak under the ceiling.
+    pub limited: bool,
+}
+
+/// Applies gain to bring the signal to the target loudness. Returns `None` if the signal is too
+/// quiet to be measured, it is left as is then.
+pub fn normalize_loudness(signal: &mut Signal, target: LoudnessTarget) -> Option<Normalization> {
+    let (measured, measurement) = measure(&signal.samples, signal.sample_rate)?;
+    let true_peak = true_peak(&signal.samples);
+    let mut gain = target.integrated - measured;
+    let limited = true_peak + gain > target.true_peak;
+    if limited {
+        gain = target.true_peak - true_peak;
+    }
+
+    let factor = 10f64.powf(gain / 20.);
+    signal
+        .samples
+        .iter_mut()
+        .for_each(|sample| *sample *= factor);
+    Some(Normalization {
+        measured,
+        measurement,
+        true_peak,
+        gain,
+        limited,
+    })
+}
+
+/// Measures loudness of a mono signal in LUFS.
+fn measure(samples: &[f64], sample_rate: u32) -> Option<(f64, Measurement)> {
+    let mut weighted = samples.to_vec();
+    for stage in k_weighting(sample_rate) {
+        stage.apply(&mut weighted);
+    }
+
+    let block = (BLOCK * sample_rate as f64).round() as usize;
+    if weighted.len() < block {
+        let loudness = power_to_lufs(mean_square(&weighted)?);
+        return (loudness > ABSOLUTE_GATE).then_some((loudness, Measurement::Momentary));
+    }
+
+    let step = (BLOCK_STEP * sample_rate as f64).round() as usize;
+    let powers = (0..=weighted.len() - block)
+        .step_by(step)
+        .filter_map(|start| mean_square(&weighted[start..start + block]))
+        .filter(|&power| power_to_lufs(power) > ABSOLUTE_GATE)
+        .collect::<Vec<_>>();
+    let threshold = power_to_lufs(mean(&powers)?) + RELATIVE_GATE;
+    let gated = powers
+        .into_iter()
+        .filter(|&power| power_to_lufs(power) > threshold)
+        .collect::<Vec<_>>();
+    Some((power_to_lufs(mean(&gated)?), Measurement::Integrated))
+}
+
+/// K-weighting filter: a high shelf modelling the head followed by a high-pass. Coefficients are
+/// derived for any sample rate the same way as in libebur128.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1. + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2. * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1. + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1., -2., 1.],
+        a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+    };
+
+    [shelf, high_pass]
+}
+
+/// Estimates the true peak level in dBTP by interpolating the signal between samples.
+fn true_peak(samples: &[f64]) -> f64 {
+    let kernels = (1..OVERSAMPLING)
+        .map(|phase| {
+            let offset = phase as f64 / OVERSAMPLING as f64;
+            (1 - TAPS..=TAPS)
+                .map(|tap| {
+                    let t = offset - tap as f64;
+                    // Sinc with a Hann window
+                    let window = (PI * t / (2 * TAPS) as f64).cos().powi(2);
+                    (tap, (PI * t).sin() / (PI * t) * window)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut peak = samples
+        .iter()
+        .fold(0f64, |peak, sample| peak.max(sample.abs()));
+    for idx in 0..samples.len() as isize {
+        for kernel in &kernels {
+            let interpolated = kernel
+                .iter()
+                .filter_map(|&(tap, coef)| {
+                    let sample = samples.get(usize::try_from(idx + tap).ok()?)?;
+                    Some(sample * coef)
+                })
+                .sum::<f64>();
+            peak = peak.max(interpolated.abs());
+        }
+    }
+    20. * peak.log10()
+}
+
+fn power_to_lufs(power: f64) -> f64 {
+    -0.691 + 10. * power.log10()
+}
+
+fn mean_square(samples: &[f64]) -> Option<f64> {
+    mean(
+        &samples
+            .iter()
+            .map(|sample| sample * sample)
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(amplitude: f64, frequency: f64, sample_rate: u32, duration: f64) -> Signal {
+        let len = (duration * sample_rate as f64) as usize;
+        let samples = (0..len)
+            .map(|idx| amplitude * (2. * PI * frequency * idx as f64 / sample_rate as f64).sin())
+            .collect();
+        Signal {
+            samples,
+            sample_rate,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn k_weighting_coefficients() {
+        // Reference coefficients for 48kHz from BS.1770
+        let [shelf, high_pass] = k_weighting(48000);
+        let expected = [1.53512485958697, -2.69169618940638, 1.19839281085285];
+        shelf
+            .b
+            .iter()
+            .zip(expected)
+            .for_each(|(b, e)| assert_close(*b, e, 1e-12));
+        assert_close(shelf.a[0], -1.69065929318241, 1e-12);
+        assert_close(shelf.a[1], 0.73248077421585, 1e-12);
+        assert_eq!(high_pass.b, [1., -2., 1.]);
+        assert_close(high_pass.a[0], -1.99004745483398, 1e-12);
+        assert_close(high_pass.a[1], 0.99007225036621, 1e-12);
+    }
+
+    #[test]
+    fn sine_loudness() {
+        // A full scale 1kHz sine reads -3.01 LUFS on a single channel
+        for sample_rate in [31250, 44100, 48000] {
+            let signal = sine(0.1, 1000., sample_rate, 2.);
+            let (loudness, measurement) = measure(&signal.samples, sample_rate).unwrap();
+            assert_close(loudness, -23.01, 0.05);
+            assert_eq!(measurement, Measurement::Integrated);
+        }
+    }
+
+    #[test]
+    fn gating() {
+        let mut signal = sine(0.1, 1000., 48000, 2.);
+        signal.samples.resize(4 * 48000, 0.);
+        // Plain average over the whole signal would be 3dB lower
+        let (loudness, _) = measure(&signal.samples, 48000).unwrap();
+        assert_close(loudness, -23.34, 0.05);
+
+        assert_eq!(measure(&vec![0.; 48000], 48000), None);
+        assert_eq!(measure(&[], 48000), None);
+        let quiet = sine(1e-4, 1000., 48000, 1.);
+        assert_eq!(measure(&quiet.samples, 48000), None);
+    }
+
+    #[test]
+    fn short_clip() {
+        let signal = sine(0.1, 1000., 48000, 0.2);
+        let (loudness, measurement) = measure(&signal.samples, 48000).unwrap();
+        assert_close(loudness, -23.01, 0.05);
+        assert_eq!(measurement, Measurement::Momentary);
+    }
+
+    #[test]
+    fn inter_sample_peak() {
+        // Samples of a quarter sample rate sine shifted by 45 degrees miss its peaks by 3dB
+        let samples = (0..400)
+            .map(|idx| (PI / 2. * idx as f64 + PI / 4.).sin())
+            .collect::<Vec<_>>();
+        let sample_peak = samples
+            .iter()
+            .fold(0f64, |peak, sample| peak.max(sample.abs()));
+        assert_close(20. * sample_peak.log10(), -3.01, 0.01);
+        assert_close(true_peak(&samples), 0., 0.2);
+
+        let signal = sine(0.5, 1000., 48000, 0.1);
+        assert_close(true_peak(&signal.samples), -6.02, 0.01);
+    }
+
+    #[test]
+    fn normalization() {
+        let target = LoudnessTarget {
+            integrated: -14.,
+            true_peak: -1.,
+        };
+        let mut signal = sine(0.1, 1000., 48000, 2.);
+        let result = normalize_loudness(&mut signal, target).unwrap();
+        assert_close(result.measured, -23.01, 0.05);
+        assert_close(result.true_peak, -20., 0.01);
+        assert_close(result.gain, 9.01, 0.05);
+        assert!(!result.limited);
+        let (loudness, _) = measure(&signal.samples, 48000).unwrap();
+        assert_close(loudness, -14., 0.01);
+
+        // A sine can not get to -1 LUFS without peaking over -1dBTP
+        let target = LoudnessTarget {
+            integrated: -1.,
+            true_peak: -1.,
+        };
+        let mut signal = sine(0.1, 1000., 48000, 2.);
+        let result = normalize_loudness(&mut signal, target).unwrap();
+        assert!(result.limited);
+        assert_close(result.gain, 19., 0.01);
+        assert_close(true_peak(&signal.samples), -1., 0.01);
+
+        let mut silence = sine(0., 1000., 48000, 1.);
+        assert!(normalize_loudness(&mut silence, target).is_none());
+        assert!(silence.samples.iter().all(|&sample| sample == 0.));
+    }
+}
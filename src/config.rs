@@ -0,0 +1,129 @@
+//! Persistent defaults loaded from the configuration file.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer};
+
+use crate::audio::MonoMode;
+use crate::device::{Backend, DeviceSpec, Pacing};
+
+/// Defaults for command line options. Options passed on the command line take precedence.
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// Interval duration to wait before sending a new chunk.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub chunk_cooldown: Option<humantime::Duration>,
+    /// How the chunk cooldown is chosen.
+    pub pacing: Option<Pacing>,
+    /// Mono convertion mode.
+    pub mono_mode: Option<MonoMode>,
+    /// Download output directory.
+    pub output_dir: Option<PathBuf>,
+    /// Sequencer client name of the device.
+    pub port: Option<String>,
+    /// Sequencer port of the device, as `client:port` or a name substring.
+    pub device: Option<DeviceSpec>,
+    /// Part of the client or port name of the device.
+    pub device_name: Option<String>,
+    /// Send the discovery request to every port if none matches.
+    #[serde(default)]
+    pub probe_all: bool,
+    /// ALSA interface used to talk to the device.
+    pub backend: Option<Backend>,
+    /// Answer confirmation prompts with yes.
+    #[serde(default)]
+    pub yes: bool,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+impl Config {
+    /// Location of the configuration file: `$XDG_CONFIG_HOME/volsa2/config.toml`, or
+    /// `~/.config/volsa2/config.toml` if `XDG_CONFIG_HOME` is not set.
+    pub fn path() -> Result<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .ok_or_else(|| anyhow!("could not locate config directory: HOME is not set"))?;
+        Ok(config_home.join("volsa2").join("config.toml"))
+    }
+
+    /// Loads the configuration file. Returns default configuration if the file does not exist.
+    ///
+    /// Unknown keys are reported, but do not cause an error.
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err).with_context(|| format!("could not read {path:?}")),
+        };
+
+        let config = Self::parse(&contents).with_context(|| format!("invalid config {path:?}"))?;
+        for key in config.unknown_keys() {
+            eprintln!("warning: unknown key {key:?} in {path:?}");
+        }
+        Ok(config)
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    fn unknown_keys(&self) -> impl Iterator<Item = &str> {
+        self.unknown.keys().map(String::as_str)
+    }
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<humantime::Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    value.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+#[test]
+fn parse_config() {
+    let config = Config::parse(
+        r#"
+        chunk_cooldown = "25ms"
+        pacing = "adaptive"
+        mono_mode = "left"
+        output_dir = "/tmp/samples"
+        device = "24:0"
+        device_name = "USB MIDI"
+        probe_all = true
+        yes = true
+        colour = "always"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.chunk_cooldown.map(Into::into),
+        Some(std::time::Duration::from_millis(25))
+    );
+    assert_eq!(config.pacing, Some(Pacing::Adaptive));
+    assert!(matches!(config.mono_mode, Some(MonoMode::Left)));
+    assert_eq!(config.output_dir, Some(PathBuf::from("/tmp/samples")));
+    assert_eq!(config.port, None);
+    assert_eq!(
+        config.device,
+        Some(DeviceSpec::Addr {
+            client: 24,
+            port: 0
+        })
+    );
+    assert_eq!(config.device_name.as_deref(), Some("USB MIDI"));
+    assert!(config.probe_all);
+    assert!(config.yes);
+    assert_eq!(config.unknown_keys().collect::<Vec<_>>(), ["colour"]);
+
+    assert!(Config::parse(r#"chunk_cooldown = "soon""#).is_err());
+}
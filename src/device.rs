@@ -1,250 +1,2003 @@
 use std::any::type_name;
-use std::ffi::CString;
-use std::fmt::Debug;
-use std::time::Duration;
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::fmt::{self, Debug};
+use std::hash::BuildHasher;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use alsa::seq::{self, ClientInfo};
-use anyhow::{anyhow, bail, Result};
+use clap::ValueEnum;
 use smallvec::SmallVec;
-use tracing::{debug, info, trace};
+use thiserror::Error;
+use tracing::{debug, info, trace, warn};
 
-use crate::proto::{self, Header};
+use crate::proto::{self, Header, Incoming, NakStatus, Outgoing, ParseError, SampleNo};
 use crate::seven_bit::U7;
-use crate::util::{hexbuf, DEBUG_TRESHOLD};
-
-const SELF_NAME: &str = "VolSa2";
-
-/// Represents connection to Volca.
-pub struct Device {
-    seq: seq::Seq,
-    me: seq::Addr,
-    volca: seq::Addr,
-    channel: U7,
-    chunk_cooldown: Duration,
-}
-
-impl Device {
-    pub fn new(chunk_cooldown: Duration) -> Result<Self> {
-        let seq = seq::Seq::open(None, None, false)?;
-        seq.set_client_name(&CString::new(SELF_NAME)?)?;
-        let mut me = seq::PortInfo::empty()?;
-        me.set_capability(
-            seq::PortCap::WRITE
-            | seq::PortCap::SUBS_WRITE
-            | seq::PortCap::READ
-            | seq::PortCap::SUBS_READ
-            // | seq::PortCap::SYNC_READ
-            // | seq::PortCap::SYNC_WRITE
-            | seq::PortCap::DUPLEX,
-        );
-        me.set_type(seq::PortType::MIDI_GENERIC | seq::PortType::APPLICATION | seq::PortType::PORT);
-        me.set_name(&CString::new(SELF_NAME)?);
+use crate::util::{self, hexbuf, DEBUG_TRESHOLD};
 
-        seq.create_port(&me)?;
+#[cfg(feature = "async")]
+pub mod async_device;
+pub mod midi_log;
+#[cfg(feature = "midir")]
+pub mod midir_device;
+#[cfg(test)]
+pub mod mock;
+#[cfg(target_os = "linux")]
+pub mod rawmidi;
+#[cfg(target_os = "linux")]
+pub mod sequencer;
 
-        let volca = find_volca(&seq)?;
-        let me = me.addr();
+pub use midi_log::{Flow, MidiLog};
+#[cfg(feature = "midir")]
+pub use midir_device::{list_midir_ports, MidirDevice};
+#[cfg(target_os = "linux")]
+pub use rawmidi::{list_rawmidi_ports, RawMidiDevice};
+#[cfg(target_os = "linux")]
+pub use sequencer::{list_ports, Device};
 
-        Ok(Self {
-            me,
-            seq,
-            volca,
-            channel: U7::new(0),
-            chunk_cooldown,
-        })
+/// Sequencer client name of Volca Sample 2.
+pub const VOLCA_CLIENT_NAME: &str = "volca sample";
+/// Largest SysEx event sent at once.
+const CHUNK_SIZE: usize = 256;
+/// Longest wait for the status reply to a write with [`Pacing::Adaptive`].
+const STATUS_TIMEOUT: Duration = Duration::from_secs(10);
+/// Echo value of the first discovery request.
+const SEARCH_ECHO: u8 = 42;
+/// Discovery requests sent before giving up on replies with a different echo value.
+const SEARCH_ATTEMPTS: u32 = 3;
+/// How long replies of other devices to the discovery request are collected after the first
+/// valid one, so that they are not taken for replies to later requests.
+const SEARCH_WINDOW: Duration = Duration::from_millis(100);
+/// Sample header requests sent before waiting for the first reply, see
+/// [`VolcaDevice::iter_selected_sample_headers`].
+pub const HEADER_WINDOW: usize = 3;
+/// Longest wait for each reply left over when header iteration stops early.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+/// Sample data dumps requested before giving up on dumps whose length does not match the
+/// header, see [`VolcaDevice::get_checked_sample`].
+const SAMPLE_DUMP_ATTEMPTS: u32 = 2;
+
+/// Errors communicating with the device.
+#[derive(Debug, Error)]
+pub enum DeviceError {
+    /// The sequencer backend reports [`DeviceError::ClientNotFound`] instead.
+    #[cfg_attr(all(target_os = "linux", not(feature = "midir")), allow(dead_code))]
+    #[error("could not find volca sample ({0:?} client)")]
+    NotFound(String),
+    #[error("none of {probed} probed ports replied to the discovery request")]
+    NoReply { probed: usize },
+    #[cfg(target_os = "linux")]
+    #[error("{0}")]
+    ClientNotFound(Box<sequencer::ClientScan>),
+    #[cfg(target_os = "linux")]
+    #[error("volca sample has no ports")]
+    NoPort,
+    #[cfg(target_os = "linux")]
+    #[error("no sequencer port matches {spec:?}, available ports:\n{available}")]
+    NoMatchingPort { spec: String, available: String },
+    #[cfg(target_os = "linux")]
+    #[error("no rawmidi device matches {spec:?}, available devices:\n{available}")]
+    NoMatchingRawMidi { spec: String, available: String },
+    #[cfg(feature = "midir")]
+    #[error("no MIDI port matches {spec:?}, available ports:\n{available}")]
+    NoMatchingMidiPort { spec: String, available: String },
+    #[error("found several devices, choose one with --device-index:\n{candidates}")]
+    Ambiguous { candidates: String },
+    #[error("device index must be less than {count}, got {index}")]
+    InvalidDeviceIndex { index: usize, count: usize },
+    #[error("device did not respond in {}", humantime::format_duration(*.0))]
+    Timeout(Duration),
+    #[error("device was disconnected")]
+    Disconnected,
+    #[error("interrupted with Ctrl-C")]
+    Interrupted,
+    #[error("device was disconnected during {request}, it may be left incomplete")]
+    InterruptedWrite { request: String },
+    #[cfg(target_os = "linux")]
+    #[error(transparent)]
+    Alsa(#[from] alsa::Error),
+    #[cfg(feature = "midir")]
+    #[error("could not {action}: {message}")]
+    Midi {
+        action: &'static str,
+        message: String,
+    },
+    #[cfg(target_os = "linux")]
+    #[error("rawmidi transfer failed")]
+    RawMidi(#[source] io::Error),
+    #[error("device refused {request}")]
+    Nak {
+        request: String,
+        #[source]
+        status: NakStatus,
+    },
+    #[error("device refused {request} after {attempts} attempts")]
+    RetriesExhausted {
+        request: String,
+        attempts: u32,
+        #[source]
+        status: NakStatus,
+    },
+    #[error("could not parse {message} message from device at byte {offset}: {snippet}")]
+    Parse {
+        message: &'static str,
+        /// Where parsing failed in the received message.
+        offset: usize,
+        /// Received bytes from `offset` on, see [`parse_snippet`].
+        snippet: String,
+        #[source]
+        source: ParseError,
+    },
+    #[error("could not encode {message} message")]
+    Encode {
+        message: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    #[cfg(target_os = "linux")]
+    #[error(
+        "sequencer input pool of {pool} events overflowed and data from the device was lost, {}",
+        sequencer::overrun_hint(*pool)
+    )]
+    InputOverrun { pool: u32 },
+    #[cfg(target_os = "linux")]
+    #[error("received SysEx event without data")]
+    EmptySysEx,
+    #[cfg(target_os = "linux")]
+    #[error("device started a new SysEx message after {received} bytes of the previous one")]
+    UnterminatedSysEx { received: usize },
+    #[error("pattern_no must be less than {}, got {0}", proto::PATTERN_COUNT)]
+    InvalidPatternNo(u8),
+    #[error("discovery reply echoed {received} instead of {sent} after {attempts} attempts")]
+    EchoMismatch {
+        sent: U7,
+        received: U7,
+        attempts: u32,
+    },
+    #[error("device sent the header of slot {0}, which was not requested")]
+    UnexpectedHeader(SampleNo),
+    #[error(
+        "sample {sample_no} has {data_length} samples of data, but its header says {header_length}"
+    )]
+    LengthMismatch {
+        sample_no: SampleNo,
+        header_length: u32,
+        data_length: usize,
+    },
+}
+
+impl DeviceError {
+    /// Whether the device refused a write because it is busy.
+    fn is_busy(&self) -> bool {
+        matches!(
+            self,
+            Self::Nak {
+                status: NakStatus::Busy,
+                ..
+            }
+        )
+    }
+
+    /// Whether retransmitting the write may succeed: the device was busy or did not reply.
+    fn is_transient(&self) -> bool {
+        self.is_busy() || matches!(self, Self::Timeout(_))
     }
 
-    pub fn connect(&mut self) -> Result<()> {
-        let sub = seq::PortSubscribe::empty()?;
-        sub.set_sender(self.volca);
-        sub.set_dest(self.me);
-        self.seq.subscribe_port(&sub)?;
+    /// Whether the device has gone away.
+    pub fn is_disconnected(&self) -> bool {
+        matches!(self, Self::Disconnected | Self::InterruptedWrite { .. })
+    }
 
-        let sub = seq::PortSubscribe::empty()?;
-        sub.set_sender(self.me);
-        sub.set_dest(self.volca);
-        self.seq.subscribe_port(&sub)?;
+    /// Whether further writes are pointless: the device has gone away or the user asked to stop.
+    pub fn stops_transfer(&self) -> bool {
+        self.is_disconnected() || matches!(self, Self::Interrupted)
+    }
 
-        let echo = U7::new(42);
-        self.send(proto::SearchDeviceRequest { echo })?;
+    /// Whether no device matched, so it may still show up.
+    pub fn is_missing(&self) -> bool {
+        match self {
+            Self::NotFound(_) | Self::NoReply { .. } => true,
+            #[cfg(target_os = "linux")]
+            Self::ClientNotFound(_)
+            | Self::NoPort
+            | Self::NoMatchingPort { .. }
+            | Self::NoMatchingRawMidi { .. } => true,
+            #[cfg(feature = "midir")]
+            Self::NoMatchingMidiPort { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Names the write that was cut off if the device disconnected during `request`.
+    fn during(self, request: &str) -> Self {
+        match self {
+            Self::Disconnected => Self::InterruptedWrite {
+                request: request.to_owned(),
+            },
+            err => err,
+        }
+    }
+
+    /// Reports how many attempts were made if the write has been retried.
+    fn after_attempts(self, attempts: u32) -> Self {
+        match self {
+            Self::Nak { request, status } if attempts > 1 => Self::RetriesExhausted {
+                request,
+                attempts,
+                status,
+            },
+            err => err,
+        }
+    }
+}
+
+type Result<T, E = DeviceError> = std::result::Result<T, E>;
 
-        let (_, response) = self.receive::<proto::SearchDeviceReply>()?;
-        info!(
-            global_channel = %response.device_id, version = %response.version,
-            "connected to volca sample 2"
+/// How writes refused with [`NakStatus::Busy`], or left without reply with [`Pacing::Adaptive`],
+/// are retransmitted. Other refusals are final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of retransmissions after the first attempt.
+    pub retries: u32,
+    /// Pause before the first retransmission. Doubles with each following one.
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    pub const DEFAULT_RETRIES: u32 = 3;
+    pub const DEFAULT_DELAY: Duration = Duration::from_millis(200);
+
+    /// Returns the pause before retransmitting a write whose `attempt` failed with `err`, or
+    /// `None` if it must not be retried.
+    fn backoff(&self, attempt: u32, err: &DeviceError) -> Option<Duration> {
+        (err.is_transient() && attempt <= self.retries)
+            .then(|| self.delay.saturating_mul(1 << (attempt - 1).min(16)))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: Self::DEFAULT_RETRIES,
+            delay: Self::DEFAULT_DELAY,
+        }
+    }
+}
+
+/// How the pause between chunks of a message is chosen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pacing {
+    /// Always pause for the configured chunk cooldown.
+    #[default]
+    Fixed,
+    /// Start from the configured chunk cooldown, shorten it while writes succeed and lengthen it
+    /// when the device is busy or does not reply.
+    Adaptive,
+}
+
+/// Chunk cooldown of a connection, adjusted after every checked write with [`Pacing::Adaptive`].
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    pacing: Pacing,
+    cooldown: Cell<Duration>,
+    /// Cooldown is not shortened below this. It is raised above every cooldown that failed, so
+    /// the cooldown converges to a value the device copes with.
+    floor: Cell<Duration>,
+}
+
+impl Pacer {
+    /// Part of the cooldown kept after a successful write.
+    const SPEEDUP: f64 = 0.9;
+    /// Factor the cooldown is multiplied by after a failed write.
+    const BACKOFF: u32 = 4;
+    /// Smallest floor after a failure, for when the cooldown has reached zero.
+    const MIN_FLOOR: Duration = Duration::from_millis(1);
+
+    pub fn new(pacing: Pacing, cooldown: Duration) -> Self {
+        Self {
+            pacing,
+            cooldown: Cell::new(cooldown),
+            floor: Cell::new(Duration::ZERO),
+        }
+    }
+
+    /// Current pause between chunks.
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown.get()
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.pacing == Pacing::Adaptive
+    }
+
+    /// Adjusts the cooldown to the result of a checked write.
+    fn record(&self, result: &Result<()>) {
+        if !self.is_adaptive() {
+            return;
+        }
+        let cooldown = self.cooldown.get();
+        match result {
+            Ok(()) => self.set(cooldown.mul_f64(Self::SPEEDUP).max(self.floor.get())),
+            Err(err) if err.is_transient() => {
+                let floor = (cooldown + cooldown / 4).max(Self::MIN_FLOOR);
+                self.floor.set(self.floor.get().max(floor));
+                self.set((cooldown * Self::BACKOFF).max(self.floor.get()));
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn set(&self, cooldown: Duration) {
+        if cooldown == self.cooldown.replace(cooldown) {
+            return;
+        }
+        let rate = match cooldown.as_secs_f64() {
+            0.0 => "unlimited".to_owned(),
+            secs => format!("{:.1} KiB/s", CHUNK_SIZE as f64 / secs / 1024.0),
+        };
+        debug!(
+            cooldown = %humantime::format_duration(cooldown), %rate,
+            "adjusted chunk cooldown"
         );
-        self.channel = response.device_id;
-        Ok(())
     }
+}
 
-    pub fn send<T>(&self, msg: T) -> Result<()>
-    where
-        T: proto::Outgoing + Debug,
-        T::Header: Debug,
-    {
-        let mut buf = SmallVec::<[u8; 6]>::new();
-        let header = T::Header::from_channel(self.channel);
-        msg.encode(header, &mut buf)?;
+/// MIDI interface used to talk to the device.
+///
+/// The ALSA backends are only available on Linux, midir needs the `midir` feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// ALSA sequencer API, shares the device with other applications.
+    #[cfg(target_os = "linux")]
+    #[default]
+    Seq,
+    /// ALSA rawmidi device, opened exclusively.
+    #[cfg(target_os = "linux")]
+    #[value(name = "rawmidi")]
+    RawMidi,
+    /// Native MIDI API of the platform: CoreMIDI, WinMM or ALSA.
+    #[cfg(feature = "midir")]
+    #[cfg_attr(not(target_os = "linux"), default)]
+    Midir,
+}
+
+/// Everything needed to open a connection, see [`VolcaDevice::open`].
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    pub backend: Backend,
+    pub spec: DeviceSpec,
+    pub selection: DeviceSelection,
+    pub chunk_cooldown: Duration,
+    pub pacing: Pacing,
+    pub retry_policy: RetryPolicy,
+    pub wait: Wait,
+    /// Shared by all connections, so that probing is logged too.
+    pub midi_log: Option<Arc<MidiLog>>,
+    /// Size of the sequencer input pool in events, `None` for a size that fits large messages.
+    #[cfg(target_os = "linux")]
+    pub input_pool: Option<u32>,
+    /// Request sample headers one by one instead of several at once.
+    pub serial_headers: bool,
+    /// Send the discovery request to every port if none matches `spec`.
+    pub probe_all: bool,
+}
 
-        if buf.len() > DEBUG_TRESHOLD {
-            debug!(msg = type_name::<T>(), len = buf.len(), "send msg");
-            trace!(?msg, raw = ?hexbuf(&buf), len = buf.len(), "send msg");
+impl ConnectOptions {
+    /// Sample header requests in flight, see [`VolcaDevice::header_window`].
+    pub fn header_window(&self) -> usize {
+        if self.serial_headers {
+            1
         } else {
-            debug!(?msg, len = buf.len(), "send msg");
+            HEADER_WINDOW
         }
+    }
+}
+
+/// Whether to wait for the device to show up when it is not found.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Wait {
+    /// Fail right away.
+    #[default]
+    No,
+    Forever,
+    Timeout(Duration),
+}
 
-        for slice in buf.chunks(256) {
-            let mut event = seq::Event::new_ext(seq::EventType::Sysex, slice);
+/// Wakes up when devices may have appeared.
+///
+/// The sequencer backend is told about new clients and ports by ALSA, other backends just sleep.
+pub struct DeviceWatcher {
+    #[cfg(target_os = "linux")]
+    announcements: Option<sequencer::Announcements>,
+}
+
+impl DeviceWatcher {
+    pub fn new(backend: Backend) -> Result<Self> {
+        Ok(match backend {
+            #[cfg(target_os = "linux")]
+            Backend::Seq => Self {
+                announcements: Some(sequencer::Announcements::new()?),
+            },
+            #[allow(unreachable_patterns)]
+            _ => Self {
+                #[cfg(target_os = "linux")]
+                announcements: None,
+            },
+        })
+    }
+
+    /// Waits for `timeout`, or less if something has changed.
+    pub fn wait(&self, timeout: Duration) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(announcements) = &self.announcements {
+            return announcements.wait(timeout);
+        }
+        std::thread::sleep(timeout);
+        Ok(())
+    }
+}
+
+/// Iterator over sample headers, see [`VolcaDevice::iter_selected_sample_headers`].
+struct SampleHeaders<'a, D: VolcaDevice + ?Sized, I> {
+    device: &'a D,
+    slots: I,
+    window: usize,
+    /// Slots whose headers were requested but not returned yet, in order.
+    requested: VecDeque<SampleNo>,
+    /// Replies that arrived ahead of the slot returned next.
+    received: Vec<proto::SampleHeader>,
+    /// Set after an error, the remaining replies cannot be matched reliably anymore.
+    failed: bool,
+}
+
+impl<D: VolcaDevice + ?Sized, I: Iterator<Item = SampleNo>> SampleHeaders<'_, D, I> {
+    fn next_header(&mut self) -> Result<Option<proto::SampleHeader>> {
+        while self.requested.len() < self.window {
+            let Some(sample_no) = self.slots.next() else {
+                break;
+            };
+            self.device
+                .send(proto::SampleHeaderDumpRequest { sample_no })?;
+            self.requested.push_back(sample_no);
+        }
+        let Some(&next) = self.requested.front() else {
+            return Ok(None);
+        };
+
+        loop {
+            if let Some(i) = self.received.iter().position(|h| h.sample_no == next) {
+                self.requested.pop_front();
+                return Ok(Some(self.received.swap_remove(i)));
+            }
+            let (_, header) = self.device.receive::<proto::SampleHeader>()?;
+            if !self.requested.contains(&header.sample_no) {
+                return Err(DeviceError::UnexpectedHeader(header.sample_no));
+            }
+            self.received.push(header);
+        }
+    }
+}
 
-            trace!(len = slice.len(), raw = ?hexbuf(slice), "send chunk");
+impl<D: VolcaDevice + ?Sized, I: Iterator<Item = SampleNo>> Iterator for SampleHeaders<'_, D, I> {
+    type Item = Result<proto::SampleHeader>;
 
-            event.set_source(self.me.port);
-            event.set_direct();
-            event.set_priority(true);
-            event.set_dest(self.volca);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if self.window == 1 {
+            // Strictly serial, the reply is taken for the requested slot as is
+            let sample_no = self.slots.next()?;
+            return Some(self.device.get_sample_header(sample_no));
+        }
+        let result = self.next_header();
+        self.failed = result.is_err();
+        result.transpose()
+    }
+}
 
-            self.seq.event_output_direct(&mut event)?;
-            if !slice.ends_with(&[proto::EOX]) && !self.chunk_cooldown.is_zero() {
-                std::thread::sleep(self.chunk_cooldown);
+impl<D: VolcaDevice + ?Sized, I> Drop for SampleHeaders<'_, D, I> {
+    /// Reads replies to requests sent ahead, so that they are not mistaken for replies to later
+    /// requests.
+    fn drop(&mut self) {
+        let pending = self.requested.len().saturating_sub(self.received.len());
+        for _ in 0..pending {
+            if !matches!(self.device.wait_for_input(DRAIN_TIMEOUT), Ok(true))
+                || self.device.receive_raw().is_err()
+            {
+                break;
             }
         }
-        self.seq.sync_output_queue()?;
-        self.seq.drain_output()?;
+    }
+}
 
+/// Picks an echo value for a discovery request that differs from `previous`.
+fn random_echo(previous: U7) -> U7 {
+    let echo = (RandomState::new().hash_one(previous.as_u8()) % 127) as u8;
+    U7::new(if echo >= previous.as_u8() {
+        echo + 1
+    } else {
+        echo
+    })
+}
+
+/// Short name of a message type for error messages.
+fn message_name<T>() -> &'static str {
+    let name = type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Connection over the backend chosen at runtime.
+pub enum AnyDevice {
+    #[cfg(target_os = "linux")]
+    Seq(Device),
+    #[cfg(target_os = "linux")]
+    RawMidi(RawMidiDevice),
+    #[cfg(feature = "midir")]
+    Midir(MidirDevice),
+}
+
+/// Evaluates `$call` with `$device` bound to the connection of whichever backend is in use.
+macro_rules! dispatch {
+    ($any:expr, $device:ident => $call:expr) => {
+        match $any {
+            #[cfg(target_os = "linux")]
+            AnyDevice::Seq($device) => $call,
+            #[cfg(target_os = "linux")]
+            AnyDevice::RawMidi($device) => $call,
+            #[cfg(feature = "midir")]
+            AnyDevice::Midir($device) => $call,
+        }
+    };
+}
+
+impl AnyDevice {
+    /// Firmware version reported by the device. Available after connecting.
+    pub fn version(&self) -> Option<proto::Version> {
+        dispatch!(self, device => device.version())
+    }
+}
+
+impl VolcaDevice for AnyDevice {
+    fn open(options: &ConnectOptions) -> Result<Self> {
+        match options.backend {
+            #[cfg(target_os = "linux")]
+            Backend::Seq => Device::open(options).map(Self::Seq),
+            #[cfg(target_os = "linux")]
+            Backend::RawMidi => RawMidiDevice::open(options).map(Self::RawMidi),
+            #[cfg(feature = "midir")]
+            Backend::Midir => MidirDevice::open(options).map(Self::Midir),
+        }
+    }
+
+    fn channel(&self) -> U7 {
+        dispatch!(self, device => device.channel())
+    }
+
+    fn chunk_cooldown(&self) -> Duration {
+        dispatch!(self, device => device.chunk_cooldown())
+    }
+
+    fn pacer(&self) -> Option<&Pacer> {
+        dispatch!(self, device => device.pacer())
+    }
+
+    fn midi_log(&self) -> Option<&MidiLog> {
+        dispatch!(self, device => device.midi_log())
+    }
+
+    fn header_window(&self) -> usize {
+        dispatch!(self, device => device.header_window())
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        dispatch!(self, device => device.retry_policy())
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        dispatch!(self, device => device.set_retry_policy(policy))
+    }
+
+    fn send_chunk(&self, chunk: &[u8]) -> Result<()> {
+        dispatch!(self, device => device.send_chunk(chunk))
+    }
+
+    fn flush(&self) -> Result<()> {
+        dispatch!(self, device => device.flush())
+    }
+
+    fn wait_for_input(&self, timeout: Duration) -> Result<bool> {
+        dispatch!(self, device => device.wait_for_input(timeout))
+    }
+
+    fn receive_raw_with_progress(&self, progress: &mut dyn ProgressSink) -> Result<Vec<u8>> {
+        dispatch!(self, device => device.receive_raw_with_progress(progress))
+    }
+}
+
+/// Operations on a connection to the device.
+///
+/// Implementors only move raw SysEx messages, message encoding and the sample operations are
+/// shared.
+pub trait VolcaDevice {
+    /// Opens a connection to the device selected by `options.spec`. `options.selection` picks one
+    /// if there are several.
+    fn open(options: &ConnectOptions) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Global channel of the device.
+    fn channel(&self) -> U7;
+
+    /// Pause between chunks of a message.
+    fn chunk_cooldown(&self) -> Duration {
+        self.pacer().map_or(Duration::ZERO, Pacer::cooldown)
+    }
+
+    /// Chooses the chunk cooldown, `None` for no pause.
+    fn pacer(&self) -> Option<&Pacer> {
+        None
+    }
+
+    /// Where the raw traffic is logged, if anywhere. Backends log received chunks and messages
+    /// themselves, sent ones are logged by
+    /// [`send_raw_with_progress`](Self::send_raw_with_progress).
+    fn midi_log(&self) -> Option<&MidiLog> {
+        None
+    }
+
+    /// How many sample header requests are sent before waiting for replies, 1 to wait for each
+    /// reply before sending the next request.
+    fn header_window(&self) -> usize {
+        HEADER_WINDOW
+    }
+
+    /// How writes are retried when the device is busy.
+    fn retry_policy(&self) -> RetryPolicy;
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy);
+
+    /// Sends a part of a SysEx message, at most [`CHUNK_SIZE`] bytes long.
+    fn send_chunk(&self, chunk: &[u8]) -> Result<()>;
+
+    /// Waits until sent chunks are delivered.
+    fn flush(&self) -> Result<()> {
         Ok(())
     }
 
-    pub fn receive<T>(&self) -> Result<(T::Header, T)>
+    /// Waits until there is incoming data. Returns `false` on timeout.
+    ///
+    /// If the data turns out not to be a message, the next receive fails with
+    /// [`DeviceError::Timeout`] once the timeout is over.
+    fn wait_for_input(&self, _timeout: Duration) -> Result<bool> {
+        Ok(true)
+    }
+
+    /// Whether the user asked to stop. Checked before each chunk.
+    fn is_interrupted(&self) -> bool {
+        util::interrupted()
+    }
+
+    /// Sends a single encoded SysEx message in chunks, reporting progress after each chunk.
+    ///
+    /// When interrupted, a message that was partly sent is terminated right away, so the device
+    /// does not keep waiting for the rest of it.
+    fn send_raw_with_progress(&self, data: &[u8], progress: &mut dyn ProgressSink) -> Result<()> {
+        let mut writer = ChunkWriter::new(self, Some(data.len()), progress);
+        writer.push(data)?;
+        writer.finish()
+    }
+
+    /// Receives a single SysEx message from the device, reassembling it from several events if
+    /// needed.
+    fn receive_raw(&self) -> Result<Vec<u8>> {
+        self.receive_raw_with_progress(&mut ())
+    }
+
+    /// Same as [`receive_raw`](Self::receive_raw), reporting the received length after each event.
+    /// The total length is not known in advance.
+    fn receive_raw_with_progress(&self, progress: &mut dyn ProgressSink) -> Result<Vec<u8>>;
+
+    fn send<T>(&self, msg: T) -> Result<()>
+    where
+        T: proto::Outgoing + Debug,
+        T::Header: Debug,
+    {
+        self.send_with_progress(&msg, &mut ())
+    }
+
+    /// Encodes `msg` right into the chunks sent to the device, so a large message is never held
+    /// in memory as a whole. Chunks and interruption are handled as in
+    /// [`send_raw_with_progress`](Self::send_raw_with_progress).
+    fn send_with_progress<T>(&self, msg: &T, progress: &mut dyn ProgressSink) -> Result<()>
+    where
+        T: proto::Outgoing + Debug,
+        T::Header: Debug,
+    {
+        let len = msg.encoded_len();
+        debug!(msg = type_name::<T>(), ?len, "send msg");
+        trace!(?msg, "send msg");
+
+        let mut writer = ChunkWriter::new(self, len, progress);
+        msg.encode(T::Header::from_channel(self.channel()), &mut writer)
+            .map_err(|source| {
+                // Errors of the device are passed through `io::Write` as `io::Error`
+                writer.error.take().unwrap_or(DeviceError::Encode {
+                    message: message_name::<T>(),
+                    source,
+                })
+            })?;
+        writer.finish()
+    }
+
+    fn receive<T>(&self) -> Result<(T::Header, T)>
     where
         T: proto::Incoming + Debug,
         T::Header: Debug,
     {
-        self.seq.set_client_pool_input(1024)?;
-        let mut input = self.seq.input();
-
-        macro_rules! next_event {
-            () => {
-                loop {
-                    let event = input.event_input()?;
-                    if event.get_type() == seq::EventType::Sysex
-                        && event.get_source() == self.volca
-                        && event.get_dest() == self.me
-                    {
-                        break event;
-                    }
+        self.receive_with_progress(&mut ())
+    }
+
+    fn receive_with_progress<T>(&self, progress: &mut dyn ProgressSink) -> Result<(T::Header, T)>
+    where
+        T: proto::Incoming + Debug,
+        T::Header: Debug,
+    {
+        parse_message(&self.receive_raw_with_progress(progress)?)
+    }
+
+    /// Sends a discovery request and returns the reply.
+    ///
+    /// Replies that can not be parsed, e.g. of other Korg devices, are skipped. A reply with a
+    /// different echo value answers someone else's request, then the request is sent again with
+    /// a new echo value. Replies arriving within [`SEARCH_WINDOW`] after the first valid one are
+    /// read as well. If several devices reply, the one on the current global channel is
+    /// preferred.
+    ///
+    /// Fails if the device does not reply during `timeout`.
+    fn search_device(&self, timeout: Option<Duration>) -> Result<proto::SearchDeviceReply> {
+        let mut echo = U7::new(SEARCH_ECHO);
+        self.send(proto::SearchDeviceRequest { echo })?;
+        let mut attempts = 1;
+        let reply = loop {
+            if let Some(timeout) = timeout {
+                if !self.wait_for_input(timeout)? {
+                    return Err(DeviceError::Timeout(timeout));
                 }
+            }
+            let Some(reply) = self.receive_search_reply()? else {
+                continue;
             };
-        }
+            if reply.echo == echo {
+                break reply;
+            }
+            if attempts == SEARCH_ATTEMPTS {
+                return Err(DeviceError::EchoMismatch {
+                    sent: echo,
+                    received: reply.echo,
+                    attempts,
+                });
+            }
+            warn!(sent = %echo, received = %reply.echo, "discovery reply has another echo, retrying");
+            echo = random_echo(echo);
+            self.send(proto::SearchDeviceRequest { echo })?;
+            attempts += 1;
+        };
 
-        let event = next_event!();
-        let mut owned_data = None;
-        let mut data = event
-            .get_ext()
-            .ok_or_else(|| anyhow!("SysEx without data"))?;
-        trace!(raw = ?hexbuf(data), len = data.len(), "recv fst chunk");
+        let mut replies = vec![reply];
+        // Bounded as a whole, so that other messages do not keep it open
+        let window = Instant::now() + SEARCH_WINDOW;
+        loop {
+            let remaining = window.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.wait_for_input(remaining)? {
+                break;
+            }
+            match self.receive_search_reply() {
+                Ok(Some(reply)) if reply.echo == echo => replies.push(reply),
+                Ok(Some(reply)) => debug!(echo = %reply.echo, "skip late discovery reply"),
+                Ok(None) => {}
+                // Woken up by an event that is not a message
+                Err(DeviceError::Timeout(_)) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        if replies.len() > 1 {
+            let channels = replies
+                .iter()
+                .map(|reply| reply.device_id.to_string())
+                .collect::<Vec<_>>();
+            warn!(
+                ?channels,
+                "several devices replied to the discovery request"
+            );
+        }
+        let index = replies
+            .iter()
+            .position(|reply| reply.device_id == self.channel())
+            .unwrap_or_default();
+        Ok(replies.swap_remove(index))
+    }
 
-        #[allow(unused_assignments)]
-        // TODO: Fix this
-        if !data.ends_with(&[proto::EOX]) {
-            owned_data.replace(data.to_vec());
-            data = &[]; // Free input borrow
+    /// Receives a message expected to be a discovery reply. Returns `None` if it is something
+    /// else, like a reply of another device.
+    fn receive_search_reply(&self) -> Result<Option<proto::SearchDeviceReply>> {
+        match self.receive::<proto::SearchDeviceReply>() {
+            Ok((_, reply)) => Ok(Some(reply)),
+            Err(DeviceError::Parse {
+                source, snippet, ..
+            }) => {
+                debug!(%source, %snippet, "skip message that is not a discovery reply");
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
 
-            while !owned_data
-                .as_ref()
-                .expect("replaced")
-                .ends_with(&[proto::EOX])
-            {
-                let event = next_event!();
-                let new_data = event
-                    .get_ext()
-                    .ok_or_else(|| anyhow!("SysEx without data"))?;
-                trace!(raw = ?hexbuf(new_data), len = new_data.len(), "recv chunk");
-                owned_data
-                    .as_mut()
-                    .expect("replaced earlier")
-                    .extend(new_data);
-            }
-            data = owned_data.as_ref().expect("replaced");
-        }
-
-        let data = &data;
-        let msg = T::parse(data).map_err(Into::into);
-        if data.len() > DEBUG_TRESHOLD {
-            debug!(msg = type_name::<T>(), len = data.len(), "recv msg");
-            trace!(?msg, raw = ?hexbuf(data), "recv_msg");
-        } else {
-            debug!(?msg, raw = ?hexbuf(data), len = data.len(), "recv_msg");
+    /// Sends the universal identity request and returns the first reply.
+    ///
+    /// Replies of any manufacturer are returned, other messages are skipped. Fails if nothing
+    /// replies during `timeout`.
+    fn identify(&self, timeout: Option<Duration>) -> Result<proto::IdentityReply> {
+        self.send(proto::IdentityRequest)?;
+        loop {
+            if let Some(timeout) = timeout {
+                if !self.wait_for_input(timeout)? {
+                    return Err(DeviceError::Timeout(timeout));
+                }
+            }
+            match self.receive::<proto::IdentityReply>() {
+                Ok((_, reply)) => return Ok(reply),
+                Err(DeviceError::Parse {
+                    source: ParseError::InvalidHeader(err),
+                    ..
+                }) => debug!(%err, "skip message while waiting for identity"),
+                Err(err) => return Err(err),
+            }
         }
-        msg
     }
 
-    pub fn iter_sample_headers(&self) -> impl Iterator<Item = Result<proto::SampleHeader>> + '_ {
-        (0..200).map(|idx| {
-            self.send(proto::SampleHeaderDumpRequest { sample_no: idx })?;
-            let (_, response) = self.receive::<proto::SampleHeader>()?;
-            Ok(response)
-        })
+    fn get_sample_space(&self) -> Result<proto::SampleSpaceDump> {
+        self.send(proto::SampleSpaceDumpRequest)?;
+        let (_, space) = self.receive::<proto::SampleSpaceDump>()?;
+        Ok(space)
     }
 
-    pub fn get_sample_header(&self, sample_no: u8) -> Result<proto::SampleHeader> {
-        // TODO: restrict this in type
-        if sample_no > 199 {
-            bail!("sample_no must be less than 200");
+    fn iter_sample_headers(&self) -> impl Iterator<Item = Result<proto::SampleHeader>> + '_ {
+        self.iter_selected_sample_headers(SampleNo::all())
+    }
+
+    /// Iterates over headers of the given sample slots in order.
+    ///
+    /// Up to [`header_window`](Self::header_window) requests are sent ahead, replies are matched to
+    /// them by slot number. Replies still pending when the iterator is dropped are discarded.
+    fn iter_selected_sample_headers(
+        &self,
+        slots: impl IntoIterator<Item = SampleNo> + 'static,
+    ) -> impl Iterator<Item = Result<proto::SampleHeader>> + '_ {
+        SampleHeaders {
+            device: self,
+            slots: slots.into_iter(),
+            window: self.header_window().max(1),
+            requested: VecDeque::new(),
+            received: Vec::new(),
+            failed: false,
         }
+    }
 
+    fn get_sample_header(&self, sample_no: SampleNo) -> Result<proto::SampleHeader> {
         self.send(proto::SampleHeaderDumpRequest { sample_no })?;
         let (_, header) = self.receive::<proto::SampleHeader>()?;
         Ok(header)
     }
 
-    pub fn get_sample(&self, sample_no: u8) -> Result<proto::SampleData> {
-        // TODO: restrict this in type
-        if sample_no > 199 {
-            bail!("sample_no must be less than 200");
-        }
+    fn get_sample(&self, sample_no: SampleNo) -> Result<proto::SampleData> {
+        self.get_sample_with_progress(sample_no, &mut ())
+    }
 
+    fn get_sample_with_progress(
+        &self,
+        sample_no: SampleNo,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<proto::SampleData> {
         self.send(proto::SampleDataDumpRequest { sample_no })?;
-        let (_, sample_data) = self.receive::<proto::SampleData>()?;
+        let (_, sample_data) = self.receive_with_progress::<proto::SampleData>(progress)?;
         Ok(sample_data)
     }
 
-    pub fn delete_sample(&self, sample_no: u8) -> Result<()> {
-        // TODO: restrict this in type
-        if sample_no > 199 {
-            bail!("sample_no must be less than 200");
+    /// Downloads the data of the sample described by `header` and checks that its length matches
+    /// the header, so that a cut short transfer is not taken for the sample.
+    ///
+    /// A dump of another length is requested again, up to [`SAMPLE_DUMP_ATTEMPTS`] times. With
+    /// `tolerate_mismatch`, the first dump is returned with a warning instead.
+    fn get_checked_sample(
+        &self,
+        header: &proto::SampleHeader,
+        tolerate_mismatch: bool,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<proto::SampleData> {
+        let mut attempts = 1;
+        loop {
+            let sample = self.get_sample_with_progress(header.sample_no, progress)?;
+            let Err(err) = check_sample_length(header, &sample) else {
+                return Ok(sample);
+            };
+            if tolerate_mismatch {
+                warn!("{err}, keeping the data as received");
+                return Ok(sample);
+            }
+            if attempts == SAMPLE_DUMP_ATTEMPTS {
+                return Err(err);
+            }
+            warn!("{err}, downloading it again");
+            attempts += 1;
+        }
+    }
+
+    fn get_pattern(&self, pattern_no: u8) -> Result<proto::PatternData> {
+        check_pattern_no(pattern_no)?;
+
+        self.send(proto::PatternDataDumpRequest { pattern_no })?;
+        let (_, pattern) = self.receive::<proto::PatternData>()?;
+        Ok(pattern)
+    }
+
+    fn send_pattern(&self, pattern: proto::PatternData) -> Result<()> {
+        check_pattern_no(pattern.pattern_no)?;
+
+        let request = format!("pattern {}", pattern.pattern_no);
+        let data = encode_message(pattern, self.channel())?;
+        self.write_checked(&data, request, &mut ())
+    }
+
+    fn get_current_pattern(&self) -> Result<proto::CurrentPatternData> {
+        self.send(proto::CurrentPatternDataDumpRequest)?;
+        let (_, pattern) = self.receive::<proto::CurrentPatternData>()?;
+        Ok(pattern)
+    }
+
+    fn send_current_pattern(&self, pattern: proto::CurrentPatternData) -> Result<()> {
+        let data = encode_message(pattern, self.channel())?;
+        self.write_checked(&data, "current pattern".to_owned(), &mut ())
+    }
+
+    fn get_global_data(&self) -> Result<proto::GlobalData> {
+        self.send(proto::GlobalDataDumpRequest)?;
+        let (_, global) = self.receive::<proto::GlobalData>()?;
+        Ok(global)
+    }
+
+    fn send_global_data(&self, global: proto::GlobalData) -> Result<()> {
+        let data = encode_message(global, self.channel())?;
+        self.write_checked(&data, "global settings".to_owned(), &mut ())
+    }
+
+    fn delete_sample(&self, sample_no: SampleNo) -> Result<()> {
+        let data = encode_message(proto::SampleHeader::empty(sample_no), self.channel())?;
+        self.write_checked(&data, format!("deletion of sample {sample_no}"), &mut ())
+    }
+
+    fn send_sample(&self, header: proto::SampleHeader, data: proto::SampleData) -> Result<()> {
+        self.send_sample_with_progress(header, data, &mut ())
+    }
+
+    /// Uploads a sample. Progress covers the header and the data as a single transfer.
+    fn send_sample_with_progress(
+        &self,
+        header: proto::SampleHeader,
+        data: proto::SampleData,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<()> {
+        // The device would take the header's word for how much data follows
+        check_sample_length(&header, &data)?;
+        let sample_no = header.sample_no;
+        let header = encode_message(header, self.channel())?;
+        let data_len = data
+            .encoded_len()
+            .expect("sample data length is known in advance");
+        let total = header.len() + data_len;
+
+        let mut part = PartProgress {
+            sink: progress,
+            offset: 0,
+            total,
+        };
+        let request = format!("sample header for slot {sample_no}");
+        self.write_checked(&header, request, &mut part)?;
+        part.offset = header.len();
+        // Sample data is encoded while it is sent, again on each retry
+        self.write_checked_with(
+            format!("sample data for slot {sample_no}"),
+            &mut part,
+            |progress| self.send_with_progress(&data, progress),
+        )?;
+        progress.finished();
+        Ok(())
+    }
+
+    /// Sends an encoded message and receives its status reply. Retransmits the message while the
+    /// device is busy, according to the [`RetryPolicy`].
+    ///
+    /// With [`Pacing::Adaptive`] the reply must arrive within [`STATUS_TIMEOUT`], and the result
+    /// adjusts the chunk cooldown.
+    fn write_checked(
+        &self,
+        data: &[u8],
+        request: String,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<()> {
+        self.write_checked_with(request, progress, |progress| {
+            self.send_raw_with_progress(data, progress)
+        })
+    }
+
+    /// Same as [`write_checked`](Self::write_checked), with the message sent by `send`.
+    fn write_checked_with(
+        &self,
+        request: String,
+        progress: &mut dyn ProgressSink,
+        mut send: impl FnMut(&mut dyn ProgressSink) -> Result<()>,
+    ) -> Result<()> {
+        let policy = self.retry_policy();
+        let adaptive = self.pacer().is_some_and(Pacer::is_adaptive);
+        let mut attempt = 1;
+        loop {
+            let interrupted = |err: DeviceError| err.during(&request);
+            send(&mut *progress).map_err(interrupted)?;
+            let result = if adaptive && !self.wait_for_input(STATUS_TIMEOUT).map_err(interrupted)? {
+                Err(DeviceError::Timeout(STATUS_TIMEOUT))
+            } else {
+                self.check_status(request.clone()).map_err(interrupted)
+            };
+            if let Some(pacer) = self.pacer() {
+                pacer.record(&result);
+            }
+            let Err(err) = result else {
+                return Ok(());
+            };
+            let Some(delay) = policy.backoff(attempt, &err) else {
+                return Err(err.after_attempts(attempt));
+            };
+            warn!(
+                %request, attempt,
+                "{}, retrying in {}",
+                if err.is_busy() { "device is busy" } else { "device did not reply" },
+                humantime::format_duration(delay)
+            );
+            std::thread::sleep(delay);
+            attempt += 1;
         }
+    }
 
-        self.send(proto::SampleHeader::empty(sample_no))?;
-        self.receive::<proto::Status>()?.1?;
+    /// Receives the [`proto::Status`] reply to `request`.
+    fn check_status(&self, request: String) -> Result<()> {
+        parse_status(&self.receive_raw()?, request)
+    }
+}
+
+/// Receives progress of transfers.
+pub trait ProgressSink {
+    /// Called after each chunk with the number of bytes transferred so far and the total number of
+    /// bytes, if known.
+    fn progress(&mut self, done: usize, total: Option<usize>);
+
+    /// Called once when the transfer is complete.
+    fn finished(&mut self) {}
+}
+
+/// Ignores progress.
+impl ProgressSink for () {
+    fn progress(&mut self, _: usize, _: Option<usize>) {}
+}
+
+impl<F: FnMut(usize, Option<usize>)> ProgressSink for F {
+    fn progress(&mut self, done: usize, total: Option<usize>) {
+        self(done, total)
+    }
+}
+
+/// Reports transfer of a message as a part of a larger transfer.
+struct PartProgress<'a> {
+    sink: &'a mut dyn ProgressSink,
+    offset: usize,
+    total: usize,
+}
+
+impl ProgressSink for PartProgress<'_> {
+    fn progress(&mut self, done: usize, _: Option<usize>) {
+        self.sink.progress(self.offset + done, Some(self.total));
+    }
+}
+
+/// Sends what is written to it as chunks of a single SysEx message, at most [`CHUNK_SIZE`] bytes
+/// long, so a message can be sent while it is encoded.
+struct ChunkWriter<'d, 'p, D: ?Sized> {
+    device: &'d D,
+    progress: &'p mut dyn ProgressSink,
+    chunk: Vec<u8>,
+    sent: usize,
+    /// Length of the whole message, if known.
+    total: Option<usize>,
+    /// The whole message, kept only if traffic is logged.
+    message: Option<Vec<u8>>,
+    /// Why the last write failed, `io::Write` only returns an `io::Error`.
+    error: Option<DeviceError>,
+}
+
+impl<'d, 'p, D: VolcaDevice + ?Sized> ChunkWriter<'d, 'p, D> {
+    fn new(device: &'d D, total: Option<usize>, progress: &'p mut dyn ProgressSink) -> Self {
+        Self {
+            device,
+            progress,
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            sent: 0,
+            total,
+            message: device.midi_log().map(|_| Vec::new()),
+            error: None,
+        }
+    }
+
+    fn push(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let len = data.len().min(CHUNK_SIZE - self.chunk.len());
+            let (head, rest) = data.split_at(len);
+            self.chunk.extend_from_slice(head);
+            data = rest;
+            if self.chunk.len() == CHUNK_SIZE {
+                self.send_chunk()?;
+            }
+        }
         Ok(())
     }
 
-    pub fn send_sample(&self, header: proto::SampleHeader, data: proto::SampleData) -> Result<()> {
-        self.send(header)?;
-        self.receive::<proto::Status>()?.1?;
-        self.send(data)?;
-        self.receive::<proto::Status>()?.1?;
+    /// Sends the collected chunk, reporting progress after it.
+    fn send_chunk(&mut self) -> Result<()> {
+        let cooldown = send_message_chunk(self.device, &self.chunk, self.sent, self.total)?;
+        if let Some(message) = &mut self.message {
+            message.extend_from_slice(&self.chunk);
+        }
+        self.sent += self.chunk.len();
+        self.progress.progress(self.sent, self.total);
+        if cooldown {
+            std::thread::sleep(self.device.chunk_cooldown());
+        }
+        self.chunk.clear();
+        Ok(())
+    }
+
+    /// Sends the last chunk and waits until the message is delivered.
+    fn finish(mut self) -> Result<()> {
+        if !self.chunk.is_empty() {
+            self.send_chunk()?;
+        }
+        finish_message(self.device, self.message.as_deref())?;
+        self.progress.finished();
         Ok(())
     }
 }
 
-fn find_volca(seq: &seq::Seq) -> Result<seq::Addr> {
-    let mut clients = seq::ClientIter::new(seq);
+/// Sends `chunk` of a message, `sent` bytes into it. Returns whether to pause before sending the
+/// next chunk, the caller does that.
+///
+/// When interrupted, a message that was partly sent is terminated right away, so the device
+/// does not keep waiting for the rest of it.
+fn send_message_chunk<D: VolcaDevice + ?Sized>(
+    device: &D,
+    chunk: &[u8],
+    sent: usize,
+    total: Option<usize>,
+) -> Result<bool> {
+    if device.is_interrupted() {
+        if sent > 0 {
+            warn!(sent, len = ?total, "terminating interrupted message");
+            device.send_chunk(&[proto::EOX])?;
+            device.flush()?;
+        }
+        return Err(DeviceError::Interrupted);
+    }
+    device.send_chunk(chunk)?;
+    if let Some(log) = device.midi_log() {
+        log.chunk(Flow::Sent, chunk);
+    }
+    Ok(needs_cooldown(chunk, device.chunk_cooldown()))
+}
 
-    let client: ClientInfo = clients
-        .find(|client| {
-            trace!(?client, "trying client");
-            client
-                .get_name()
-                .ok()
-                .filter(|&name| name == "volca sample")
-                .is_some()
+/// Waits until a sent message is delivered and logs it, if it was kept.
+fn finish_message<D: VolcaDevice + ?Sized>(device: &D, message: Option<&[u8]>) -> Result<()> {
+    device.flush()?;
+    if let (Some(log), Some(message)) = (device.midi_log(), message) {
+        log.message(Flow::Sent, message);
+    }
+    Ok(())
+}
+
+impl<D: VolcaDevice + ?Sized> io::Write for ChunkWriter<'_, '_, D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.push(buf).map_err(|err| {
+            let io_err = io::Error::other(err.to_string());
+            self.error = Some(err);
+            io_err
+        })?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encodes `msg` with the header for `channel`.
+fn encode_message<T>(msg: T, channel: U7) -> Result<SmallVec<[u8; 6]>>
+where
+    T: proto::Outgoing + Debug,
+    T::Header: Debug,
+{
+    let mut buf = SmallVec::<[u8; 6]>::with_capacity(T::len_hint().unwrap_or_default());
+    msg.encode(T::Header::from_channel(channel), &mut buf)
+        .map_err(|source| DeviceError::Encode {
+            message: message_name::<T>(),
+            source,
+        })?;
+
+    if buf.len() > DEBUG_TRESHOLD {
+        debug!(msg = type_name::<T>(), len = buf.len(), "send msg");
+        trace!(?msg, raw = ?hexbuf(&buf), len = buf.len(), "send msg");
+    } else {
+        debug!(?msg, len = buf.len(), "send msg");
+    }
+    Ok(buf)
+}
+
+fn parse_message<T>(data: &[u8]) -> Result<(T::Header, T)>
+where
+    T: proto::Incoming + Debug,
+    T::Header: Debug,
+{
+    let msg = T::parse_with_offset(data).map_err(|(source, offset)| {
+        // The snippet in the error is cut short, keep the whole message for bug reports
+        trace!(msg = type_name::<T>(), raw = ?hexbuf(data), len = data.len(), "could not parse");
+        DeviceError::Parse {
+            message: message_name::<T>(),
+            offset,
+            snippet: parse_snippet(data, offset),
+            source,
+        }
+    });
+    if data.len() > DEBUG_TRESHOLD {
+        debug!(msg = type_name::<T>(), len = data.len(), "recv msg");
+        trace!(?msg, raw = ?hexbuf(data), "recv_msg");
+    } else {
+        debug!(?msg, raw = ?hexbuf(data), len = data.len(), "recv_msg");
+    }
+    msg
+}
+
+/// Longest part of a message shown in parse errors.
+const SNIPPET_LEN: usize = 16;
+
+/// Hex dump of `data` from `offset` on, cut to [`SNIPPET_LEN`] bytes.
+fn parse_snippet(data: &[u8], offset: usize) -> String {
+    let rest = data.get(offset..).unwrap_or_default();
+    if rest.len() > SNIPPET_LEN {
+        format!(
+            "{:?} and {} more bytes",
+            hexbuf(&rest[..SNIPPET_LEN]),
+            rest.len() - SNIPPET_LEN
+        )
+    } else {
+        format!("{:?}", hexbuf(rest))
+    }
+}
+
+/// Parses the [`proto::Status`] reply to `request`.
+fn parse_status(data: &[u8], request: String) -> Result<()> {
+    debug!(raw = ?hexbuf(data), "recv status");
+    let (_, status) =
+        proto::Status::parse_with_offset(data).map_err(|(source, offset)| DeviceError::Parse {
+            message: "Status",
+            offset,
+            snippet: parse_snippet(data, offset),
+            source,
+        })?;
+    status.map_err(|status| DeviceError::Nak { request, status })
+}
+
+/// Whether to pause after sending `chunk`. The last chunk of a message needs no pause.
+fn needs_cooldown(chunk: &[u8], cooldown: Duration) -> bool {
+    !chunk.ends_with(&[proto::EOX]) && !cooldown.is_zero()
+}
+
+/// Checks that `data` has as many samples as `header` says.
+fn check_sample_length(header: &proto::SampleHeader, data: &proto::SampleData) -> Result<()> {
+    if header.length as usize != data.data.len() {
+        return Err(DeviceError::LengthMismatch {
+            sample_no: header.sample_no,
+            header_length: header.length,
+            data_length: data.data.len(),
+        });
+    }
+    Ok(())
+}
+
+fn check_pattern_no(pattern_no: u8) -> Result<()> {
+    if pattern_no >= proto::PATTERN_COUNT {
+        return Err(DeviceError::InvalidPatternNo(pattern_no));
+    }
+    Ok(())
+}
+
+/// Timeout of the handshake when telling several devices apart.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Timeout of the handshake when looking for the device on every port. Short, so that a number
+/// of silent ports do not hold up the start for long.
+const PROBE_ALL_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Creates devices for the ports selected by `options.spec` with `new`.
+///
+/// If no port matches and `options.probe_all` is set, every port is sent the discovery request
+/// and the devices that reply are returned instead.
+fn find_devices<T>(
+    options: &ConnectOptions,
+    new: impl Fn(&DeviceSpec) -> Result<Vec<T>>,
+    answers: fn(&mut T) -> bool,
+) -> Result<Vec<T>> {
+    let err = match new(&options.spec) {
+        Err(err) if options.probe_all && err.is_missing() => err,
+        result => return result,
+    };
+    info!(%err, "probing all ports");
+    let mut devices = new(&DeviceSpec::Any)?;
+    let probed = devices.len();
+    devices.retain_mut(answers);
+    if devices.is_empty() {
+        return Err(DeviceError::NoReply { probed });
+    }
+    Ok(devices)
+}
+
+/// Whether the device replies to the discovery request within [`PROBE_ALL_TIMEOUT`], or
+/// identifies itself as a Volca Sample 2 in reply to the universal identity request.
+fn answers<T: VolcaDevice>(
+    device: &mut T,
+    addr: &str,
+    connect: fn(&mut T, Option<Duration>) -> Result<proto::SearchDeviceReply>,
+) -> bool {
+    match connect(device, Some(PROBE_ALL_TIMEOUT)) {
+        Ok(_) => {
+            info!(%addr, "found device by probing");
+            return true;
+        }
+        Err(err) => debug!(%addr, %err, "port did not reply"),
+    }
+    // Some setups only pass the universal identity request through
+    match device.identify(Some(PROBE_ALL_TIMEOUT)) {
+        Ok(identity) if identity.is_volca_sample_2() => {
+            info!(%addr, %identity, "found device by identity request");
+            true
+        }
+        Ok(identity) => {
+            info!(%addr, %identity, "port belongs to another device");
+            false
+        }
+        Err(err) => {
+            debug!(%addr, %err, "port did not reply to identity request");
+            false
+        }
+    }
+}
+
+/// How to pick one of several devices matching a [`DeviceSpec`].
+#[derive(Debug, Clone, Copy)]
+pub enum DeviceSelection {
+    /// Device at this index, in the order of their addresses.
+    Index(usize),
+    /// Let the function choose between candidates. It is not called for a single device, `None`
+    /// means no choice was made.
+    Ask(fn(&[Candidate]) -> Option<usize>),
+}
+
+impl Default for DeviceSelection {
+    /// Never chooses between several devices.
+    fn default() -> Self {
+        Self::Ask(|_| None)
+    }
+}
+
+/// Picks one of `devices` according to `selection`, probing them if the choice is asked.
+fn select<T>(
+    mut devices: Vec<T>,
+    selection: DeviceSelection,
+    probe: fn(&mut T) -> Candidate,
+) -> Result<T> {
+    let index = match selection {
+        DeviceSelection::Index(index) => index,
+        DeviceSelection::Ask(_) if devices.len() == 1 => 0,
+        DeviceSelection::Ask(choose) => {
+            let candidates = devices.iter_mut().map(probe).collect::<Vec<_>>();
+            choose(&candidates).ok_or_else(|| DeviceError::Ambiguous {
+                candidates: candidates
+                    .iter()
+                    .enumerate()
+                    .map(|(index, candidate)| format!("  [{index}] {candidate}"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            })?
+        }
+    };
+    if index >= devices.len() {
+        return Err(DeviceError::InvalidDeviceIndex {
+            index,
+            count: devices.len(),
+        });
+    }
+    Ok(devices.swap_remove(index))
+}
+
+/// Connects with a short timeout and reads the sample memory use, to tell several devices apart.
+fn probe<T: VolcaDevice>(
+    device: &mut T,
+    addr: String,
+    connect: fn(&mut T, Option<Duration>) -> Result<proto::SearchDeviceReply>,
+) -> Candidate {
+    let mut probe = || -> Result<_> {
+        let reply = connect(device, Some(PROBE_TIMEOUT))?;
+        Ok((reply.version, device.get_sample_space()?))
+    };
+    let (version, space) = match probe() {
+        Ok((version, space)) => (Some(version), Some(space)),
+        Err(err) => {
+            warn!(%addr, %err, "could not probe device");
+            (None, None)
+        }
+    };
+    Candidate {
+        addr,
+        version,
+        space,
+    }
+}
+
+/// One of several devices matching a [`DeviceSpec`].
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    /// Sequencer address or rawmidi device name.
+    pub addr: String,
+    /// Firmware version, `None` if the device did not reply.
+    pub version: Option<proto::Version>,
+    pub space: Option<proto::SampleSpaceDump>,
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<7}", self.addr)?;
+        match (self.version, &self.space) {
+            (Some(version), Some(space)) => write!(
+                f,
+                " firmware {version}, {}% of sample memory used",
+                u32::from(space.used_sector_size) * 100 / u32::from(space.all_sector_size).max(1)
+            ),
+            _ => write!(f, " did not reply"),
+        }
+    }
+}
+
+/// Selects the sequencer port of the device.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum DeviceSpec {
+    /// First port of the client with exactly this name. Used by the automatic search.
+    Client(String),
+    /// `client:port` address.
+    Addr { client: i32, port: i32 },
+    /// First port whose client or port name contains this string, ignoring case.
+    Name(String),
+    /// Every port that can both send and receive. Only used when probing all ports.
+    Any,
+}
+
+impl Default for DeviceSpec {
+    fn default() -> Self {
+        Self::Client(VOLCA_CLIENT_NAME.to_owned())
+    }
+}
+
+impl fmt::Display for DeviceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Client(name) | Self::Name(name) => f.pad(name),
+            Self::Addr { client, port } => f.pad(&format!("{client}:{port}")),
+            Self::Any => f.pad("any port"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("device spec must not be empty")]
+pub struct EmptyDeviceSpec;
+
+impl FromStr for DeviceSpec {
+    type Err = EmptyDeviceSpec;
+
+    /// Parses `client:port` addresses, anything else is a name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(EmptyDeviceSpec);
+        }
+        let addr = s
+            .split_once(':')
+            .and_then(|(client, port)| Some((client.parse().ok()?, port.parse().ok()?)));
+        Ok(match addr {
+            Some((client, port)) => Self::Addr { client, port },
+            None => Self::Name(s.to_owned()),
+        })
+    }
+}
+
+impl TryFrom<String> for DeviceSpec {
+    type Error = EmptyDeviceSpec;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[test]
+fn parse_error_context() {
+    use hex_literal::hex;
+
+    let err = parse_message::<proto::SampleSpaceDump>(&hex!("F0 42 30 00 01 2D 4C 01 02 F7"))
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "could not parse SampleSpaceDump message from device at byte 6: [4C, 01, 02, F7]"
+    );
+
+    let mut long = hex!("F0 42 30 00 01 2D 4B").to_vec();
+    long.extend([0; 20]);
+    long.push(proto::EOX);
+    let err = parse_message::<proto::SampleSpaceDump>(&long).unwrap_err();
+    assert!(matches!(
+        err,
+        DeviceError::Parse {
+            offset: 7,
+            source: ParseError::NotEnoughData,
+            ..
+        }
+    ));
+    assert!(err.to_string().ends_with(" and 5 more bytes"));
+
+    let err = parse_status(&hex!("F0 42 30 00 01 2D 23 00"), String::new()).unwrap_err();
+    assert!(matches!(
+        err,
+        DeviceError::Parse {
+            offset: 7,
+            source: ParseError::InvalidEndByte,
+            ..
+        }
+    ));
+
+    // An 8-bit byte deep in a sample dump
+    let (_, data) = proto::SampleData::new(mock::slot(4), "pad", vec![1; 100]);
+    let mut dump = encode_message(data, U7::new(0)).unwrap();
+    dump[150] |= 0x80;
+    let err = parse_message::<proto::SampleData>(&dump).unwrap_err();
+    assert!(matches!(
+        err,
+        DeviceError::Parse {
+            offset: 150,
+            source: ParseError::Not7Bit(_),
+            ..
+        }
+    ));
+}
+
+#[test]
+fn sample_length_mismatch() {
+    use mock::{slot, MockDevice};
+
+    let volca = MockDevice::new().with_sample(5, "kick", vec![1, 2, 3]);
+    let header = volca.header(5);
+    volca.send_short_dumps(1);
+    let sample = volca.get_checked_sample(&header, false, &mut ()).unwrap();
+    assert_eq!(sample.data, [1, 2, 3]);
+
+    volca.send_short_dumps(SAMPLE_DUMP_ATTEMPTS as usize);
+    let err = volca
+        .get_checked_sample(&header, false, &mut ())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "sample 5 has 2 samples of data, but its header says 3"
+    );
+
+    volca.send_short_dumps(1);
+    let sample = volca.get_checked_sample(&header, true, &mut ()).unwrap();
+    assert_eq!(sample.data, [1, 2]);
+
+    // Nothing is sent if the header does not describe the data
+    let (mut header, data) = proto::SampleData::new(slot(6), "snare", vec![4; 10]);
+    header.length = 12;
+    let err = volca.send_sample(header, data).unwrap_err();
+    assert!(matches!(
+        err,
+        DeviceError::LengthMismatch {
+            header_length: 12,
+            data_length: 10,
+            ..
+        }
+    ));
+    assert_eq!(volca.writes(), 0);
+}
+
+#[test]
+fn unknown_status() {
+    use mock::{slot, MockDevice};
+
+    let volca = MockDevice::new();
+    volca.refuse_next_write(NakStatus::Unknown(0x31));
+    let err = volca.delete_sample(slot(4)).unwrap_err();
+    assert!(matches!(
+        err,
+        DeviceError::Nak {
+            status: NakStatus::Unknown(0x31),
+            ..
+        }
+    ));
+    assert_eq!(
+        anyhow::Error::from(err).chain().last().unwrap().to_string(),
+        "unknown status 0x31"
+    );
+}
+
+#[test]
+fn probe_all_ports() {
+    // Ports are stood in for by numbers, even ones reply
+    let new = |spec: &DeviceSpec| match spec {
+        DeviceSpec::Any => Ok(vec![1, 2, 3, 4]),
+        _ => Err(DeviceError::NotFound(spec.to_string())),
+    };
+    let answers = |port: &mut u8| port.is_multiple_of(2);
+
+    let mut options = ConnectOptions::default();
+    assert!(matches!(
+        find_devices(&options, new, answers),
+        Err(DeviceError::NotFound(_))
+    ));
+    options.probe_all = true;
+    assert_eq!(find_devices(&options, new, answers).unwrap(), [2, 4]);
+
+    let err = find_devices(&options, new, |_| false).unwrap_err();
+    assert!(matches!(err, DeviceError::NoReply { probed: 4 }));
+    assert!(err.is_missing());
+}
+
+#[test]
+fn sample_upload_progress() {
+    use mock::{slot, MockDevice};
+
+    #[derive(Default)]
+    struct Recorder {
+        reports: Vec<(usize, Option<usize>)>,
+        finished: usize,
+    }
+
+    impl ProgressSink for Recorder {
+        fn progress(&mut self, done: usize, total: Option<usize>) {
+            self.reports.push((done, total));
+        }
+
+        fn finished(&mut self) {
+            self.finished += 1;
+        }
+    }
+
+    let volca = MockDevice::new();
+    let (header, data) = proto::SampleData::new(slot(5), "pad", vec![7; 1000]);
+    let mut recorder = Recorder::default();
+    volca
+        .send_sample_with_progress(header, data, &mut recorder)
+        .unwrap();
+
+    let total = recorder.reports[0].1.unwrap();
+    assert!(recorder.reports.iter().all(|&(_, t)| t == Some(total)));
+    assert!(recorder.reports.windows(2).all(|w| w[0].0 < w[1].0));
+    assert!(recorder.reports.len() > 2);
+    assert_eq!(recorder.reports.last().unwrap().0, total);
+    assert_eq!(recorder.finished, 1);
+
+    let mut received = Vec::new();
+    let sample = volca
+        .get_sample_with_progress(slot(5), &mut |done, total| received.push((done, total)))
+        .unwrap();
+    assert_eq!(sample.data, [7; 1000]);
+    assert_eq!(received.last().map(|&(_, total)| total), Some(None));
+}
+
+#[test]
+fn busy_retry() {
+    use mock::{slot, MockDevice};
+
+    let mut volca = MockDevice::new();
+    volca.set_retry_policy(RetryPolicy {
+        retries: 2,
+        delay: Duration::from_millis(1),
+    });
+
+    volca.refuse_writes(NakStatus::Busy, 2);
+    volca.delete_sample(slot(1)).unwrap();
+    assert_eq!(volca.writes(), 3);
+
+    volca.refuse_writes(NakStatus::Busy, 3);
+    let err = volca.delete_sample(slot(1)).unwrap_err();
+    assert!(matches!(
+        err,
+        DeviceError::RetriesExhausted { attempts: 3, .. }
+    ));
+    assert!(err.to_string().contains("after 3 attempts"));
+
+    // Only busy writes are retried
+    volca.refuse_writes(NakStatus::SampleFull, 2);
+    let (header, data) = proto::SampleData::new(slot(2), "kick", vec![1, 2]);
+    assert!(matches!(
+        volca.send_sample(header, data),
+        Err(DeviceError::Nak {
+            status: NakStatus::SampleFull,
+            ..
         })
-        .ok_or_else(|| anyhow!("could not find volca sample"))?;
+    ));
+    assert_eq!(volca.writes(), 7);
+}
+
+#[test]
+fn device_specs() {
+    assert_eq!(
+        "20:0".parse::<DeviceSpec>().unwrap(),
+        DeviceSpec::Addr {
+            client: 20,
+            port: 0
+        }
+    );
+    assert_eq!(
+        "volca sample:1".parse::<DeviceSpec>().unwrap(),
+        DeviceSpec::Name("volca sample:1".into())
+    );
+    assert_eq!(
+        "20:x".parse::<DeviceSpec>().unwrap(),
+        DeviceSpec::Name("20:x".into())
+    );
+    assert!("".parse::<DeviceSpec>().is_err());
+
+    let spec = DeviceSpec::Addr {
+        client: 24,
+        port: 0,
+    };
+    assert_eq!(spec.to_string().parse::<DeviceSpec>().unwrap(), spec);
+}
+
+#[test]
+fn candidate_display() {
+    let reply = [
+        0xF0, 0x42, 0x50, 0x01, 0, 42, 0x2D, 0x01, 0x08, 0x00, 2, 0, 1, 0, 0xF7,
+    ];
+    let (_, reply) = proto::SearchDeviceReply::parse(&reply).unwrap();
+    let candidate = Candidate {
+        addr: "24:0".to_owned(),
+        version: Some(reply.version),
+        space: Some(proto::SampleSpaceDump {
+            all_sector_size: 4096,
+            used_sector_size: 1024,
+        }),
+    };
+    assert_eq!(
+        candidate.to_string(),
+        "24:0    firmware 1.2, 25% of sample memory used"
+    );
+
+    let silent = Candidate {
+        addr: "hw:2,0,0".to_owned(),
+        version: None,
+        space: None,
+    };
+    assert_eq!(silent.to_string(), "hw:2,0,0 did not reply");
+}
+
+#[test]
+fn adaptive_pacing() {
+    let busy = || {
+        Err(DeviceError::Nak {
+            request: "sample data".into(),
+            status: NakStatus::Busy,
+        })
+    };
+    let ms = Duration::from_millis;
+
+    let fixed = Pacer::new(Pacing::Fixed, ms(10));
+    fixed.record(&Ok(()));
+    fixed.record(&busy());
+    assert_eq!(fixed.cooldown(), ms(10));
+
+    let pacer = Pacer::new(Pacing::Adaptive, ms(10));
+    for _ in 0..20 {
+        pacer.record(&Ok(()));
+    }
+    assert!(pacer.cooldown() < ms(2));
+
+    // Backs off sharply and never goes below the cooldown that failed
+    let failed = pacer.cooldown();
+    pacer.record(&busy());
+    assert_eq!(pacer.cooldown(), failed * 4);
+    for _ in 0..50 {
+        pacer.record(&Ok(()));
+    }
+    assert_eq!(pacer.cooldown(), (failed + failed / 4).max(ms(1)));
+
+    pacer.record(&Err(DeviceError::Timeout(STATUS_TIMEOUT)));
+    assert!(pacer.cooldown() > failed * 4);
+
+    // Other errors are not caused by pacing
+    let cooldown = pacer.cooldown();
+    pacer.record(&Err(DeviceError::InvalidPatternNo(20)));
+    assert_eq!(pacer.cooldown(), cooldown);
+}
+
+#[test]
+fn disconnect_mid_upload() {
+    use mock::{slot, MockDevice};
+
+    let volca = MockDevice::new();
+    volca.disconnect_after_writes(1);
+    let (header, data) = proto::SampleData::new(slot(5), "kick", vec![1, 2, 3]);
+    let err = volca.send_sample(header, data).unwrap_err();
+    assert!(err.is_disconnected());
+    assert_eq!(
+        err.to_string(),
+        "device was disconnected during sample data for slot 5, it may be left incomplete"
+    );
+    assert!(volca.delete_sample(slot(5)).unwrap_err().is_disconnected());
+}
+
+#[test]
+fn pipelined_headers() {
+    use mock::MockDevice;
+
+    let volca = MockDevice::new()
+        .with_sample(0, "kick", vec![1; 4])
+        .with_sample(7, "snare", vec![2; 4])
+        .with_sample(199, "hat", vec![3; 4]);
+    volca.reply_in_bursts(HEADER_WINDOW);
+    let headers = volca
+        .iter_sample_headers()
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(headers.len(), 200);
+    assert!(headers
+        .iter()
+        .enumerate()
+        .all(|(i, header)| usize::from(header.sample_no) == i));
+    assert_eq!(headers[0].name, "kick");
+    assert_eq!(headers[7].name, "snare");
+    assert_eq!(headers[199].name, "hat");
+    assert_eq!(volca.max_headers_in_flight(), HEADER_WINDOW);
+
+    // Replies to requests sent ahead are not taken for later replies
+    let empty = volca
+        .iter_sample_headers()
+        .find(|header| header.as_ref().is_ok_and(|header| header.is_empty()))
+        .unwrap()
+        .unwrap();
+    assert_eq!(u8::from(empty.sample_no), 1);
+    assert_eq!(volca.get_sample_space().unwrap().all_sector_size, 4096);
+
+    let serial = MockDevice::new().with_header_window(1);
+    assert_eq!(serial.iter_sample_headers().count(), 200);
+    assert_eq!(serial.max_headers_in_flight(), 1);
+}
+
+#[test]
+fn search_echo() {
+    use mock::MockDevice;
+
+    let volca = MockDevice::new();
+    volca.reply_with_wrong_echo(SEARCH_ATTEMPTS as usize - 1);
+    assert!(volca.search_device(None).is_ok());
+
+    volca.reply_with_wrong_echo(SEARCH_ATTEMPTS as usize);
+    let err = volca.search_device(None).unwrap_err();
+    assert!(matches!(
+        err,
+        DeviceError::EchoMismatch { sent, received, attempts: SEARCH_ATTEMPTS }
+            if received.as_u8() == sent.as_u8() ^ 1
+    ));
+
+    for _ in 0..100 {
+        let echo = U7::new(SEARCH_ECHO);
+        assert_ne!(random_echo(echo), echo);
+    }
+}
+
+#[test]
+fn search_with_other_devices() {
+    use hex_literal::hex;
+    use mock::MockDevice;
+
+    let volca = MockDevice::new();
+    // Another Korg device, a truncated reply and another volca on channel 5
+    volca.reply_to_discovery_with(hex!("F0 42 50 01 00 2A 2C 01 08 00 01 00 01 00 F7").to_vec());
+    volca.reply_to_discovery_with(hex!("F0 42 50 01 05 2A F7").to_vec());
+    volca.reply_to_discovery_with(hex!("F0 42 50 01 05 2A 2D 01 08 00 01 00 01 00 F7").to_vec());
+    let reply = volca.search_device(Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(reply.device_id, volca.channel());
+    // Late replies are not taken for the reply to the next request
+    assert!(volca.get_sample_space().is_ok());
+
+    // Messages that are not discovery replies do not keep the window open
+    let volca = MockDevice::new();
+    volca.keep_sending(hex!("F0 42 50 01 00 2A 2C 01 08 00 01 00 01 00 F7").to_vec());
+    let started = Instant::now();
+    let reply = volca.search_device(Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(reply.device_id, volca.channel());
+    assert!(started.elapsed() < SEARCH_WINDOW * 10);
+}
+
+#[test]
+fn identity_probe() {
+    use mock::MockDevice;
+
+    let volca = MockDevice::new();
+    let identity = volca.identify(None).unwrap();
+    assert!(identity.is_volca_sample_2());
+    assert_eq!(identity.korg_version().to_string(), "1.2");
+
+    // A port that ignores the Korg discovery request is still recognized
+    let mut volca = MockDevice::new();
+    let silent = |_: &mut MockDevice, timeout: Option<Duration>| {
+        Err(DeviceError::Timeout(timeout.unwrap_or_default()))
+    };
+    assert!(answers(&mut volca, "mock", silent));
+}
+
+#[test]
+fn streamed_send() {
+    use mock::{slot, MockDevice};
+
+    for len in [0, 1, 500, 5000] {
+        let samples = (0..len).map(|x| (x * 37) as i16).collect();
+        let (_, data) = proto::SampleData::new(slot(3), "pad", samples);
+
+        let buffered = MockDevice::new();
+        let encoded = encode_message(data.clone(), buffered.channel()).unwrap();
+        buffered.send_raw_with_progress(&encoded, &mut ()).unwrap();
+
+        let streamed = MockDevice::new();
+        let mut reports = Vec::new();
+        streamed
+            .send_with_progress(&data, &mut |done, total| reports.push((done, total)))
+            .unwrap();
+
+        let chunks = streamed.sent_chunks();
+        assert_eq!(chunks, buffered.sent_chunks());
+        assert!(chunks.iter().all(|chunk| chunk.len() <= CHUNK_SIZE));
+        assert_eq!(reports.last(), Some(&(encoded.len(), Some(encoded.len()))));
+        assert_eq!(streamed.data(3), data.data);
+    }
+}
 
-    let port = seq::PortIter::new(seq, client.get_client())
-        .next()
-        .ok_or_else(|| anyhow!("no port"))?;
+#[test]
+fn interrupted_upload() {
+    use mock::{slot, MockDevice};
 
-    Ok(port.addr())
+    let volca = MockDevice::new();
+    volca.interrupt_after_chunks(2);
+    let (header, data) = proto::SampleData::new(slot(9), "pad", vec![1; 1000]);
+    let err = volca.send_sample(header, data).unwrap_err();
+    assert!(matches!(err, DeviceError::Interrupted));
+    // The header went through, the data message was terminated after its first chunk
+    assert!(volca.cut_short());
+    assert_eq!(volca.writes(), 1);
+    assert!(volca.data(9).is_empty());
 }
@@ -0,0 +1,261 @@
+//! Async API for device operations.
+//!
+//! The CLI itself is synchronous, this is meant for embedding into tokio-based applications.
+#![allow(dead_code)]
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio::task;
+use tracing::warn;
+
+use crate::proto::{self, SampleNo};
+use crate::seven_bit::U7;
+
+use super::{
+    check_sample_length, encode_message, finish_message, parse_message, parse_status,
+    send_message_chunk, ConnectOptions, Result, RetryPolicy, VolcaDevice, CHUNK_SIZE,
+};
+
+/// Wraps a device connection for use from async code.
+///
+/// Chunk cooldowns are awaited with [`tokio::time::sleep`] and blocking calls run on the blocking
+/// thread pool, so the executor is not stalled. Operations are serialized, a receive waits for a
+/// pending send to finish.
+pub struct AsyncDevice<D> {
+    device: Arc<Mutex<D>>,
+    channel: U7,
+    retry_policy: RetryPolicy,
+}
+
+impl<D: VolcaDevice + Send + 'static> AsyncDevice<D> {
+    pub fn new(device: D) -> Self {
+        Self {
+            channel: device.channel(),
+            retry_policy: device.retry_policy(),
+            device: Arc::new(Mutex::new(device)),
+        }
+    }
+
+    /// Opens a connection to the device selected by `options`. See [`VolcaDevice::open`].
+    pub async fn open(options: ConnectOptions) -> Result<Self> {
+        let device = task::spawn_blocking(move || D::open(&options))
+            .await
+            .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))?;
+        Ok(Self::new(device))
+    }
+
+    /// Runs `f` with the device on the blocking thread pool.
+    async fn blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&D) -> Result<T> + Send + 'static,
+    {
+        let device = Arc::clone(&self.device).lock_owned().await;
+        Self::with_lock(device, f).await.1
+    }
+
+    /// Same as [`blocking`](Self::blocking) with the device already locked, the lock is handed
+    /// back so that several calls can be made without other operations coming in between.
+    async fn with_lock<T, F>(device: OwnedMutexGuard<D>, f: F) -> (OwnedMutexGuard<D>, Result<T>)
+    where
+        T: Send + 'static,
+        F: FnOnce(&D) -> Result<T> + Send + 'static,
+    {
+        task::spawn_blocking(move || {
+            let result = f(&device);
+            (device, result)
+        })
+        .await
+        .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+    }
+
+    /// Sends a single encoded SysEx message in chunks. See
+    /// [`VolcaDevice::send_raw_with_progress`].
+    pub async fn send_raw(&self, data: &[u8]) -> Result<()> {
+        let mut device = Arc::clone(&self.device).lock_owned().await;
+        let total = Some(data.len());
+        for (i, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            let chunk = chunk.to_vec();
+            let (guard, cooldown) = Self::with_lock(device, move |device| {
+                send_message_chunk(device, &chunk, i * CHUNK_SIZE, total)
+            })
+            .await;
+            device = guard;
+            if cooldown? {
+                tokio::time::sleep(device.chunk_cooldown()).await;
+            }
+        }
+        let data = data.to_vec();
+        Self::with_lock(device, move |device| finish_message(device, Some(&data)))
+            .await
+            .1
+    }
+
+    pub async fn receive_raw(&self) -> Result<Vec<u8>> {
+        self.blocking(|device| device.receive_raw()).await
+    }
+
+    pub async fn send<T>(&self, msg: T) -> Result<()>
+    where
+        T: proto::Outgoing + Debug,
+        T::Header: Debug,
+    {
+        let buf = encode_message(msg, self.channel)?;
+        self.send_raw(&buf).await
+    }
+
+    pub async fn receive<T>(&self) -> Result<(T::Header, T)>
+    where
+        T: proto::Incoming + Debug,
+        T::Header: Debug,
+    {
+        parse_message(&self.receive_raw().await?)
+    }
+
+    /// Sends a discovery request and returns the reply. See [`VolcaDevice::search_device`].
+    pub async fn search_device(&self) -> Result<proto::SearchDeviceReply> {
+        self.blocking(|device| device.search_device(None)).await
+    }
+
+    pub async fn get_sample_space(&self) -> Result<proto::SampleSpaceDump> {
+        self.send(proto::SampleSpaceDumpRequest).await?;
+        let (_, space) = self.receive::<proto::SampleSpaceDump>().await?;
+        Ok(space)
+    }
+
+    pub async fn get_sample_header(&self, sample_no: SampleNo) -> Result<proto::SampleHeader> {
+        self.send(proto::SampleHeaderDumpRequest { sample_no })
+            .await?;
+        let (_, header) = self.receive::<proto::SampleHeader>().await?;
+        Ok(header)
+    }
+
+    pub async fn get_sample(&self, sample_no: SampleNo) -> Result<proto::SampleData> {
+        self.send(proto::SampleDataDumpRequest { sample_no })
+            .await?;
+        let (_, sample_data) = self.receive::<proto::SampleData>().await?;
+        Ok(sample_data)
+    }
+
+    pub async fn delete_sample(&self, sample_no: SampleNo) -> Result<()> {
+        let data = encode_message(proto::SampleHeader::empty(sample_no), self.channel)?;
+        self.write_checked(&data, format!("deletion of sample {sample_no}"))
+            .await
+    }
+
+    pub async fn send_sample(
+        &self,
+        header: proto::SampleHeader,
+        data: proto::SampleData,
+    ) -> Result<()> {
+        check_sample_length(&header, &data)?;
+        let sample_no = header.sample_no;
+        let header = encode_message(header, self.channel)?;
+        let data = encode_message(data, self.channel)?;
+        self.write_checked(&header, format!("sample header for slot {sample_no}"))
+            .await?;
+        self.write_checked(&data, format!("sample data for slot {sample_no}"))
+            .await
+    }
+
+    /// Sends an encoded message and receives its status reply, retransmitting it while the device
+    /// is busy. See [`VolcaDevice::write_checked`].
+    async fn write_checked(&self, data: &[u8], request: String) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            self.send_raw(data)
+                .await
+                .map_err(|err| err.during(&request))?;
+            let result = match self.receive_raw().await {
+                Ok(reply) => parse_status(&reply, request.clone()),
+                Err(err) => return Err(err.during(&request)),
+            };
+            if let Some(pacer) = self.device.lock().await.pacer() {
+                pacer.record(&result);
+            }
+            let Err(err) = result else {
+                return Ok(());
+            };
+            let Some(delay) = self.retry_policy.backoff(attempt, &err) else {
+                return Err(err.after_attempts(attempt));
+            };
+            warn!(
+                %request, attempt,
+                "device is busy, retrying in {}", humantime::format_duration(delay)
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+#[test]
+fn async_handshake() {
+    use super::mock::MockDevice;
+
+    let volca = AsyncDevice::new(MockDevice::new());
+    let reply = block_on(volca.search_device()).unwrap();
+    assert_eq!(reply.device_id.as_u8(), volca.channel.as_u8());
+    assert_eq!(reply.echo.as_u8(), super::SEARCH_ECHO);
+}
+
+#[test]
+fn async_sample_upload() {
+    use super::mock::{slot, MockDevice};
+    use super::DeviceError;
+
+    // Long enough to be sent in several chunks
+    let samples = (0..1000).map(|i| i * 30).collect::<Vec<i16>>();
+    let volca = AsyncDevice::new(MockDevice::new());
+    block_on(async {
+        let (header, data) = proto::SampleData::new(slot(12), "loop", samples.clone());
+        volca.send_sample(header, data).await.unwrap();
+
+        let header = volca.get_sample_header(slot(12)).await.unwrap();
+        assert_eq!(header.name, "loop");
+        assert_eq!(volca.get_sample(slot(12)).await.unwrap().data, samples);
+
+        volca.delete_sample(slot(12)).await.unwrap();
+        assert!(volca.get_sample_header(slot(12)).await.unwrap().is_empty());
+
+        volca
+            .device
+            .lock()
+            .await
+            .refuse_next_write(proto::NakStatus::SampleFull);
+        let (header, data) = proto::SampleData::new(slot(13), "full", samples.clone());
+        let err = volca.send_sample(header, data).await.unwrap_err();
+        assert!(matches!(err, DeviceError::Nak { .. }));
+    });
+}
+
+#[test]
+fn async_busy_retry() {
+    use super::mock::{slot, MockDevice};
+
+    use std::time::Duration;
+
+    let mut mock = MockDevice::new();
+    mock.set_retry_policy(RetryPolicy {
+        retries: 2,
+        delay: Duration::from_millis(1),
+    });
+    mock.refuse_writes(proto::NakStatus::Busy, 2);
+    let volca = AsyncDevice::new(mock);
+    block_on(async {
+        let (header, data) = proto::SampleData::new(slot(13), "busy", vec![1, 2, 3]);
+        volca.send_sample(header, data).await.unwrap();
+        assert_eq!(volca.device.lock().await.writes(), 4);
+    });
+}
@@ -0,0 +1,97 @@
+//! Log of the raw SysEx traffic, for attaching to protocol bug reports.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime};
+
+use tracing::warn;
+
+use crate::util::hexbuf;
+
+/// Which way data went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Sent,
+    Received,
+}
+
+/// Appends every chunk and message exchanged with the device to a file, one line each.
+///
+/// Lines are written with a single unbuffered write, so nothing is lost if the process crashes.
+/// Failed writes are reported once and otherwise ignored, the log never fails a transfer.
+#[derive(Debug)]
+pub struct MidiLog {
+    file: Mutex<File>,
+    start: Instant,
+    failed: AtomicBool,
+}
+
+impl MidiLog {
+    /// Opens `path` for appending and marks the start of a session in it.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(
+            file,
+            "# volsa2-cli {} session started at {}",
+            env!("CARGO_PKG_VERSION"),
+            humantime::format_rfc3339_millis(SystemTime::now())
+        )?;
+        Ok(Self {
+            file: Mutex::new(file),
+            start: Instant::now(),
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    /// Logs a part of a message as it was passed to or received from the MIDI API.
+    pub fn chunk(&self, flow: Flow, data: &[u8]) {
+        self.write("chunk", flow, data);
+    }
+
+    /// Logs a whole message, reassembled from its chunks when receiving.
+    pub fn message(&self, flow: Flow, data: &[u8]) {
+        self.write("message", flow, data);
+    }
+
+    fn write(&self, kind: &str, flow: Flow, data: &[u8]) {
+        let mut line = format_line(self.start.elapsed().as_secs_f64(), kind, flow, data);
+        line.push('\n');
+        let result = self
+            .file
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .write_all(line.as_bytes());
+        if let Err(err) = result {
+            if !self.failed.swap(true, Ordering::Relaxed) {
+                warn!("could not write MIDI log: {err}");
+            }
+        }
+    }
+}
+
+fn format_line(elapsed: f64, kind: &str, flow: Flow, data: &[u8]) -> String {
+    let flow = match flow {
+        Flow::Sent => "send",
+        Flow::Received => "recv",
+    };
+    format!(
+        "[{elapsed:>12.6}s] {flow} {kind:<7} {:>6} bytes: {:?}",
+        data.len(),
+        hexbuf(data)
+    )
+}
+
+#[test]
+fn log_line() {
+    assert_eq!(
+        format_line(1.5, "message", Flow::Sent, &[0xF0, 0x42, 0xF7]),
+        "[    1.500000s] send message      3 bytes: [F0, 42, F7]"
+    );
+    assert_eq!(
+        format_line(12.25, "chunk", Flow::Received, &[0x7F]),
+        "[   12.250000s] recv chunk        1 bytes: [7F]"
+    );
+}
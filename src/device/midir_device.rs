@@ -0,0 +1,386 @@
+//! Transport over midir, which wraps the native MIDI API of each platform.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use tracing::{debug, info, trace};
+
+use crate::proto::{self, EOX, EST};
+use crate::seven_bit::U7;
+use crate::util::hexbuf;
+
+use super::{
+    answers, find_devices, probe, select, Candidate, ConnectOptions, DeviceError, DeviceSpec, Flow,
+    MidiLog, Pacer, Pacing, ProgressSink, Result, RetryPolicy, VolcaDevice, HEADER_WINDOW,
+};
+
+/// Client and connection name shown to other MIDI applications.
+const CLIENT_NAME: &str = "VolSa2";
+
+/// Represents connection to Volca over midir.
+pub struct MidirDevice {
+    output: RefCell<MidiOutputConnection>,
+    /// Keeps the input open, messages arrive through `messages`.
+    _input: MidiInputConnection<()>,
+    messages: Receiver<Vec<u8>>,
+    /// Message received while waiting for input.
+    peeked: RefCell<Option<Vec<u8>>>,
+    /// Chunks of the message being sent, see [`MidirDevice::send_chunk`].
+    #[cfg(windows)]
+    unsent: RefCell<Vec<u8>>,
+    name: String,
+    channel: U7,
+    version: Option<proto::Version>,
+    pacer: Pacer,
+    midi_log: Option<Arc<MidiLog>>,
+    header_window: usize,
+    retry_policy: RetryPolicy,
+}
+
+impl MidirDevice {
+    /// Connects to input and output of `port`. The device is not connected yet.
+    pub fn new(chunk_cooldown: Duration, port: &MidirPort) -> Result<Self> {
+        let mut input = MidiInput::new(CLIENT_NAME).map_err(midi_error("open MIDI input"))?;
+        // SysEx is ignored by default
+        input.ignore(Ignore::TimeAndActiveSense);
+        let input_port = input
+            .ports()
+            .into_iter()
+            .nth(port.input)
+            .ok_or_else(|| port_gone(port))?;
+        let (sender, messages) = mpsc::channel();
+        let input = input
+            .connect(
+                &input_port,
+                CLIENT_NAME,
+                move |_, message, _| {
+                    // Fails only after the device is dropped
+                    let _ = sender.send(message.to_vec());
+                },
+                (),
+            )
+            .map_err(midi_error("connect to MIDI input"))?;
+
+        let output = MidiOutput::new(CLIENT_NAME).map_err(midi_error("open MIDI output"))?;
+        let output_port = output
+            .ports()
+            .into_iter()
+            .nth(port.output)
+            .ok_or_else(|| port_gone(port))?;
+        let output = output
+            .connect(&output_port, CLIENT_NAME)
+            .map_err(midi_error("connect to MIDI output"))?;
+
+        Ok(Self {
+            output: RefCell::new(output),
+            _input: input,
+            messages,
+            peeked: RefCell::default(),
+            #[cfg(windows)]
+            unsent: RefCell::default(),
+            name: port.name.clone(),
+            channel: U7::new(0),
+            version: None,
+            pacer: Pacer::new(Pacing::Fixed, chunk_cooldown),
+            midi_log: None,
+            header_window: HEADER_WINDOW,
+            retry_policy: RetryPolicy::default(),
+        })
+    }
+
+    pub fn connect(&mut self) -> Result<()> {
+        self.connect_with_timeout(None).map(drop)
+    }
+
+    /// Connects to the device and returns its discovery reply.
+    ///
+    /// Fails if the device does not reply during `timeout`.
+    pub fn connect_with_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<proto::SearchDeviceReply> {
+        let response = self.search_device(timeout)?;
+        info!(
+            port = %self.name, global_channel = %response.device_id,
+            version = %response.version, "connected to volca sample 2 over midir"
+        );
+        self.channel = response.device_id;
+        self.version = Some(response.version);
+        Ok(response)
+    }
+
+    /// Connects with a short timeout and reads the sample memory use, to tell several devices
+    /// apart.
+    pub fn probe(&mut self) -> Candidate {
+        let name = self.name.clone();
+        probe(self, name, Self::connect_with_timeout)
+    }
+
+    /// Whether the device replies to the discovery request, when probing all ports.
+    pub fn answers(&mut self) -> bool {
+        let name = self.name.clone();
+        answers(self, &name, Self::connect_with_timeout)
+    }
+
+    /// Firmware version reported by the device. Available after connecting.
+    pub fn version(&self) -> Option<proto::Version> {
+        self.version
+    }
+
+    fn send_message(&self, message: &[u8]) -> Result<()> {
+        self.output.borrow_mut().send(message).map_err(|err| {
+            // midir does not report why sending failed, so look whether the port is still there
+            if self.is_gone() {
+                DeviceError::Disconnected
+            } else {
+                midi_error("send MIDI message")(err)
+            }
+        })
+    }
+
+    /// Whether the port has disappeared from the list of MIDI ports.
+    fn is_gone(&self) -> bool {
+        list_midir_ports().is_ok_and(|ports| ports.iter().all(|port| port.name != self.name))
+    }
+
+    fn next_message(&self, timeout: Option<Duration>) -> Result<Option<Vec<u8>>> {
+        if let Some(message) = self.peeked.borrow_mut().take() {
+            return Ok(Some(message));
+        }
+        let message = match timeout {
+            Some(timeout) => match self.messages.recv_timeout(timeout) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => return Err(input_closed()),
+            },
+            None => self.messages.recv().map_err(|_| input_closed())?,
+        };
+        Ok(Some(message))
+    }
+}
+
+impl VolcaDevice for MidirDevice {
+    fn open(options: &ConnectOptions) -> Result<Self> {
+        let devices = find_devices(
+            options,
+            |spec| {
+                let mut devices = Vec::new();
+                for port in find_midir_ports(spec)? {
+                    match Self::new(options.chunk_cooldown, &port) {
+                        Ok(device) => devices.push(device),
+                        // Ports in use by other applications are no candidates when probing
+                        Err(err) if *spec == DeviceSpec::Any => {
+                            debug!(%port, %err, "skipping MIDI port");
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+                for device in &mut devices {
+                    device.midi_log.clone_from(&options.midi_log);
+                }
+                Ok(devices)
+            },
+            Self::answers,
+        )?;
+        let mut device = select(devices, options.selection, Self::probe)?;
+        if device.version.is_none() {
+            device.connect()?;
+        }
+        device.pacer = Pacer::new(options.pacing, options.chunk_cooldown);
+        device.header_window = options.header_window();
+        device.set_retry_policy(options.retry_policy);
+        Ok(device)
+    }
+
+    fn channel(&self) -> U7 {
+        self.channel
+    }
+
+    fn pacer(&self) -> Option<&Pacer> {
+        Some(&self.pacer)
+    }
+
+    fn midi_log(&self) -> Option<&MidiLog> {
+        self.midi_log.as_deref()
+    }
+
+    fn header_window(&self) -> usize {
+        self.header_window
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// WinMM only sends SysEx passed as a whole, so on Windows chunks are collected and the
+    /// message is sent with its last chunk. The driver paces the transfer there.
+    fn send_chunk(&self, chunk: &[u8]) -> Result<()> {
+        trace!(len = chunk.len(), raw = ?hexbuf(chunk), "send chunk");
+        #[cfg(windows)]
+        {
+            let mut unsent = self.unsent.borrow_mut();
+            unsent.extend_from_slice(chunk);
+            if !chunk.ends_with(&[EOX]) {
+                return Ok(());
+            }
+            let message = std::mem::take(&mut *unsent);
+            self.send_message(&message)
+        }
+        #[cfg(not(windows))]
+        self.send_message(chunk)
+    }
+
+    fn wait_for_input(&self, timeout: Duration) -> Result<bool> {
+        let message = self.next_message(Some(timeout))?;
+        let received = message.is_some();
+        *self.peeked.borrow_mut() = message;
+        Ok(received)
+    }
+
+    fn receive_raw_with_progress(&self, progress: &mut dyn ProgressSink) -> Result<Vec<u8>> {
+        // midir delivers whole messages on most platforms, large SysEx may still be split
+        let mut data = Vec::new();
+        while !data.ends_with(&[EOX]) {
+            let message = self.next_message(None)?.expect("no timeout");
+            if data.is_empty() && message.first() != Some(&EST) {
+                trace!(raw = ?hexbuf(&message), "skip message");
+                continue;
+            }
+            trace!(raw = ?hexbuf(&message), len = message.len(), "recv message");
+            if let Some(log) = &self.midi_log {
+                log.chunk(Flow::Received, &message);
+            }
+            data.extend_from_slice(&message);
+            progress.progress(data.len(), None);
+        }
+        if let Some(log) = &self.midi_log {
+            log.message(Flow::Received, &data);
+        }
+        progress.finished();
+        Ok(data)
+    }
+}
+
+fn midi_error<E: fmt::Display>(action: &'static str) -> impl FnOnce(E) -> DeviceError {
+    move |err| DeviceError::Midi {
+        action,
+        message: err.to_string(),
+    }
+}
+
+fn port_gone(port: &MidirPort) -> DeviceError {
+    DeviceError::Midi {
+        action: "open MIDI port",
+        message: format!("{:?} has disappeared", port.name),
+    }
+}
+
+fn input_closed() -> DeviceError {
+    DeviceError::Midi {
+        action: "receive MIDI message",
+        message: "input connection was closed".to_owned(),
+    }
+}
+
+/// MIDI port that has both an input and an output with the same name.
+#[derive(Debug, Clone)]
+pub struct MidirPort {
+    pub name: String,
+    /// Index among midir input ports.
+    input: usize,
+    /// Index among midir output ports.
+    output: usize,
+}
+
+impl MidirPort {
+    /// Whether `spec` selects this port. Both client and plain names match a part of the port
+    /// name, ignoring case, addresses never match.
+    pub fn matches(&self, spec: &DeviceSpec) -> bool {
+        match spec {
+            DeviceSpec::Client(name) | DeviceSpec::Name(name) => {
+                self.name.to_lowercase().contains(&name.to_lowercase())
+            }
+            DeviceSpec::Addr { .. } => false,
+            DeviceSpec::Any => true,
+        }
+    }
+}
+
+impl fmt::Display for MidirPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.name)
+    }
+}
+
+/// Lists MIDI ports that can both send and receive. Inputs and outputs with the same name are
+/// paired in order, so several units of the same model are told apart.
+pub fn list_midir_ports() -> Result<Vec<MidirPort>> {
+    let input = MidiInput::new(CLIENT_NAME).map_err(midi_error("open MIDI input"))?;
+    let output = MidiOutput::new(CLIENT_NAME).map_err(midi_error("open MIDI output"))?;
+    let mut outputs = output
+        .ports()
+        .iter()
+        .map(|port| output.port_name(port).ok())
+        .enumerate()
+        .collect::<Vec<_>>();
+    let ports = input
+        .ports()
+        .iter()
+        .enumerate()
+        .filter_map(|(input_index, port)| {
+            let name = input.port_name(port).ok()?;
+            let position = outputs
+                .iter()
+                .position(|(_, output_name)| output_name.as_ref() == Some(&name))?;
+            let (output_index, _) = outputs.remove(position);
+            Some(MidirPort {
+                name,
+                input: input_index,
+                output: output_index,
+            })
+        })
+        .collect();
+    Ok(ports)
+}
+
+/// Looks for MIDI ports selected by `spec`.
+fn find_midir_ports(spec: &DeviceSpec) -> Result<Vec<MidirPort>> {
+    let (matching, other): (Vec<_>, Vec<_>) = list_midir_ports()?
+        .into_iter()
+        .partition(|port| port.matches(spec));
+    if matching.is_empty() {
+        return Err(match spec {
+            DeviceSpec::Client(client_name) => DeviceError::NotFound(client_name.to_owned()),
+            _ => DeviceError::NoMatchingMidiPort {
+                spec: spec.to_string(),
+                available: other
+                    .iter()
+                    .map(|port| format!("  {port}"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            },
+        });
+    }
+    Ok(matching)
+}
+
+#[test]
+fn midir_port_matching() {
+    let port = MidirPort {
+        name: "volca sample 2".into(),
+        input: 0,
+        output: 1,
+    };
+    assert!(port.matches(&DeviceSpec::default()));
+    assert!(port.matches(&"Sample 2".parse().unwrap()));
+    assert!(!port.matches(&"minilogue".parse().unwrap()));
+    assert!(!port.matches(&"24:0".parse().unwrap()));
+}
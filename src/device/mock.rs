@@ -0,0 +1,457 @@
+//! In-memory device for tests.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::proto::{
+    CurrentPatternData, GlobalData, Header, IdentityReply, Incoming, Manufacturer, Message,
+    NakStatus, Outgoing, PatternData, PatternDataDumpRequest, SampleData, SampleDataDumpRequest,
+    SampleHeader, SampleHeaderDumpRequest, SampleNo, SampleSpaceDump, SearchDeviceReply,
+    SearchDeviceRequest, Status, Version, EOX, KORG_ID, PATTERN_COUNT, UNIVERSAL_NON_REALTIME,
+};
+use crate::seven_bit::U7;
+
+use super::{
+    ConnectOptions, DeviceError, ProgressSink, Result, RetryPolicy, VolcaDevice, HEADER_WINDOW,
+};
+
+/// Sample memory size in sectors.
+const ALL_SECTORS: u16 = 4096;
+/// Global channel reported on discovery.
+const CHANNEL: u8 = 3;
+/// Size of the stored patterns, much smaller than on the device.
+pub const PATTERN_LEN: usize = 16;
+
+/// Simulates 200 sample slots and the replies of the device.
+pub struct MockDevice {
+    slots: RefCell<Vec<(SampleHeader, Vec<i16>)>>,
+    patterns: RefCell<Vec<Vec<u8>>>,
+    current_pattern: RefCell<Vec<u8>>,
+    global: RefCell<Vec<u8>>,
+    pending: RefCell<Vec<u8>>,
+    replies: RefCell<VecDeque<Vec<u8>>>,
+    nak: Cell<Option<NakStatus>>,
+    naks_left: Cell<usize>,
+    writes: Cell<usize>,
+    /// Writes accepted before the device is unplugged, `None` if it stays connected.
+    writes_left: Cell<Option<usize>>,
+    /// Header replies held back to be sent together, in reverse order, once there are this many.
+    burst: Cell<usize>,
+    held: RefCell<Vec<Vec<u8>>>,
+    /// Header requests not replied to yet, and the most there have been at once.
+    headers_in_flight: Cell<usize>,
+    max_headers_in_flight: Cell<usize>,
+    header_window: usize,
+    /// Discovery replies sent with a wrong echo value before the right one.
+    wrong_echoes: Cell<usize>,
+    /// Raw messages sent after each discovery reply, as if other devices replied too.
+    other_replies: RefCell<Vec<Vec<u8>>>,
+    /// Raw message sent over and over whenever no reply is pending.
+    chatter: RefCell<Option<Vec<u8>>>,
+    chunks: Cell<usize>,
+    /// Every chunk as it was sent.
+    sent: RefCell<Vec<Vec<u8>>>,
+    /// Chunks sent before Ctrl-C is pressed, `None` if it is not.
+    chunks_left: Cell<Option<usize>>,
+    /// Whether a message was cut short by a lone EOX.
+    cut_short: Cell<bool>,
+    /// Sample data dumps sent without their last sample.
+    short_dumps: Cell<usize>,
+    retry_policy: RetryPolicy,
+}
+
+impl MockDevice {
+    pub fn new() -> Self {
+        Self {
+            slots: RefCell::new(
+                SampleNo::all()
+                    .map(|sample_no| (SampleHeader::empty(sample_no), Vec::new()))
+                    .collect(),
+            ),
+            patterns: RefCell::new(
+                (0..PATTERN_COUNT)
+                    .map(|pattern_no| vec![pattern_no; PATTERN_LEN])
+                    .collect(),
+            ),
+            current_pattern: RefCell::default(),
+            global: RefCell::default(),
+            pending: RefCell::default(),
+            replies: RefCell::default(),
+            nak: Cell::default(),
+            naks_left: Cell::default(),
+            writes: Cell::default(),
+            writes_left: Cell::default(),
+            burst: Cell::default(),
+            held: RefCell::default(),
+            headers_in_flight: Cell::default(),
+            max_headers_in_flight: Cell::default(),
+            header_window: HEADER_WINDOW,
+            wrong_echoes: Cell::default(),
+            other_replies: RefCell::default(),
+            chatter: RefCell::default(),
+            chunks: Cell::default(),
+            sent: RefCell::default(),
+            chunks_left: Cell::default(),
+            cut_short: Cell::default(),
+            short_dumps: Cell::default(),
+            retry_policy: RetryPolicy {
+                retries: 0,
+                delay: Duration::ZERO,
+            },
+        }
+    }
+
+    /// Loads a sample into a slot without counting it as a write.
+    pub fn with_sample(self, sample_no: u8, name: &str, data: Vec<i16>) -> Self {
+        let (header, data) = SampleData::new(slot(sample_no), name, data);
+        self.slots.borrow_mut()[usize::from(sample_no)] = (header, data.data);
+        self
+    }
+
+    pub fn header(&self, sample_no: u8) -> SampleHeader {
+        self.slots.borrow()[usize::from(sample_no)].0.clone()
+    }
+
+    pub fn data(&self, sample_no: u8) -> Vec<i16> {
+        self.slots.borrow()[usize::from(sample_no)].1.clone()
+    }
+
+    /// Chunks sent to the device so far.
+    pub fn sent_chunks(&self) -> Vec<Vec<u8>> {
+        self.sent.borrow().clone()
+    }
+
+    /// Data of a stored pattern, numbered from 0. Each is filled with its number at first.
+    pub fn pattern(&self, pattern_no: u8) -> Vec<u8> {
+        self.patterns.borrow()[usize::from(pattern_no)].clone()
+    }
+
+    /// Data of the pattern being edited.
+    pub fn current_pattern(&self) -> Vec<u8> {
+        self.current_pattern.borrow().clone()
+    }
+
+    pub fn with_current_pattern(self, data: Vec<u8>) -> Self {
+        *self.current_pattern.borrow_mut() = data;
+        self
+    }
+
+    /// Global settings.
+    pub fn global_data(&self) -> Vec<u8> {
+        self.global.borrow().clone()
+    }
+
+    pub fn with_global_data(self, data: Vec<u8>) -> Self {
+        *self.global.borrow_mut() = data;
+        self
+    }
+
+    /// Refuses the next header, data or pattern write with `status`.
+    pub fn refuse_next_write(&self, status: NakStatus) {
+        self.refuse_writes(status, 1);
+    }
+
+    /// Refuses the next `count` header, data or pattern writes with `status`.
+    pub fn refuse_writes(&self, status: NakStatus, count: usize) {
+        self.nak.set(Some(status));
+        self.naks_left.set(count);
+    }
+
+    /// Makes the device disappear after accepting `count` more writes.
+    pub fn disconnect_after_writes(&self, count: usize) {
+        self.writes_left.set(Some(count));
+    }
+
+    /// Sets how many header requests are sent ahead, see [`VolcaDevice::header_window`].
+    pub fn with_header_window(mut self, window: usize) -> Self {
+        self.header_window = window;
+        self
+    }
+
+    /// Holds sample header replies back until `count` of them are ready, then sends them in
+    /// reverse order. Held replies are also sent when nothing else is left to receive.
+    pub fn reply_in_bursts(&self, count: usize) {
+        self.burst.set(count);
+    }
+
+    /// Simulates Ctrl-C after `count` more chunks are sent.
+    pub fn interrupt_after_chunks(&self, count: usize) {
+        self.chunks_left.set(Some(count));
+    }
+
+    /// Number of chunks sent to the device.
+    pub fn chunks(&self) -> usize {
+        self.chunks.get()
+    }
+
+    /// Whether a message was terminated before it was complete.
+    pub fn cut_short(&self) -> bool {
+        self.cut_short.get()
+    }
+
+    /// Leaves the last sample out of the next `count` sample data dumps.
+    pub fn send_short_dumps(&self, count: usize) {
+        self.short_dumps.set(count);
+    }
+
+    /// Answers the next `count` discovery requests with a wrong echo value.
+    pub fn reply_with_wrong_echo(&self, count: usize) {
+        self.wrong_echoes.set(count);
+    }
+
+    /// Sends `message` after every discovery reply, as another device on the port would.
+    pub fn reply_to_discovery_with(&self, message: Vec<u8>) {
+        self.other_replies.borrow_mut().push(message);
+    }
+
+    /// Sends `message` whenever no reply is pending, as a busy port would.
+    pub fn keep_sending(&self, message: Vec<u8>) {
+        *self.chatter.borrow_mut() = Some(message);
+    }
+
+    /// Largest number of sample header requests that were waiting for a reply at once.
+    pub fn max_headers_in_flight(&self) -> usize {
+        self.max_headers_in_flight.get()
+    }
+
+    fn release_held(&self) {
+        let held = std::mem::take(&mut *self.held.borrow_mut());
+        self.replies.borrow_mut().extend(held.into_iter().rev());
+    }
+
+    /// Number of writes of any kind, including refused ones.
+    pub fn writes(&self) -> usize {
+        self.writes.get()
+    }
+
+    fn reply<T: Outgoing>(&self, msg: T) {
+        let mut buf = Vec::new();
+        msg.encode(T::Header::from_channel(self.channel()), &mut buf)
+            .unwrap();
+        self.replies.borrow_mut().push_back(buf);
+    }
+
+    fn reply_status(&self) {
+        self.writes.set(self.writes.get() + 1);
+        self.writes_left
+            .set(self.writes_left.get().map(|left| left.saturating_sub(1)));
+        let status: Status = self.nak.get().map_or(Ok(()), Err);
+        if self.naks_left.get() > 0 {
+            self.naks_left.set(self.naks_left.get() - 1);
+        }
+        if self.naks_left.get() == 0 {
+            self.nak.set(None);
+        }
+        self.reply(status);
+    }
+
+    fn used_sectors(&self) -> u16 {
+        let space = SampleSpaceDump {
+            all_sector_size: ALL_SECTORS,
+            used_sector_size: 0,
+        };
+        self.slots
+            .borrow()
+            .iter()
+            .map(|(header, _)| space.sectors_for(header.length) as u16)
+            .sum()
+    }
+
+    /// Replies to a complete message.
+    fn handle(&self, data: &[u8]) {
+        if data[1] == UNIVERSAL_NON_REALTIME {
+            self.reply(IdentityReply {
+                manufacturer: Manufacturer::Short(KORG_ID),
+                family: [0x2D, 0x01],
+                model: [0x08, 0x00],
+                version: [2, 0, 1, 0],
+            });
+            return;
+        }
+        if let Ok((_, request)) = SearchDeviceRequest::parse(data) {
+            let mut echo = request.echo;
+            if self.wrong_echoes.get() > 0 {
+                self.wrong_echoes.set(self.wrong_echoes.get() - 1);
+                echo = U7::new(echo.as_u8() ^ 1);
+            }
+            self.reply(SearchDeviceReply {
+                echo,
+                device_id: U7::new(CHANNEL),
+                version: Version(1, 2),
+            });
+            let other = self.other_replies.borrow().clone();
+            self.replies.borrow_mut().extend(other);
+            return;
+        }
+
+        match data[<SampleHeader as Message>::Header::LEN] {
+            0x0E => self.reply(GlobalData {
+                data: self.global_data(),
+            }),
+            0x10 => self.reply(CurrentPatternData {
+                data: self.current_pattern(),
+            }),
+            0x1B => self.reply(SampleSpaceDump {
+                all_sector_size: ALL_SECTORS,
+                used_sector_size: self.used_sectors(),
+            }),
+            0x1C => {
+                let (_, request) = PatternDataDumpRequest::parse(data).unwrap();
+                self.reply(PatternData {
+                    pattern_no: request.pattern_no,
+                    data: self.pattern(request.pattern_no),
+                });
+            }
+            0x1E => {
+                let (_, request) = SampleHeaderDumpRequest::parse(data).unwrap();
+                let header = self.slots.borrow()[usize::from(request.sample_no)]
+                    .0
+                    .clone();
+                let in_flight = self.headers_in_flight.get() + 1;
+                self.headers_in_flight.set(in_flight);
+                self.max_headers_in_flight
+                    .set(self.max_headers_in_flight.get().max(in_flight));
+                self.reply(header);
+                if self.burst.get() > 0 {
+                    let reply = self.replies.borrow_mut().pop_back().unwrap();
+                    self.held.borrow_mut().push(reply);
+                    if self.held.borrow().len() >= self.burst.get() {
+                        self.release_held();
+                    }
+                }
+            }
+            0x1F => {
+                let (_, request) = SampleDataDumpRequest::parse(data).unwrap();
+                let (header, mut data) =
+                    self.slots.borrow()[usize::from(request.sample_no)].clone();
+                if self.short_dumps.get() > 0 {
+                    self.short_dumps.set(self.short_dumps.get() - 1);
+                    data.pop();
+                }
+                self.reply(SampleData {
+                    sample_no: header.sample_no,
+                    data,
+                });
+            }
+            0x40 => {
+                let (_, pattern) = CurrentPatternData::parse(data).unwrap();
+                if self.nak.get().is_none() {
+                    *self.current_pattern.borrow_mut() = pattern.data;
+                }
+                self.reply_status();
+            }
+            0x4C => {
+                let (_, pattern) = PatternData::parse(data).unwrap();
+                if self.nak.get().is_none() {
+                    self.patterns.borrow_mut()[usize::from(pattern.pattern_no)] = pattern.data;
+                }
+                self.reply_status();
+            }
+            0x51 => {
+                let (_, global) = GlobalData::parse(data).unwrap();
+                if self.nak.get().is_none() {
+                    *self.global.borrow_mut() = global.data;
+                }
+                self.reply_status();
+            }
+            0x4E => {
+                let (_, header) = SampleHeader::parse(data).unwrap();
+                if self.nak.get().is_none() {
+                    let slot = usize::from(header.sample_no);
+                    self.slots.borrow_mut()[slot] = (header, Vec::new());
+                }
+                self.reply_status();
+            }
+            0x4F => {
+                let (_, data) = SampleData::parse(data).unwrap();
+                if self.nak.get().is_none() {
+                    let slot = usize::from(data.sample_no);
+                    self.slots.borrow_mut()[slot].1 = data.data;
+                }
+                self.reply_status();
+            }
+            id => panic!("unsupported message {id:#04X}"),
+        }
+    }
+}
+
+impl VolcaDevice for MockDevice {
+    fn open(_: &ConnectOptions) -> Result<Self> {
+        Ok(Self::new())
+    }
+
+    fn channel(&self) -> U7 {
+        U7::new(CHANNEL)
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    fn send_chunk(&self, chunk: &[u8]) -> Result<()> {
+        if self.writes_left.get() == Some(0) {
+            return Err(DeviceError::Disconnected);
+        }
+        self.chunks.set(self.chunks.get() + 1);
+        self.sent.borrow_mut().push(chunk.to_vec());
+        self.chunks_left
+            .set(self.chunks_left.get().map(|left| left.saturating_sub(1)));
+        let mut pending = self.pending.borrow_mut();
+        if chunk == [EOX] && !pending.is_empty() {
+            // The device drops a message that ends early
+            pending.clear();
+            self.cut_short.set(true);
+            return Ok(());
+        }
+        pending.extend_from_slice(chunk);
+        if chunk.ends_with(&[EOX]) {
+            let message = std::mem::take(&mut *pending);
+            drop(pending);
+            self.handle(&message);
+        }
+        Ok(())
+    }
+
+    fn header_window(&self) -> usize {
+        self.header_window
+    }
+
+    fn is_interrupted(&self) -> bool {
+        self.chunks_left.get() == Some(0)
+    }
+
+    fn wait_for_input(&self, _timeout: Duration) -> Result<bool> {
+        Ok(!self.replies.borrow().is_empty()
+            || !self.held.borrow().is_empty()
+            || self.chatter.borrow().is_some())
+    }
+
+    fn receive_raw_with_progress(&self, progress: &mut dyn ProgressSink) -> Result<Vec<u8>> {
+        if self.replies.borrow().is_empty() {
+            self.release_held();
+        }
+        let reply = self
+            .replies
+            .borrow_mut()
+            .pop_front()
+            .or_else(|| self.chatter.borrow().clone())
+            .expect("no reply is pending");
+        let id = &reply[<SampleHeader as Message>::Header::LEN..];
+        if id.starts_with(&SampleHeader::ID) {
+            self.headers_in_flight.set(self.headers_in_flight.get() - 1);
+        }
+        progress.progress(reply.len(), None);
+        progress.finished();
+        Ok(reply)
+    }
+}
+
+/// Shorthand for a valid slot number.
+pub fn slot(sample_no: u8) -> SampleNo {
+    SampleNo::try_from(sample_no).unwrap()
+}
@@ -0,0 +1,375 @@
+//! Transport over an ALSA rawmidi device, an alternative to the sequencer API.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+
+use alsa::rawmidi::{self, Rawmidi};
+use alsa::{Direction, PollDescriptors};
+use tracing::{debug, info, trace};
+
+use crate::proto::{self, EOX, EST};
+use crate::seven_bit::U7;
+use crate::util::hexbuf;
+
+use super::{
+    answers, find_devices, probe, select, Candidate, ConnectOptions, DeviceError, DeviceSpec, Flow,
+    MidiLog, Pacer, Pacing, ProgressSink, Result, RetryPolicy, VolcaDevice, CHUNK_SIZE,
+    HEADER_WINDOW,
+};
+
+/// Represents connection to Volca over a rawmidi device.
+pub struct RawMidiDevice {
+    input: Rawmidi,
+    output: Rawmidi,
+    /// Device name, e.g. `hw:2,0,0`.
+    name: String,
+    reader: RefCell<SysExReader>,
+    channel: U7,
+    version: Option<proto::Version>,
+    pacer: Pacer,
+    midi_log: Option<Arc<MidiLog>>,
+    header_window: usize,
+    retry_policy: RetryPolicy,
+}
+
+impl RawMidiDevice {
+    /// Opens both directions of the rawmidi device `name`. The device is not connected yet.
+    pub fn new(chunk_cooldown: Duration, name: &str) -> Result<Self> {
+        Ok(Self {
+            input: Rawmidi::new(name, Direction::Capture, false)?,
+            output: Rawmidi::new(name, Direction::Playback, false)?,
+            name: name.to_owned(),
+            reader: RefCell::default(),
+            channel: U7::new(0),
+            version: None,
+            pacer: Pacer::new(Pacing::Fixed, chunk_cooldown),
+            midi_log: None,
+            header_window: HEADER_WINDOW,
+            retry_policy: RetryPolicy::default(),
+        })
+    }
+
+    pub fn connect(&mut self) -> Result<()> {
+        self.connect_with_timeout(None).map(drop)
+    }
+
+    /// Connects to the device and returns its discovery reply.
+    ///
+    /// Fails if the device does not reply during `timeout`.
+    pub fn connect_with_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<proto::SearchDeviceReply> {
+        let response = self.search_device(timeout)?;
+        info!(
+            device = %self.name, global_channel = %response.device_id,
+            version = %response.version, "connected to volca sample 2 over rawmidi"
+        );
+        self.channel = response.device_id;
+        self.version = Some(response.version);
+        Ok(response)
+    }
+
+    /// Connects with a short timeout and reads the sample memory use, to tell several devices
+    /// apart.
+    pub fn probe(&mut self) -> Candidate {
+        let name = self.name.clone();
+        probe(self, name, Self::connect_with_timeout)
+    }
+
+    /// Whether the device replies to the discovery request, when probing all ports.
+    pub fn answers(&mut self) -> bool {
+        let name = self.name.clone();
+        answers(self, &name, Self::connect_with_timeout)
+    }
+
+    /// Firmware version reported by the device. Available after connecting.
+    pub fn version(&self) -> Option<proto::Version> {
+        self.version
+    }
+}
+
+impl VolcaDevice for RawMidiDevice {
+    fn open(options: &ConnectOptions) -> Result<Self> {
+        let devices = find_devices(
+            options,
+            |spec| {
+                let mut devices = Vec::new();
+                for port in find_rawmidi(spec)? {
+                    match Self::new(options.chunk_cooldown, &port.name) {
+                        Ok(device) => devices.push(device),
+                        // Devices in use by other applications are no candidates when probing
+                        Err(err) if *spec == DeviceSpec::Any => {
+                            debug!(device = %port.name, %err, "skipping rawmidi device");
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+                for device in &mut devices {
+                    device.midi_log.clone_from(&options.midi_log);
+                }
+                Ok(devices)
+            },
+            Self::answers,
+        )?;
+        let mut device = select(devices, options.selection, Self::probe)?;
+        if device.version.is_none() {
+            device.connect()?;
+        }
+        device.pacer = Pacer::new(options.pacing, options.chunk_cooldown);
+        device.header_window = options.header_window();
+        device.set_retry_policy(options.retry_policy);
+        Ok(device)
+    }
+
+    fn channel(&self) -> U7 {
+        self.channel
+    }
+
+    fn pacer(&self) -> Option<&Pacer> {
+        Some(&self.pacer)
+    }
+
+    fn midi_log(&self) -> Option<&MidiLog> {
+        self.midi_log.as_deref()
+    }
+
+    fn header_window(&self) -> usize {
+        self.header_window
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    fn send_chunk(&self, chunk: &[u8]) -> Result<()> {
+        trace!(len = chunk.len(), raw = ?hexbuf(chunk), "send chunk");
+        self.output.io().write_all(chunk).map_err(transfer_error)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.output
+            .drain()
+            .map_err(|err| match err.errno() as i32 {
+                ENODEV => DeviceError::Disconnected,
+                _ => DeviceError::Alsa(err),
+            })?;
+        Ok(())
+    }
+
+    fn wait_for_input(&self, timeout: Duration) -> Result<bool> {
+        if self.reader.borrow().has_pending() {
+            return Ok(true);
+        }
+
+        let mut fds = self.input.get()?;
+        let timeout = timeout.as_millis().try_into().unwrap_or(i32::MAX);
+        Ok(alsa::poll::poll(&mut fds, timeout)? > 0)
+    }
+
+    fn receive_raw_with_progress(&self, progress: &mut dyn ProgressSink) -> Result<Vec<u8>> {
+        let mut reader = self.reader.borrow_mut();
+        let mut buf = [0; CHUNK_SIZE];
+        let data = loop {
+            if let Some(data) = reader.next_message() {
+                break data;
+            }
+            progress.progress(reader.received(), None);
+
+            let len = self.input.io().read(&mut buf).map_err(transfer_error)?;
+            trace!(raw = ?hexbuf(&buf[..len]), len, "recv bytes");
+            if let Some(log) = &self.midi_log {
+                log.chunk(Flow::Received, &buf[..len]);
+            }
+            reader.extend(&buf[..len]);
+        };
+        if let Some(log) = &self.midi_log {
+            log.message(Flow::Received, &data);
+        }
+        progress.progress(data.len(), None);
+        progress.finished();
+        Ok(data)
+    }
+}
+
+/// Error ALSA returns when the device was unplugged.
+const ENODEV: i32 = 19;
+
+/// Tells errors caused by the device going away apart from other failures.
+fn transfer_error(err: io::Error) -> DeviceError {
+    // Rawmidi reports negated error codes
+    match err.raw_os_error().map(i32::abs) {
+        Some(ENODEV) => DeviceError::Disconnected,
+        _ => DeviceError::RawMidi(err),
+    }
+}
+
+/// Reassembles SysEx messages from a MIDI byte stream.
+///
+/// Realtime bytes may be interleaved with SysEx data and are dropped, as are bytes outside of
+/// SysEx messages. Bytes following a complete message are kept for the next one.
+#[derive(Debug, Default)]
+struct SysExReader {
+    pending: VecDeque<u8>,
+    message: Vec<u8>,
+    in_sysex: bool,
+}
+
+impl SysExReader {
+    fn extend(&mut self, bytes: &[u8]) {
+        self.pending.extend(bytes);
+    }
+
+    fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Number of bytes of the incomplete message.
+    fn received(&self) -> usize {
+        self.message.len()
+    }
+
+    /// Consumes pending bytes until a message is complete.
+    fn next_message(&mut self) -> Option<Vec<u8>> {
+        while let Some(byte) = self.pending.pop_front() {
+            match byte {
+                // Realtime messages
+                0xF8..=0xFF => {}
+                EST => {
+                    self.message.clear();
+                    self.message.push(EST);
+                    self.in_sysex = true;
+                }
+                EOX if self.in_sysex => {
+                    self.message.push(EOX);
+                    self.in_sysex = false;
+                    return Some(mem::take(&mut self.message));
+                }
+                // Any other status byte aborts the message
+                _ if byte & 0x80 != 0 => {
+                    self.message.clear();
+                    self.in_sysex = false;
+                }
+                _ if self.in_sysex => self.message.push(byte),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// Rawmidi subdevice that can both send and receive.
+#[derive(Debug, Clone)]
+pub struct RawMidiPort {
+    /// Device name, e.g. `hw:2,0,0`.
+    pub name: String,
+    pub card_name: String,
+    pub subdevice_name: String,
+}
+
+impl RawMidiPort {
+    /// Whether `spec` selects this device. Client names of the sequencer are card names here,
+    /// addresses never match.
+    pub fn matches(&self, spec: &DeviceSpec) -> bool {
+        match spec {
+            DeviceSpec::Client(name) => self.card_name == *name,
+            DeviceSpec::Addr { .. } => false,
+            DeviceSpec::Any => true,
+            DeviceSpec::Name(name) => {
+                let name = name.to_lowercase();
+                self.name == name
+                    || self.card_name.to_lowercase().contains(&name)
+                    || self.subdevice_name.to_lowercase().contains(&name)
+            }
+        }
+    }
+}
+
+impl fmt::Display for RawMidiPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<9} {} - {}",
+            self.name, self.card_name, self.subdevice_name
+        )
+    }
+}
+
+/// Lists rawmidi subdevices of all cards. Cards that cannot be queried are skipped.
+pub fn list_rawmidi_ports() -> Vec<RawMidiPort> {
+    let mut ports = Vec::new();
+    for card in alsa::card::Iter::new().filter_map(|card| card.ok()) {
+        let Ok(ctl) = alsa::Ctl::from_card(&card, false) else {
+            continue;
+        };
+        let card_name = card.get_name().unwrap_or_default();
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        for info in rawmidi::Iter::new(&ctl).filter_map(|info| info.ok()) {
+            let key = (info.get_device(), info.get_subdevice());
+            let name = info.get_subdevice_name().unwrap_or_default();
+            match info.get_stream() {
+                Direction::Capture => inputs.push(key),
+                Direction::Playback => outputs.push((key, name)),
+            }
+        }
+        ports.extend(
+            outputs
+                .into_iter()
+                .filter(|(key, _)| inputs.contains(key))
+                .map(|((device, subdevice), subdevice_name)| RawMidiPort {
+                    name: format!("hw:{},{device},{subdevice}", card.get_index()),
+                    card_name: card_name.clone(),
+                    subdevice_name,
+                }),
+        );
+    }
+    ports
+}
+
+/// Looks for rawmidi devices selected by `spec`.
+fn find_rawmidi(spec: &DeviceSpec) -> Result<Vec<RawMidiPort>> {
+    let ports = list_rawmidi_ports();
+    let (matching, other): (Vec<_>, Vec<_>) =
+        ports.into_iter().partition(|port| port.matches(spec));
+    if matching.is_empty() {
+        return Err(DeviceError::NoMatchingRawMidi {
+            spec: spec.to_string(),
+            available: other
+                .iter()
+                .map(|port| format!("  {port}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        });
+    }
+    Ok(matching)
+}
+
+#[test]
+fn sysex_reader() {
+    let mut reader = SysExReader::default();
+    // Running status garbage, then a message with clock and active sensing bytes inside
+    reader.extend(&[0x05, EST, 0x42, 0xF8, 0x30]);
+    assert_eq!(reader.next_message(), None);
+    assert_eq!(reader.received(), 3);
+    reader.extend(&[0xFE, 0x00, EOX, EST, 0x42]);
+    assert_eq!(
+        reader.next_message(),
+        Some(vec![EST, 0x42, 0x30, 0x00, EOX])
+    );
+    assert_eq!(reader.next_message(), None);
+
+    // A note on aborts the unfinished message
+    reader.extend(&[0x90, 0x40, 0x7F, EST, 0x01, EOX]);
+    assert_eq!(reader.next_message(), Some(vec![EST, 0x01, EOX]));
+    assert!(!reader.has_pending());
+}
@@ -0,0 +1,773 @@
+//! Transport over the ALSA sequencer.
+
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use alsa::seq;
+use alsa::PollDescriptors;
+use tracing::{info, trace, warn};
+
+use crate::proto;
+use crate::seven_bit::{Convert, U8ToU7, U7};
+use crate::util::hexbuf;
+
+use super::{
+    answers, find_devices, probe, select, Candidate, ConnectOptions, DeviceError, DeviceSpec, Flow,
+    MidiLog, Pacer, Pacing, ProgressSink, Result, RetryPolicy, VolcaDevice, HEADER_WINDOW,
+};
+
+const SELF_NAME: &CStr = c"VolSa2";
+/// Bytes of SysEx data stored in each cell of the client pool, the size of a sequencer event.
+const POOL_CELL_SIZE: usize = 28;
+/// Largest input pool ALSA accepts.
+pub const MAX_INPUT_POOL: u32 = 2000;
+
+/// Suggests how to avoid an input overrun with a pool of `pool` events.
+pub fn overrun_hint(pool: u32) -> String {
+    if pool < MAX_INPUT_POOL {
+        format!("try a larger --input-pool (up to {MAX_INPUT_POOL})")
+    } else {
+        "the pool is as large as ALSA allows, try again with less load on the system".to_owned()
+    }
+}
+
+/// Input pool cells needed to hold the largest message, a dump of the whole sample memory, as
+/// far as ALSA allows.
+pub fn default_input_pool() -> u32 {
+    let largest = U8ToU7::output_len(proto::SampleSpaceDump::MEMORY_SIZE as usize);
+    (largest / POOL_CELL_SIZE + 1).min(MAX_INPUT_POOL as usize) as u32
+}
+
+/// Represents connection to Volca.
+pub struct Device {
+    seq: seq::Seq,
+    me: seq::Addr,
+    volca: seq::Addr,
+    subscribed: bool,
+    channel: U7,
+    version: Option<proto::Version>,
+    pacer: Pacer,
+    /// Size of the sequencer input pool in events, set when connecting.
+    input_pool: u32,
+    midi_log: Option<Arc<MidiLog>>,
+    header_window: usize,
+    retry_policy: RetryPolicy,
+    /// Deadline and timeout of the last [`wait_for_input`](VolcaDevice::wait_for_input), kept
+    /// for the next receive.
+    deadline: Cell<Option<(Instant, Duration)>>,
+}
+
+impl Device {
+    /// Opens sequencer and looks for ports selected by `spec`. Returns an unconnected device for
+    /// each of them, in the order of their addresses.
+    pub fn new(chunk_cooldown: Duration, spec: &DeviceSpec) -> Result<Vec<Self>> {
+        let (seq, me) = Self::open_seq()?;
+        let ports = find_ports(&seq, spec)?;
+        let mut seq = Some((seq, me));
+        ports
+            .into_iter()
+            .map(|volca| {
+                let (seq, me) = match seq.take() {
+                    Some(opened) => opened,
+                    None => Self::open_seq()?,
+                };
+                Ok(Self {
+                    me,
+                    seq,
+                    volca,
+                    subscribed: false,
+                    channel: U7::new(0),
+                    version: None,
+                    pacer: Pacer::new(Pacing::Fixed, chunk_cooldown),
+                    input_pool: default_input_pool(),
+                    midi_log: None,
+                    header_window: HEADER_WINDOW,
+                    retry_policy: RetryPolicy::default(),
+                    deadline: Cell::new(None),
+                })
+            })
+            .collect()
+    }
+
+    /// Opens a sequencer client with a port for talking to the device.
+    fn open_seq() -> Result<(seq::Seq, seq::Addr)> {
+        let seq = seq::Seq::open(None, None, false)?;
+        seq.set_client_name(SELF_NAME)?;
+        let mut me = seq::PortInfo::empty()?;
+        me.set_capability(
+            seq::PortCap::WRITE
+            | seq::PortCap::SUBS_WRITE
+            | seq::PortCap::READ
+            | seq::PortCap::SUBS_READ
+            // | seq::PortCap::SYNC_READ
+            // | seq::PortCap::SYNC_WRITE
+            | seq::PortCap::DUPLEX,
+        );
+        me.set_type(seq::PortType::MIDI_GENERIC | seq::PortType::APPLICATION | seq::PortType::PORT);
+        me.set_name(SELF_NAME);
+
+        seq.create_port(&me)?;
+
+        let me = me.addr();
+        Ok((seq, me))
+    }
+
+    /// Sequencer address of the device port.
+    pub fn addr(&self) -> seq::Addr {
+        self.volca
+    }
+
+    /// Waits until there is an event of any kind. Returns `false` on timeout.
+    fn poll_input(&self, timeout: Duration) -> Result<bool> {
+        if self.seq.input().event_input_pending(true)? > 0 {
+            return Ok(true);
+        }
+
+        let mut fds = (&self.seq, Some(alsa::Direction::Capture)).get()?;
+        let timeout = timeout.as_millis().try_into().unwrap_or(i32::MAX);
+        Ok(alsa::poll::poll(&mut fds, timeout)? > 0)
+    }
+
+    pub fn connect(&mut self) -> Result<()> {
+        self.connect_with_timeout(None).map(drop)
+    }
+
+    /// Connects to the device and returns its discovery reply.
+    ///
+    /// Fails if the device does not reply during `timeout`.
+    pub fn connect_with_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<proto::SearchDeviceReply> {
+        if !self.subscribed {
+            self.seq.set_client_pool_input(self.input_pool)?;
+
+            let sub = seq::PortSubscribe::empty()?;
+            sub.set_sender(self.volca);
+            sub.set_dest(self.me);
+            self.seq.subscribe_port(&sub)?;
+
+            let sub = seq::PortSubscribe::empty()?;
+            sub.set_sender(self.me);
+            sub.set_dest(self.volca);
+            self.seq.subscribe_port(&sub)?;
+
+            // Announces when the device goes away
+            let sub = seq::PortSubscribe::empty()?;
+            sub.set_sender(seq::Addr::system_announce());
+            sub.set_dest(self.me);
+            self.seq.subscribe_port(&sub)?;
+            self.subscribed = true;
+        }
+
+        let response = self.search_device(timeout)?;
+        info!(
+            addr = %format_addr(self.volca), global_channel = %response.device_id,
+            version = %response.version, "connected to volca sample 2"
+        );
+        self.channel = response.device_id;
+        self.version = Some(response.version);
+        Ok(response)
+    }
+
+    /// Connects with a short timeout and reads the sample memory use, to tell several devices
+    /// apart.
+    pub fn probe(&mut self) -> Candidate {
+        let addr = format_addr(self.volca);
+        probe(self, addr, Self::connect_with_timeout)
+    }
+
+    /// Whether the device replies to the discovery request, when probing all ports.
+    pub fn answers(&mut self) -> bool {
+        let addr = format_addr(self.volca);
+        answers(self, &addr, Self::connect_with_timeout)
+    }
+
+    /// Firmware version reported by the device. Available after connecting.
+    pub fn version(&self) -> Option<proto::Version> {
+        self.version
+    }
+}
+
+/// Sequencer client that is told when clients and ports come and go.
+pub struct Announcements {
+    seq: seq::Seq,
+}
+
+impl Announcements {
+    pub fn new() -> Result<Self> {
+        let (seq, me) = Device::open_seq()?;
+        let sub = seq::PortSubscribe::empty()?;
+        sub.set_sender(seq::Addr::system_announce());
+        sub.set_dest(me);
+        seq.subscribe_port(&sub)?;
+        Ok(Self { seq })
+    }
+
+    /// Waits up to `timeout` for an announcement and discards the received ones.
+    pub fn wait(&self, timeout: Duration) -> Result<()> {
+        let mut fds = (&self.seq, Some(alsa::Direction::Capture)).get()?;
+        let timeout = timeout.as_millis().try_into().unwrap_or(i32::MAX);
+        alsa::poll::poll(&mut fds, timeout)?;
+        let mut input = self.seq.input();
+        while input.event_input_pending(true)? > 0 {
+            let event = input.event_input()?;
+            trace!(kind = ?event.get_type(), "announcement");
+        }
+        Ok(())
+    }
+}
+
+impl VolcaDevice for Device {
+    fn open(options: &ConnectOptions) -> Result<Self> {
+        let devices = find_devices(
+            options,
+            |spec| {
+                let mut devices = Self::new(options.chunk_cooldown, spec)?;
+                for device in &mut devices {
+                    device.midi_log.clone_from(&options.midi_log);
+                    device.input_pool = options.input_pool.unwrap_or_else(default_input_pool);
+                }
+                Ok(devices)
+            },
+            Self::answers,
+        )?;
+        let mut device = select(devices, options.selection, Self::probe)?;
+        if device.version.is_none() {
+            device.connect()?;
+        }
+        device.pacer = Pacer::new(options.pacing, options.chunk_cooldown);
+        device.header_window = options.header_window();
+        device.set_retry_policy(options.retry_policy);
+        Ok(device)
+    }
+
+    fn channel(&self) -> U7 {
+        self.channel
+    }
+
+    fn pacer(&self) -> Option<&Pacer> {
+        Some(&self.pacer)
+    }
+
+    fn midi_log(&self) -> Option<&MidiLog> {
+        self.midi_log.as_deref()
+    }
+
+    fn header_window(&self) -> usize {
+        self.header_window
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    fn send_chunk(&self, chunk: &[u8]) -> Result<()> {
+        let mut event = seq::Event::new_ext(seq::EventType::Sysex, chunk);
+
+        trace!(len = chunk.len(), raw = ?hexbuf(chunk), "send chunk");
+
+        event.set_source(self.me.port);
+        event.set_direct();
+        event.set_priority(true);
+        event.set_dest(self.volca);
+
+        self.seq
+            .event_output_direct(&mut event)
+            .map_err(transfer_error)?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.seq.sync_output_queue().map_err(transfer_error)?;
+        self.seq.drain_output().map_err(transfer_error)?;
+        Ok(())
+    }
+
+    fn wait_for_input(&self, timeout: Duration) -> Result<bool> {
+        self.deadline.set(Some((Instant::now() + timeout, timeout)));
+        self.poll_input(timeout)
+    }
+
+    /// Receives a message, skipping other events such as announcements of the system port.
+    ///
+    /// When those wake up [`wait_for_input`](VolcaDevice::wait_for_input), the wait goes on only
+    /// for the rest of its timeout, as the sequencer blocks until an event arrives.
+    fn receive_raw_with_progress(&self, progress: &mut dyn ProgressSink) -> Result<Vec<u8>> {
+        let mut deadline = self.deadline.take();
+        let mut reassembly = Reassembly::new(self.volca, self.me);
+        loop {
+            if let Some((deadline, timeout)) = deadline {
+                if !self.poll_input(deadline.saturating_duration_since(Instant::now()))? {
+                    return Err(DeviceError::Timeout(timeout));
+                }
+            }
+
+            // The input is borrowed only while copying the event so that `progress` may use the
+            // sequencer.
+            let mut input = self.seq.input();
+            let event = input
+                .event_input()
+                .map_err(|err| match err.errno() as i32 {
+                    ENOSPC => DeviceError::InputOverrun {
+                        pool: self.input_pool,
+                    },
+                    _ => transfer_error(err),
+                })?;
+            match event.get_type() {
+                seq::EventType::Sysex => {}
+                seq::EventType::ClientExit | seq::EventType::PortExit => {
+                    let exited = event.get_data::<seq::Addr>();
+                    let gone = exited.is_some_and(|addr| match event.get_type() {
+                        seq::EventType::ClientExit => addr.client == self.volca.client,
+                        _ => addr == self.volca,
+                    });
+                    if gone {
+                        return Err(DeviceError::Disconnected);
+                    }
+                    continue;
+                }
+                _ => continue,
+            }
+            // The message has started, the rest of it arrives as fast as the device sends it
+            deadline = None;
+
+            let chunk = event.get_ext().ok_or(DeviceError::EmptySysEx)?;
+            trace!(raw = ?hexbuf(chunk), len = chunk.len(), "recv chunk");
+            if let Some(log) = &self.midi_log {
+                log.chunk(Flow::Received, chunk);
+            }
+            let message = reassembly.push(event.get_source(), event.get_dest(), chunk)?;
+            drop(input);
+            if let Some(data) = message {
+                if let Some(log) = &self.midi_log {
+                    log.message(Flow::Received, &data);
+                }
+                progress.progress(data.len(), None);
+                progress.finished();
+                return Ok(data);
+            }
+            progress.progress(reassembly.len(), None);
+        }
+    }
+}
+
+/// Error ALSA returns when events were dropped because the input pool was full.
+const ENOSPC: i32 = 28;
+
+/// Tells errors caused by the device going away apart from other failures.
+fn transfer_error(err: alsa::Error) -> DeviceError {
+    const ENOENT: i32 = 2;
+    const ENXIO: i32 = 6;
+    const ENODEV: i32 = 19;
+
+    match err.errno() as i32 {
+        ENOENT | ENXIO | ENODEV => DeviceError::Disconnected,
+        _ => DeviceError::Alsa(err),
+    }
+}
+
+/// Collects the SysEx chunks of one message from the device.
+///
+/// Chunks from other clients, which may send SysEx to our port at any time, are discarded.
+struct Reassembly {
+    volca: seq::Addr,
+    me: seq::Addr,
+    data: Vec<u8>,
+}
+
+impl Reassembly {
+    fn new(volca: seq::Addr, me: seq::Addr) -> Self {
+        Self {
+            volca,
+            me,
+            data: Vec::new(),
+        }
+    }
+
+    /// Number of bytes collected so far.
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Adds a chunk sent from `source` to `dest`. Returns the message once its last chunk is
+    /// added.
+    ///
+    /// Fails if the device starts a new message before ending the current one.
+    fn push(
+        &mut self,
+        source: seq::Addr,
+        dest: seq::Addr,
+        chunk: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        if source != self.volca || dest != self.me {
+            warn!(
+                source = %format_addr(source), len = chunk.len(),
+                "discarding SysEx from another client"
+            );
+            return Ok(None);
+        }
+        match chunk.first() {
+            Some(&proto::EST) if !self.data.is_empty() => {
+                let received = mem::take(&mut self.data).len();
+                return Err(DeviceError::UnterminatedSysEx { received });
+            }
+            Some(&proto::EST) => {}
+            _ if self.data.is_empty() => {
+                warn!(
+                    len = chunk.len(),
+                    "discarding SysEx continuation without a start"
+                );
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        self.data.extend_from_slice(chunk);
+        Ok(self
+            .data
+            .ends_with(&[proto::EOX])
+            .then(|| mem::take(&mut self.data)))
+    }
+}
+
+impl DeviceSpec {
+    pub fn matches(&self, port: &PortDescription) -> bool {
+        match self {
+            Self::Client(name) => port.client_name == *name,
+            Self::Addr {
+                client,
+                port: port_no,
+            } => port.addr.client == *client && port.addr.port == *port_no,
+            Self::Name(name) => {
+                let name = name.to_lowercase();
+                port.client_name.to_lowercase().contains(&name)
+                    || port.port_name.to_lowercase().contains(&name)
+            }
+            // The system client only announces, it never talks MIDI
+            Self::Any => {
+                port.addr.client != seq::Addr::system_timer().client
+                    && port.capability.contains(
+                        seq::PortCap::READ
+                            | seq::PortCap::WRITE
+                            | seq::PortCap::SUBS_READ
+                            | seq::PortCap::SUBS_WRITE,
+                    )
+            }
+        }
+    }
+}
+
+/// Finds ports selected by `spec`, at most one per client unless any port is selected.
+fn find_ports(seq: &seq::Seq, spec: &DeviceSpec) -> Result<Vec<seq::Addr>> {
+    let me = seq.client_id()?;
+    let ports = list_ports(seq)
+        .into_iter()
+        .filter(|port| port.addr.client != me)
+        .collect::<Vec<_>>();
+    let mut found = ports
+        .iter()
+        .filter(|port| {
+            trace!(?port.addr, %port.client_name, "trying port");
+            spec.matches(port)
+        })
+        .map(|port| port.addr)
+        .collect::<Vec<_>>();
+    if *spec != DeviceSpec::Any {
+        found.dedup_by_key(|addr| addr.client);
+    }
+    if !found.is_empty() {
+        return Ok(found);
+    }
+
+    Err(match spec {
+        DeviceSpec::Client(client_name) => {
+            let client_exists = seq::ClientIter::new(seq)
+                .any(|client| client.get_name().is_ok_and(|name| name == client_name));
+            if client_exists {
+                DeviceError::NoPort
+            } else {
+                DeviceError::ClientNotFound(Box::new(ClientScan::new(seq, client_name)))
+            }
+        }
+        _ => DeviceError::NoMatchingPort {
+            spec: spec.to_string(),
+            available: ports
+                .iter()
+                .map(|port| format!("  {port}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        },
+    })
+}
+
+/// Sequencer clients seen while looking for the device, to tell the user what is connected.
+#[derive(Debug, Clone)]
+pub struct ClientScan {
+    /// Client name that was looked for.
+    pub client_name: String,
+    pub clients: Vec<ScannedClient>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScannedClient {
+    pub id: i32,
+    pub name: String,
+    pub ports: Vec<PortDescription>,
+}
+
+impl ClientScan {
+    /// Lists all sequencer clients except our own.
+    fn new(seq: &seq::Seq, client_name: &str) -> Self {
+        let me = seq.client_id().ok();
+        let ports = list_ports(seq);
+        let clients = seq::ClientIter::new(seq)
+            .filter(|client| Some(client.get_client()) != me)
+            .map(|client| ScannedClient {
+                id: client.get_client(),
+                name: client.get_name().unwrap_or_default().to_owned(),
+                ports: ports
+                    .iter()
+                    .filter(|port| port.addr.client == client.get_client())
+                    .cloned()
+                    .collect(),
+            })
+            .collect();
+        Self {
+            client_name: client_name.to_owned(),
+            clients,
+        }
+    }
+
+    /// Ports that look like they belong to a Korg device.
+    fn korg_ports(&self) -> impl Iterator<Item = &PortDescription> {
+        self.clients
+            .iter()
+            .flat_map(|client| &client.ports)
+            .filter(|port| {
+                let names = format!("{} {}", port.client_name, port.port_name).to_lowercase();
+                names.contains("korg") || names.contains("volca")
+            })
+    }
+}
+
+impl fmt::Display for ClientScan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "could not find volca sample ({:?} client)",
+            self.client_name
+        )?;
+        if self.clients.is_empty() {
+            writeln!(f, "The sequencer shows no clients at all.")?;
+        } else {
+            writeln!(f, "Sequencer clients:")?;
+        }
+        for client in &self.clients {
+            writeln!(f, "  {:<3} {}", client.id, client.name)?;
+            for port in &client.ports {
+                writeln!(
+                    f,
+                    "      {:<7} {} ({})",
+                    port.spec(),
+                    port.port_name,
+                    describe_capability(port.capability)
+                )?;
+            }
+        }
+
+        let mut korg = self.korg_ports().peekable();
+        if korg.peek().is_some() {
+            writeln!(
+                f,
+                "These ports look like a Korg device, select one with --device <client:port>:"
+            )?;
+            for port in korg {
+                writeln!(f, "  {port}")?;
+            }
+        } else {
+            writeln!(f, "No Korg device was seen.")?;
+        }
+        write!(
+            f,
+            "Check that:\n\
+             - the volca is switched on, and connected with a USB cable that carries data\n\
+             - the volca has finished starting up and is not in a firmware update mode\n\
+             - behind a USB MIDI adapter, the port is found with --device-name or --probe-all\n\
+             - you may open /dev/snd/seq, usually by being a member of the audio group"
+        )
+    }
+}
+
+/// Readable port capabilities, e.g. `read, write`.
+fn describe_capability(capability: seq::PortCap) -> String {
+    let mut words = Vec::new();
+    if capability.contains(seq::PortCap::READ) {
+        words.push("read");
+    }
+    if capability.contains(seq::PortCap::WRITE) {
+        words.push("write");
+    }
+    if !capability.intersects(seq::PortCap::SUBS_READ | seq::PortCap::SUBS_WRITE) {
+        words.push("no subscriptions");
+    }
+    if words.is_empty() {
+        "no access".to_owned()
+    } else {
+        words.join(", ")
+    }
+}
+
+fn format_addr(addr: seq::Addr) -> String {
+    format!("{}:{}", addr.client, addr.port)
+}
+
+/// Sequencer port description.
+#[derive(Debug, Clone)]
+pub struct PortDescription {
+    pub addr: seq::Addr,
+    pub client_name: String,
+    pub port_name: String,
+    pub capability: seq::PortCap,
+}
+
+impl PortDescription {
+    /// Spec selecting this port, as accepted by `--device`.
+    pub fn spec(&self) -> DeviceSpec {
+        DeviceSpec::Addr {
+            client: self.addr.client,
+            port: self.addr.port,
+        }
+    }
+}
+
+impl fmt::Display for PortDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<7} {} - {}",
+            self.spec(),
+            self.client_name,
+            self.port_name
+        )
+    }
+}
+
+/// Lists all ports visible to the sequencer.
+pub fn list_ports(seq: &seq::Seq) -> Vec<PortDescription> {
+    seq::ClientIter::new(seq)
+        .flat_map(|client| {
+            let client_name = client.get_name().unwrap_or_default().to_owned();
+            seq::PortIter::new(seq, client.get_client()).map(move |port| PortDescription {
+                addr: port.addr(),
+                client_name: client_name.clone(),
+                port_name: port.get_name().unwrap_or_default().to_owned(),
+                capability: port.get_capability(),
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn interleaved_sysex() {
+    use proto::{EOX, EST};
+
+    let volca = seq::Addr {
+        client: 24,
+        port: 0,
+    };
+    let me = seq::Addr {
+        client: 128,
+        port: 0,
+    };
+    let other = seq::Addr {
+        client: 130,
+        port: 0,
+    };
+
+    let mut reassembly = Reassembly::new(volca, me);
+    let events: [(seq::Addr, &[u8]); 5] = [
+        (other, &[0x33, 0x44, EOX]),
+        (volca, &[EST, 0x42, 0x30]),
+        (other, &[EST, 0x7E, 0x01, EOX]),
+        (volca, &[0x00, 0x01]),
+        (other, &[0x55]),
+    ];
+    for (source, chunk) in events {
+        assert_eq!(reassembly.push(source, me, chunk).unwrap(), None);
+    }
+    assert_eq!(reassembly.len(), 5);
+    assert_eq!(
+        reassembly.push(volca, me, &[0x2D, EOX]).unwrap(),
+        Some(vec![EST, 0x42, 0x30, 0x00, 0x01, 0x2D, EOX])
+    );
+
+    // Continuation of a message that was not started is dropped
+    assert_eq!(reassembly.push(volca, me, &[0x01, EOX]).unwrap(), None);
+
+    assert_eq!(reassembly.push(volca, me, &[EST, 0x42]).unwrap(), None);
+    assert!(matches!(
+        reassembly.push(volca, me, &[EST, 0x42, EOX]),
+        Err(DeviceError::UnterminatedSysEx { received: 2 })
+    ));
+    assert_eq!(reassembly.len(), 0);
+}
+
+#[test]
+fn input_pool() {
+    // A dump of the whole memory takes far more than the pool can hold
+    assert_eq!(default_input_pool(), MAX_INPUT_POOL);
+    let overrun = DeviceError::InputOverrun { pool: 512 };
+    assert!(overrun
+        .to_string()
+        .ends_with("try a larger --input-pool (up to 2000)"));
+}
+
+#[test]
+fn client_scan_display() {
+    let port =
+        |client: i32, client_name: &str, port: i32, port_name: &str, capability| PortDescription {
+            addr: seq::Addr { client, port },
+            client_name: client_name.into(),
+            port_name: port_name.into(),
+            capability,
+        };
+    let rw = seq::PortCap::READ
+        | seq::PortCap::WRITE
+        | seq::PortCap::SUBS_READ
+        | seq::PortCap::SUBS_WRITE;
+    let mut scan = ClientScan {
+        client_name: "volca sample".into(),
+        clients: vec![
+            ScannedClient {
+                id: 0,
+                name: "System".into(),
+                ports: vec![port(0, "System", 1, "Announce", seq::PortCap::READ)],
+            },
+            ScannedClient {
+                id: 24,
+                name: "volca sample 2".into(),
+                ports: vec![port(24, "volca sample 2", 0, "volca sample 2 MIDI 1", rw)],
+            },
+        ],
+    };
+    let text = scan.to_string();
+    assert!(text.starts_with("could not find volca sample (\"volca sample\" client)\n"));
+    assert!(text.contains("\n  0   System\n      0:1     Announce (read, no subscriptions)\n"));
+    assert!(text.contains("\n      24:0    volca sample 2 MIDI 1 (read, write)\n"));
+    assert!(text.contains("look like a Korg device"));
+    assert!(text.contains("\n  24:0    volca sample 2 - volca sample 2 MIDI 1\n"));
+    assert!(text.contains("/dev/snd/seq"));
+
+    scan.clients.truncate(1);
+    let text = scan.to_string();
+    assert!(text.contains("No Korg device was seen."));
+}
@@ -0,0 +1,320 @@
+//! Connectivity diagnostics.
+
+use std::fmt::Display;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use alsa::seq;
+
+#[cfg(feature = "midir")]
+use crate::device::{list_midir_ports, MidirDevice};
+#[cfg(target_os = "linux")]
+use crate::device::{list_ports, list_rawmidi_ports, Device, RawMidiDevice};
+use crate::device::{Backend, ConnectOptions, DeviceError, DeviceSpec, VolcaDevice};
+use crate::proto;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Runs all checks and prints the results. Returns `true` if all checks of the selected backend
+/// have passed.
+///
+/// All backends are checked, problems with the other ones are only reported.
+pub fn run(options: &ConnectOptions) -> bool {
+    #[cfg(target_os = "linux")]
+    let seq = check_sequencer(options.backend == Backend::Seq);
+    #[cfg(target_os = "linux")]
+    let rawmidi = check_rawmidi(&options.spec, options.backend == Backend::RawMidi);
+    #[cfg(feature = "midir")]
+    let midir = check_midir(&options.spec, options.backend == Backend::Midir);
+    match options.backend {
+        #[cfg(target_os = "linux")]
+        Backend::Seq => {
+            seq.is_some_and(|seq| check_ports(&seq, &options.spec))
+                && check_handshake(options.chunk_cooldown, &options.spec)
+        }
+        #[cfg(target_os = "linux")]
+        Backend::RawMidi => {
+            rawmidi && check_rawmidi_handshake(options.chunk_cooldown, &options.spec)
+        }
+        #[cfg(feature = "midir")]
+        Backend::Midir => midir && check_midir_handshake(options.chunk_cooldown, &options.spec),
+    }
+}
+
+fn pass(message: impl Display) {
+    println!("[PASS] {message}");
+}
+
+fn fail(message: impl Display, remedies: &[&str]) {
+    println!("[FAIL] {message}");
+    for remedy in remedies {
+        println!("       - {remedy}");
+    }
+}
+
+/// Reports a failure of a check that only matters for the other backend.
+fn note(message: impl Display) {
+    println!("[INFO] {message}");
+}
+
+/// Reports a failed check, as a failure only if it is `required`.
+fn report(required: bool, message: impl Display, remedies: &[&str]) {
+    if required {
+        fail(message, remedies);
+    } else {
+        note(message);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_sequencer(required: bool) -> Option<seq::Seq> {
+    match seq::Seq::open(None, None, false) {
+        Ok(seq) => {
+            pass("sequencer backend is available");
+            Some(seq)
+        }
+        Err(err) => {
+            report(
+                required,
+                format_args!("could not open ALSA sequencer: {err}"),
+                &[
+                    "make sure /dev/snd/seq exists (the snd-seq kernel module is loaded)",
+                    "make sure your user is in the audio group",
+                ],
+            );
+            None
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_rawmidi(spec: &DeviceSpec, required: bool) -> bool {
+    let ports = list_rawmidi_ports();
+    if ports.is_empty() {
+        report(
+            required,
+            "rawmidi backend is not available: no rawmidi devices found",
+            &[
+                "make sure the snd-usb-audio kernel module is loaded",
+                "make sure your user is in the audio group",
+            ],
+        );
+        return false;
+    }
+
+    pass("rawmidi backend is available");
+    println!("Visible rawmidi devices:");
+    for port in &ports {
+        println!("  {} {port}", if port.matches(spec) { "*" } else { " " });
+    }
+    match ports.iter().find(|port| port.matches(spec)) {
+        Some(port) => {
+            pass(format_args!(
+                r#""{spec}" corresponds to rawmidi device {}"#,
+                port.name
+            ));
+            true
+        }
+        None => {
+            report(
+                required,
+                format_args!("no rawmidi device matches {:?}", spec.to_string()),
+                &["pass one of the hw: names listed above to --device"],
+            );
+            false
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_ports(seq: &seq::Seq, spec: &DeviceSpec) -> bool {
+    let ports = list_ports(seq);
+    println!("Visible sequencer ports:");
+    for port in &ports {
+        let is_volca = port.client_name.to_lowercase().contains("volca")
+            || port.port_name.to_lowercase().contains("volca");
+        println!(
+            "  {} {port} [{:?}]",
+            if is_volca { "*" } else { " " },
+            port.capability
+        );
+    }
+
+    let DeviceSpec::Client(client_name) = spec else {
+        return match ports.iter().find(|port| spec.matches(port)) {
+            Some(port) => {
+                pass(format_args!(r#""{spec}" selects port {port}"#));
+                true
+            }
+            None => {
+                fail(
+                    format_args!("no port matches {:?}", spec.to_string()),
+                    &["pass one of the addresses listed above to --device"],
+                );
+                false
+            }
+        };
+    };
+
+    if ports.iter().any(|port| port.client_name == *client_name) {
+        pass(format_args!(r#"found "{client_name}" client"#));
+        true
+    } else if ports
+        .iter()
+        .any(|port| port.client_name.to_lowercase().contains("volca"))
+    {
+        fail(
+            format_args!(r#"found a volca, but no client is named "{client_name}""#),
+            &["make sure the connected device is a Volca Sample 2"],
+        );
+        false
+    } else {
+        fail(
+            format_args!(r#"could not find "{client_name}" client"#),
+            &[
+                "make sure the device is powered on and connected with a USB data cable",
+                "try another USB port or cable",
+            ],
+        );
+        false
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_handshake(chunk_cooldown: Duration, spec: &DeviceSpec) -> bool {
+    let devices = match Device::new(chunk_cooldown, spec) {
+        Ok(devices) => devices,
+        Err(err) => return report_handshake("device", Err(err)),
+    };
+    // Every device is checked, even after a failure
+    let results = devices
+        .into_iter()
+        .map(|mut device| {
+            let addr = device.addr();
+            let addr = format!("{}:{}", addr.client, addr.port);
+            check_device_handshake(&addr, &mut device, Device::connect_with_timeout)
+        })
+        .collect::<Vec<_>>();
+    results.into_iter().all(|passed| passed)
+}
+
+#[cfg(target_os = "linux")]
+fn check_rawmidi_handshake(chunk_cooldown: Duration, spec: &DeviceSpec) -> bool {
+    let results = list_rawmidi_ports()
+        .into_iter()
+        .filter(|port| port.matches(spec))
+        .map(
+            |port| match RawMidiDevice::new(chunk_cooldown, &port.name) {
+                Ok(mut device) => check_device_handshake(
+                    &port.name,
+                    &mut device,
+                    RawMidiDevice::connect_with_timeout,
+                ),
+                Err(err) => report_handshake(&port.name, Err(err)),
+            },
+        )
+        .collect::<Vec<_>>();
+    results.into_iter().all(|passed| passed)
+}
+
+#[cfg(feature = "midir")]
+fn check_midir(spec: &DeviceSpec, required: bool) -> bool {
+    let ports = match list_midir_ports() {
+        Ok(ports) => ports,
+        Err(err) => {
+            report(
+                required,
+                format_args!("midir backend is not available: {err}"),
+                &["make sure the system MIDI service is running"],
+            );
+            return false;
+        }
+    };
+
+    pass("midir backend is available");
+    println!("Visible MIDI ports:");
+    for port in &ports {
+        println!("  {} {port}", if port.matches(spec) { "*" } else { " " });
+    }
+    if ports.iter().any(|port| port.matches(spec)) {
+        pass(format_args!(r#"found a port matching "{spec}""#));
+        true
+    } else {
+        report(
+            required,
+            format_args!(r#"no MIDI port matches "{spec}""#),
+            &[
+                "make sure the device is powered on and connected with a USB data cable",
+                "pass a part of one of the port names listed above to --device",
+            ],
+        );
+        false
+    }
+}
+
+#[cfg(feature = "midir")]
+fn check_midir_handshake(chunk_cooldown: Duration, spec: &DeviceSpec) -> bool {
+    let ports = match list_midir_ports() {
+        Ok(ports) => ports,
+        Err(err) => return report_handshake("device", Err(err)),
+    };
+    let results = ports
+        .iter()
+        .filter(|port| port.matches(spec))
+        .map(|port| match MidirDevice::new(chunk_cooldown, port) {
+            Ok(mut device) => {
+                check_device_handshake(&port.name, &mut device, MidirDevice::connect_with_timeout)
+            }
+            Err(err) => report_handshake(&port.name, Err(err)),
+        })
+        .collect::<Vec<_>>();
+    results.into_iter().all(|passed| passed)
+}
+
+/// Performs the handshake with `connect`. If it fails, asks for the universal identity to tell
+/// what is listening on the port.
+fn check_device_handshake<T: VolcaDevice>(
+    addr: &str,
+    device: &mut T,
+    connect: fn(&mut T, Option<Duration>) -> Result<proto::SearchDeviceReply, DeviceError>,
+) -> bool {
+    let passed = report_handshake(addr, connect(device, Some(HANDSHAKE_TIMEOUT)));
+    if !passed {
+        match device.identify(Some(HANDSHAKE_TIMEOUT)) {
+            Ok(identity) if identity.is_volca_sample_2() => note(format_args!(
+                "{addr} identifies as Volca Sample 2 with firmware version {}",
+                identity.korg_version()
+            )),
+            Ok(identity) => note(format_args!(
+                "{addr} identifies as another device: {identity}"
+            )),
+            Err(err) => note(format_args!(
+                "{addr} did not reply to the identity request either: {err:#}"
+            )),
+        }
+    }
+    passed
+}
+
+fn report_handshake(addr: &str, result: Result<proto::SearchDeviceReply, DeviceError>) -> bool {
+    match result {
+        Ok(reply) => {
+            pass(format_args!(
+                "{addr} replied: firmware version {}, global channel {}",
+                reply.version, reply.device_id
+            ));
+            true
+        }
+        Err(err) => {
+            fail(
+                format_args!("handshake with {addr} failed: {err:#}"),
+                &[
+                    "make sure the device is not in the middle of another operation",
+                    "make sure the device is not in sequencer mode",
+                    "power-cycle the device and try again",
+                ],
+            );
+            false
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Process exit codes.
+
+use std::error::Error;
+use std::io;
+use std::iter;
+
+use crate::audio::AudioError;
+use crate::device::DeviceError;
+use crate::proto::{NakStatus, ParseError};
+use crate::syx::SyxError;
+use crate::util::{Aborted, Interrupted};
+use crate::AppError;
+
+/// Any other failure.
+pub const FAILURE: u8 = 1;
+/// Device was not found, or communication with it failed.
+pub const DEVICE: u8 = 2;
+/// Device refused to perform an operation.
+pub const DEVICE_NAK: u8 = 3;
+/// Local file could not be read or written, or audio could not be processed.
+pub const LOCAL: u8 = 4;
+/// User declined a confirmation prompt.
+pub const ABORTED: u8 = 5;
+/// Interrupted with Ctrl-C, the same code shells report for processes killed by SIGINT.
+pub const INTERRUPTED: u8 = 130;
+
+pub const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0    Success
+  1    Other failure
+  2    Device not found or connection failure
+  3    Device refused the operation
+  4    Local file or audio error
+  5    Aborted at a confirmation prompt
+  130  Interrupted with Ctrl-C";
+
+/// Errors in the chain of `err`. An [`AppError`] is replaced by the error it wraps, so that it can
+/// be told apart by its type.
+pub fn causes<'a>(
+    err: &'a (dyn Error + 'static),
+) -> impl Iterator<Item = &'a (dyn Error + 'static)> {
+    iter::successors(Some(err), |&cause| cause.source()).map(|cause| {
+        cause
+            .downcast_ref::<AppError>()
+            .map_or(cause, AppError::inner)
+    })
+}
+
+/// Chooses exit code by the first error in the chain that belongs to a known category.
+pub fn code(err: &(dyn Error + 'static)) -> u8 {
+    causes(err)
+        .find_map(|cause| {
+            if cause.is::<Aborted>() {
+                Some(ABORTED)
+            } else if cause.is::<Interrupted>() {
+                Some(INTERRUPTED)
+            } else if cause.is::<SyxError>() {
+                // Checked before `ParseError`, which it wraps
+                Some(LOCAL)
+            } else if let Some(err) = cause.downcast_ref::<DeviceError>() {
+                match err {
+                    DeviceError::Nak { .. } | DeviceError::RetriesExhausted { .. } => {
+                        Some(DEVICE_NAK)
+                    }
+                    DeviceError::Interrupted => Some(INTERRUPTED),
+                    _ => Some(DEVICE),
+                }
+            } else if cause.is::<NakStatus>() {
+                Some(DEVICE_NAK)
+            } else if is_alsa_error(cause) || cause.is::<ParseError>() {
+                Some(DEVICE)
+            } else if cause.is::<AudioError>()
+                || cause.is::<hound::Error>()
+                || cause.is::<io::Error>()
+            {
+                Some(LOCAL)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(FAILURE)
+}
+
+#[cfg(target_os = "linux")]
+fn is_alsa_error(err: &(dyn Error + 'static)) -> bool {
+    err.is::<alsa::Error>()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_alsa_error(_: &(dyn Error + 'static)) -> bool {
+    false
+}
+
+#[test]
+fn error_codes() {
+    use anyhow::{anyhow, Context};
+
+    let not_found = anyhow!(DeviceError::NotFound("volca sample".into()));
+    assert_eq!(code(&*not_found), DEVICE);
+    let refused = anyhow!(DeviceError::Nak {
+        request: "sample data for slot 3".into(),
+        status: NakStatus::SampleFull,
+    });
+    assert_eq!(code(&*refused), DEVICE_NAK);
+
+    let nak = Err::<(), _>(NakStatus::SampleFull)
+        .context("could not upload sample")
+        .unwrap_err();
+    assert_eq!(code(&*nak), DEVICE_NAK);
+
+    let io = anyhow!(io::Error::from(io::ErrorKind::NotFound));
+    assert_eq!(code(&*io), LOCAL);
+    let audio = anyhow!(AudioError::Io(io::Error::from(io::ErrorKind::NotFound)));
+    assert_eq!(code(&*audio), LOCAL);
+
+    let aborted = anyhow!(Aborted("aborted".into()));
+    assert_eq!(code(&*aborted), ABORTED);
+    let interrupted = anyhow!(Interrupted("waiting for the device"));
+    assert_eq!(code(&*interrupted), INTERRUPTED);
+    let interrupted = anyhow!(DeviceError::Interrupted).context("could not upload sample");
+    assert_eq!(code(&*interrupted), INTERRUPTED);
+
+    assert_eq!(code(&*anyhow!("could not find empty slot")), FAILURE);
+
+    // Errors of the app are told apart by the error they wrap
+    let app = AppError::from(io::Error::from(io::ErrorKind::NotFound));
+    assert_eq!(code(&app), LOCAL);
+    let app = anyhow!(AppError::from(DeviceError::Interrupted)).context("stopped");
+    assert_eq!(code(&*app), INTERRUPTED);
+}
@@ -1,121 +1,1041 @@
+#[cfg(not(any(target_os = "linux", feature = "midir")))]
+compile_error!("the ALSA backends only work on Linux, enable the `midir` feature elsewhere");
+
 mod audio;
+mod config;
 mod device;
+mod doctor;
+mod exit;
+mod monitor;
 mod opt;
+mod playback;
 mod proto;
 mod seven_bit;
+mod shell;
+mod syx;
+#[cfg(feature = "tui")]
+mod tui;
 mod util;
+mod watch;
 
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, IsTerminal};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
+use thiserror::Error;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+use crate::audio::{
+    apply_filter, apply_limiter, normalize_loudness, resample_i16, write_sample, AudioError,
+    AudioReader, LoudnessTarget, Measurement, MonoMode, OutputFormat, Pass, Resampling, Signal,
+    ECONOMY_SAMPLERATE, VOLCA_SAMPLERATE,
+};
+use crate::config::Config;
+use crate::device::{
+    AnyDevice, ConnectOptions, DeviceError, DeviceSelection, DeviceSpec, DeviceWatcher, MidiLog,
+    RetryPolicy, VolcaDevice, Wait,
+};
+use crate::opt::{SampleSelector, SortKey};
+use crate::proto::SampleNo;
+use crate::util::{
+    ask, ask_with_default, escape_porcelain, extract_file_name, format_duration, glob_match,
+    interrupted, is_glob, normalize_path, resolve_conflict, sample_hash, sanitize_sample_name,
+    split_evenly, Aborted, CatchInterrupt, Interrupted, OnConflict, TransferProgress,
+};
+use crate::watch::FileWatcher;
+
+/// Name of the file that maps pattern numbers to pattern files.
+const PATTERN_INDEX_FILE: &str = "patterns.yaml";
+const DEFAULT_CHUNK_COOLDOWN: Duration = Duration::from_millis(10);
+/// Longest pause between looking for the device with `--wait`.
+const WAIT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Options shared by uploads of all files.
+struct UploadOptions {
+    mono_mode: MonoMode,
+    output: Option<PathBuf>,
+    dry_run: bool,
+    listen: bool,
+    replace_by_name: bool,
+    strict: bool,
+    from_slot: SampleNo,
+    no_wrap: bool,
+    skip_identical: bool,
+    deep: bool,
+    sample_rate: u32,
+    speed: proto::Speed,
+    level: proto::Level,
+    strict_names: bool,
+    ignore_space: bool,
+    processing: Processing,
+}
+
+/// Processing applied to the mono signal on its way to the device.
+#[derive(Default)]
+struct Processing {
+    highpass: Option<u32>,
+    lowpass: Option<u32>,
+    loudness: Option<LoudnessTarget>,
+    resampling: Resampling,
+    /// Limiter ceiling in dBFS, applied after resampling.
+    limit: Option<f64>,
+}
 
-use crate::audio::{write_sample_to_file, AudioReader, MonoMode};
-use crate::device::Device;
-use crate::util::{ask, extract_file_name, normalize_path};
+struct DownloadOptions {
+    format: OutputFormat,
+    on_conflict: OnConflict,
+    apply_speed: bool,
+    cheap: bool,
+    tolerate_length_mismatch: bool,
+}
 
-struct App {
-    chunk_cooldown: Duration,
-    volca: Option<Device>,
+/// Failure of an [`App`] command. Turned into [`anyhow::Error`] only when the command is run from
+/// the command line.
+#[derive(Debug, Error)]
+enum AppError {
+    #[error(transparent)]
+    Device(#[from] DeviceError),
+    #[error(transparent)]
+    Audio(#[from] AudioError),
+    #[error(transparent)]
+    Header(#[from] proto::SampleHeaderError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Aborted(#[from] Aborted),
+    #[error(transparent)]
+    Interrupted(#[from] Interrupted),
+    /// Any other failure, with the context added along the way.
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
 }
 
+impl AppError {
+    /// The wrapped error, so that it can be told apart by its type.
+    fn inner(&self) -> &(dyn std::error::Error + 'static) {
+        match self {
+            Self::Device(err) => err,
+            Self::Audio(err) => err,
+            Self::Header(err) => err,
+            Self::Io(err) => err,
+            Self::Aborted(err) => err,
+            Self::Interrupted(err) => err,
+            Self::Other(err) => err.as_ref(),
+        }
+    }
+}
+
+type AppResult<T> = std::result::Result<T, AppError>;
+
+struct App<D = AnyDevice> {
+    connect_options: ConnectOptions,
+    volca: Option<D>,
+}
+
+/// Commands that need a real device connection.
 impl App {
-    fn new(chunk_cooldown: Duration) -> Self {
+    fn monitor(&mut self, decode: bool) -> AppResult<()> {
+        Ok(monitor::run(self.volca()?, decode)?)
+    }
+
+    fn shell(&mut self, config: &Config) -> AppResult<()> {
+        let version = self.volca()?.version().expect("connected");
+        Ok(shell::run(self, config, &format!("volca {version}> "))?)
+    }
+}
+
+impl<D: VolcaDevice> App<D> {
+    fn new(connect_options: ConnectOptions) -> Self {
         Self {
-            chunk_cooldown,
+            connect_options,
             volca: None,
         }
     }
 
-    fn volca(&mut self) -> Result<&Device> {
+    #[cfg(test)]
+    fn with_device(device: D) -> Self {
+        Self {
+            connect_options: ConnectOptions {
+                retry_policy: device.retry_policy(),
+                ..ConnectOptions::default()
+            },
+            volca: Some(device),
+        }
+    }
+
+    fn volca(&mut self) -> AppResult<&D> {
         if self.volca.is_none() {
-            let mut volca = Device::new(self.chunk_cooldown)?;
-            volca.connect()?;
+            let volca = match D::open(&self.connect_options) {
+                Err(err) if err.is_missing() && self.connect_options.wait != Wait::No => {
+                    self.wait_for_volca(err)?
+                }
+                volca => volca?,
+            };
             self.volca.replace(volca);
         }
 
         Ok(self.volca.as_ref().unwrap())
     }
 
-    fn list_samples(&mut self, show_empty: bool) -> Result<()> {
-        let volca = self.volca()?;
+    /// Opens the device once it shows up, giving up after the `--wait` timeout or on Ctrl-C.
+    fn wait_for_volca(&self, mut missing: DeviceError) -> AppResult<D> {
+        let deadline = match self.connect_options.wait {
+            Wait::Timeout(timeout) => Some((Instant::now() + timeout, timeout)),
+            _ => None,
+        };
+        let interrupt = CatchInterrupt::new()?;
+        let watcher = DeviceWatcher::new(self.connect_options.backend)?;
+        eprintln!("Waiting for the device to show up, press Ctrl-C to stop");
+        loop {
+            let mut interval = WAIT_INTERVAL;
+            if let Some((deadline, timeout)) = deadline {
+                interval = interval.min(deadline.saturating_duration_since(Instant::now()));
+                if interval.is_zero() {
+                    return Err(anyhow::Error::new(missing)
+                        .context(format!(
+                            "device did not show up within {}",
+                            humantime::format_duration(timeout)
+                        ))
+                        .into());
+                }
+            }
+            let waited = watcher.wait(interval);
+            // Polling fails when interrupted by the signal
+            if interrupt.interrupted() {
+                return Err(Interrupted("waiting for the device").into());
+            }
+            waited?;
+
+            match D::open(&self.connect_options) {
+                Err(err) if err.is_missing() => missing = err,
+                volca => return Ok(volca?),
+            }
+        }
+    }
 
-        volca.send(proto::SampleSpaceDumpRequest)?;
-        let (_, response) = volca.receive::<proto::SampleSpaceDump>()?;
-        println!("Occupied space: {:.1}%", response.occupied() * 100.);
+    fn list_samples(
+        &mut self,
+        show_empty: bool,
+        sort: SortKey,
+        reverse: bool,
+        porcelain: bool,
+        checksum: bool,
+        raw: bool,
+    ) -> AppResult<()> {
+        let volca = self.volca()?;
 
-        let mut last_printed = 0;
-        for header in volca
+        let mut headers = volca
             .iter_sample_headers()
-            .filter(|res| res.as_ref().map_or(true, |header| !header.is_empty()))
-        {
-            let header = header?;
-            if show_empty {
-                for idx in (last_printed + 1)..header.sample_no {
-                    println!("{idx:3}: <EMPTY>");
+            .filter(|res| show_empty || res.as_ref().map_or(true, |header| !header.is_empty()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        headers.sort_by(|a, b| compare_headers(a, b, sort, reverse));
+
+        let mut checksums = BTreeMap::new();
+        if checksum {
+            let total = headers.iter().filter(|header| !header.is_empty()).count();
+            for (idx, header) in headers.iter().filter(|h| !h.is_empty()).enumerate() {
+                eprint!(
+                    "\rDownloading samples to compute checksums: {}/{total}",
+                    idx + 1
+                );
+                let sample = volca.get_sample(header.sample_no)?;
+                checksums.insert(header.sample_no, sample_hash(&sample.data));
+            }
+            if total > 0 {
+                eprintln!();
+            }
+        }
+        let checksum_of = |header: &proto::SampleHeader| {
+            checksums.get(&header.sample_no).map_or("-", String::as_str)
+        };
+
+        if porcelain {
+            for header in &headers {
+                let name = if header.is_empty() {
+                    "-".into()
+                } else {
+                    escape_porcelain(&header.name)
+                };
+                print!(
+                    "{}\t{name}\t{}\t{}\t{}",
+                    header.sample_no, header.length, header.speed, header.level
+                );
+                if checksum {
+                    print!("\t{}", checksum_of(header));
                 }
+                println!();
             }
-            last_printed = header.sample_no;
-            println!(
-                "{:3}: {:24} - length: {:8}, speed: {:5}, level: {:5}",
-                header.sample_no, header.name, header.length, header.speed, header.level
-            );
+            return Ok(());
         }
 
+        for header in &headers {
+            if header.is_empty() {
+                println!("{:3}: <EMPTY>", header.sample_no);
+            } else {
+                let (speed, level) = if raw {
+                    (header.speed.to_string(), header.level.to_string())
+                } else {
+                    (header.speed().to_string(), header.level().to_string())
+                };
+                print!(
+                    "{:3}: {:24} - length: {:8} ({:>7}), speed: {speed:>6}, level: {level:>5}",
+                    header.sample_no,
+                    header.name,
+                    header.length,
+                    format_duration(header.duration()),
+                );
+                if checksum {
+                    print!(", checksum: {}", checksum_of(header));
+                }
+                if header.is_economy() {
+                    print!(" (economy)");
+                }
+                println!();
+            }
+        }
+
+        let response = volca.get_sample_space()?;
+        let total_duration = headers.iter().map(proto::SampleHeader::duration).sum();
+        println!(
+            "Occupied space: {:.1}%, total duration: {}",
+            response.occupied() * 100.,
+            format_duration(total_duration)
+        );
+
         Ok(())
     }
 
-    fn download_sample(&mut self, sample_no: u8, output: PathBuf, sample_type: &str) -> Result<()> {
+    fn download_sample(
+        &mut self,
+        sample_no: SampleNo,
+        output: PathBuf,
+        sample_type: &str,
+        options: &DownloadOptions,
+    ) -> AppResult<()> {
         let volca = self.volca()?;
 
         let header = volca.get_sample_header(sample_no)?;
         println!(r#"Downloading sample "{}" from Volca"#, header.name);
-        let sample_data = volca.get_sample(sample_no)?;
+        let sample_data = volca.get_checked_sample(
+            &header,
+            options.tolerate_length_mismatch,
+            &mut TransferProgress::new("Downloading"),
+        )?;
+        let (data, sample_rate) = Self::apply_speed(&header, sample_data.data, options)?;
 
-        Self::save_sample(&sample_data.data, &output, &header.name, sample_type)
+        Self::save_sample(
+            &data,
+            &output,
+            &header.name,
+            options.format,
+            sample_type,
+            options.on_conflict,
+            sample_rate,
+        )
     }
 
-    fn upload_sample(&mut self, sample_no: Option<u8>, name: &str, data: Vec<i16>) -> Result<()> {
+    /// Converts downloaded sample data so that it sounds as on the device, if requested.
+    /// Returns the data and its sample rate.
+    fn apply_speed(
+        header: &proto::SampleHeader,
+        data: Vec<i16>,
+        options: &DownloadOptions,
+    ) -> AppResult<(Vec<i16>, u32)> {
+        if !options.apply_speed || header.speed == proto::SampleHeader::DEFAULT_SPEED {
+            return Ok((data, VOLCA_SAMPLERATE));
+        }
+
+        let playback_rate = header.playback_rate();
+        println!("Applying sample speed, playback rate is {playback_rate}Hz");
+        if options.cheap {
+            Ok((data, playback_rate))
+        } else {
+            let data = resample_i16(&data, playback_rate, VOLCA_SAMPLERATE)?;
+            Ok((data, VOLCA_SAMPLERATE))
+        }
+    }
+
+    /// Finds samples with the exact name. If there are none, finds samples whose names start
+    /// with `name` ignoring case.
+    fn find_samples(&mut self, name: &str) -> AppResult<Vec<proto::SampleHeader>> {
+        let headers = self
+            .volca()?
+            .iter_sample_headers()
+            .filter(|res| res.as_ref().map_or(true, |header| !header.is_empty()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let exact = headers
+            .iter()
+            .filter(|header| header.name == name)
+            .cloned()
+            .collect::<Vec<_>>();
+        if !exact.is_empty() {
+            return Ok(exact);
+        }
+
+        let prefix = name.to_lowercase();
+        Ok(headers
+            .into_iter()
+            .filter(|header| header.name.to_lowercase().starts_with(&prefix))
+            .collect())
+    }
+
+    /// Resolves sample name to matching headers. Fails if nothing matches or if several samples
+    /// match and `all` is not set.
+    fn resolve_name(
+        &mut self,
+        name: &str,
+        all: bool,
+        action: &str,
+    ) -> AppResult<Vec<proto::SampleHeader>> {
+        let headers = self.find_samples(name)?;
+        match headers.len() {
+            0 => Err(anyhow!("could not find sample {name:?}").into()),
+            1 => Ok(headers),
+            _ if all => Ok(headers),
+            _ => {
+                println!("Several samples match {name:?}:");
+                for header in &headers {
+                    println!("{:3}: {}", header.sample_no, header.name);
+                }
+                Err(anyhow!("sample name is ambiguous, use --all to {action} all of them").into())
+            }
+        }
+    }
+
+    /// Converts and uploads an audio file. Returns the slot number the sample was loaded into,
+    /// or `None` on dry run.
+    fn upload_file(
+        &mut self,
+        file: &Path,
+        sample_no: Option<SampleNo>,
+        name: Option<String>,
+        options: &UploadOptions,
+    ) -> AppResult<Option<SampleNo>> {
+        let name = match name {
+            Some(name) => name.into(),
+            None => extract_file_name(file)?,
+        };
+        let name = Self::device_name(&name, options.strict_names)?;
+
+        // The slot is chosen before the conversion, which may take a while. A dry run does not
+        // talk to the device at all.
+        let target = if options.dry_run {
+            None
+        } else {
+            let replaced = if options.replace_by_name {
+                self.find_slot_by_name(&name)?
+            } else {
+                None
+            };
+            let target = match (replaced, sample_no) {
+                (Some(sample_no), _) => {
+                    println!("Replacing sample {name} in slot {sample_no}");
+                    (sample_no, false)
+                }
+                (None, _) if options.strict => {
+                    return Err(anyhow!("could not find sample {name:?} to replace").into())
+                }
+                (None, Some(sample_no)) => (sample_no, true),
+                (None, None) => {
+                    let sample_no = self.find_empty_slot(options.from_slot, !options.no_wrap)?;
+                    println!("Using empty slot {sample_no} for {name}");
+                    (sample_no, true)
+                }
+            };
+            Some(target)
+        };
+
+        let sample = Self::load_audio_file_at(
+            file,
+            options.mono_mode.clone(),
+            options.sample_rate,
+            &options.processing,
+        )?;
+        options
+            .output
+            .as_ref()
+            .map(|path| {
+                Self::save_sample(
+                    &sample,
+                    path,
+                    &name,
+                    OutputFormat::Wav,
+                    "processed",
+                    OnConflict::Overwrite,
+                    options.sample_rate,
+                )
+            })
+            .transpose()?;
+
+        // Played before anything is written to the device
+        if options.listen && !Self::listen(&sample, options.sample_rate, options.dry_run)? {
+            return Err(Aborted("upload declined".into()).into());
+        }
+
+        let Some((sample_no, confirm)) = target else {
+            return Ok(None);
+        };
+        if options.skip_identical && self.is_up_to_date(sample_no, &name, &sample, options.deep)? {
+            println!("slot {sample_no:03} already up to date");
+            return Ok(Some(sample_no));
+        }
+        let speed = options
+            .speed
+            .at_rate(options.sample_rate)
+            .with_context(|| {
+                format!(
+                    "speed {} does not fit at sample rate {}Hz",
+                    options.speed, options.sample_rate
+                )
+            })?;
+        let current = self.volca()?.get_sample_header(sample_no)?;
+        self.check_space(
+            &[sample.len() as u32],
+            &[current],
+            options.sample_rate,
+            options.ignore_space,
+        )?;
+        self.upload_sample_with(sample_no, &name, sample, speed, options.level, confirm)?;
+        Ok(Some(sample_no))
+    }
+
+    /// Makes sure that samples of `lengths` fit into the device memory once the `replaced`
+    /// samples are overwritten. Fails with the shortfall in seconds at `sample_rate`, or only
+    /// warns with `ignore_space`.
+    fn check_space(
+        &mut self,
+        lengths: &[u32],
+        replaced: &[proto::SampleHeader],
+        sample_rate: u32,
+        ignore_space: bool,
+    ) -> AppResult<()> {
+        let space = self.volca()?.get_sample_space()?;
+        let needed = lengths.iter().map(|&len| space.sectors_for(len)).sum();
+        let freed = replaced
+            .iter()
+            .map(|header| space.sectors_for(header.length))
+            .sum();
+        let Some(missing) = space.shortfall(needed, freed) else {
+            return Ok(());
+        };
+        let missing = format_duration(Duration::from_secs_f64(
+            f64::from(space.length_for(missing)) / f64::from(sample_rate),
+        ));
+        if ignore_space {
+            eprintln!("warning: device memory is short by {missing} of audio, uploading anyway");
+            return Ok(());
+        }
+        Err(anyhow!(
+            "sample does not fit into device memory, {missing} of audio too long \
+            (pass --ignore-space to upload anyway)"
+        )
+        .into())
+    }
+
+    /// Checks whether the slot already holds a sample with the same name and length. With `deep`
+    /// the sample data is downloaded and compared as well.
+    fn is_up_to_date(
+        &mut self,
+        sample_no: SampleNo,
+        name: &str,
+        data: &[i16],
+        deep: bool,
+    ) -> AppResult<bool> {
+        let name = proto::SampleHeader::truncate_name(name);
         let volca = self.volca()?;
-        let sample_no = sample_no
-            .map(Ok)
-            .or_else(|| {
-                volca.iter_sample_headers().find_map(|result| {
-                    result
-                        .map(|header| header.is_empty().then_some(header.sample_no))
-                        .transpose()
+        let header = volca.get_sample_header(sample_no)?;
+        if header.is_empty() || header.name != name || header.length as usize != data.len() {
+            return Ok(false);
+        }
+        if !deep {
+            return Ok(true);
+        }
+        Ok(volca.get_sample(sample_no)?.data == data)
+    }
+
+    /// Uploads several files into consecutive slots starting from `start`, or into the first
+    /// empty slots. Failed files do not stop the upload.
+    fn upload_files(
+        &mut self,
+        files: &[PathBuf],
+        start: Option<SampleNo>,
+        options: &UploadOptions,
+    ) -> AppResult<()> {
+        let mut uploaded = Vec::new();
+        let mut failed = Vec::new();
+        let mut interrupted = None;
+        for (idx, file) in files.iter().enumerate() {
+            let sample_no = start
+                .map(|start| {
+                    start
+                        .checked_add(idx)
+                        .map_err(|_| anyhow!("no more sample slots after {start}"))
                 })
+                .transpose();
+            let result = sample_no
+                .map_err(AppError::from)
+                .and_then(|sample_no| self.upload_file(file, sample_no, None, options));
+            match result {
+                Ok(Some(sample_no)) => uploaded.push((sample_no, file)),
+                Ok(None) => {}
+                Err(err) if exit::code(&err) == exit::INTERRUPTED => {
+                    interrupted = Some((err, &files[idx..]));
+                    break;
+                }
+                Err(err) => {
+                    eprintln!("Failed to upload {file:?}: {err:#}");
+                    failed.push(file);
+                }
+            }
+        }
+
+        let uploaded_count = uploaded.len();
+        if !uploaded.is_empty() {
+            println!("Uploaded samples:");
+            uploaded.sort();
+            for (sample_no, file) in uploaded {
+                let name = extract_file_name(file)?;
+                println!("{sample_no}: {name}");
+            }
+        }
+        if let Some((err, left)) = interrupted {
+            println!("Not uploaded:");
+            for file in left {
+                println!("{}", file.display());
+            }
+            let context = format!(
+                "stopped after uploading {uploaded_count} of {} files, the slot of {:?} may be \
+                 incomplete",
+                files.len(),
+                left[0]
+            );
+            return Err(anyhow::Error::new(err).context(context).into());
+        }
+        if !failed.is_empty() {
+            return Err(
+                anyhow!("failed to upload {} of {} files", failed.len(), files.len()).into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Finds the slot holding a sample named exactly as a sample uploaded with `name` would be.
+    /// Fails if there are several such slots.
+    fn find_slot_by_name(&mut self, name: &str) -> AppResult<Option<SampleNo>> {
+        let name = proto::SampleHeader::truncate_name(name);
+        let headers = self
+            .volca()?
+            .iter_sample_headers()
+            .filter(|res| {
+                res.as_ref()
+                    .map_or(true, |header| !header.is_empty() && header.name == name)
             })
-            .ok_or_else(|| anyhow!("could not find empty slot"))??;
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match headers.as_slice() {
+            [] => Ok(None),
+            [header] => Ok(Some(header.sample_no)),
+            headers => {
+                println!("Several slots hold sample {name:?}:");
+                for header in headers {
+                    println!("{:3}: {}", header.sample_no, header.name);
+                }
+                Err(anyhow!("sample name is ambiguous").into())
+            }
+        }
+    }
+
+    fn download_by_name(
+        &mut self,
+        name: &str,
+        output: PathBuf,
+        all: bool,
+        options: &DownloadOptions,
+    ) -> AppResult<()> {
+        let headers = self.resolve_name(name, all, "download")?;
+        let append_slot = headers.len() > 1;
 
+        for header in headers {
+            println!(r#"Downloading sample "{}" from Volca"#, header.name);
+            let sample_data = self.volca()?.get_checked_sample(
+                &header,
+                options.tolerate_length_mismatch,
+                &mut (),
+            )?;
+            let (data, sample_rate) = Self::apply_speed(&header, sample_data.data, options)?;
+            let file_name = if append_slot {
+                format!("{}_{}", header.name, header.sample_no)
+            } else {
+                header.name
+            };
+            Self::save_sample(
+                &data,
+                &output,
+                &file_name,
+                options.format,
+                "",
+                options.on_conflict,
+                sample_rate,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Finds the first empty slot starting from `from`. The search continues from the first slot
+    /// when `wrap` is set.
+    fn find_empty_slot(&mut self, from: SampleNo, wrap: bool) -> AppResult<SampleNo> {
+        let slots = from.up_from().chain(from.below().filter(move |_| wrap));
+        self.volca()?
+            .iter_selected_sample_headers(slots)
+            .find_map(|result| {
+                result
+                    .map(|header| header.is_empty().then_some(header.sample_no))
+                    .transpose()
+            })
+            .ok_or_else(|| anyhow!("could not find empty slot"))?
+            .map_err(Into::into)
+    }
+
+    fn upload_sample(
+        &mut self,
+        sample_no: SampleNo,
+        name: &str,
+        data: Vec<i16>,
+        confirm: bool,
+    ) -> AppResult<()> {
+        let (speed, level) = (proto::Speed::DEFAULT, proto::Level::DEFAULT);
+        self.upload_sample_with(sample_no, name, data, speed, level, confirm)
+    }
+
+    fn upload_sample_with(
+        &mut self,
+        sample_no: SampleNo,
+        name: &str,
+        data: Vec<i16>,
+        speed: proto::Speed,
+        level: proto::Level,
+        confirm: bool,
+    ) -> AppResult<()> {
+        let truncated = proto::SampleHeader::truncate_name(name);
+        if truncated != name {
+            eprintln!("warning: sample name {name:?} is truncated to {truncated:?}");
+        }
+        let header = proto::SampleHeader::builder(sample_no)
+            .name(truncated)
+            .length_of(&data)
+            .speed(speed)
+            .level(level)
+            .build()?;
+
+        let volca = self.volca()?;
         let current_header = volca.get_sample_header(sample_no)?;
-        if !current_header.is_empty() {
+        if confirm && !current_header.is_empty() {
             // TODO: format_args?
             let question = format!(
                 "Sample slot is not empty (current - {}). Do you want to overwrite?",
                 current_header.name
             );
             if !ask(&question)? {
-                bail!("sample slot is not empty");
+                return Err(Aborted("sample slot is not empty".into()).into());
             }
 
-            if ask(&format!(
-                "Do you want to backup the loaded sample ({})?",
-                current_header.name
-            ))? {
-                self.download_sample(sample_no, "./".into(), "backup")?;
+            if ask_with_default(
+                &format!(
+                    "Do you want to backup the loaded sample ({})?",
+                    current_header.name
+                ),
+                Some(false),
+            )? {
+                let options = DownloadOptions {
+                    format: OutputFormat::Wav,
+                    on_conflict: OnConflict::Rename,
+                    apply_speed: false,
+                    cheap: false,
+                    tolerate_length_mismatch: false,
+                };
+                self.download_sample(sample_no, "./".into(), "backup", &options)?;
             }
         }
 
-        let (header, data) = proto::SampleData::new(sample_no, name, data);
-        self.volca()?.send_sample(header, data)?;
+        let data = proto::SampleData { sample_no, data };
+        let name = header.name.clone();
+        self.volca()?.send_sample_with_progress(
+            header,
+            data,
+            &mut TransferProgress::new("Uploading"),
+        )?;
         println!("Loaded sample {name} in slot {sample_no}");
 
         Ok(())
     }
 
-    fn delete_sample(&mut self, sample_no: u8, print_name: bool) -> Result<()> {
+    /// Saves header and data dumps of a sample into a file as they are sent by the device.
+    fn export_syx(
+        &mut self,
+        sample_no: SampleNo,
+        output: &Path,
+        tolerate_length_mismatch: bool,
+    ) -> AppResult<()> {
+        use proto::{Header, Message, Outgoing};
+
+        let volca = self.volca()?;
+        volca.send(proto::SampleHeaderDumpRequest { sample_no })?;
+        let (sysex_header, header) = volca.receive::<proto::SampleHeader>()?;
+        if header.is_empty() {
+            return Err(anyhow!("sample slot {sample_no} is empty").into());
+        }
+        println!(r#"Downloading sample "{}" from Volca"#, header.name);
+        let data = volca.get_checked_sample(&header, tolerate_length_mismatch, &mut ())?;
+        let data_sysex_header =
+            <proto::SampleData as Message>::Header::from_channel(volca.channel());
+
+        let mut buf = Vec::new();
+        header.encode(sysex_header, &mut buf)?;
+        data.encode(data_sysex_header, &mut buf)?;
+        fs::write(output, buf)?;
+        println!("Wrote SysEx dump to {output:?}");
+
+        Ok(())
+    }
+
+    fn import_syx(&mut self, file: &Path, slot: Option<SampleNo>, dry_run: bool) -> AppResult<()> {
+        let buf = fs::read(file).with_context(|| format!("could not read {file:?}"))?;
+        let samples =
+            syx::parse_samples(&buf).with_context(|| format!("invalid SysEx file {file:?}"))?;
+        let mut slots = slot
+            .map(|slot| slot.range(samples.len()))
+            .transpose()
+            .map_err(|_| {
+                anyhow!(
+                    "{} samples do not fit into slots starting from {}",
+                    samples.len(),
+                    slot.unwrap_or_default()
+                )
+            })?;
+
+        for sample in samples {
+            let syx::SampleDump {
+                offset,
+                mut header,
+                mut data,
+            } = sample;
+            let sample_no = slots
+                .as_mut()
+                .and_then(Iterator::next)
+                .unwrap_or(header.sample_no);
+            if dry_run {
+                println!(
+                    r#"{offset:#08x}: "{}" ({}, stored for slot {:03}) -> slot {sample_no:03}"#,
+                    header.name,
+                    format_duration(header.duration()),
+                    header.sample_no
+                );
+                continue;
+            }
+
+            println!(r#"Uploading "{}" to slot {sample_no:03}"#, header.name);
+            header.sample_no = sample_no;
+            data.sample_no = sample_no;
+            self.volca()?
+                .send_sample(header, data)
+                .with_context(|| format!("could not upload sample at offset {offset:#x}"))?;
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn slice_file(
+        &mut self,
+        file: &Path,
+        parts: u8,
+        start_slot: SampleNo,
+        mono_mode: MonoMode,
+        force: bool,
+        dry_run: bool,
+        ignore_space: bool,
+    ) -> AppResult<()> {
+        let slots = start_slot.range(parts.into()).map_err(|_| {
+            anyhow!("{parts} slices do not fit into slots starting from {start_slot}")
+        })?;
+
+        let stem = sanitize_sample_name(&extract_file_name(file)?);
+        let sample = Self::load_audio_file(file, mono_mode, &Processing::default())?;
+        // Every slice needs at least one sample, the device does not store empty ones
+        if sample.len() < parts.into() {
+            return Err(anyhow!(
+                "sample is {} samples long, too short for {parts} slices",
+                sample.len()
+            )
+            .into());
+        }
+        let width = parts.to_string().len().max(2);
+        // Keep the suffix when the name is truncated to the device limit
+        let stem = stem
+            .chars()
+            .take(proto::SampleHeader::NAME_LEN - width - 1)
+            .collect::<String>();
+        let slices = split_evenly(sample.len(), parts.into())
+            .into_iter()
+            .zip(slots.clone())
+            .enumerate()
+            .map(|(idx, (range, slot))| (slot, format!("{stem}_{:0width$}", idx + 1), range))
+            .collect::<Vec<_>>();
+
+        if dry_run {
+            let seconds = |idx: usize| idx as f64 / VOLCA_SAMPLERATE as f64;
+            for (slot, name, range) in &slices {
+                println!(
+                    "{slot:3}: {name} {:.3}s - {:.3}s",
+                    seconds(range.start),
+                    seconds(range.end)
+                );
+            }
+            return Ok(());
+        }
+
+        let volca = self.volca()?;
+        let headers = volca
+            .iter_selected_sample_headers(slots)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let lengths = slices
+            .iter()
+            .map(|(_, _, range)| range.len() as u32)
+            .collect::<Vec<_>>();
+        self.check_space(&lengths, &headers, VOLCA_SAMPLERATE, ignore_space)?;
+
+        if !force {
+            for header in headers.iter().filter(|header| !header.is_empty()) {
+                let question = format!(
+                    "Sample slot {} is not empty (current - {}). Do you want to overwrite?",
+                    header.sample_no, header.name
+                );
+                if !ask(&question)? {
+                    return Err(Aborted("sample slot is not empty".into()).into());
+                }
+            }
+        }
+
+        for (slot, name, range) in slices {
+            self.upload_sample(slot, &name, sample[range].to_vec(), false)?;
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn concat_files(
+        &mut self,
+        files: &[PathBuf],
+        slot: Option<SampleNo>,
+        name: &str,
+        gap: Duration,
+        mono_mode: MonoMode,
+        loudness: Option<LoudnessTarget>,
+        limit: Option<f64>,
+        output: Option<&Path>,
+    ) -> AppResult<()> {
+        let name = &Self::device_name(name, false)?;
+        let gap = vec![0; (gap.as_secs_f64() * VOLCA_SAMPLERATE as f64).round() as usize];
+        let processing = Processing {
+            loudness,
+            limit,
+            ..Processing::default()
+        };
+        let mut sample = Vec::new();
+        for (idx, file) in files.iter().enumerate() {
+            if idx > 0 {
+                sample.extend_from_slice(&gap);
+            }
+            let part = Self::load_audio_file(file, mono_mode.clone(), &processing)
+                .with_context(|| format!("could not load {file:?}"))?;
+            sample.extend(part);
+        }
+        println!(
+            "Joined {} files, total duration: {}",
+            files.len(),
+            format_duration(Duration::from_secs_f64(
+                sample.len() as f64 / VOLCA_SAMPLERATE as f64
+            ))
+        );
+
+        // Only warnings, the joined file is worth saving anyway
+        let max_len = proto::SampleSpaceDump::MEMORY_SIZE / 2;
+        if sample.len() > max_len as usize {
+            eprintln!(
+                "warning: joined sample is longer than the device can hold, which is {}",
+                format_duration(Duration::from_secs_f64(
+                    f64::from(max_len) / VOLCA_SAMPLERATE as f64
+                ))
+            );
+        }
+        if let Some(slot) = slot {
+            let current = self.volca()?.get_sample_header(slot)?;
+            self.check_space(&[sample.len() as u32], &[current], VOLCA_SAMPLERATE, true)?;
+        }
+
+        if let Some(path) = output {
+            Self::save_sample(
+                &sample,
+                path,
+                name,
+                OutputFormat::Wav,
+                "joined",
+                OnConflict::Overwrite,
+                VOLCA_SAMPLERATE,
+            )?;
+        }
+
+        let Some(slot) = slot else {
+            return Ok(());
+        };
+        self.upload_sample(slot, name, sample, true)
+    }
+
+    fn watch_file(
+        &mut self,
+        file: &Path,
+        sample_no: SampleNo,
+        mono_mode: MonoMode,
+        debounce: Duration,
+    ) -> AppResult<()> {
+        let name = Self::device_name(&extract_file_name(file)?, false)?;
+        let watcher = FileWatcher::new(file, debounce)?;
+        // Connect before the first change so it does not delay the upload.
+        self.volca()?;
+        println!("Watching {file:?} for changes, press Ctrl-C to stop");
+
+        loop {
+            if interrupted() {
+                return Err(Interrupted("watching for changes").into());
+            }
+            if !watcher.wait_for_change(WAIT_INTERVAL)? {
+                continue;
+            }
+            let timestamp = humantime::format_rfc3339_seconds(SystemTime::now());
+            let result = Self::load_audio_file(file, mono_mode.clone(), &Processing::default())
+                .and_then(|sample| self.upload_sample(sample_no, &name, sample, false));
+            if let Err(err) = result {
+                // The file may be incomplete or locked while it is being exported, so we just
+                // wait for the next change.
+                eprintln!("[{timestamp}] Failed to upload {file:?}: {err:#}");
+            } else {
+                println!("[{timestamp}] Uploaded {file:?}");
+            }
+        }
+    }
+
+    fn delete_sample(&mut self, sample_no: SampleNo, print_name: bool) -> AppResult<()> {
         let volca = self.volca()?;
         let name = if print_name {
             let mut header = volca.get_sample_header(sample_no)?;
@@ -135,20 +1055,329 @@ impl App {
         Ok(())
     }
 
-    fn load_audio_file(path: &Path, mono_mode: MonoMode) -> Result<Vec<i16>> {
+    fn delete_by_name(&mut self, name: &str, all: bool) -> AppResult<()> {
+        let headers = if is_glob(name) {
+            let headers = self
+                .volca()?
+                .iter_sample_headers()
+                .filter(|res| {
+                    res.as_ref().map_or(true, |header| {
+                        !header.is_empty() && glob_match(name, &header.name)
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if headers.is_empty() {
+                return Err(anyhow!("no samples match {name:?}").into());
+            }
+            headers
+        } else {
+            self.resolve_name(name, all, "remove")?
+        };
+
+        let question = match headers.as_slice() {
+            [header] => format!(
+                "Remove sample {} at slot {}?",
+                header.name, header.sample_no
+            ),
+            headers => {
+                println!("Matching samples:");
+                for header in headers {
+                    println!("{:3}: {}", header.sample_no, header.name);
+                }
+                format!("Remove {} samples?", headers.len())
+            }
+        };
+        if !ask(&question)? {
+            return Err(Aborted("aborted".into()).into());
+        }
+
+        let volca = self.volca()?;
+        for header in headers {
+            volca.delete_sample(header.sample_no)?;
+            println!(
+                "Removed sample {} at slot {}",
+                header.name, header.sample_no
+            );
+        }
+        Ok(())
+    }
+
+    fn backup_patterns(&mut self, dir: &Path) -> AppResult<()> {
+        fs::create_dir_all(dir)?;
+        let volca = self.volca()?;
+
+        let mut index = BTreeMap::new();
+        for pattern_no in 0..proto::PATTERN_COUNT {
+            let pattern = match volca.get_pattern(pattern_no) {
+                Ok(pattern) => pattern,
+                Err(err) if err.stops_transfer() => {
+                    // Keep what was read so far restorable
+                    write_pattern_index(dir, &index)?;
+                    return Err(anyhow::Error::new(err)
+                        .context(format!(
+                            "backed up {} of {} patterns, run backup again to finish",
+                            index.len(),
+                            proto::PATTERN_COUNT
+                        ))
+                        .into());
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let file_name = format!("pattern_{:02}.bin", pattern_no + 1);
+            fs::write(dir.join(&file_name), &pattern.data)?;
+            println!("Wrote pattern {} to {file_name}", pattern_no + 1);
+            index.insert(pattern_no + 1, file_name);
+        }
+
+        Ok(write_pattern_index(dir, &index)?)
+    }
+
+    fn restore_patterns(&mut self, path: &Path, to: Option<u8>) -> AppResult<()> {
+        let patterns = if path.is_dir() {
+            if to.is_some() {
+                return Err(anyhow!("--to can only be used with a single pattern file").into());
+            }
+            let index_path = path.join(PATTERN_INDEX_FILE);
+            let index: BTreeMap<u8, PathBuf> =
+                serde_yaml::from_reader(fs::File::open(&index_path)?)
+                    .with_context(|| format!("invalid pattern index {index_path:?}"))?;
+            index
+                .into_iter()
+                .map(|(pattern_no, file)| (pattern_no, path.join(file)))
+                .collect::<Vec<_>>()
+        } else {
+            let pattern_no = to
+                .or_else(|| {
+                    path.file_stem()?
+                        .to_str()?
+                        .strip_prefix("pattern_")?
+                        .parse()
+                        .ok()
+                })
+                .ok_or_else(|| anyhow!("could not infer pattern number, use --to"))?;
+            vec![(pattern_no, path.to_path_buf())]
+        };
+
+        if patterns.is_empty() {
+            return Err(anyhow!("no patterns to load").into());
+        }
+
+        let mut data = Vec::with_capacity(patterns.len());
+        for (pattern_no, path) in &patterns {
+            if !(1..=proto::PATTERN_COUNT).contains(pattern_no) {
+                return Err(anyhow!("invalid pattern number {pattern_no} for {path:?}").into());
+            }
+            data.push(fs::read(path)?);
+        }
+
+        // Check sizes against an actual pattern before writing anything
+        let volca = self.volca()?;
+        let expected_len = volca.get_pattern(patterns[0].0 - 1)?.data.len();
+        let mut valid = true;
+        for ((_, path), data) in patterns.iter().zip(&data) {
+            if data.len() != expected_len {
+                eprintln!(
+                    "{path:?} has invalid size: expected {expected_len} bytes, found {}",
+                    data.len()
+                );
+                valid = false;
+            }
+        }
+        if !valid {
+            return Err(anyhow!("invalid pattern files").into());
+        }
+
+        let mut failed = 0;
+        for (i, ((pattern_no, path), data)) in patterns.iter().zip(data).enumerate() {
+            let pattern = proto::PatternData {
+                pattern_no: pattern_no - 1,
+                data,
+            };
+            match volca.send_pattern(pattern) {
+                Ok(()) => println!("Pattern {pattern_no:2}: loaded {path:?}"),
+                Err(err) if err.stops_transfer() => {
+                    let remaining = patterns[i..]
+                        .iter()
+                        .map(|(pattern_no, _)| pattern_no.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let context = format!(
+                        "loaded {i} of {} patterns, pattern {pattern_no} may be incomplete, \
+                         patterns left to restore: {remaining}",
+                        patterns.len()
+                    );
+                    return Err(anyhow::Error::new(err).context(context).into());
+                }
+                Err(err) => {
+                    println!("Pattern {pattern_no:2}: failed to load {path:?}: {err:#}");
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed > 0 {
+            return Err(anyhow!("failed to load {failed} of {} patterns", patterns.len()).into());
+        }
+        Ok(())
+    }
+
+    fn get_current_pattern(&mut self, file: &Path) -> AppResult<()> {
+        let pattern = self.volca()?.get_current_pattern()?;
+        fs::write(file, &pattern.data)?;
+        println!("Wrote current pattern to {file:?}");
+        Ok(())
+    }
+
+    fn set_current_pattern(&mut self, file: &Path) -> AppResult<()> {
+        let data = fs::read(file)?;
+        let volca = self.volca()?;
+        // Pattern size is only known from the device, check it before writing
+        let expected_len = volca.get_current_pattern()?.data.len();
+        if data.len() != expected_len {
+            return Err(anyhow!(
+                "{file:?} has invalid size: expected {expected_len} bytes, found {}",
+                data.len()
+            )
+            .into());
+        }
+        volca.send_current_pattern(proto::CurrentPatternData { data })?;
+        println!("Loaded {file:?} as current pattern");
+        Ok(())
+    }
+
+    fn dump_settings(&mut self, file: &Path) -> AppResult<()> {
+        let global = self.volca()?.get_global_data()?;
+        fs::write(file, &global.data)?;
+        println!("Wrote settings to {file:?}");
+        Ok(())
+    }
+
+    fn restore_settings(&mut self, file: &Path) -> AppResult<()> {
+        let data = fs::read(file)?;
+        let volca = self.volca()?;
+        // Settings saved with another firmware version may not fit
+        let expected_len = volca.get_global_data()?.data.len();
+        if data.len() != expected_len {
+            return Err(anyhow!(
+                "{file:?} has invalid size: expected {expected_len} bytes, found {}, \
+                 it may have been saved with another firmware version",
+                data.len()
+            )
+            .into());
+        }
+        volca.send_global_data(proto::GlobalData { data })?;
+        println!("Restored settings from {file:?}");
+        Ok(())
+    }
+
+    /// Sanitizes a sample name for the device. Warns if the name changes, or fails if `strict`.
+    fn device_name(name: &str, strict: bool) -> AppResult<String> {
+        let sanitized = sanitize_sample_name(name);
+        if sanitized != name {
+            if strict {
+                return Err(
+                    anyhow!("sample name {name:?} can not be stored on the device as is").into(),
+                );
+            }
+            eprintln!("warning: sample name {name:?} is stored as {sanitized:?}");
+        }
+        Ok(sanitized)
+    }
+
+    fn load_audio_file(
+        path: &Path,
+        mono_mode: MonoMode,
+        processing: &Processing,
+    ) -> AppResult<Vec<i16>> {
+        Self::load_audio_file_at(path, mono_mode, VOLCA_SAMPLERATE, processing)
+    }
+
+    fn load_audio_file_at(
+        path: &Path,
+        mono_mode: MonoMode,
+        sample_rate: u32,
+        processing: &Processing,
+    ) -> AppResult<Vec<i16>> {
         let reader = AudioReader::open_file(path)?;
-        let sample = match (reader.channels(), mono_mode) {
-            (1, _) | (_, MonoMode::Left) => reader.take_channel(0).resample_to_volca()?,
-            (_, MonoMode::Right) => reader.take_channel(1).resample_to_volca()?,
-            (_, MonoMode::Mid) => reader.take_mid().resample_to_volca()?,
-            (_, MonoMode::Side) => reader.take_side().resample_to_volca()?,
+        let mut signal = match (reader.channels(), mono_mode) {
+            (1, _) | (_, MonoMode::Left) => reader.take_channel(0).into_signal()?,
+            (_, MonoMode::Right) => reader.take_channel(1).into_signal()?,
+            (_, MonoMode::Mid) => reader.take_mid().into_signal()?,
+            (_, MonoMode::Side) => reader.take_side().into_signal()?,
         };
-        Ok(sample)
+        if let Some(cutoff) = processing.highpass {
+            apply_filter(&mut signal, Pass::High, cutoff)?;
+        }
+        if let Some(cutoff) = processing.lowpass {
+            apply_filter(&mut signal, Pass::Low, cutoff)?;
+        }
+        // Measured after filtering, which changes the loudness
+        if let Some(target) = processing.loudness {
+            Self::apply_loudness(path, &mut signal, target);
+        }
+        let mut signal = signal.resample(sample_rate, processing.resampling)?;
+        if let Some(ceiling) = processing.limit {
+            let reduction = apply_limiter(&mut signal, ceiling);
+            if reduction > 0. {
+                println!(
+                    "Limited {path:?} to {ceiling:.1} dBFS, gain reduced by up to {reduction:.1} dB"
+                );
+            }
+        }
+        Ok(signal.quantize())
     }
 
-    fn save_sample(data: &[i16], path: &Path, name: &str, sample_type: &str) -> Result<()> {
-        let output = normalize_path(path, name)?;
-        write_sample_to_file(data, &output)?;
+    fn apply_loudness(path: &Path, signal: &mut Signal, target: LoudnessTarget) {
+        let Some(result) = normalize_loudness(signal, target) else {
+            eprintln!("warning: {path:?} is too quiet to measure loudness, gain is not changed");
+            return;
+        };
+        if result.measurement == Measurement::Momentary {
+            eprintln!(
+                "warning: {path:?} is too short for integrated loudness, \
+                 momentary loudness of the whole file is used"
+            );
+        }
+        let limited = if result.limited {
+            format!(", limited by {:.1} dBTP ceiling", target.true_peak)
+        } else {
+            String::new()
+        };
+        println!(
+            "Loudness of {path:?}: {:.1} LUFS, true peak {:.1} dBTP, applied {:+.1} dB{limited}",
+            result.measured, result.true_peak, result.gain
+        );
+    }
+
+    /// Plays a converted sample and asks whether to upload it, unless it is a dry run. Does
+    /// nothing if stdin is not a terminal, as there is no one to ask.
+    fn listen(sample: &[i16], sample_rate: u32, dry_run: bool) -> AppResult<bool> {
+        if !io::stdin().is_terminal() {
+            tracing::debug!("stdin is not a terminal, not playing the sample");
+            return Ok(true);
+        }
+        println!(
+            "Playing {} of converted audio",
+            format_duration(Duration::from_secs_f64(
+                sample.len() as f64 / sample_rate as f64
+            ))
+        );
+        playback::play(sample, sample_rate)?;
+        Ok(dry_run || ask("Upload this?")?)
+    }
+
+    fn save_sample(
+        data: &[i16],
+        path: &Path,
+        name: &str,
+        format: OutputFormat,
+        sample_type: &str,
+        on_conflict: OnConflict,
+        sample_rate: u32,
+    ) -> AppResult<()> {
+        let output = normalize_path(path, name, format.extension())?;
+        let output = resolve_conflict(output, on_conflict)?;
+        write_sample(data, &output, format, sample_rate)?;
         let space = if sample_type.is_empty() { "" } else { " " };
         println!("Wrote {sample_type}{space}sample to {output:?}");
 
@@ -156,39 +1385,1005 @@ impl App {
     }
 }
 
-fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+/// Writes the index of backed up patterns, mapping pattern numbers to files in `dir`.
+fn write_pattern_index(dir: &Path, index: &BTreeMap<u8, String>) -> Result<()> {
+    let index_path = dir.join(PATTERN_INDEX_FILE);
+    serde_yaml::to_writer(fs::File::create(&index_path)?, index)?;
+    println!("Wrote pattern index to {index_path:?}");
+    Ok(())
+}
+
+/// Resolves limiter options into the limiter ceiling. Limiter is on by default when gain is
+/// changed.
+fn resolve_limit(
+    limit: bool,
+    no_limit: bool,
+    ceiling: f64,
+    changes_gain: bool,
+) -> Result<Option<f64>> {
+    if ceiling > 0. {
+        bail!("limiter ceiling {ceiling} dBFS is above full scale");
+    }
+    Ok((limit || (changes_gain && !no_limit)).then_some(ceiling))
+}
+
+fn main() {
+    // Log level can be raised with RUST_LOG, e.g. to see pacing adjustments
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
 
+    if let Err(err) = run() {
+        if let Some(err) = err.downcast_ref::<clap::Error>() {
+            err.exit();
+        }
+        eprintln!("Error: {err:?}");
+        let unparsable = exit::causes(&*err)
+            .any(|cause| matches!(cause.downcast_ref(), Some(DeviceError::Parse { .. })));
+        if unparsable {
+            eprintln!(
+                "\nRun again with --midi-log <file> or RUST_LOG=trace to capture the messages \
+                 exchanged with the device."
+            );
+        }
+        std::process::exit(exit::code(&*err).into());
+    }
+}
+
+/// Order of samples in the list. Samples that compare equal are ordered by slot, and empty slots
+/// go last regardless of the order unless sorted by slot.
+fn compare_headers(
+    a: &proto::SampleHeader,
+    b: &proto::SampleHeader,
+    sort: SortKey,
+    reverse: bool,
+) -> Ordering {
+    let ordering = match sort {
+        SortKey::Slot => a.sample_no.cmp(&b.sample_no),
+        SortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+        SortKey::Length => a.length.cmp(&b.length),
+    };
+    let ordering = if reverse {
+        ordering.reverse()
+    } else {
+        ordering
+    };
+    match sort {
+        SortKey::Slot => ordering,
+        _ => a
+            .is_empty()
+            .cmp(&b.is_empty())
+            .then(ordering)
+            .then(a.sample_no.cmp(&b.sample_no)),
+    }
+}
+
+/// Picks the port of the device. Command line options take precedence over the config file,
+/// where `device` is preferred to `device_name` and `device_name` to `port`.
+fn resolve_device_spec(opts: &opt::Opts, config: &Config) -> DeviceSpec {
+    let given = |device: &Option<DeviceSpec>, name: &Option<String>, port: &Option<String>| {
+        device
+            .clone()
+            .or_else(|| name.clone().map(DeviceSpec::Name))
+            .or_else(|| port.clone().map(DeviceSpec::Client))
+    };
+    given(&opts.device, &opts.device_name, &opts.port)
+        .or_else(|| given(&config.device, &config.device_name, &config.port))
+        .unwrap_or_default()
+}
+
+fn run() -> Result<()> {
     let opts = opt::Opts::parse();
-    let mut app = App::new(opts.chunk_cooldown.into());
+    // Config command must work even if the config file is broken
+    let config = if matches!(opts.cmd, opt::Operation::Config { .. }) {
+        Config::default()
+    } else {
+        Config::load()?
+    };
+    let chunk_cooldown = opts
+        .chunk_cooldown
+        .or(config.chunk_cooldown)
+        .map_or(DEFAULT_CHUNK_COOLDOWN, Into::into);
+    let pacing = opts.pacing.or(config.pacing).unwrap_or_default();
+    let device_spec = resolve_device_spec(&opts, &config);
+    let retry_policy = RetryPolicy {
+        retries: opts.retries.unwrap_or(RetryPolicy::DEFAULT_RETRIES),
+        delay: opts
+            .retry_delay
+            .map_or(RetryPolicy::DEFAULT_DELAY, Into::into),
+    };
+    util::set_assume_yes(opts.yes || config.yes);
+    let device_selection = opts.device_index.map_or(
+        DeviceSelection::Ask(util::choose_device),
+        DeviceSelection::Index,
+    );
+    let backend = opts.backend.or(config.backend).unwrap_or_default();
+    let midi_log = opts
+        .midi_log
+        .as_deref()
+        .map(|path| {
+            MidiLog::open(path)
+                .map(Arc::new)
+                .with_context(|| format!("could not open MIDI log {path:?}"))
+        })
+        .transpose()?;
+    let mut app = App::new(ConnectOptions {
+        backend,
+        spec: device_spec,
+        selection: device_selection,
+        chunk_cooldown,
+        pacing,
+        retry_policy,
+        wait: match opts.wait {
+            None => Wait::No,
+            Some(None) => Wait::Forever,
+            Some(Some(timeout)) => Wait::Timeout(timeout.into()),
+        },
+        midi_log,
+        #[cfg(target_os = "linux")]
+        input_pool: opts.input_pool,
+        serial_headers: opts.serial_headers,
+        probe_all: opts.probe_all || config.probe_all,
+    });
 
-    match opts.cmd {
-        opt::Operation::List { show_empty } => app.list_samples(show_empty)?,
-        opt::Operation::Download { sample_no, output } => {
-            app.download_sample(sample_no, output, "")?
+    execute(&mut app, &config, opts.cmd)
+}
+
+fn execute(app: &mut App, config: &Config, cmd: opt::Operation) -> Result<()> {
+    let resolve_mono_mode = |mono_mode: Option<MonoMode>| {
+        mono_mode
+            .or_else(|| config.mono_mode.clone())
+            .unwrap_or_default()
+    };
+
+    // Writes stop cleanly between chunks on Ctrl-C, instead of leaving a message unfinished
+    let catch_interrupt = match cmd {
+        opt::Operation::Upload { .. }
+        | opt::Operation::Watch { .. }
+        | opt::Operation::Slice { .. }
+        | opt::Operation::Concat { .. }
+        | opt::Operation::Pattern { .. }
+        | opt::Operation::Settings { .. }
+        | opt::Operation::ImportSyx { .. } => true,
+        #[cfg(feature = "tui")]
+        opt::Operation::Tui => true,
+        _ => false,
+    };
+    let _interrupt = catch_interrupt.then(CatchInterrupt::new).transpose()?;
+
+    match cmd {
+        opt::Operation::List {
+            show_empty,
+            sort,
+            reverse,
+            porcelain,
+            checksum,
+            raw,
+        } => app.list_samples(show_empty, sort, reverse, porcelain, checksum, raw)?,
+        opt::Operation::Download {
+            sample,
+            output,
+            format,
+            all,
+            on_conflict,
+            apply_speed,
+            cheap,
+            tolerate_length_mismatch,
+        } => {
+            let output = output
+                .or_else(|| config.output_dir.clone())
+                .unwrap_or_else(|| PathBuf::from("./"));
+            let options = DownloadOptions {
+                format,
+                on_conflict: on_conflict.unwrap_or_else(OnConflict::default_for_stdin),
+                apply_speed,
+                cheap,
+                tolerate_length_mismatch,
+            };
+            match sample {
+                SampleSelector::Slot(sample_no) => {
+                    app.download_sample(sample_no, output, "", &options)?
+                }
+                SampleSelector::Name(name) => app.download_by_name(&name, output, all, &options)?,
+            }
         }
         opt::Operation::Upload {
-            sample_no,
-            file,
+            files,
+            start,
             mono_mode,
             output,
             dry_run,
+            listen,
+            name,
+            replace_by_name,
+            strict,
+            from_slot,
+            no_wrap,
+            skip_identical,
+            deep,
+            economy,
+            target_rate,
+            speed,
+            level,
+            strict_names,
+            ignore_space,
+            highpass,
+            lowpass,
+            loudness,
+            true_peak,
+            limit,
+            no_limit,
+            ceiling,
+            resampler,
+            quality,
         } => {
-            let name = extract_file_name(&file)?;
-            let sample = App::load_audio_file(&file, mono_mode)?;
-            output
-                .map(|path| App::save_sample(&sample, &path, &name, "processed"))
-                .transpose()?;
+            let (files, sample_no) =
+                opt::split_upload_args(files, start, name.as_deref(), replace_by_name)?;
+            let options = UploadOptions {
+                mono_mode: resolve_mono_mode(mono_mode),
+                output,
+                dry_run,
+                listen,
+                replace_by_name,
+                strict,
+                from_slot,
+                no_wrap,
+                skip_identical,
+                deep,
+                sample_rate: match (economy, target_rate) {
+                    (true, _) => ECONOMY_SAMPLERATE,
+                    (false, rate) => rate.unwrap_or(VOLCA_SAMPLERATE),
+                },
+                speed: speed.unwrap_or(proto::Speed::DEFAULT),
+                level: level.unwrap_or(proto::Level::DEFAULT),
+                strict_names,
+                ignore_space,
+                processing: Processing {
+                    highpass,
+                    lowpass,
+                    loudness: loudness.map(|integrated| LoudnessTarget {
+                        integrated,
+                        true_peak,
+                    }),
+                    resampling: Resampling {
+                        mode: resampler,
+                        quality,
+                    },
+                    limit: resolve_limit(limit, no_limit, ceiling, loudness.is_some())?,
+                },
+            };
 
-            if !dry_run {
-                app.upload_sample(sample_no, &name, sample)?;
+            if let [file] = files.as_slice() {
+                app.upload_file(file, sample_no.or(start), name, &options)?;
+            } else {
+                app.upload_files(&files, start, &options)?;
             }
         }
-        opt::Operation::Remove {
+        opt::Operation::Watch {
+            file,
+            sample_no,
+            mono_mode,
+            debounce,
+        } => app.watch_file(
+            &file,
+            sample_no,
+            resolve_mono_mode(mono_mode),
+            debounce.into(),
+        )?,
+        opt::Operation::Slice {
+            file,
+            parts,
+            start_slot,
+            mono_mode,
+            force,
+            dry_run,
+            ignore_space,
+        } => app.slice_file(
+            &file,
+            parts,
+            start_slot,
+            resolve_mono_mode(mono_mode),
+            force,
+            dry_run,
+            ignore_space,
+        )?,
+        opt::Operation::Concat {
+            files,
+            slot,
+            name,
+            gap,
+            mono_mode,
+            output,
+            loudness,
+            true_peak,
+            limit,
+            no_limit,
+            ceiling,
+        } => app.concat_files(
+            &files,
+            slot,
+            &name,
+            gap.into(),
+            resolve_mono_mode(mono_mode),
+            loudness.map(|integrated| LoudnessTarget {
+                integrated,
+                true_peak,
+            }),
+            resolve_limit(limit, no_limit, ceiling, loudness.is_some())?,
+            output.as_deref(),
+        )?,
+        opt::Operation::Pattern { cmd } => match cmd {
+            opt::PatternOperation::Backup { dir } => app.backup_patterns(&dir)?,
+            opt::PatternOperation::Restore { path, to } => app.restore_patterns(&path, to)?,
+            opt::PatternOperation::GetCurrent { file } => app.get_current_pattern(&file)?,
+            opt::PatternOperation::SetCurrent { file } => app.set_current_pattern(&file)?,
+        },
+        opt::Operation::Settings { cmd } => match cmd {
+            opt::SettingsOperation::Dump { file } => app.dump_settings(&file)?,
+            opt::SettingsOperation::Restore { file } => app.restore_settings(&file)?,
+        },
+        opt::Operation::ExportSyx {
             sample_no,
+            output,
+            tolerate_length_mismatch,
+        } => app.export_syx(sample_no, &output, tolerate_length_mismatch)?,
+        opt::Operation::ImportSyx {
+            file,
+            slot,
+            dry_run,
+        } => app.import_syx(&file, slot, dry_run)?,
+        opt::Operation::Monitor { decode } => app.monitor(decode)?,
+        opt::Operation::Shell => app.shell(config)?,
+        #[cfg(feature = "tui")]
+        opt::Operation::Tui => {
+            let output_dir = config
+                .output_dir
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("./"));
+            tui::run(app.volca()?, output_dir)?
+        }
+        opt::Operation::Doctor => {
+            if !doctor::run(&app.connect_options) {
+                bail!("some checks have failed");
+            }
+        }
+        opt::Operation::ListPorts => match app.connect_options.backend {
+            #[cfg(target_os = "linux")]
+            device::Backend::Seq => {
+                let seq = alsa::seq::Seq::open(None, None, false)
+                    .context("could not open ALSA sequencer")?;
+                for port in device::list_ports(&seq) {
+                    println!("{port}");
+                }
+            }
+            #[cfg(target_os = "linux")]
+            device::Backend::RawMidi => {
+                for port in device::list_rawmidi_ports() {
+                    println!("{port}");
+                }
+            }
+            #[cfg(feature = "midir")]
+            device::Backend::Midir => {
+                for port in device::list_midir_ports()? {
+                    println!("{port}");
+                }
+            }
+        },
+        opt::Operation::Config { cmd } => match cmd {
+            opt::ConfigOperation::Path => println!("{}", Config::path()?.display()),
+        },
+        opt::Operation::Remove {
+            sample,
             print_name,
-        } => app.delete_sample(sample_no, print_name)?,
+            all,
+        } => match sample {
+            SampleSelector::Slot(sample_no) => app.delete_sample(sample_no, print_name)?,
+            SampleSelector::Name(name) => app.delete_by_name(&name, all)?,
+        },
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+fn write_test_wav(name: &str, samples: &[i16]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("volsa2-{}-{name}.wav", std::process::id()));
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: VOLCA_SAMPLERATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+    for &sample in samples {
+        writer.write_sample(sample).unwrap();
+    }
+    writer.finalize().unwrap();
+    path
+}
+
+#[cfg(test)]
+fn test_upload_options(from_slot: SampleNo, no_wrap: bool) -> UploadOptions {
+    UploadOptions {
+        mono_mode: MonoMode::default(),
+        output: None,
+        dry_run: false,
+        listen: false,
+        replace_by_name: false,
+        strict: false,
+        from_slot,
+        no_wrap,
+        skip_identical: false,
+        deep: false,
+        sample_rate: VOLCA_SAMPLERATE,
+        speed: proto::Speed::DEFAULT,
+        level: proto::Level::DEFAULT,
+        strict_names: false,
+        ignore_space: false,
+        processing: Processing::default(),
+    }
+}
+
+#[test]
+fn upload_to_empty_slot() {
+    use crate::device::mock::{slot, MockDevice};
+
+    let file = write_test_wav("upload", &[0, 100, -100, 0]);
+    let volca = MockDevice::new()
+        .with_sample(198, "used", vec![1; 4])
+        .with_sample(0, "first", vec![1; 4]);
+    let mut app = App::with_device(volca);
+
+    let options = test_upload_options(slot(198), false);
+    let sample_no = app.upload_file(&file, None, None, &options).unwrap();
+    assert_eq!(sample_no, Some(slot(199)));
+    let sample_no = app.upload_file(&file, None, None, &options).unwrap();
+    assert_eq!(sample_no, Some(slot(1)));
+
+    let volca = app.volca.as_ref().unwrap();
+    assert_eq!(
+        volca.header(1).name,
+        format!("volsa2-{}-upload", std::process::id())
+    );
+    assert_eq!(volca.data(199), [0, 100, -100, 0]);
+
+    let options = test_upload_options(slot(199), true);
+    assert!(app.upload_file(&file, None, None, &options).is_err());
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn slot_chosen_before_conversion() {
+    use crate::device::mock::{slot, MockDevice};
+
+    let garbage = std::env::temp_dir().join(format!("volsa2-{}-garbage.wav", std::process::id()));
+    fs::write(&garbage, b"not audio").unwrap();
+    let mut app = App::with_device(MockDevice::new().with_sample(199, "used", vec![1; 4]));
+    // No empty slot is found before the file is even opened
+    let options = test_upload_options(slot(199), true);
+    let err = app.upload_file(&garbage, None, None, &options).unwrap_err();
+    assert_eq!(err.to_string(), "could not find empty slot");
+
+    let mut options = test_upload_options(slot(199), true);
+    options.dry_run = true;
+    let err = app.upload_file(&garbage, None, None, &options).unwrap_err();
+    assert_ne!(err.to_string(), "could not find empty slot");
+    fs::remove_file(garbage).unwrap();
+}
+
+#[test]
+fn filtered_output() {
+    use crate::device::mock::{slot, MockDevice};
+
+    // DC offset with a 1kHz tone on top
+    let samples = (0..VOLCA_SAMPLERATE)
+        .map(|idx| {
+            let tone = (2. * std::f64::consts::PI * 1000. * idx as f64 / 31250.).sin();
+            (8000. + 8000. * tone) as i16
+        })
+        .collect::<Vec<_>>();
+    let file = write_test_wav("filter", &samples);
+    let output = std::env::temp_dir().join(format!("volsa2-{}-filtered.wav", std::process::id()));
+    let mut app = App::with_device(MockDevice::new());
+
+    let mut options = test_upload_options(slot(0), false);
+    options.dry_run = true;
+    options.output = Some(output.clone());
+    options.processing.highpass = Some(40);
+    assert_eq!(app.upload_file(&file, None, None, &options).unwrap(), None);
+
+    let filtered = hound::WavReader::open(&output)
+        .unwrap()
+        .into_samples::<i16>()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(filtered.len(), samples.len());
+    let middle = &filtered[filtered.len() / 4..filtered.len() * 3 / 4];
+    let mean = middle.iter().map(|&sample| f64::from(sample)).sum::<f64>() / middle.len() as f64;
+    assert!(mean.abs() < 100., "DC offset {mean} is not removed");
+    assert!(middle.iter().any(|&sample| sample > 7000));
+
+    options.processing.highpass = Some(20000);
+    assert!(app.upload_file(&file, None, None, &options).is_err());
+    fs::remove_file(file).unwrap();
+    fs::remove_file(output).unwrap();
+}
+
+#[test]
+fn limit_defaults() {
+    assert_eq!(resolve_limit(false, false, -0.1, false).unwrap(), None);
+    assert_eq!(resolve_limit(false, false, -0.1, true).unwrap(), Some(-0.1));
+    assert_eq!(resolve_limit(false, true, -0.1, true).unwrap(), None);
+    assert_eq!(resolve_limit(true, false, -1., false).unwrap(), Some(-1.));
+    assert!(resolve_limit(true, false, 0.5, false).is_err());
+}
+
+#[test]
+fn import_syx_dry_run() {
+    use crate::device::mock::{slot, MockDevice};
+    use crate::proto::{Header, Outgoing};
+    use crate::seven_bit::U7;
+
+    let path = std::env::temp_dir().join(format!("volsa2-{}-import.syx", std::process::id()));
+    let mut buf = Vec::new();
+    for (sample_no, name) in [(3, "kick"), (4, "snare")] {
+        let (header, data) = proto::SampleData::new(slot(sample_no), name, vec![5, -5]);
+        header
+            .encode(Header::from_channel(U7::new(0)), &mut buf)
+            .unwrap();
+        data.encode(Header::from_channel(U7::new(0)), &mut buf)
+            .unwrap();
+    }
+    fs::write(&path, buf).unwrap();
+
+    let mut app = App::with_device(MockDevice::new());
+    app.import_syx(&path, None, true).unwrap();
+    assert_eq!(app.volca.as_ref().unwrap().writes(), 0);
+
+    app.import_syx(&path, Some(slot(10)), false).unwrap();
+    let volca = app.volca.as_ref().unwrap();
+    assert_eq!(volca.writes(), 4);
+    assert!(volca.header(3).is_empty());
+    assert_eq!(volca.header(11).name, "snare");
+    assert_eq!(volca.data(11), [5, -5]);
+
+    assert!(app.import_syx(&path, Some(slot(199)), false).is_err());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn delete_empty_slot() {
+    use crate::device::mock::{slot, MockDevice};
+
+    let mut app = App::with_device(MockDevice::new().with_sample(7, "hat", vec![1, 2]));
+    app.delete_sample(slot(8), true).unwrap();
+    assert_eq!(app.volca.as_ref().unwrap().writes(), 0);
+
+    app.delete_sample(slot(7), true).unwrap();
+    let volca = app.volca.as_ref().unwrap();
+    assert_eq!(volca.writes(), 1);
+    assert!(volca.header(7).is_empty());
+}
+
+#[test]
+fn refused_upload() {
+    use crate::device::mock::{slot, MockDevice};
+    use crate::proto::NakStatus;
+
+    let volca = MockDevice::new();
+    volca.refuse_next_write(NakStatus::SampleFull);
+    let mut app = App::with_device(volca);
+    let err = app
+        .upload_sample(slot(0), "kick", vec![1, 2, 3], false)
+        .unwrap_err();
+    assert_eq!(exit::code(&err), exit::DEVICE_NAK);
+    assert!(matches!(
+        err,
+        AppError::Device(DeviceError::Nak {
+            status: NakStatus::SampleFull,
+            ..
+        })
+    ));
+    assert!(app.volca.as_ref().unwrap().header(0).is_empty());
+}
+
+#[test]
+fn upload_space_check() {
+    use crate::device::mock::MockDevice;
+
+    let mut app = App::with_device(MockDevice::new());
+    app.check_space(&[VOLCA_SAMPLERATE * 60], &[], VOLCA_SAMPLERATE, false)
+        .unwrap();
+    // 8MiB of memory hold a little over two minutes of audio
+    let err = app
+        .check_space(&[VOLCA_SAMPLERATE * 180], &[], VOLCA_SAMPLERATE, false)
+        .unwrap_err();
+    assert!(err.to_string().contains("--ignore-space"));
+    app.check_space(&[VOLCA_SAMPLERATE * 180], &[], VOLCA_SAMPLERATE, true)
+        .unwrap();
+
+    assert_eq!(app.volca.as_ref().unwrap().writes(), 0);
+}
+
+#[test]
+fn interrupted_upload_files() {
+    use crate::device::mock::{slot, MockDevice};
+
+    let file = write_test_wav("interrupted", &[7; 600]);
+    let options = test_upload_options(slot(0), false);
+    let mut app = App::with_device(MockDevice::new());
+    app.upload_files(std::slice::from_ref(&file), Some(slot(10)), &options)
+        .unwrap();
+    let chunks_per_file = app.volca.as_ref().unwrap().chunks();
+
+    let mut app = App::with_device(MockDevice::new());
+    let volca = app.volca.as_ref().unwrap();
+    // Slot header and free space requests, another slot header request, header write and the
+    // first chunk of data of the second file
+    volca.interrupt_after_chunks(chunks_per_file + 5);
+    let err = app
+        .upload_files(&[file.clone(), file.clone()], Some(slot(10)), &options)
+        .unwrap_err();
+    assert_eq!(exit::code(&err), exit::INTERRUPTED);
+    assert!(err
+        .to_string()
+        .starts_with("stopped after uploading 1 of 2 files"));
+    let volca = app.volca.as_ref().unwrap();
+    assert!(volca.cut_short());
+    assert_eq!(volca.data(10), [7; 600]);
+    assert!(volca.data(11).is_empty());
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn current_pattern_round_trip() {
+    use crate::device::mock::MockDevice;
+
+    let file = std::env::temp_dir().join(format!("volsa2-current-pattern-{}", std::process::id()));
+    let pattern = (0..=255).cycle().take(300).collect::<Vec<u8>>();
+    let mut app = App::with_device(MockDevice::new().with_current_pattern(pattern.clone()));
+    app.get_current_pattern(&file).unwrap();
+    assert_eq!(fs::read(&file).unwrap(), pattern);
+
+    let mut app = App::with_device(MockDevice::new().with_current_pattern(vec![0; 300]));
+    app.set_current_pattern(&file).unwrap();
+    assert_eq!(app.volca.as_ref().unwrap().current_pattern(), pattern);
+
+    let mut app = App::with_device(MockDevice::new().with_current_pattern(vec![0; 10]));
+    let err = app.set_current_pattern(&file).unwrap_err();
+    assert!(err.to_string().contains("invalid size"));
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn settings_round_trip() {
+    use crate::device::mock::MockDevice;
+    use crate::proto::NakStatus;
+
+    let file = std::env::temp_dir().join(format!("volsa2-settings-{}", std::process::id()));
+    let settings = (0..64).collect::<Vec<u8>>();
+    let mut app = App::with_device(MockDevice::new().with_global_data(settings.clone()));
+    app.dump_settings(&file).unwrap();
+    assert_eq!(fs::read(&file).unwrap(), settings);
+
+    let mut app = App::with_device(MockDevice::new().with_global_data(vec![0; 64]));
+    app.restore_settings(&file).unwrap();
+    assert_eq!(app.volca.as_ref().unwrap().global_data(), settings);
+
+    let mut app = App::with_device(MockDevice::new().with_global_data(vec![0; 64]));
+    app.volca
+        .as_ref()
+        .unwrap()
+        .refuse_next_write(NakStatus::DataFormat);
+    assert!(app.restore_settings(&file).is_err());
+    assert_eq!(app.volca.as_ref().unwrap().global_data(), [0; 64]);
+
+    let mut app = App::with_device(MockDevice::new().with_global_data(vec![0; 80]));
+    let err = app.restore_settings(&file).unwrap_err();
+    assert!(err.to_string().contains("invalid size"));
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn device_spec_precedence() {
+    let opts = |args: &[&str]| {
+        opt::Opts::try_parse_from(["volsa2-cli"].iter().chain(args).chain(&["list"]))
+    };
+    let mut config = Config::default();
+    let spec = |args: &[&str], config: &Config| resolve_device_spec(&opts(args).unwrap(), config);
+    assert_eq!(spec(&[], &config), DeviceSpec::default());
+
+    config.port = Some("volca".to_owned());
+    assert_eq!(spec(&[], &config), DeviceSpec::Client("volca".to_owned()));
+    config.device_name = Some("USB MIDI".to_owned());
+    assert_eq!(spec(&[], &config), DeviceSpec::Name("USB MIDI".to_owned()));
+    config.device = Some(DeviceSpec::Addr {
+        client: 24,
+        port: 0,
+    });
+    assert_eq!(
+        spec(&[], &config),
+        DeviceSpec::Addr {
+            client: 24,
+            port: 0
+        }
+    );
+
+    // Any of the options on the command line overrides all of them in the config file
+    assert_eq!(
+        spec(&["--port", "other"], &config),
+        DeviceSpec::Client("other".to_owned())
+    );
+    assert_eq!(
+        spec(&["--device-name", "MIDI"], &config),
+        DeviceSpec::Name("MIDI".to_owned())
+    );
+
+    // Global, and only one of them at a time
+    let parsed = opt::Opts::try_parse_from(["volsa2-cli", "list", "--port", "other"]).unwrap();
+    assert_eq!(parsed.port.as_deref(), Some("other"));
+    assert!(opts(&["--port", "other", "--device", "24:0"]).is_err());
+    assert!(opts(&["--port", "other", "--device-name", "MIDI"]).is_err());
+}
+
+#[test]
+fn concat_with_gap() {
+    use crate::device::mock::{slot, MockDevice};
+
+    let first = write_test_wav("concat-first", &[1, 2]);
+    let second = write_test_wav("concat-second", &[3]);
+    let files = [first.clone(), second.clone()];
+    // Two samples of silence at 31.25kHz
+    let gap = Duration::from_micros(64);
+    let output = std::env::temp_dir().join(format!("volsa2-{}-joined.wav", std::process::id()));
+    let read_output = || {
+        hound::WavReader::open(&output)
+            .unwrap()
+            .samples::<i16>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    };
+
+    // Only saved without a slot
+    let mut app = App::with_device(MockDevice::new());
+    let concat = |app: &mut App<MockDevice>, slot, output| {
+        app.concat_files(
+            &files,
+            slot,
+            "joined",
+            gap,
+            MonoMode::default(),
+            None,
+            None,
+            output,
+        )
+    };
+    concat(&mut app, None, Some(&output)).unwrap();
+    assert_eq!(read_output(), [1, 2, 0, 0, 3]);
+    assert_eq!(app.volca.as_ref().unwrap().writes(), 0);
+
+    concat(&mut app, Some(slot(5)), None).unwrap();
+    let volca = app.volca.as_ref().unwrap();
+    assert_eq!(volca.header(5).name, "joined");
+    assert_eq!(volca.data(5), [1, 2, 0, 0, 3]);
+
+    // Too little free memory is only a warning
+    let sector = proto::SampleSpaceDump::MEMORY_SIZE as usize / 4096 / 2;
+    let mut app = App::with_device(MockDevice::new().with_sample(0, "big", vec![0; sector * 4096]));
+    concat(&mut app, Some(slot(5)), None).unwrap();
+    assert_eq!(app.volca.as_ref().unwrap().data(5), [1, 2, 0, 0, 3]);
+
+    // So is a result too long for the device, which is still saved
+    let gap = Duration::from_secs(135);
+    let mut app = App::with_device(MockDevice::new());
+    app.concat_files(
+        &files,
+        None,
+        "joined",
+        gap,
+        MonoMode::default(),
+        None,
+        None,
+        Some(&output),
+    )
+    .unwrap();
+    assert!(read_output().len() > proto::SampleSpaceDump::MEMORY_SIZE as usize / 2);
+
+    for file in [first, second, output] {
+        fs::remove_file(file).unwrap();
+    }
+}
+
+#[test]
+fn slice_too_short() {
+    use crate::device::mock::{slot, MockDevice};
+
+    let file = write_test_wav("slice-short", &[1, 2]);
+    let mut app = App::with_device(MockDevice::new());
+    for dry_run in [true, false] {
+        let err = app
+            .slice_file(&file, 3, slot(0), MonoMode::default(), true, dry_run, false)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "sample is 2 samples long, too short for 3 slices"
+        );
+    }
+    assert_eq!(app.volca.as_ref().unwrap().writes(), 0);
+
+    app.slice_file(&file, 2, slot(0), MonoMode::default(), true, false, false)
+        .unwrap();
+    let volca = app.volca.as_ref().unwrap();
+    assert_eq!(volca.data(0), [1]);
+    assert_eq!(volca.data(1), [2]);
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn restore_patterns() {
+    use crate::device::mock::{MockDevice, PATTERN_LEN};
+    use crate::proto::NakStatus;
+
+    let dir = std::env::temp_dir().join(format!("volsa2-{}-patterns", std::process::id()));
+    let mut app = App::with_device(MockDevice::new());
+    app.backup_patterns(&dir).unwrap();
+
+    // A single file is loaded where --to says, whatever its name
+    let file = dir.join("groove.bin");
+    fs::write(&file, [42; PATTERN_LEN]).unwrap();
+    let mut app = App::with_device(MockDevice::new());
+    let err = app.restore_patterns(&file, None).unwrap_err();
+    assert_eq!(err.to_string(), "could not infer pattern number, use --to");
+    app.restore_patterns(&file, Some(9)).unwrap();
+    let volca = app.volca.as_ref().unwrap();
+    assert_eq!(volca.pattern(8), [42; PATTERN_LEN]);
+    assert_eq!(volca.writes(), 1);
+    fs::remove_file(file).unwrap();
+
+    // Files of the wrong size are rejected before anything is sent
+    fs::write(dir.join("pattern_16.bin"), [15; PATTERN_LEN + 1]).unwrap();
+    let mut app = App::with_device(MockDevice::new());
+    let err = app.restore_patterns(&dir, None).unwrap_err();
+    assert_eq!(err.to_string(), "invalid pattern files");
+    assert_eq!(app.volca.as_ref().unwrap().writes(), 0);
+    fs::write(dir.join("pattern_16.bin"), [15; PATTERN_LEN]).unwrap();
+
+    // A refused pattern does not stop the rest, failures are counted at the end
+    fs::write(dir.join("pattern_01.bin"), [100; PATTERN_LEN]).unwrap();
+    fs::write(dir.join("pattern_02.bin"), [101; PATTERN_LEN]).unwrap();
+    let volca = MockDevice::new();
+    volca.refuse_next_write(NakStatus::DataFormat);
+    let mut app = App::with_device(volca);
+    let err = app.restore_patterns(&dir, None).unwrap_err();
+    assert_eq!(err.to_string(), "failed to load 1 of 16 patterns");
+    let volca = app.volca.as_ref().unwrap();
+    assert_eq!(volca.writes(), 16);
+    assert_eq!(volca.pattern(0), [0; PATTERN_LEN]);
+    assert_eq!(volca.pattern(1), [101; PATTERN_LEN]);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn replace_by_name() {
+    use crate::device::mock::{slot, MockDevice};
+
+    let file = write_test_wav("replace", &[5, 6, 7]);
+    let mut options = test_upload_options(slot(0), false);
+    options.replace_by_name = true;
+    let upload = |app: &mut App<MockDevice>, sample_no, name: &str, options: &UploadOptions| {
+        app.upload_file(&file, sample_no, Some(name.to_owned()), options)
+    };
+
+    // The matching slot wins over the one given
+    let volca = MockDevice::new().with_sample(7, "kick", vec![1; 4]);
+    let mut app = App::with_device(volca);
+    let sample_no = upload(&mut app, Some(slot(20)), "kick", &options).unwrap();
+    assert_eq!(sample_no, Some(slot(7)));
+    let volca = app.volca.as_ref().unwrap();
+    assert_eq!(volca.data(7), [5, 6, 7]);
+    assert!(volca.header(20).is_empty());
+
+    // Without a match the sample goes into the given or the next empty slot
+    let sample_no = upload(&mut app, Some(slot(20)), "snare", &options).unwrap();
+    assert_eq!(sample_no, Some(slot(20)));
+    let sample_no = upload(&mut app, None, "hat", &options).unwrap();
+    assert_eq!(sample_no, Some(slot(0)));
+
+    // Unless --strict is passed
+    options.strict = true;
+    let writes = app.volca.as_ref().unwrap().writes();
+    let err = upload(&mut app, None, "clap", &options).unwrap_err();
+    assert_eq!(err.to_string(), "could not find sample \"clap\" to replace");
+    assert_eq!(app.volca.as_ref().unwrap().writes(), writes);
+
+    // Several samples with the name are not told apart
+    let volca = MockDevice::new()
+        .with_sample(7, "kick", vec![1; 4])
+        .with_sample(9, "kick", vec![2; 4]);
+    let mut app = App::with_device(volca);
+    let err = upload(&mut app, None, "kick", &options).unwrap_err();
+    assert_eq!(err.to_string(), "sample name is ambiguous");
+    let volca = app.volca.as_ref().unwrap();
+    assert_eq!(volca.writes(), 0);
+    assert_eq!(volca.data(7), [1; 4]);
+    assert_eq!(volca.data(9), [2; 4]);
+
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn skip_identical() {
+    use crate::device::mock::{slot, MockDevice};
+
+    let file = write_test_wav("identical", &[5, 6, 7]);
+    let mut options = test_upload_options(slot(0), false);
+    options.replace_by_name = true;
+    options.skip_identical = true;
+    options.speed = proto::Speed::from_semitones(3.).unwrap();
+    options.level = proto::Level::from_percent(50.).unwrap();
+    let upload = |app: &mut App<MockDevice>, options: &UploadOptions| {
+        app.upload_file(&file, None, Some("kick".to_owned()), options)
+    };
+
+    // Only the name and length are compared by default
+    let mut app = App::with_device(MockDevice::new().with_sample(3, "kick", vec![1, 1, 1]));
+    assert_eq!(upload(&mut app, &options).unwrap(), Some(slot(3)));
+    let volca = app.volca.as_ref().unwrap();
+    assert_eq!(volca.writes(), 0);
+    assert_eq!(volca.data(3), [1, 1, 1]);
+    let header = volca.header(3);
+    assert_eq!(header.speed, proto::Speed::DEFAULT.raw());
+    assert_eq!(header.level, proto::Level::DEFAULT.raw());
+
+    // The data as well with --deep
+    options.deep = true;
+    assert_eq!(upload(&mut app, &options).unwrap(), Some(slot(3)));
+    let volca = app.volca.as_ref().unwrap();
+    assert_eq!(volca.data(3), [5, 6, 7]);
+    let header = volca.header(3);
+    assert_ne!(header.speed, proto::Speed::DEFAULT.raw());
+    assert_eq!(header.level, options.level.raw());
+
+    let writes = volca.writes();
+    assert_eq!(upload(&mut app, &options).unwrap(), Some(slot(3)));
+    assert_eq!(app.volca.as_ref().unwrap().writes(), writes);
+
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn list_order() {
+    use crate::device::mock::slot;
+
+    let sample =
+        |sample_no, name, len| proto::SampleData::new(slot(sample_no), name, vec![1; len]).0;
+    let headers = [
+        proto::SampleHeader::empty(slot(0)),
+        sample(1, "b", 5),
+        sample(2, "A", 3),
+        proto::SampleHeader::empty(slot(3)),
+        sample(4, "a", 3),
+    ];
+    let order = |sort, reverse| {
+        let mut headers = headers.to_vec();
+        headers.sort_by(|a, b| compare_headers(a, b, sort, reverse));
+        headers
+            .iter()
+            .map(|header| u8::from(header.sample_no))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(order(SortKey::Slot, false), [0, 1, 2, 3, 4]);
+    assert_eq!(order(SortKey::Slot, true), [4, 3, 2, 1, 0]);
+    // Names are compared ignoring case
+    assert_eq!(order(SortKey::Name, false), [2, 4, 1, 0, 3]);
+    assert_eq!(order(SortKey::Length, false), [2, 4, 1, 0, 3]);
+    // Empty slots stay last, and ties stay in slot order
+    assert_eq!(order(SortKey::Name, true), [1, 2, 4, 0, 3]);
+    assert_eq!(order(SortKey::Length, true), [1, 2, 4, 0, 3]);
+}
@@ -0,0 +1,102 @@
+//! Raw SysEx traffic monitoring.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::device::VolcaDevice;
+use crate::proto::{self, Incoming};
+use crate::util::{hexbuf, CatchInterrupt};
+
+/// How often to check whether monitoring was interrupted.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Prints every SysEx message sent by the device until interrupted with Ctrl-C.
+pub fn run(device: &impl VolcaDevice, decode: bool) -> Result<()> {
+    let interrupt = CatchInterrupt::new()?;
+    let running = || !interrupt.interrupted();
+
+    println!("Monitoring messages from the device, press Ctrl-C to stop");
+    let start = Instant::now();
+    let mut count = 0;
+    while running() {
+        match device.wait_for_input(POLL_INTERVAL) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            // Poll is interrupted by the signal
+            Err(_) if !running() => break,
+            Err(err) => return Err(err.into()),
+        }
+
+        let data = device.receive_raw()?;
+        count += 1;
+        println!(
+            "[{:>10.3}s] {} bytes: {:?}",
+            start.elapsed().as_secs_f64(),
+            data.len(),
+            hexbuf(&data)
+        );
+        if decode {
+            match decode_message(&data) {
+                Some(msg) => println!("              {msg}"),
+                None => println!("              unknown message"),
+            }
+        }
+    }
+
+    println!("Received {count} messages");
+    Ok(())
+}
+
+/// Tries to parse the message as one of the known incoming messages.
+///
+/// Sample and pattern data are summarized instead of being printed in full.
+fn decode_message(data: &[u8]) -> Option<String> {
+    if let Ok((_, msg)) = proto::SearchDeviceReply::parse(data) {
+        return Some(format!("{msg:?}"));
+    }
+    if let Ok((_, msg)) = proto::IdentityReply::parse(data) {
+        return Some(format!("{msg:?}"));
+    }
+    if let Ok((_, msg)) = proto::SampleSpaceDump::parse(data) {
+        return Some(format!("{msg:?}"));
+    }
+    if let Ok((_, msg)) = proto::SampleHeader::parse(data) {
+        return Some(format!("{msg:?}"));
+    }
+    if let Ok((_, msg)) = proto::SampleData::parse(data) {
+        return Some(format!(
+            "SampleData {{ sample_no: {}, length: {} }}",
+            msg.sample_no,
+            msg.data.len()
+        ));
+    }
+    if let Ok((_, msg)) = proto::PatternData::parse(data) {
+        return Some(format!(
+            "PatternData {{ pattern_no: {}, size: {} }}",
+            msg.pattern_no,
+            msg.data.len()
+        ));
+    }
+    if let Ok((_, msg)) = proto::CurrentPatternData::parse(data) {
+        return Some(format!("CurrentPatternData {{ size: {} }}", msg.data.len()));
+    }
+    if let Ok((_, msg)) = proto::GlobalData::parse(data) {
+        return Some(format!("GlobalData {{ size: {} }}", msg.data.len()));
+    }
+    if let Ok((_, msg)) = proto::Status::parse(data) {
+        return Some(format!("Status({msg:?})"));
+    }
+    None
+}
+
+#[test]
+fn decode() {
+    use hex_literal::hex;
+
+    assert_eq!(
+        decode_message(&hex!("F0 42 30 00 01 2D 23 F7")).as_deref(),
+        Some("Status(Ok(()))")
+    );
+    assert_eq!(decode_message(&hex!("F0 42 30 00 01 2D 7A 00 F7")), None);
+}
@@ -1,11 +1,18 @@
 use std::path::PathBuf;
+use std::str::FromStr;
 
-use clap::{Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 
-use crate::audio::MonoMode;
+use crate::audio::{MonoMode, OutputFormat, ResampleMode, SincQuality};
+use crate::device::{Backend, DeviceSpec, Pacing};
+use crate::exit::EXIT_CODES_HELP;
+use crate::proto::{Level, ParseSampleNoError, SampleNo, Speed};
+use crate::util::OnConflict;
 
 #[derive(Parser)]
 /// Korg Volca Sample CLI.
+#[command(after_help = EXIT_CODES_HELP)]
 pub struct Opts {
     #[command(subcommand)]
     pub cmd: Operation,
@@ -13,8 +20,85 @@ pub struct Opts {
     ///
     /// Volca Sample 2 can hang when receiving long messages (SampleDataDump specifically).
     /// We introduce a "cooldown" for sending a chunk to avoid this.
-    #[arg(short, long, default_value = "10ms")]
-    pub chunk_cooldown: humantime::Duration,
+    ///
+    /// Defaults to 10ms.
+    #[arg(short, long)]
+    pub chunk_cooldown: Option<humantime::Duration>,
+    /// How the chunk cooldown is chosen. `adaptive` starts from `--chunk-cooldown`, shortens it
+    /// while the device accepts writes and lengthens it when the device is busy or does not reply.
+    #[arg(long, value_enum)]
+    pub pacing: Option<Pacing>,
+    /// How many times to resend a message the device refused because it is busy.
+    ///
+    /// Defaults to 3.
+    #[arg(long, global = true)]
+    pub retries: Option<u32>,
+    /// Pause before resending a message the device refused because it is busy. Doubles after
+    /// each attempt.
+    ///
+    /// Defaults to 200ms.
+    #[arg(long, global = true)]
+    pub retry_delay: Option<humantime::Duration>,
+    /// Sequencer client name of the device. Defaults to "volca sample".
+    #[arg(long, global = true, conflicts_with_all = ["device", "device_name"])]
+    pub port: Option<String>,
+    /// Sequencer port of the device, bypassing the search by client name.
+    ///
+    /// Accepts a `client:port` address, as printed by `list-ports` and `doctor`, or a part of the
+    /// client or port name.
+    #[arg(long, global = true)]
+    pub device: Option<DeviceSpec>,
+    /// Part of the client or port name of the device, ignoring case. Unlike `--device`, it is
+    /// never taken for an address.
+    ///
+    /// For devices behind a USB MIDI adapter, e.g. "USB MIDI Interface".
+    #[arg(long, global = true, conflicts_with = "device")]
+    pub device_name: Option<String>,
+    /// If no port matches, send the discovery request to every port and use the one that
+    /// replies. Each port is given 300ms to reply.
+    #[arg(long, global = true)]
+    pub probe_all: bool,
+    /// Which device to use when several match, as numbered in the list printed when asking.
+    ///
+    /// Without it, the device is asked for on the terminal.
+    #[arg(long, global = true)]
+    pub device_index: Option<usize>,
+    /// MIDI interface used to talk to the device. `rawmidi` opens the device exclusively, `--device`
+    /// then matches the card name or a `hw:card,device,subdevice` name. `midir` uses the native
+    /// MIDI API of the platform, `--device` then matches a part of the port name.
+    #[arg(long, global = true, value_enum)]
+    pub backend: Option<Backend>,
+    /// Wait for the device to show up when it is not found, for at most the given time if any.
+    ///
+    /// The time must be attached with `=`, e.g. `--wait=30s`.
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true)]
+    pub wait: Option<Option<humantime::Duration>>,
+    /// Number of events the ALSA sequencer buffers before it drops data from the device.
+    ///
+    /// Defaults to 2000, the largest size ALSA allows, so that as much of a sample dump as
+    /// possible fits. Only used by the `seq` backend.
+    #[cfg(target_os = "linux")]
+    #[arg(long, global = true, value_parser = clap::value_parser!(u32).range(1..=2000))]
+    pub input_pool: Option<u32>,
+    /// Request sample headers one at a time, waiting for each reply. Slower, for firmware that
+    /// misbehaves when several requests are sent ahead.
+    #[arg(long, global = true)]
+    pub serial_headers: bool,
+    /// Append every SysEx message exchanged with the device to this file, with timestamps and
+    /// hex dumps of the payload. Useful for bug reports, independent of `RUST_LOG`.
+    #[arg(long, global = true)]
+    pub midi_log: Option<PathBuf>,
+    /// Answer confirmation prompts with yes. Prompts are required to have a terminal otherwise.
+    #[arg(short, long, global = true)]
+    pub yes: bool,
+}
+
+/// Command entered in the interactive shell.
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+pub struct ShellCommand {
+    #[command(subcommand)]
+    pub cmd: Operation,
 }
 
 #[derive(Subcommand)]
@@ -25,40 +109,639 @@ pub enum Operation {
         /// Print empty sample slots in the output.
         #[arg(short = 'a', long, default_value = "false")]
         show_empty: bool,
+        /// Sort samples by the given key. Empty slots are printed last unless sorted by slot.
+        #[arg(short, long, value_enum, default_value_t = SortKey::Slot)]
+        sort: SortKey,
+        /// Reverse the sort order.
+        #[arg(short, long, default_value = "false")]
+        reverse: bool,
+        /// Print stable machine-readable output.
+        ///
+        /// Each sample is printed on its own line as tab-separated `slot`, `name`, `length`,
+        /// `speed` and `level` fields. Tabs, line breaks and backslashes in names are escaped
+        /// as `\t`, `\n`, `\r` and `\\`. Empty slots have `-` as name. This format will not change.
+        #[arg(long, default_value = "false")]
+        porcelain: bool,
+        /// Print content hash of each sample.
+        ///
+        /// Sample data has to be downloaded for this, so it takes a while. In porcelain output
+        /// the hash is printed as an additional field.
+        #[arg(long, default_value = "false")]
+        checksum: bool,
+        /// Print speed and level as the raw values stored on the device.
+        #[arg(long, default_value = "false", conflicts_with = "porcelain")]
+        raw: bool,
     },
     /// Download a sample from the device.
     #[command(alias = "dl")]
     Download {
-        /// Sample ID as shown in the device "sample" menu or in the output of List command.
-        sample_no: u8, // TODO: bound this
+        /// Sample ID as shown in the device "sample" menu or in the output of List command,
+        /// or sample name.
+        ///
+        /// Numbers are always treated as sample IDs. Names are matched exactly, or by
+        /// case-insensitive prefix if there is no exact match.
+        sample: SampleSelector,
         /// Output path. Sample name will be used if the provided path points to a directory.
-        #[arg(short, long, default_value = "./")]
-        output: PathBuf,
+        ///
+        /// Defaults to the working directory.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Output file format.
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Wav)]
+        format: OutputFormat,
+        /// Download all samples matching the name. Slot number is appended to file names.
+        #[arg(long, default_value = "false")]
+        all: bool,
+        /// What to do if the output file already exists.
+        ///
+        /// Defaults to `prompt` when run interactively and to `fail` otherwise.
+        #[arg(long, value_enum)]
+        on_conflict: Option<OnConflict>,
+        /// Resample the sample so that it sounds as on the device at its current speed.
+        ///
+        /// By default sample data is saved as is.
+        #[arg(long, default_value = "false")]
+        apply_speed: bool,
+        /// Do not resample, write the playback rate into the file header instead.
+        #[arg(long, default_value = "false", requires = "apply_speed")]
+        cheap: bool,
+        /// Save the sample data even if its length differs from the sample header.
+        ///
+        /// By default such a sample is downloaded again, and the download fails if the length
+        /// still differs, since the transfer was probably cut short.
+        #[arg(long, default_value = "false")]
+        tolerate_length_mismatch: bool,
     },
     /// Load sample into the device.
     #[command(alias = "up")]
     Upload {
-        /// Path to audio file to upload.
-        file: PathBuf,
-        /// Sample slot number. Will choose first empty slot if not provided.
-        sample_no: Option<u8>,
-        /// Mono convertion mode.
-        #[arg(short, long, value_enum, default_value_t = MonoMode::Mid)]
-        mono_mode: MonoMode,
+        /// Paths to audio files to upload.
+        ///
+        /// A single file can be followed by a sample slot number. Otherwise each file is
+        /// uploaded into the first empty slot or into consecutive slots starting from `--start`.
+        #[arg(required = true, value_name = "FILE [SAMPLE_NO]")]
+        files: Vec<PathBuf>,
+        /// First sample slot for uploading files into consecutive slots.
+        #[arg(short, long)]
+        start: Option<SampleNo>,
+        /// Sample slot to start searching for an empty slot from.
+        #[arg(long, default_value_t = SampleNo::default(), conflicts_with = "start")]
+        from_slot: SampleNo,
+        /// Do not continue searching for an empty slot from the first slot.
+        #[arg(long, default_value = "false")]
+        no_wrap: bool,
+        /// Mono convertion mode. Defaults to mid.
+        #[arg(short, long, value_enum)]
+        mono_mode: Option<MonoMode>,
         /// Converted audio output path.
         #[arg(short, long)]
         output: Option<PathBuf>,
         /// Do not upload the sample after convertion.
         #[arg(long, default_value = "false")]
         dry_run: bool,
+        /// Play the converted sample and ask before uploading it.
+        ///
+        /// Nothing is played if stdin is not a terminal. With `--dry-run`, the sample is played
+        /// without asking.
+        #[arg(long, default_value = "false")]
+        listen: bool,
+        /// Sample name. File name is used if not provided. Only for a single file.
+        #[arg(short, long)]
+        name: Option<String>,
+        /// Overwrite the slot holding a sample with the same name without confirmation.
+        ///
+        /// Falls back to the first empty slot if there is no such sample.
+        #[arg(long, default_value = "false", conflicts_with = "start")]
+        replace_by_name: bool,
+        /// Fail if there is no sample to replace.
+        #[arg(long, default_value = "false", requires = "replace_by_name")]
+        strict: bool,
+        /// Do not upload a sample if the slot already holds it.
+        ///
+        /// Only sample name and length are compared unless `--deep` is passed. Speed and level
+        /// of a skipped slot are kept.
+        #[arg(long, default_value = "false")]
+        skip_identical: bool,
+        /// Download the sample in the slot and compare its data as well.
+        #[arg(long, default_value = "false", requires = "skip_identical")]
+        deep: bool,
+        /// Convert the sample to half of the device sample rate to save memory.
+        ///
+        /// Sample speed is set so that it plays at the original pitch. Same as
+        /// `--target-rate 15625`.
+        #[arg(long, default_value = "false", conflicts_with = "target_rate")]
+        economy: bool,
+        /// Sample rate to convert the sample to. Sample speed is adjusted accordingly.
+        #[arg(long, value_parser = clap::value_parser!(u32).range(7813..=31250))]
+        target_rate: Option<u32>,
+        /// Resampling algorithm.
+        ///
+        /// `sinc` is slower than the default `fft`, but can be tuned with `--quality`. `linear`
+        /// is the fastest, but aliases when downsampling.
+        #[arg(long, value_enum, default_value_t = ResampleMode::Fft)]
+        resampler: ResampleMode,
+        /// Quality of the `sinc` resampler: length and window of the interpolation filter.
+        #[arg(long, value_enum, default_value_t = SincQuality::Medium)]
+        quality: SincQuality,
+        /// Playback speed: semitones like `+3st` or `-5st`, a ratio like `x1.5`, or a raw value.
+        ///
+        /// Applied on top of the speed compensating `--economy` or `--target-rate`. Defaults to
+        /// `+0st`, the original pitch.
+        #[arg(long, allow_hyphen_values = true)]
+        speed: Option<Speed>,
+        /// Playback level: a percentage like `80%` or a raw value. Defaults to `100%`.
+        #[arg(long)]
+        level: Option<Level>,
+        /// Fail instead of changing sample names the device can not store.
+        ///
+        /// Names are limited to 24 ASCII characters. By default, accented letters are
+        /// transliterated, other characters are dropped and long names are truncated.
+        #[arg(long, default_value = "false")]
+        strict_names: bool,
+        /// Cut frequencies below the cutoff in Hz, like rumble under 40Hz.
+        ///
+        /// A Butterworth filter is applied forward and backward, so phase is kept, the cutoff is
+        /// 6dB down and the slope is 24dB per octave.
+        #[arg(long, value_name = "HZ", value_parser = clap::value_parser!(u32).range(1..))]
+        highpass: Option<u32>,
+        /// Cut frequencies above the cutoff in Hz. Same filter as `--highpass`.
+        #[arg(long, value_name = "HZ", value_parser = clap::value_parser!(u32).range(1..))]
+        lowpass: Option<u32>,
+        /// Normalize loudness to the integrated loudness in LUFS, like `-14`.
+        ///
+        /// Loudness is measured per ITU-R BS.1770 after mono convertion. Gain is reduced if the
+        /// sample would peak over `--true-peak`.
+        #[arg(long, allow_hyphen_values = true, value_name = "LUFS")]
+        loudness: Option<f64>,
+        /// True peak ceiling in dBTP for `--loudness`.
+        #[arg(
+            long,
+            default_value_t = -1.,
+            allow_hyphen_values = true,
+            requires = "loudness",
+            value_name = "DBTP"
+        )]
+        true_peak: f64,
+        /// Keep peaks under `--ceiling` with a look-ahead limiter instead of clipping them.
+        ///
+        /// On by default with `--loudness`. Applied after resampling, right before conversion
+        /// to 16 bit.
+        #[arg(long, default_value = "false", overrides_with = "no_limit")]
+        limit: bool,
+        /// Do not limit peaks, even with `--loudness`.
+        #[arg(long, default_value = "false", overrides_with = "limit")]
+        no_limit: bool,
+        /// Limiter ceiling in dBFS.
+        #[arg(long, default_value_t = -0.1, allow_hyphen_values = true, value_name = "DBFS")]
+        ceiling: f64,
+        /// Upload even if the device reports too little free memory.
+        ///
+        /// By default an upload that does not fit fails before anything is sent.
+        #[arg(long, default_value = "false")]
+        ignore_space: bool,
+    },
+    /// Upload a file into a slot every time the file changes.
+    ///
+    /// The sample slot is overwritten without confirmation.
+    Watch {
+        /// Path to audio file to watch.
+        file: PathBuf,
+        /// Sample slot number.
+        sample_no: SampleNo,
+        /// Mono convertion mode. Defaults to mid.
+        #[arg(short, long, value_enum)]
+        mono_mode: Option<MonoMode>,
+        /// Time to wait for the file to settle before uploading it.
+        #[arg(short, long, default_value = "500ms")]
+        debounce: humantime::Duration,
+    },
+    /// Split an audio file into equal parts and load them into consecutive slots.
+    ///
+    /// Slices are named after the file with a slice number appended.
+    Slice {
+        /// Path to audio file to split.
+        file: PathBuf,
+        /// Number of slices.
+        #[arg(short, long, value_parser = clap::value_parser!(u8).range(1..=i64::from(SampleNo::COUNT)))]
+        parts: u8,
+        /// Sample slot for the first slice.
+        #[arg(short, long)]
+        start_slot: SampleNo,
+        /// Mono convertion mode. Defaults to mid.
+        #[arg(short, long, value_enum)]
+        mono_mode: Option<MonoMode>,
+        /// Overwrite occupied slots without confirmation.
+        #[arg(short, long, default_value = "false")]
+        force: bool,
+        /// Print slice boundaries without uploading anything.
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
+        /// Upload even if the device reports too little free memory.
+        #[arg(long, default_value = "false")]
+        ignore_space: bool,
+    },
+    /// Join several audio files into one sample.
+    #[command(group(clap::ArgGroup::new("target").required(true).multiple(true).args(["slot", "output"])))]
+    Concat {
+        /// Paths to audio files to join.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Sample slot number to load the result into.
+        #[arg(short, long)]
+        slot: Option<SampleNo>,
+        /// Sample name.
+        #[arg(short, long)]
+        name: String,
+        /// Silence to insert between files.
+        #[arg(short, long, default_value = "0s")]
+        gap: humantime::Duration,
+        /// Mono convertion mode. Defaults to mid.
+        #[arg(short, long, value_enum)]
+        mono_mode: Option<MonoMode>,
+        /// Save the result at the provided path. Nothing is uploaded unless `--slot` is passed.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Normalize loudness to the integrated loudness in LUFS, like `-14`.
+        ///
+        /// Every file is normalized separately before they are joined. Loudness is measured per
+        /// ITU-R BS.1770 after mono convertion. Gain is reduced if a file would peak over
+        /// `--true-peak`.
+        #[arg(long, allow_hyphen_values = true, value_name = "LUFS")]
+        loudness: Option<f64>,
+        /// True peak ceiling in dBTP for `--loudness`.
+        #[arg(
+            long,
+            default_value_t = -1.,
+            allow_hyphen_values = true,
+            requires = "loudness",
+            value_name = "DBTP"
+        )]
+        true_peak: f64,
+        /// Keep peaks under `--ceiling` with a look-ahead limiter instead of clipping them.
+        ///
+        /// On by default with `--loudness`. Applied after resampling, right before conversion
+        /// to 16 bit.
+        #[arg(long, default_value = "false", overrides_with = "no_limit")]
+        limit: bool,
+        /// Do not limit peaks, even with `--loudness`.
+        #[arg(long, default_value = "false", overrides_with = "limit")]
+        no_limit: bool,
+        /// Limiter ceiling in dBFS.
+        #[arg(long, default_value_t = -0.1, allow_hyphen_values = true, value_name = "DBFS")]
+        ceiling: f64,
+    },
+    /// Save a sample as a SysEx file.
+    ///
+    /// The file contains sample header and data dumps, which can be sent to the device by any
+    /// SysEx tool to load the sample back.
+    ExportSyx {
+        /// Sample slot number.
+        sample_no: SampleNo,
+        /// Output file path.
+        output: PathBuf,
+        /// Save the sample data even if its length differs from the sample header.
+        #[arg(long, default_value = "false")]
+        tolerate_length_mismatch: bool,
+    },
+    /// Upload samples from a SysEx file.
+    ///
+    /// The file must contain one or more Volca Sample 2 sample header and data dump pairs, such
+    /// as the files written by `export-syx`.
+    ImportSyx {
+        /// SysEx file path.
+        file: PathBuf,
+        /// Upload into this slot instead of the one stored in the file. Further samples go into
+        /// the following slots.
+        #[arg(short, long)]
+        slot: Option<SampleNo>,
+        /// Print samples contained in the file without uploading them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Manage sequence patterns.
+    Pattern {
+        #[command(subcommand)]
+        cmd: PatternOperation,
+    },
+    /// Save or restore the global settings of the device.
+    Settings {
+        #[command(subcommand)]
+        cmd: SettingsOperation,
+    },
+    /// Run commands interactively over a single device connection.
+    Shell,
+    /// Browse the sample memory in a full-screen terminal interface.
+    #[cfg(feature = "tui")]
+    Tui,
+    /// Print SysEx messages sent by the device.
+    Monitor {
+        /// Print decoded contents of known messages.
+        #[arg(short, long, default_value = "false")]
+        decode: bool,
+    },
+    /// Diagnose connectivity problems.
+    Doctor,
+    /// List ports of the selected backend with the names accepted by `--device`.
+    ListPorts,
+    /// Inspect configuration.
+    Config {
+        #[command(subcommand)]
+        cmd: ConfigOperation,
     },
     /// Erase sample from device memory
     #[command(alias = "rm")]
     Remove {
-        /// Sample slot number.
-        sample_no: u8,
+        /// Sample slot number, sample name or a name pattern.
+        ///
+        /// Numbers are always treated as slot numbers. Names are matched the same way as in
+        /// Download command. Patterns may contain `*` and `?` wildcards and can match several
+        /// samples.
+        sample: SampleSelector,
         /// Print sample name.
         #[arg(short, long, default_value = "false")]
         print_name: bool,
+        /// Remove all samples matching the name.
+        #[arg(long, default_value = "false")]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SettingsOperation {
+    /// Save the global settings, e.g. MIDI channels and tempo range, to a file.
+    Dump {
+        /// Output file.
+        file: PathBuf,
+    },
+    /// Write global settings saved by `dump` back into the device.
+    Restore {
+        /// Settings file.
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum PatternOperation {
+    /// Save all patterns stored in the device.
+    ///
+    /// Each pattern is written to its own file along with an index file.
+    Backup {
+        /// Output directory. Will be created if it does not exist.
+        dir: PathBuf,
+    },
+    /// Load patterns into the device.
+    Restore {
+        /// Pattern backup directory or a single pattern file.
+        path: PathBuf,
+        /// Pattern number to load a single pattern file into. Inferred from the file name if
+        /// not provided.
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=16))]
+        to: Option<u8>,
+    },
+    /// Save the pattern being edited on the device, including unsaved changes.
+    GetCurrent {
+        /// Output file.
+        file: PathBuf,
+    },
+    /// Replace the pattern being edited on the device. The stored patterns are not changed.
+    ///
+    /// Accepts files written by `get-current` and `backup`.
+    SetCurrent {
+        /// Pattern file.
+        file: PathBuf,
     },
 }
+
+#[derive(Subcommand)]
+pub enum ConfigOperation {
+    /// Print the location of the configuration file.
+    Path,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, Default)]
+pub enum SortKey {
+    /// Sample slot number.
+    #[default]
+    Slot,
+    /// Sample name (case-insensitive).
+    Name,
+    /// Sample length.
+    Length,
+}
+
+/// Splits `upload` positional arguments into files and an optional sample slot number, which
+/// is only allowed after a single file.
+pub fn split_upload_args(
+    mut files: Vec<PathBuf>,
+    start: Option<SampleNo>,
+    name: Option<&str>,
+    replace_by_name: bool,
+) -> Result<(Vec<PathBuf>, Option<SampleNo>), clap::Error> {
+    let conflict = |msg: &str| Opts::command().error(ErrorKind::ArgumentConflict, msg);
+
+    let sample_no = match files.last() {
+        Some(last) if files.len() > 1 && !last.exists() => last
+            .to_str()
+            .filter(|arg| arg.bytes().all(|c| c.is_ascii_digit()))
+            .map(|arg| {
+                arg.parse::<SampleNo>().map_err(|err| {
+                    Opts::command().error(
+                        ErrorKind::ValueValidation,
+                        format!("invalid sample slot number '{arg}': {err}"),
+                    )
+                })
+            })
+            .transpose()?,
+        _ => None,
+    };
+    if sample_no.is_some() {
+        files.pop();
+    }
+
+    if sample_no.is_some() && files.len() > 1 {
+        return Err(conflict(
+            "sample slot number can only be used with a single file, use --start instead",
+        ));
+    }
+    if sample_no.is_some() && start.is_some() {
+        return Err(conflict("sample slot number cannot be used with --start"));
+    }
+    if sample_no.is_some() && replace_by_name {
+        return Err(conflict(
+            "sample slot number cannot be used with --replace-by-name",
+        ));
+    }
+    if name.is_some() && files.len() > 1 {
+        return Err(conflict("--name can only be used with a single file"));
+    }
+
+    Ok((files, sample_no))
+}
+
+/// Sample referenced either by slot number or by name.
+#[derive(Debug, Clone)]
+pub enum SampleSelector {
+    Slot(SampleNo),
+    Name(String),
+}
+
+impl FromStr for SampleSelector {
+    type Err = ParseSampleNoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit()) {
+            s.parse().map(Self::Slot)
+        } else {
+            Ok(Self::Name(s.to_owned()))
+        }
+    }
+}
+
+#[test]
+fn sample_selector_parse() {
+    let slot = |no: u8| SampleNo::try_from(no).unwrap();
+    assert!(matches!("42".parse(), Ok(SampleSelector::Slot(no)) if no == slot(42)));
+    assert!(matches!("199".parse(), Ok(SampleSelector::Slot(no)) if no == SampleNo::MAX));
+    assert!(matches!("kick909".parse(), Ok(SampleSelector::Name(name)) if name == "kick909"));
+    assert!(matches!("kick 42".parse(), Ok(SampleSelector::Name(name)) if name == "kick 42"));
+    // Numbers are never treated as names
+    assert!("200".parse::<SampleSelector>().is_err());
+    assert!("420".parse::<SampleSelector>().is_err());
+}
+
+#[test]
+fn upload_args() {
+    let paths = |args: &[&str]| args.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+    let (files, sample_no) =
+        split_upload_args(paths(&["kick.wav", "12"]), None, None, false).unwrap();
+    assert_eq!(files, paths(&["kick.wav"]));
+    assert_eq!(sample_no, SampleNo::try_from(12u8).ok());
+
+    let (files, sample_no) =
+        split_upload_args(paths(&["kick.wav", "snare.wav"]), None, None, false).unwrap();
+    assert_eq!(files, paths(&["kick.wav", "snare.wav"]));
+    assert_eq!(sample_no, None);
+
+    assert!(split_upload_args(paths(&["kick.wav", "snare.wav", "12"]), None, None, false).is_err());
+    assert!(split_upload_args(
+        paths(&["kick.wav", "12"]),
+        SampleNo::try_from(3u8).ok(),
+        None,
+        false
+    )
+    .is_err());
+    assert!(split_upload_args(paths(&["kick.wav", "200"]), None, None, false).is_err());
+    assert!(split_upload_args(paths(&["kick.wav", "300"]), None, None, false).is_err());
+    assert!(split_upload_args(paths(&["kick.wav", "snare.wav"]), None, Some("x"), false).is_err());
+}
+
+#[test]
+fn shell_command_parse() {
+    let parse = |line: &str| ShellCommand::try_parse_from(shell_words::split(line).unwrap());
+
+    assert!(matches!(
+        parse("up 'my kick.wav' 12").map(|cmd| cmd.cmd),
+        Ok(Operation::Upload { files, .. })
+            if files == [PathBuf::from("my kick.wav"), PathBuf::from("12")]
+    ));
+    assert!(matches!(
+        parse("rm 13").map(|cmd| cmd.cmd),
+        Ok(Operation::Remove {
+            sample: SampleSelector::Slot(no),
+            ..
+        }) if no.get() == 13
+    ));
+    assert!(parse("frobnicate").is_err());
+}
+
+#[test]
+fn loudness_parse() {
+    let parse = |line: &str| ShellCommand::try_parse_from(shell_words::split(line).unwrap());
+
+    assert!(matches!(
+        parse("up kick.wav --loudness -14").map(|cmd| cmd.cmd),
+        Ok(Operation::Upload {
+            loudness: Some(loudness),
+            true_peak,
+            ..
+        }) if loudness == -14. && true_peak == -1.
+    ));
+    assert!(matches!(
+        parse("up kick.wav --loudness=-9.5 --true-peak -0.3").map(|cmd| cmd.cmd),
+        Ok(Operation::Upload {
+            loudness: Some(loudness),
+            true_peak,
+            ..
+        }) if loudness == -9.5 && true_peak == -0.3
+    ));
+    assert!(parse("up kick.wav --true-peak -2").is_err());
+}
+
+#[test]
+fn limit_parse() {
+    let parse = |line: &str| {
+        ShellCommand::try_parse_from(shell_words::split(line).unwrap()).map(|cmd| match cmd.cmd {
+            Operation::Upload {
+                limit,
+                no_limit,
+                ceiling,
+                ..
+            } => (limit, no_limit, ceiling),
+            _ => unreachable!(),
+        })
+    };
+
+    assert_eq!(parse("up kick.wav").unwrap(), (false, false, -0.1));
+    assert_eq!(
+        parse("up kick.wav --limit --ceiling -1").unwrap(),
+        (true, false, -1.)
+    );
+    assert_eq!(
+        parse("up kick.wav --limit --no-limit").unwrap(),
+        (false, true, -0.1)
+    );
+}
+
+#[test]
+fn resampler_parse() {
+    let parse = |line: &str| {
+        ShellCommand::try_parse_from(shell_words::split(line).unwrap()).map(|cmd| match cmd.cmd {
+            Operation::Upload {
+                resampler, quality, ..
+            } => (resampler, quality),
+            _ => unreachable!(),
+        })
+    };
+
+    assert_eq!(
+        parse("up kick.wav").unwrap(),
+        (ResampleMode::Fft, SincQuality::Medium)
+    );
+    assert_eq!(
+        parse("up kick.wav --resampler sinc --quality high").unwrap(),
+        (ResampleMode::Sinc, SincQuality::High)
+    );
+    assert_eq!(
+        parse("up kick.wav --resampler linear").unwrap().0,
+        ResampleMode::Linear
+    );
+    assert!(parse("up kick.wav --resampler cubic").is_err());
+}
+
+#[test]
+fn wait_parse() {
+    let wait = |args: &[&str]| {
+        let args = ["volsa2-cli"].iter().chain(args).chain(&["list"]);
+        Opts::try_parse_from(args).map(|opts| {
+            opts.wait
+                .map(|wait| wait.map(Into::<std::time::Duration>::into))
+        })
+    };
+    assert_eq!(wait(&[]).unwrap(), None);
+    assert_eq!(wait(&["--wait"]).unwrap(), Some(None));
+    assert_eq!(
+        wait(&["--wait=30s"]).unwrap(),
+        Some(Some(std::time::Duration::from_secs(30)))
+    );
+}
@@ -0,0 +1,60 @@
+//! Playback of converted samples through the local audio output.
+
+use anyhow::Result;
+
+#[cfg(target_os = "linux")]
+use anyhow::{bail, Context};
+
+#[cfg(target_os = "linux")]
+use crate::util::{interrupted, Interrupted};
+
+/// Frames written at once, so that playback can be stopped with Ctrl-C.
+#[cfg(target_os = "linux")]
+const PERIOD: usize = 1024;
+
+/// Plays 16 bit mono audio through the default ALSA output and waits until it ends.
+#[cfg(target_os = "linux")]
+pub fn play(data: &[i16], sample_rate: u32) -> Result<()> {
+    use alsa::pcm::{Access, Format, HwParams, PCM};
+    use alsa::{Direction, ValueOr};
+
+    let pcm = PCM::new("default", Direction::Playback, false)
+        .context("could not open the default audio output")?;
+    {
+        let params = HwParams::any(&pcm)?;
+        params.set_channels(1)?;
+        params.set_rate(sample_rate, ValueOr::Nearest)?;
+        params.set_format(Format::s16())?;
+        params.set_access(Access::RWInterleaved)?;
+        pcm.hw_params(&params)?;
+        // The output may pick another rate, the sample would play at a wrong pitch then
+        let rate = params.get_rate()?;
+        if rate != sample_rate {
+            bail!(
+                "the audio output does not support {sample_rate}Hz, the nearest rate is {rate}Hz"
+            );
+        }
+    }
+    let io = pcm.io_i16()?;
+
+    for mut period in data.chunks(PERIOD) {
+        if interrupted() {
+            pcm.drop()?;
+            bail!(Interrupted("playing the sample"));
+        }
+        while !period.is_empty() {
+            match io.writei(period) {
+                Ok(written) => period = &period[written..],
+                // Recovers from underruns
+                Err(err) => pcm.try_recover(err, false)?,
+            }
+        }
+    }
+    pcm.drain()?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn play(_data: &[i16], _sample_rate: u32) -> Result<()> {
+    anyhow::bail!("playback is only supported on Linux")
+}
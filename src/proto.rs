@@ -1,4 +1,6 @@
+mod global;
 mod header;
+mod pattern;
 mod sample;
 mod system;
 
@@ -9,13 +11,21 @@ use derive_more::Display;
 use hex_literal::hex;
 use thiserror::Error;
 
-use crate::seven_bit::U7;
+use crate::seven_bit::{cast_checked, InvalidU7, U7};
 use crate::util;
 
-pub use header::{ExtendedKorgSysEx, Header, KorgSysEx, ParseHeaderError};
+pub use global::{GlobalData, GlobalDataDumpRequest};
+pub use header::{Header, ParseHeaderError};
+pub use pattern::{CurrentPatternData, CurrentPatternDataDumpRequest};
+pub use pattern::{PatternData, PatternDataDumpRequest, PATTERN_COUNT};
+pub use sample::SampleHeaderError;
+pub use sample::{Level, SampleSpaceDump, SampleSpaceDumpRequest, Speed};
+pub use sample::{ParseSampleNoError, SampleNo};
 pub use sample::{SampleData, SampleDataDumpRequest, SampleHeader, SampleHeaderDumpRequest};
-pub use sample::{SampleSpaceDump, SampleSpaceDumpRequest};
-pub use system::{SearchDeviceReply, SearchDeviceRequest, Status};
+#[cfg(test)]
+pub use system::Manufacturer;
+pub use system::{IdentityReply, IdentityRequest};
+pub use system::{NakStatus, SearchDeviceReply, SearchDeviceRequest, Status};
 
 #[derive(Debug, Error)]
 pub enum ParseError {
@@ -34,6 +44,24 @@ pub enum ParseError {
     InvalidEndByte,
     #[error("invalid string: {0}")]
     MalformedString(#[from] FromUtf8Error),
+    #[error("invalid 7-bit data: {0}")]
+    Not7Bit(#[from] InvalidU7),
+    /// Parsing the payload failed `offset` bytes into it.
+    #[error("{source} at byte {offset} of the payload")]
+    At {
+        offset: usize,
+        source: Box<ParseError>,
+    },
+}
+
+impl ParseError {
+    /// Marks where in the payload parsing failed, see [`Incoming::parse_with_offset`].
+    fn at(self, offset: usize) -> Self {
+        Self::At {
+            offset,
+            source: Box::new(self),
+        }
+    }
 }
 
 /// Exclusive status magic.
@@ -41,15 +69,18 @@ pub const EST: u8 = 0xF0;
 /// End of exclusive magic.
 pub const EOX: u8 = 0xF7;
 
+/// Universal Non-Realtime SysEx ID.
+pub const UNIVERSAL_NON_REALTIME: u8 = 0x7E;
+
 /// KORG manufacturer ID.
 pub const KORG_ID: u8 = 0x42;
 /// Volca Sample 2 ID.
 pub const VOLCA_SAMPLE_2_ID: [u8; 4] = hex!("2D 01 08 00");
 
-/// Volca Sample firmware version
-#[derive(Debug, Display, Clone, Copy)]
+/// Volca Sample firmware version, major and minor.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
 #[display(fmt = "{}.{}", "self.0", "self.1")]
-pub struct Version(u16, u16);
+pub struct Version(pub u16, pub u16);
 
 /// A common message trait that is implemented for all supported SysEx message types.
 pub trait Message: Sized {
@@ -71,28 +102,47 @@ pub trait Message: Sized {
 }
 
 /// A Message that can be *transmitted by* KORG Volca Sample 2.
+///
+/// Requests implement it too, so that traffic can be decoded from the device's side.
 pub trait Incoming: Message {
     fn parse(slice: &[u8]) -> Result<(Self::Header, Self), ParseError> {
-        let (header, data) = Self::Header::split_and_parse(slice)?;
+        Self::parse_with_offset(slice).map_err(|(err, _)| err)
+    }
+
+    /// Like [`parse`](Self::parse), but also returns the offset of the part of `slice` that could
+    /// not be parsed: the header, function ID, end byte or payload. Payload parsers mark where
+    /// they failed with `ParseError::at`, otherwise the start of the payload is reported.
+    fn parse_with_offset(slice: &[u8]) -> Result<(Self::Header, Self), (ParseError, usize)> {
+        let id_offset = <Self::Header as Header>::LEN;
+        let data_offset = id_offset + <Self::Id as util::Array>::LEN;
+
+        let (header, data) = Self::Header::split_and_parse(slice).map_err(|err| (err.into(), 0))?;
         if data.len() < <Self::Id as util::Array>::LEN {
-            return Err(ParseHeaderError::InvalidLength.into());
+            return Err((ParseHeaderError::InvalidLength.into(), id_offset));
         }
         let (id, data) = data.split_at(<Self::Id as util::Array>::LEN);
 
         if id != Self::ID.as_ref() {
-            return Err(ParseHeaderError::IvanlidId {
+            let err = ParseHeaderError::IvanlidId {
                 expected: Self::ID.as_ref().to_vec().into_boxed_slice(),
-                received: id.as_ref().to_vec().into_boxed_slice(),
-            }
-            .into());
+                received: id.to_vec().into_boxed_slice(),
+            };
+            return Err((err.into(), id_offset));
         }
-        let (end, data) = data.split_last().ok_or(ParseHeaderError::InvalidLength)?;
+        let (end, data) = data
+            .split_last()
+            .ok_or((ParseHeaderError::InvalidLength.into(), data_offset))?;
         if *end != EOX {
-            return Err(ParseError::InvalidEndByte);
+            return Err((ParseError::InvalidEndByte, slice.len() - 1));
         }
 
-        Self::check_length(data)?;
-        Self::parse_data(data).map(|data| (header, data))
+        Self::check_length(data).map_err(|err| (err, data_offset))?;
+        Self::parse_data(data)
+            .map(|data| (header, data))
+            .map_err(|err| match err {
+                ParseError::At { offset, source } => (*source, data_offset + offset),
+                err => (err, data_offset),
+            })
     }
 
     fn check_length(slice: &[u8]) -> Result<(), ParseError> {
@@ -110,6 +160,8 @@ pub trait Incoming: Message {
 }
 
 /// A Message that can be *received by* KORG Volca Sample 2.
+///
+/// Replies implement it too, e.g. to emulate the device.
 pub trait Outgoing: Message {
     fn encode(&self, header: Self::Header, mut dest: impl io::Write) -> io::Result<()> {
         dest.write_all(header.encode().as_ref())?;
@@ -119,6 +171,11 @@ pub trait Outgoing: Message {
     }
 
     fn encode_data(&self, dest: impl io::Write) -> io::Result<()>;
+
+    /// Length of the encoded message, if it is known before encoding.
+    fn encoded_len(&self) -> Option<usize> {
+        Self::len_hint()
+    }
 }
 
 fn write_u8(mut dest: impl io::Write, value: u8) -> io::Result<()> {
@@ -126,14 +183,115 @@ fn write_u8(mut dest: impl io::Write, value: u8) -> io::Result<()> {
     dest.write_all(&[lsb.as_u8(), msb])
 }
 
-// Panics if slice length is less than 2
-fn read_u8(slice: &[u8]) -> (u8, &[u8]) {
-    let (sample_no, data) = slice.split_at(2);
-    let [lsb, msb]: [u8; 2] = sample_no.try_into().expect("checked at split");
-    (U7::new(lsb).merge(msb == 1), data)
+/// Reads a byte written by [`write_u8`] and returns it with the rest of `slice`.
+fn read_u8(slice: &[u8]) -> Result<(u8, &[u8]), ParseError> {
+    let (&[lsb, msb], data) = slice
+        .split_first_chunk::<2>()
+        .ok_or(ParseError::NotEnoughData)?;
+    let msb = match msb {
+        0 => false,
+        1 => true,
+        _ => return Err(ParseError::InvalidData),
+    };
+    Ok((U7::try_from(lsb)?.merge(msb), data))
+}
+
+/// Checks that `slice`, found `offset` bytes into the payload, holds only 7-bit values. Fails at
+/// the first byte that does not.
+fn cast_payload(slice: &[u8], offset: usize) -> Result<&[U7], ParseError> {
+    cast_checked(slice).map_err(|err| {
+        let position = slice
+            .iter()
+            .position(|byte| byte & 0b1000_0000 != 0)
+            .unwrap_or_default();
+        ParseError::from(err).at(offset + position)
+    })
+}
+
+#[test]
+fn read_u8_bounds() {
+    for value in [0, 126, 127, 128, 255] {
+        let mut raw = Vec::new();
+        write_u8(&mut raw, value).unwrap();
+        raw.push(EOX);
+        assert_eq!(read_u8(&raw).unwrap(), (value, &[EOX][..]));
+    }
+    assert!(matches!(read_u8(&[0x80, 0]), Err(ParseError::Not7Bit(_))));
+    assert!(matches!(read_u8(&[0x00, 2]), Err(ParseError::InvalidData)));
+    assert!(matches!(read_u8(&[0x00]), Err(ParseError::NotEnoughData)));
 }
 
 #[test]
 fn version_fmt() {
     assert_eq!(format!("{}", Version(42, 69)), "42.69");
 }
+
+#[test]
+fn messages_round_trip() {
+    use std::fmt::Debug;
+
+    fn round_trip<T: Incoming + Outgoing + PartialEq + Debug>(msg: T) {
+        let mut raw = Vec::new();
+        msg.encode(T::Header::from_channel(U7::new(5)), &mut raw)
+            .unwrap();
+        if let Some(len) = T::len_hint() {
+            assert_eq!(raw.len(), len, "{msg:?}");
+        }
+        let (_, parsed) = T::parse(&raw).unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    let sample_no = SampleNo::try_from(123u8).unwrap();
+    let data = (0..=255).cycle().take(100).collect::<Vec<u8>>();
+
+    round_trip(GlobalDataDumpRequest);
+    round_trip(GlobalData { data: data.clone() });
+    round_trip(PatternDataDumpRequest { pattern_no: 15 });
+    round_trip(PatternData {
+        pattern_no: 15,
+        data: data.clone(),
+    });
+    round_trip(CurrentPatternDataDumpRequest);
+    round_trip(CurrentPatternData { data });
+    round_trip(SampleSpaceDumpRequest);
+    round_trip(SampleSpaceDump {
+        all_sector_size: 4096,
+        used_sector_size: 1234,
+    });
+    round_trip(SampleHeaderDumpRequest { sample_no });
+    round_trip(
+        SampleHeader::builder(sample_no)
+            .name("kick")
+            .length_of(&[1, 2, 3])
+            .build()
+            .unwrap(),
+    );
+    round_trip(SampleHeader::empty(sample_no));
+    round_trip(SampleDataDumpRequest { sample_no });
+    round_trip(SampleData {
+        sample_no,
+        data: vec![0, 1, -1, i16::MIN, i16::MAX],
+    });
+    round_trip::<Status>(Ok(()));
+    round_trip::<Status>(Err(NakStatus::Busy));
+    round_trip::<Status>(Err(NakStatus::Unknown(0x31)));
+    round_trip(SearchDeviceRequest { echo: U7::new(42) });
+    round_trip(SearchDeviceReply {
+        echo: U7::new(42),
+        device_id: U7::new(3),
+        version: Version(1, 2),
+    });
+    round_trip(IdentityRequest);
+    round_trip(IdentityReply {
+        manufacturer: Manufacturer::Short(KORG_ID),
+        family: [0x2D, 0x01],
+        model: [0x08, 0x00],
+        version: [0x02, 0x00, 0x01, 0x00],
+    });
+    round_trip(IdentityReply {
+        manufacturer: Manufacturer::Extended([0x20, 0x29]),
+        family: [1, 2],
+        model: [3, 4],
+        version: [1, 2, 3, 4],
+    });
+}
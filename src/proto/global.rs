@@ -0,0 +1,103 @@
+//! Messages for interacting with volca's global settings.
+
+use std::io;
+
+use bytemuck::cast_slice;
+
+use crate::seven_bit::{FromKorgData, IntoKorgData};
+
+use super::header::ExtendedKorgSysEx;
+use super::{cast_payload, Incoming, Message, Outgoing, ParseError};
+
+/// Request [`GlobalData`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GlobalDataDumpRequest;
+
+impl Message for GlobalDataDumpRequest {
+    type Header = ExtendedKorgSysEx;
+    type Id = [u8; 1];
+
+    const ID: [u8; 1] = [0x0E];
+    const LEN: Option<usize> = Some(0);
+}
+
+impl Outgoing for GlobalDataDumpRequest {
+    fn encode_data(&self, _: impl io::Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Incoming for GlobalDataDumpRequest {
+    fn parse_data(_: &[u8]) -> Result<Self, ParseError> {
+        Ok(Self)
+    }
+}
+
+/// Global settings of the device, e.g. MIDI channels and tempo range.
+///
+/// Settings are not interpreted, the decoded payload is kept as is. Its length is not checked,
+/// firmware updates may add settings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GlobalData {
+    pub data: Vec<u8>,
+}
+
+impl Message for GlobalData {
+    type Header = ExtendedKorgSysEx;
+    type Id = [u8; 1];
+
+    const ID: [u8; 1] = [0x51];
+}
+
+impl Incoming for GlobalData {
+    fn parse_data(slice: &[u8]) -> Result<Self, ParseError> {
+        let data = FromKorgData::from_slice(cast_payload(slice, 0)?).collect();
+        Ok(Self { data })
+    }
+}
+
+impl Outgoing for GlobalData {
+    fn encode_data(&self, mut dest: impl io::Write) -> io::Result<()> {
+        let buf = IntoKorgData::new(self.data.iter().copied()).collect::<Vec<_>>();
+        dest.write_all(cast_slice(&buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::Header;
+    use crate::seven_bit::U7;
+    use hex_literal::hex;
+
+    #[test]
+    fn global_data_request() {
+        let mut raw = Vec::new();
+        GlobalDataDumpRequest
+            .encode(ExtendedKorgSysEx::from_channel(U7::new(0)), &mut raw)
+            .unwrap();
+        assert_eq!(raw, hex!("F0 42 30 00 01 2D 0E F7"));
+    }
+
+    #[test]
+    fn global_data_any_length() {
+        for len in [0, 1, 7, 8, 100, 1001] {
+            let global = GlobalData {
+                data: (0..=255).cycle().take(len).collect(),
+            };
+            let mut raw = Vec::new();
+            global
+                .encode(ExtendedKorgSysEx::from_channel(U7::new(0)), &mut raw)
+                .unwrap();
+            let (_, parsed) = GlobalData::parse(&raw).unwrap();
+            assert_eq!(parsed, global);
+        }
+
+        // Payload of a longer dump cut anywhere still parses
+        let raw = hex!("F0 42 30 00 01 2D 51 01 7F 05 F7");
+        let (_, parsed) = GlobalData::parse(&raw).unwrap();
+        assert_eq!(parsed.data, [0xFF, 0x05]);
+        let raw = hex!("F0 42 30 00 01 2D 51 01 7F 05 00 01 02 03 04 00 F7");
+        assert!(GlobalData::parse(&raw).is_ok());
+    }
+}
@@ -6,7 +6,7 @@ use thiserror::Error;
 use crate::seven_bit::U7;
 use crate::util;
 
-use super::{EST, KORG_ID};
+use super::{EST, KORG_ID, UNIVERSAL_NON_REALTIME};
 
 #[derive(Debug, Error)]
 pub enum ParseHeaderError {
@@ -115,3 +115,38 @@ impl Header for ExtendedKorgSysEx {
         }
     }
 }
+
+/// Universal Non-Realtime Exclusive header "F0 7E dd", where `dd` is the device ID. Not specific to
+/// Korg, any MIDI device may reply to these messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniversalSysEx {
+    pub device_id: U7,
+}
+
+impl UniversalSysEx {
+    /// Device ID that addresses every device on the port.
+    pub const ALL_CALL: Self = Self { device_id: U7::MAX };
+}
+
+impl Header for UniversalSysEx {
+    type Array = [u8; 3];
+
+    fn parse(slice: &[u8]) -> Result<Self, ParseHeaderError> {
+        match *slice {
+            [EST, UNIVERSAL_NON_REALTIME, device_id] => Ok(Self {
+                device_id: U7::new_checked(device_id).ok_or(ParseHeaderError::InvalidData)?,
+            }),
+            [_, _, _] => Err(ParseHeaderError::InvalidData),
+            _ => Err(ParseHeaderError::InvalidLength),
+        }
+    }
+
+    fn encode(self) -> Self::Array {
+        [EST, UNIVERSAL_NON_REALTIME, self.device_id.as_u8()]
+    }
+
+    /// Requests are sent to all devices, since the channel is not known before discovery.
+    fn from_channel(_: U7) -> Self {
+        Self::ALL_CALL
+    }
+}
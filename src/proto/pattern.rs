@@ -0,0 +1,202 @@
+//! Messages for interacting with volca's pattern storage.
+
+use std::io;
+
+use bytemuck::cast_slice;
+
+use crate::seven_bit::{FromKorgData, IntoKorgData, U7};
+
+use super::header::ExtendedKorgSysEx;
+use super::{cast_payload, Incoming, Message, Outgoing, ParseError};
+
+/// Number of patterns stored in the device memory.
+pub const PATTERN_COUNT: u8 = 16;
+
+/// Request [`PatternData`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternDataDumpRequest {
+    pub pattern_no: u8,
+}
+
+impl Message for PatternDataDumpRequest {
+    type Header = ExtendedKorgSysEx;
+    type Id = [u8; 1];
+
+    const ID: [u8; 1] = [0x1C];
+    const LEN: Option<usize> = Some(1);
+}
+
+impl Outgoing for PatternDataDumpRequest {
+    fn encode_data(&self, mut dest: impl io::Write) -> io::Result<()> {
+        dest.write_all(&[U7::new(self.pattern_no).as_u8()])
+    }
+}
+
+impl Incoming for PatternDataDumpRequest {
+    fn parse_data(slice: &[u8]) -> Result<Self, ParseError> {
+        Ok(Self {
+            pattern_no: U7::try_from(slice[0])?.as_u8(),
+        })
+    }
+}
+
+/// Pattern data.
+///
+/// Pattern fields are not interpreted, the decoded payload is kept as is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatternData {
+    pub pattern_no: u8,
+    pub data: Vec<u8>,
+}
+
+impl Message for PatternData {
+    type Header = ExtendedKorgSysEx;
+    type Id = [u8; 1];
+
+    const ID: [u8; 1] = [0x4C];
+}
+
+impl Incoming for PatternData {
+    fn parse_data(slice: &[u8]) -> Result<Self, ParseError> {
+        let (pattern_no, data) = slice.split_first().ok_or(ParseError::NotEnoughData)?;
+        let data = FromKorgData::from_slice(cast_payload(data, 1)?).collect();
+        Ok(Self {
+            pattern_no: U7::try_from(*pattern_no)?.as_u8(),
+            data,
+        })
+    }
+}
+
+impl Outgoing for PatternData {
+    fn encode_data(&self, mut dest: impl io::Write) -> io::Result<()> {
+        dest.write_all(&[U7::new(self.pattern_no).as_u8()])?;
+        let buf = IntoKorgData::new(self.data.iter().copied()).collect::<Vec<_>>();
+        dest.write_all(cast_slice(&buf))
+    }
+}
+
+/// Request [`CurrentPatternData`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurrentPatternDataDumpRequest;
+
+impl Message for CurrentPatternDataDumpRequest {
+    type Header = ExtendedKorgSysEx;
+    type Id = [u8; 1];
+
+    const ID: [u8; 1] = [0x10];
+    const LEN: Option<usize> = Some(0);
+}
+
+impl Outgoing for CurrentPatternDataDumpRequest {
+    fn encode_data(&self, _: impl io::Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Incoming for CurrentPatternDataDumpRequest {
+    fn parse_data(_: &[u8]) -> Result<Self, ParseError> {
+        Ok(Self)
+    }
+}
+
+/// Data of the pattern being edited. Sending it replaces the current pattern without writing
+/// it to the pattern memory.
+///
+/// Like [`PatternData`], the decoded payload is kept as is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CurrentPatternData {
+    pub data: Vec<u8>,
+}
+
+impl Message for CurrentPatternData {
+    type Header = ExtendedKorgSysEx;
+    type Id = [u8; 1];
+
+    const ID: [u8; 1] = [0x40];
+}
+
+impl Incoming for CurrentPatternData {
+    fn parse_data(slice: &[u8]) -> Result<Self, ParseError> {
+        let data = FromKorgData::from_slice(cast_payload(slice, 0)?).collect();
+        Ok(Self { data })
+    }
+}
+
+impl Outgoing for CurrentPatternData {
+    fn encode_data(&self, mut dest: impl io::Write) -> io::Result<()> {
+        let buf = IntoKorgData::new(self.data.iter().copied()).collect::<Vec<_>>();
+        dest.write_all(cast_slice(&buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::Header;
+    use crate::seven_bit::U7;
+    use hex_literal::hex;
+
+    #[test]
+    fn pattern_data_round_trip() {
+        let pattern = PatternData {
+            pattern_no: 7,
+            data: (0..=255).cycle().take(1000).collect(),
+        };
+
+        let mut raw = Vec::new();
+        pattern
+            .encode(ExtendedKorgSysEx::from_channel(U7::new(0)), &mut raw)
+            .unwrap();
+        let (_, parsed) = PatternData::parse(&raw).unwrap();
+        assert_eq!(parsed, pattern);
+
+        let mut reencoded = Vec::new();
+        parsed
+            .encode(ExtendedKorgSysEx::from_channel(U7::new(0)), &mut reencoded)
+            .unwrap();
+        assert_eq!(reencoded, raw);
+    }
+
+    #[test]
+    fn current_pattern_request() {
+        let mut raw = Vec::new();
+        CurrentPatternDataDumpRequest
+            .encode(ExtendedKorgSysEx::from_channel(U7::new(2)), &mut raw)
+            .unwrap();
+        assert_eq!(raw, hex!("F0 42 32 00 01 2D 10 F7"));
+    }
+
+    #[test]
+    fn current_pattern_data() {
+        // Each group of 7 bytes is preceded by their high bits
+        let raw = hex!("F0 42 30 00 01 2D 40  05 7F 00 01 02 03 04 05  00 06 07 F7");
+        let (_, parsed) = CurrentPatternData::parse(&raw).unwrap();
+        assert_eq!(
+            parsed.data,
+            [0xFF, 0x00, 0x81, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07]
+        );
+
+        let mut reencoded = Vec::new();
+        parsed
+            .encode(ExtendedKorgSysEx::from_channel(U7::new(0)), &mut reencoded)
+            .unwrap();
+        assert_eq!(reencoded, raw);
+
+        // A stored pattern is not the current one
+        assert!(PatternData::parse(&raw).is_err());
+    }
+
+    #[test]
+    fn pattern_no_not_7bit() {
+        let raw = hex!("F0 42 30 00 01 2D 1C 80 F7");
+        assert!(matches!(
+            PatternDataDumpRequest::parse(&raw),
+            Err(ParseError::Not7Bit(_))
+        ));
+        let raw = hex!("F0 42 30 00 01 2D 4C 81 00 F7");
+        assert!(matches!(
+            PatternData::parse(&raw),
+            Err(ParseError::Not7Bit(_))
+        ));
+    }
+}
@@ -1,21 +1,28 @@
 //! Messages for interacting with volca's sample storage.
 
+use std::fmt;
 use std::io;
 use std::mem;
+use std::num::ParseIntError;
+use std::str::FromStr;
+use std::time::Duration;
 
 use arrayref::{array_ref, array_refs};
 use bytemuck::cast_slice;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use crate::seven_bit::{FromKorgData, IntoKorgData, U7ToU8, U8ToU7, U7};
+use crate::audio::{ECONOMY_SAMPLERATE, VOLCA_SAMPLERATE};
+use crate::seven_bit::{Convert, FromKorgData, IntoKorgData, U7ToU8, U8ToU7, U7};
 use crate::util::array_type_refs;
 
 use super::header::ExtendedKorgSysEx;
-use super::{read_u8, write_u8, Incoming, Message, Outgoing, ParseError};
+use super::{cast_payload, read_u8, write_u8, Header, Incoming, Message, Outgoing, ParseError};
 
 // ===== Sample Space =====
 
 /// Request [`SampleSpaceDump`].
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SampleSpaceDumpRequest;
 
 impl Message for SampleSpaceDumpRequest {
@@ -32,17 +39,55 @@ impl Outgoing for SampleSpaceDumpRequest {
     }
 }
 
+impl Incoming for SampleSpaceDumpRequest {
+    fn parse_data(_: &[u8]) -> Result<Self, ParseError> {
+        Ok(Self)
+    }
+}
+
 /// Info about used and available storage.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SampleSpaceDump {
     pub all_sector_size: u16,
     pub used_sector_size: u16,
 }
 
 impl SampleSpaceDump {
+    /// Size of Volca Sample 2 sample memory in bytes.
+    pub const MEMORY_SIZE: u32 = 8 * 1024 * 1024;
+
     pub fn occupied(&self) -> f64 {
         self.used_sector_size as f64 / self.all_sector_size as f64
     }
+
+    pub fn free_sectors(&self) -> u32 {
+        self.all_sector_size
+            .saturating_sub(self.used_sector_size)
+            .into()
+    }
+
+    /// Size of a single sector in bytes.
+    pub fn sector_size(&self) -> u32 {
+        Self::MEMORY_SIZE / u32::from(self.all_sector_size.max(1))
+    }
+
+    /// Estimates how many sectors a sample of `length` occupies.
+    pub fn sectors_for(&self, length: u32) -> u32 {
+        (length * 2).div_ceil(self.sector_size())
+    }
+
+    /// Sample length that fills `sectors` completely.
+    pub fn length_for(&self, sectors: u32) -> u32 {
+        sectors * self.sector_size() / 2
+    }
+
+    /// Number of sectors missing to store `needed` sectors once `freed` sectors are released,
+    /// or `None` if they fit.
+    pub fn shortfall(&self, needed: u32, freed: u32) -> Option<u32> {
+        needed
+            .checked_sub(self.free_sectors() + freed)
+            .filter(|&missing| missing > 0)
+    }
 }
 
 impl Message for SampleSpaceDump {
@@ -72,12 +117,126 @@ impl Incoming for SampleSpaceDump {
     }
 }
 
+impl Outgoing for SampleSpaceDump {
+    fn encode_data(&self, mut dest: impl io::Write) -> io::Result<()> {
+        for sectors in [self.used_sector_size, self.all_sector_size] {
+            dest.write_all(&[(sectors & 0x7F) as u8, (sectors >> 7 & 0x7F) as u8])?;
+        }
+        Ok(())
+    }
+}
+
 // ===== Sample Header =====
 
+/// Sample slot number, less than [`SampleNo::COUNT`].
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct SampleNo(u8);
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("sample slot must be less than {}, got {0}", SampleNo::COUNT)]
+pub struct InvalidSampleNo(pub usize);
+
+impl SampleNo {
+    /// Number of sample slots.
+    pub const COUNT: u8 = 200;
+    pub const MAX: Self = Self(Self::COUNT - 1);
+
+    /// All slots in ascending order.
+    pub fn all() -> impl DoubleEndedIterator<Item = Self> + Clone {
+        (0..Self::COUNT).map(Self)
+    }
+
+    /// Slots from `self` up to the last one.
+    pub fn up_from(self) -> impl DoubleEndedIterator<Item = Self> + Clone {
+        (self.0..Self::COUNT).map(Self)
+    }
+
+    /// Slots before `self`.
+    pub fn below(self) -> impl DoubleEndedIterator<Item = Self> + Clone {
+        (0..self.0).map(Self)
+    }
+
+    pub fn get(self) -> u8 {
+        self.0
+    }
+
+    /// `count` consecutive slots starting from this one. Fails if they do not fit.
+    pub fn range(
+        self,
+        count: usize,
+    ) -> Result<impl DoubleEndedIterator<Item = Self> + Clone + 'static, InvalidSampleNo> {
+        let end = Self::try_from(usize::from(self.0) + count.saturating_sub(1))?;
+        Ok((self.0..=end.0).map(Self).take(count))
+    }
+
+    /// Slot `offset` slots after this one.
+    pub fn checked_add(self, offset: usize) -> Result<Self, InvalidSampleNo> {
+        Self::try_from(usize::from(self.0) + offset)
+    }
+}
+
+impl TryFrom<u8> for SampleNo {
+    type Error = InvalidSampleNo;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::try_from(usize::from(value))
+    }
+}
+
+impl TryFrom<usize> for SampleNo {
+    type Error = InvalidSampleNo;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        if value < Self::COUNT.into() {
+            Ok(Self(value as u8))
+        } else {
+            Err(InvalidSampleNo(value))
+        }
+    }
+}
+
+impl From<SampleNo> for u8 {
+    fn from(value: SampleNo) -> Self {
+        value.0
+    }
+}
+
+impl From<SampleNo> for usize {
+    fn from(value: SampleNo) -> Self {
+        value.0.into()
+    }
+}
+
+impl fmt::Display for SampleNo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Forward to keep width and fill working
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ParseSampleNoError {
+    #[error("invalid sample slot number: {0}")]
+    NotANumber(#[from] ParseIntError),
+    #[error(transparent)]
+    OutOfRange(#[from] InvalidSampleNo),
+}
+
+impl FromStr for SampleNo {
+    type Err = ParseSampleNoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::try_from(s.parse::<usize>()?)?)
+    }
+}
+
 /// Request [`SampleHeader`].
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SampleHeaderDumpRequest {
-    pub sample_no: u8,
+    pub sample_no: SampleNo,
 }
 
 impl Message for SampleHeaderDumpRequest {
@@ -90,14 +249,22 @@ impl Message for SampleHeaderDumpRequest {
 
 impl Outgoing for SampleHeaderDumpRequest {
     fn encode_data(&self, dest: impl io::Write) -> io::Result<()> {
-        write_u8(dest, self.sample_no)
+        write_u8(dest, self.sample_no.get())
+    }
+}
+
+impl Incoming for SampleHeaderDumpRequest {
+    fn parse_data(slice: &[u8]) -> Result<Self, ParseError> {
+        let (sample_no, _) = read_u8(slice)?;
+        let sample_no = SampleNo::try_from(sample_no).map_err(|_| ParseError::InvalidData)?;
+        Ok(Self { sample_no })
     }
 }
 
 /// Meta information about sample.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SampleHeader {
-    pub sample_no: u8,
+    pub sample_no: SampleNo,
     pub name: String,
     pub length: u32,
     pub level: u16,
@@ -106,15 +273,76 @@ pub struct SampleHeader {
 
 impl SampleHeader {
     const DATA_SIZE_7BIT: usize = 37;
-    const NAME_LEN: usize = 24;
-    const DEFAULT_SPEED: u16 = 16384;
+    pub const NAME_LEN: usize = 24;
+    /// Speed value that plays a sample at its original pitch. Speed scales playback rate
+    /// linearly.
+    pub const DEFAULT_SPEED: u16 = 16384;
     const DEFAULT_LEVEL: u16 = 65535;
 
     pub fn is_empty(&self) -> bool {
         self.name.is_empty() && self.length == 0 && self.level == 0 && self.speed == 0
     }
 
-    pub fn empty(sample_no: u8) -> Self {
+    /// Longest prefix of `name` that fits into [`Self::NAME_LEN`] bytes without splitting a
+    /// character.
+    pub fn truncate_name(name: &str) -> &str {
+        let len = (0..=name.len().min(Self::NAME_LEN))
+            .rev()
+            .find(|&len| name.is_char_boundary(len))
+            .unwrap_or_default();
+        &name[..len]
+    }
+
+    /// Sample playback duration at the current speed.
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.length as f64 / self.playback_rate().max(1) as f64)
+    }
+
+    /// Speed value that plays audio sampled at `sample_rate` at its original pitch.
+    ///
+    /// Returns `None` if the speed does not fit into the header.
+    pub fn speed_for_rate(sample_rate: u32) -> Option<u16> {
+        let speed =
+            u64::from(Self::DEFAULT_SPEED) * u64::from(sample_rate) / u64::from(VOLCA_SAMPLERATE);
+        speed.try_into().ok()
+    }
+
+    /// Effective playback sample rate at the current speed.
+    pub fn playback_rate(&self) -> u32 {
+        let rate =
+            u64::from(VOLCA_SAMPLERATE) * u64::from(self.speed) / u64::from(Self::DEFAULT_SPEED);
+        rate as u32
+    }
+
+    /// Current speed in readable units.
+    pub fn speed(&self) -> Speed {
+        Speed(self.speed)
+    }
+
+    /// Current level as a percentage.
+    pub fn level(&self) -> Level {
+        Level(self.level)
+    }
+
+    /// Checks if the sample was stored at [`ECONOMY_SAMPLERATE`].
+    pub fn is_economy(&self) -> bool {
+        Self::speed_for_rate(ECONOMY_SAMPLERATE) == Some(self.speed)
+    }
+
+    /// Starts building a header of a sample in `sample_no`, at the default speed and level.
+    pub fn builder(sample_no: SampleNo) -> SampleHeaderBuilder {
+        SampleHeaderBuilder {
+            header: Self {
+                sample_no,
+                name: String::new(),
+                length: 0,
+                level: Self::DEFAULT_LEVEL,
+                speed: Self::DEFAULT_SPEED,
+            },
+        }
+    }
+
+    pub fn empty(sample_no: SampleNo) -> Self {
         Self {
             sample_no,
             name: String::new(),
@@ -125,6 +353,253 @@ impl SampleHeader {
     }
 }
 
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum SampleHeaderError {
+    #[error("sample name is empty")]
+    EmptyName,
+    #[error("sample name {0:?} is longer than {len} bytes", len = SampleHeader::NAME_LEN)]
+    NameTooLong(String),
+    #[error("sample name {name:?} contains {invalid:?}, only printable ASCII is allowed")]
+    InvalidCharacter { name: String, invalid: char },
+    #[error("sample of {0} samples does not fit into device memory")]
+    TooLong(u32),
+}
+
+/// Builds a [`SampleHeader`] the device can store, see [`SampleHeader::builder`].
+#[derive(Debug, Clone)]
+pub struct SampleHeaderBuilder {
+    header: SampleHeader,
+}
+
+impl SampleHeaderBuilder {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.header.name = name.into();
+        self
+    }
+
+    /// Sets the length to the number of samples in `data`.
+    pub fn length_of(mut self, data: &[i16]) -> Self {
+        self.header.length = data.len().try_into().unwrap_or(u32::MAX);
+        self
+    }
+
+    pub fn level(mut self, level: Level) -> Self {
+        self.header.level = level.raw();
+        self
+    }
+
+    pub fn speed(mut self, speed: Speed) -> Self {
+        self.header.speed = speed.raw();
+        self
+    }
+
+    /// Checks that the name is not empty and fits into [`SampleHeader::NAME_LEN`] bytes of
+    /// printable ASCII, and that the sample fits into the device memory.
+    pub fn build(self) -> Result<SampleHeader, SampleHeaderError> {
+        let header = self.header;
+        if header.name.is_empty() {
+            return Err(SampleHeaderError::EmptyName);
+        }
+        if let Some(invalid) = header
+            .name
+            .chars()
+            .find(|&c| !c.is_ascii_graphic() && c != ' ')
+        {
+            return Err(SampleHeaderError::InvalidCharacter {
+                name: header.name,
+                invalid,
+            });
+        }
+        if header.name.len() > SampleHeader::NAME_LEN {
+            return Err(SampleHeaderError::NameTooLong(header.name));
+        }
+        if u64::from(header.length) * 2 > u64::from(SampleSpaceDump::MEMORY_SIZE) {
+            return Err(SampleHeaderError::TooLong(header.length));
+        }
+        Ok(header)
+    }
+}
+
+/// Sample playback speed, as stored in [`SampleHeader::speed`].
+///
+/// The raw value scales the playback rate linearly, [`SampleHeader::DEFAULT_SPEED`] plays at
+/// the original pitch. Displayed in semitones, or as a playback ratio if the speed is between
+/// semitones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Speed(u16);
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum SpeedError {
+    #[error(
+        "speed must be from -24st to +24st, x0.25 to x4 or {} to {}, got {0}",
+        Speed::MIN.0,
+        Speed::MAX.0
+    )]
+    OutOfRange(String),
+    #[error("invalid speed {0:?}, expected semitones like +3st, a ratio like x1.5 or a raw value")]
+    Invalid(String),
+}
+
+impl Speed {
+    pub const DEFAULT: Self = Self(SampleHeader::DEFAULT_SPEED);
+    /// Two octaves down.
+    pub const MIN: Self = Self(SampleHeader::DEFAULT_SPEED / 4);
+    /// Largest value the header stores, a hair short of two octaves up.
+    pub const MAX: Self = Self(u16::MAX);
+
+    pub fn from_raw(raw: u16) -> Result<Self, SpeedError> {
+        if raw < Self::MIN.0 {
+            return Err(SpeedError::OutOfRange(raw.to_string()));
+        }
+        Ok(Self(raw))
+    }
+
+    /// Speed that scales the playback rate by `ratio`. Ratios up to 4 are accepted, although
+    /// exactly 4 is stored as [`Speed::MAX`].
+    pub fn from_ratio(ratio: f64) -> Result<Self, SpeedError> {
+        let raw = (ratio * f64::from(SampleHeader::DEFAULT_SPEED)).round();
+        if !(f64::from(Self::MIN.0)..=f64::from(Self::MAX.0) + 1.).contains(&raw) {
+            return Err(SpeedError::OutOfRange(format!("x{ratio}")));
+        }
+        Ok(Self((raw as u32).min(Self::MAX.0.into()) as u16))
+    }
+
+    /// Speed that shifts the pitch by `semitones`.
+    pub fn from_semitones(semitones: f64) -> Result<Self, SpeedError> {
+        Self::from_ratio(2f64.powf(semitones / 12.))
+            .map_err(|_| SpeedError::OutOfRange(format!("{semitones:+}st")))
+    }
+
+    pub fn raw(self) -> u16 {
+        self.0
+    }
+
+    /// Speed with the same pitch shift for audio sampled at `sample_rate`.
+    pub fn at_rate(self, sample_rate: u32) -> Result<Self, SpeedError> {
+        let raw = u64::from(self.0) * u64::from(sample_rate) / u64::from(VOLCA_SAMPLERATE);
+        let raw = u16::try_from(raw).map_err(|_| SpeedError::OutOfRange(raw.to_string()))?;
+        Self::from_raw(raw)
+    }
+
+    /// Playback rate relative to the original pitch.
+    pub fn ratio(self) -> f64 {
+        f64::from(self.0) / f64::from(SampleHeader::DEFAULT_SPEED)
+    }
+
+    /// Pitch shift in semitones.
+    pub fn semitones(self) -> f64 {
+        12. * self.ratio().log2()
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let semitones = self.semitones();
+        // Raw values are rounded, so whole semitones are off by a little
+        if (semitones - semitones.round()).abs() < 0.01 {
+            f.pad(&format!("{:+} st", semitones.round() as i32))
+        } else {
+            f.pad(&format!("×{:.2}", self.ratio()))
+        }
+    }
+}
+
+impl FromStr for Speed {
+    type Err = SpeedError;
+
+    /// Parses semitones (`+3st`, `-5 st`), ratios (`x1.5`, `×0.5`) and raw values (`16384`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SpeedError::Invalid(s.to_owned());
+        let trimmed = s.trim();
+        if let Some(semitones) = trimmed.strip_suffix("st") {
+            let semitones = semitones.trim_end().parse().map_err(|_| invalid())?;
+            return Self::from_semitones(semitones);
+        }
+        if let Some(ratio) = ['x', 'X', '×']
+            .iter()
+            .find_map(|x| trimmed.strip_prefix(*x))
+        {
+            return Self::from_ratio(ratio.trim_start().parse().map_err(|_| invalid())?);
+        }
+        match trimmed.parse::<u32>() {
+            Ok(raw) => Self::from_raw(
+                raw.try_into()
+                    .map_err(|_| SpeedError::OutOfRange(s.into()))?,
+            ),
+            Err(_) => Err(invalid()),
+        }
+    }
+}
+
+/// Sample playback level, as stored in [`SampleHeader::level`].
+///
+/// Displayed as a whole percentage of the full level. Only the extremes are shown as `0%` and
+/// `100%`, anything in between is kept off them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Level(u16);
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum LevelError {
+    #[error("level must be from 0% to 100% or 0 to {}, got {0}", u16::MAX)]
+    OutOfRange(String),
+    #[error("invalid level {0:?}, expected a percentage like 80% or a raw value")]
+    Invalid(String),
+}
+
+impl Level {
+    pub const DEFAULT: Self = Self(SampleHeader::DEFAULT_LEVEL);
+
+    pub fn from_raw(raw: u16) -> Self {
+        Self(raw)
+    }
+
+    pub fn from_percent(percent: f64) -> Result<Self, LevelError> {
+        if !(0. ..=100.).contains(&percent) {
+            return Err(LevelError::OutOfRange(format!("{percent}%")));
+        }
+        Ok(Self((percent / 100. * f64::from(u16::MAX)).round() as u16))
+    }
+
+    pub fn raw(self) -> u16 {
+        self.0
+    }
+
+    pub fn percent(self) -> f64 {
+        f64::from(self.0) * 100. / f64::from(u16::MAX)
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = match self.0 {
+            0 => 0,
+            u16::MAX => 100,
+            _ => (self.percent().round() as u8).clamp(1, 99),
+        };
+        f.pad(&format!("{percent}%"))
+    }
+}
+
+impl FromStr for Level {
+    type Err = LevelError;
+
+    /// Parses percentages (`80%`, `12.5 %`) and raw values (`52428`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || LevelError::Invalid(s.to_owned());
+        let trimmed = s.trim();
+        if let Some(percent) = trimmed.strip_suffix('%') {
+            return Self::from_percent(percent.trim_end().parse().map_err(|_| invalid())?);
+        }
+        match trimmed.parse::<u32>() {
+            Ok(raw) => raw
+                .try_into()
+                .map(Self::from_raw)
+                .map_err(|_| LevelError::OutOfRange(s.into())),
+            Err(_) => Err(invalid()),
+        }
+    }
+}
+
 impl Message for SampleHeader {
     type Header = ExtendedKorgSysEx;
     type Id = [u8; 1];
@@ -136,12 +611,12 @@ impl Message for SampleHeader {
 impl Incoming for SampleHeader {
     #[allow(clippy::ptr_offset_with_cast)]
     fn parse_data(slice: &[u8]) -> Result<Self, ParseError> {
-        let (sample_no, data) = read_u8(slice);
+        let (sample_no, data) = read_u8(slice)?;
+        let sample_no = SampleNo::try_from(sample_no).map_err(|_| ParseError::InvalidData)?;
 
-        // TODO: POD cast, reserve
-        let mut data: Vec<u8> = FromKorgData::new(data.iter().copied().map(U7::new)).collect();
+        let mut data: Vec<u8> = FromKorgData::from_slice(cast_payload(data, 2)?).collect();
         if data.len() < 32 {
-            return Err(ParseError::NotEnoughData);
+            return Err(ParseError::NotEnoughData.at(slice.len()));
         }
 
         let sample_props = array_ref![
@@ -163,21 +638,21 @@ impl Incoming for SampleHeader {
             length,
             level,
             speed,
-            name: String::from_utf8(data)?,
+            name: String::from_utf8(data).map_err(|err| ParseError::from(err).at(2))?,
         })
     }
 }
 
 impl Outgoing for SampleHeader {
     fn encode_data(&self, mut dest: impl io::Write) -> io::Result<()> {
-        write_u8(&mut dest, self.sample_no)?;
+        write_u8(&mut dest, self.sample_no.get())?;
         let mut buf = [U7::new(0); Self::DATA_SIZE_7BIT];
 
-        let name_padding = Self::NAME_LEN - self.name.len();
-        let raw_data = self
-            .name
+        let name = Self::truncate_name(&self.name);
+        let name_padding = Self::NAME_LEN - name.len();
+        let raw_data = name
             .bytes()
-            .chain(std::iter::repeat(0).take(name_padding))
+            .chain(std::iter::repeat_n(0, name_padding))
             .chain(self.length.to_le_bytes())
             .chain(self.level.to_le_bytes())
             .chain(self.speed.to_le_bytes());
@@ -192,9 +667,9 @@ impl Outgoing for SampleHeader {
 // ===== Sample Data =====
 
 /// Request [`SampleData`].
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SampleDataDumpRequest {
-    pub sample_no: u8,
+    pub sample_no: SampleNo,
 }
 
 impl Message for SampleDataDumpRequest {
@@ -207,21 +682,29 @@ impl Message for SampleDataDumpRequest {
 
 impl Outgoing for SampleDataDumpRequest {
     fn encode_data(&self, dest: impl io::Write) -> io::Result<()> {
-        write_u8(dest, self.sample_no)
+        write_u8(dest, self.sample_no.get())
+    }
+}
+
+impl Incoming for SampleDataDumpRequest {
+    fn parse_data(slice: &[u8]) -> Result<Self, ParseError> {
+        let (sample_no, _) = read_u8(slice)?;
+        let sample_no = SampleNo::try_from(sample_no).map_err(|_| ParseError::InvalidData)?;
+        Ok(Self { sample_no })
     }
 }
 
 /// Sample audio data.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SampleData {
-    pub sample_no: u8,
+    pub sample_no: SampleNo,
     pub data: Vec<i16>,
 }
 
 impl SampleData {
-    pub fn new(sample_no: u8, name: &str, data: Vec<i16>) -> (SampleHeader, SampleData) {
-        let name_len = name.len().min(SampleHeader::NAME_LEN);
-        let name = name[..name_len].to_string();
+    #[cfg(test)]
+    pub fn new(sample_no: SampleNo, name: &str, data: Vec<i16>) -> (SampleHeader, SampleData) {
+        let name = SampleHeader::truncate_name(name).to_string();
         let header = SampleHeader {
             sample_no,
             name,
@@ -244,19 +727,14 @@ impl Message for SampleData {
 
 impl Incoming for SampleData {
     fn parse_data(slice: &[u8]) -> Result<Self, ParseError> {
-        let (sample_no, data) = read_u8(slice);
-        let mut buf = Vec::with_capacity(U7ToU8::convert_len(data.len()) / 2 + 1);
-        let mut current_num = [0, 0];
-        FromKorgData::new(data.iter().copied().map(U7::new)) // TODO: Pod cast
-            .enumerate()
-            .for_each(|(idx, byte)| {
-                if idx % 2 == 0 {
-                    current_num = [byte, 0];
-                } else {
-                    current_num[1] = byte;
-                    buf.push(i16::from_le_bytes(current_num));
-                }
-            });
+        let (sample_no, data) = read_u8(slice)?;
+        let sample_no = SampleNo::try_from(sample_no).map_err(|_| ParseError::InvalidData)?;
+        let mut bytes = Vec::with_capacity(U7ToU8::output_len(data.len()));
+        U7ToU8::convert_slice(cast_payload(data, 2)?, &mut bytes);
+        let buf = bytes
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
         Ok(SampleData {
             sample_no,
             data: buf,
@@ -264,27 +742,47 @@ impl Incoming for SampleData {
     }
 }
 
+impl SampleData {
+    /// Samples encoded at once. 2 bytes per sample make it a whole number of 7-byte groups, so
+    /// blocks are converted independently.
+    const ENCODE_BLOCK: usize = 7 * 32;
+}
+
 impl Outgoing for SampleData {
+    /// Encodes the samples block by block, the 7-bit data is never held in memory as a whole.
     fn encode_data(&self, mut dest: impl io::Write) -> io::Result<()> {
-        write_u8(&mut dest, self.sample_no)?;
+        write_u8(&mut dest, self.sample_no.get())?;
+
+        let mut bytes_u8 = Vec::with_capacity(Self::ENCODE_BLOCK * 2);
+        let mut buf = Vec::with_capacity(U8ToU7::output_len(Self::ENCODE_BLOCK * 2));
+        for block in self.data.chunks(Self::ENCODE_BLOCK) {
+            bytes_u8.clear();
+            for sample in block {
+                bytes_u8.extend_from_slice(&sample.to_le_bytes());
+            }
+            buf.clear();
+            U8ToU7::convert_slice(&bytes_u8, &mut buf);
+            dest.write_all(cast_slice(&buf))?;
+        }
+        Ok(())
+    }
 
-        let buf_len = U8ToU7::convert_len(self.data.len() * 2);
-        let mut buf = Vec::with_capacity(buf_len);
-        let bytes_u8 = self.data.iter().copied().flat_map(i16::to_le_bytes);
-        let bytes_u7 = IntoKorgData::new(bytes_u8);
-        buf.extend(bytes_u7);
-        dest.write_all(cast_slice(&buf))
+    fn encoded_len(&self) -> Option<usize> {
+        // 2 bytes for the sample number and 7-bit data, 1 for END_OF_EX
+        let data_len = 2 + U8ToU7::output_len(self.data.len() * 2);
+        Some(<Self::Header as Header>::LEN + Self::ID.len() + data_len + 1)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::fs::File;
-    use std::io::Read;
-
     use hound::WavReader;
+    use proptest::arbitrary::any;
+    use proptest::collection::vec;
+    use proptest::{prop_assert, prop_assert_eq, proptest};
 
     use super::*;
+    use crate::proto::Header;
 
     fn test_template(idx: usize) {
         let expected = WavReader::open(format!("test_data/sample{idx}.wav.raw"))
@@ -293,16 +791,192 @@ mod tests {
             .collect::<Result<Vec<_>, _>>()
             .unwrap();
 
-        let data_dump = File::open(format!("test_data/sample_data_dump{idx}.raw"))
-            .unwrap()
-            .bytes()
-            .collect::<Result<Vec<_>, _>>()
-            .unwrap();
+        let data_dump = std::fs::read(format!("test_data/sample_data_dump{idx}.raw")).unwrap();
         let sample_data = SampleData::parse(&data_dump).unwrap().1;
 
         assert_eq!(sample_data.data, expected);
     }
 
+    #[test]
+    fn sample_no_bounds() {
+        assert_eq!(SampleNo::try_from(199u8), Ok(SampleNo::MAX));
+        assert_eq!(SampleNo::try_from(200u8), Err(InvalidSampleNo(200)));
+        assert_eq!(SampleNo::try_from(300usize), Err(InvalidSampleNo(300)));
+        assert_eq!("199".parse::<SampleNo>().unwrap(), SampleNo::MAX);
+        assert_eq!(
+            "250".parse::<SampleNo>().unwrap_err().to_string(),
+            "sample slot must be less than 200, got 250"
+        );
+        assert!("-1".parse::<SampleNo>().is_err());
+        assert_eq!(format!("{:03}", SampleNo::try_from(7u8).unwrap()), "007");
+
+        assert_eq!(
+            serde_yaml::from_str::<SampleNo>("199").unwrap(),
+            SampleNo::MAX
+        );
+        assert!(serde_yaml::from_str::<SampleNo>("200").is_err());
+
+        let start = SampleNo::try_from(198u8).unwrap();
+        assert_eq!(start.range(2).unwrap().last(), Some(SampleNo::MAX));
+        assert!(start.range(3).is_err());
+        assert_eq!(start.range(0).unwrap().count(), 0);
+        assert_eq!(start.checked_add(1), Ok(SampleNo::MAX));
+        assert!(start.checked_add(2).is_err());
+        assert_eq!(SampleNo::all().count(), 200);
+    }
+
+    #[test]
+    fn new_header_length() {
+        for len in [0, 1, 7, 1000] {
+            let (header, data) = SampleData::new(SampleNo::MAX, "x", vec![-1; len]);
+            assert_eq!(header.length as usize, data.data.len());
+
+            // The encoded data decodes to as many samples as the header advertises
+            let mut raw = Vec::new();
+            data.encode(ExtendedKorgSysEx::from_channel(U7::new(0)), &mut raw)
+                .unwrap();
+            let (_, parsed) = SampleData::parse(&raw).unwrap();
+            assert_eq!(parsed.data.len(), header.length as usize);
+        }
+    }
+
+    #[test]
+    fn stray_8bit_byte() {
+        let (header, data) = SampleData::new(SampleNo::MAX, "kick", vec![1, 2, 3]);
+        let channel = ExtendedKorgSysEx::from_channel(U7::new(0));
+        let mut raw_header = Vec::new();
+        header.encode(channel.clone(), &mut raw_header).unwrap();
+        let mut raw_data = Vec::new();
+        data.encode(channel, &mut raw_data).unwrap();
+
+        // Past the header, function ID and sample number
+        raw_header[10] |= 0x80;
+        raw_data[10] |= 0x80;
+        assert!(matches!(
+            SampleHeader::parse(&raw_header),
+            Err(ParseError::Not7Bit(_))
+        ));
+        assert!(matches!(
+            SampleData::parse(&raw_data),
+            Err(ParseError::Not7Bit(_))
+        ));
+    }
+
+    #[test]
+    fn name_truncation() {
+        // "é" takes bytes 23 and 24
+        let name = "snare_eclair_superlong_é";
+        assert_eq!(SampleHeader::truncate_name(name), "snare_eclair_superlong_");
+        let (header, _) = SampleData::new(SampleNo::default(), name, vec![]);
+        assert_eq!(header.name, "snare_eclair_superlong_");
+
+        // "é" takes bytes 24 and 25
+        let name = "snare_eclair_superlong_xé";
+        assert_eq!(
+            SampleHeader::truncate_name(name),
+            "snare_eclair_superlong_x"
+        );
+        // "é" ends at byte 24
+        let name = "snare_eclair_superlongé";
+        assert_eq!(SampleHeader::truncate_name(name), name);
+        assert_eq!(
+            SampleHeader::truncate_name("🎸🎸🎸🎸🎸🎸🎸"),
+            "🎸🎸🎸🎸🎸🎸"
+        );
+
+        // Names that are too long must not break encoding
+        let header = SampleHeader {
+            name: "ééééééééééééééééééééé".into(),
+            ..header
+        };
+        let mut encoded = Vec::new();
+        header
+            .encode(ExtendedKorgSysEx::from_channel(U7::new(0)), &mut encoded)
+            .unwrap();
+        let (_, parsed) = SampleHeader::parse(&encoded).unwrap();
+        assert_eq!(parsed.name, "éééééééééééé");
+    }
+
+    #[test]
+    fn header_builder() {
+        let slot = SampleNo::try_from(7u8).unwrap();
+        let header = SampleHeader::builder(slot)
+            .name("kick 01")
+            .length_of(&[1, 2, 3])
+            .speed(Speed::from_semitones(-12.).unwrap())
+            .level(Level::from_percent(50.).unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(header.sample_no, slot);
+        assert_eq!(header.name, "kick 01");
+        assert_eq!(header.length, 3);
+        assert_eq!(header.speed, 8192);
+        assert_eq!(header.level, 32768);
+
+        let header = SampleHeader::builder(slot).name("kick").build().unwrap();
+        assert_eq!(header.speed(), Speed::DEFAULT);
+        assert_eq!(header.level(), Level::DEFAULT);
+        assert!(!header.is_empty());
+
+        let build = |name: &str| SampleHeader::builder(slot).name(name).build();
+        assert_eq!(build("").unwrap_err(), SampleHeaderError::EmptyName);
+        assert!(build("snare_eclair_superlong_x").is_ok());
+        assert_eq!(
+            build("snare_eclair_superlong_xy").unwrap_err(),
+            SampleHeaderError::NameTooLong("snare_eclair_superlong_xy".into())
+        );
+        assert!(matches!(
+            build("éclair").unwrap_err(),
+            SampleHeaderError::InvalidCharacter { invalid: 'é', .. }
+        ));
+        assert!(matches!(
+            build("tab\there").unwrap_err(),
+            SampleHeaderError::InvalidCharacter { invalid: '\t', .. }
+        ));
+
+        let long = SampleHeader::builder(slot)
+            .name("long")
+            .length_of(&vec![0; SampleSpaceDump::MEMORY_SIZE as usize / 2 + 1])
+            .build();
+        assert!(matches!(long, Err(SampleHeaderError::TooLong(_))));
+    }
+
+    #[test]
+    fn header_encoding_edge_cases() {
+        let encode = |header: &SampleHeader| {
+            let mut encoded = Vec::new();
+            header
+                .encode(ExtendedKorgSysEx::from_channel(U7::new(0)), &mut encoded)
+                .unwrap();
+            SampleHeader::parse(&encoded).unwrap().1
+        };
+
+        // A name one byte too long, set past the builder, used to underflow the padding
+        let mut header = SampleHeader::builder(SampleNo::MAX)
+            .name("snare")
+            .build()
+            .unwrap();
+        header.name = "snare_eclair_superlong_xy".into();
+        assert_eq!(encode(&header).name, "snare_eclair_superlong_x");
+
+        let empty = SampleHeader::empty(SampleNo::MAX);
+        let parsed = encode(&empty);
+        assert!(parsed.is_empty());
+        assert_eq!(parsed.sample_no, SampleNo::MAX);
+    }
+
+    #[test]
+    fn sample_data_round_trip() {
+        for idx in 1..=14 {
+            let data_dump = std::fs::read(format!("test_data/sample_data_dump{idx}.raw")).unwrap();
+            let (header, sample_data) = SampleData::parse(&data_dump).unwrap();
+
+            let mut encoded = Vec::new();
+            sample_data.encode(header, &mut encoded).unwrap();
+            assert_eq!(encoded, data_dump, "sample {idx}");
+        }
+    }
+
     #[test]
     fn test_sample_1() {
         test_template(1)
@@ -372,4 +1046,161 @@ mod tests {
     fn test_sample_14() {
         test_template(14)
     }
+
+    #[test]
+    fn speed_encoding() {
+        assert_eq!(SampleHeader::speed_for_rate(VOLCA_SAMPLERATE), Some(16384));
+        assert_eq!(SampleHeader::speed_for_rate(ECONOMY_SAMPLERATE), Some(8192));
+        assert_eq!(SampleHeader::speed_for_rate(62500), Some(32768));
+        assert_eq!(SampleHeader::speed_for_rate(125_000), None);
+
+        let mut header = SampleHeader::empty(SampleNo::default());
+        header.length = 31250;
+        header.speed = SampleHeader::DEFAULT_SPEED;
+        assert_eq!(header.playback_rate(), VOLCA_SAMPLERATE);
+        assert_eq!(header.duration(), Duration::from_secs(1));
+        assert!(!header.is_economy());
+        header.speed = 8192;
+        assert_eq!(header.playback_rate(), ECONOMY_SAMPLERATE);
+        assert_eq!(header.duration(), Duration::from_secs(2));
+        assert!(header.is_economy());
+    }
+
+    #[test]
+    fn speed_units() {
+        assert_eq!(Speed::DEFAULT.raw(), 16384);
+        assert_eq!(Speed::DEFAULT.semitones(), 0.);
+        assert_eq!(Speed::DEFAULT.to_string(), "+0 st");
+        assert_eq!(Speed::from_semitones(12.).unwrap().raw(), 32768);
+        assert_eq!(Speed::from_semitones(-12.).unwrap().raw(), 8192);
+        assert_eq!(Speed::from_semitones(-24.).unwrap(), Speed::MIN);
+        assert_eq!(Speed::from_semitones(24.).unwrap(), Speed::MAX);
+        assert_eq!(Speed::from_semitones(-5.).unwrap().to_string(), "-5 st");
+        assert_eq!(Speed::from_raw(20000).unwrap().to_string(), "×1.22");
+        assert!(Speed::from_semitones(-25.).is_err());
+        assert!(Speed::from_semitones(25.).is_err());
+        assert!(Speed::from_raw(4095).is_err());
+
+        assert_eq!("+3st".parse(), Speed::from_semitones(3.));
+        assert_eq!("-5 st".parse(), Speed::from_semitones(-5.));
+        assert_eq!("x1.5".parse::<Speed>().unwrap().raw(), 24576);
+        assert_eq!("×0.5".parse::<Speed>().unwrap().raw(), 8192);
+        assert_eq!("16384".parse(), Ok(Speed::DEFAULT));
+        assert!(matches!(
+            "x5".parse::<Speed>(),
+            Err(SpeedError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            "70000".parse::<Speed>(),
+            Err(SpeedError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            "fast".parse::<Speed>(),
+            Err(SpeedError::Invalid(_))
+        ));
+
+        assert_eq!(
+            Speed::DEFAULT.at_rate(ECONOMY_SAMPLERATE).unwrap().raw(),
+            8192
+        );
+        assert!(Speed::MIN.at_rate(ECONOMY_SAMPLERATE).is_err());
+    }
+
+    #[test]
+    fn level_units() {
+        assert_eq!(Level::DEFAULT.to_string(), "100%");
+        assert_eq!(Level::from_raw(0).to_string(), "0%");
+        assert_eq!(Level::from_raw(1).to_string(), "1%");
+        assert_eq!(Level::from_raw(65534).to_string(), "99%");
+        assert_eq!(Level::from_percent(0.).unwrap().raw(), 0);
+        assert_eq!(Level::from_percent(100.).unwrap(), Level::DEFAULT);
+        assert_eq!(Level::from_percent(50.).unwrap().raw(), 32768);
+        assert!(Level::from_percent(-0.1).is_err());
+        assert!(Level::from_percent(100.1).is_err());
+
+        assert_eq!("80%".parse::<Level>().unwrap().raw(), 52428);
+        assert_eq!("12.5 %".parse::<Level>().unwrap().raw(), 8192);
+        assert_eq!("1000".parse(), Ok(Level::from_raw(1000)));
+        assert!(matches!(
+            "120%".parse::<Level>(),
+            Err(LevelError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            "70000".parse::<Level>(),
+            Err(LevelError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            "loud".parse::<Level>(),
+            Err(LevelError::Invalid(_))
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn level_percent_round_trip(raw: u16) {
+            let level = Level::from_raw(raw);
+            let round_trip = Level::from_percent(level.percent()).unwrap();
+            prop_assert!(round_trip.raw().abs_diff(raw) <= 1);
+        }
+
+        #[test]
+        fn level_raw_round_trip(percent in 0f64..=100.) {
+            let level = Level::from_percent(percent).unwrap();
+            let step = 100. / f64::from(u16::MAX);
+            prop_assert!((level.percent() - percent).abs() <= step);
+        }
+
+        #[test]
+        fn block_encoding(data in vec(any::<i16>(), 0..4096)) {
+            let sample_data = SampleData {
+                sample_no: SampleNo::MAX,
+                data,
+            };
+            let mut encoded = Vec::new();
+            sample_data.encode_data(&mut encoded).unwrap();
+
+            // Whole payload converted at once
+            let bytes_u8 = sample_data.data.iter().copied().flat_map(i16::to_le_bytes);
+            let mut expected = Vec::new();
+            write_u8(&mut expected, SampleNo::MAX.get()).unwrap();
+            expected.extend(IntoKorgData::new(bytes_u8).map(U7::as_u8));
+            prop_assert_eq!(&encoded, &expected);
+
+            let mut message = Vec::new();
+            let header = ExtendedKorgSysEx::from_channel(U7::new(0));
+            sample_data.encode(header, &mut message).unwrap();
+            prop_assert_eq!(sample_data.encoded_len(), Some(message.len()));
+            prop_assert_eq!(SampleData::parse(&message).unwrap().1, sample_data);
+        }
+    }
+
+    #[test]
+    fn sample_space_sectors() {
+        let space = SampleSpaceDump {
+            all_sector_size: 1024,
+            used_sector_size: 1000,
+        };
+        // 8KiB sectors
+        assert_eq!(space.sectors_for(0), 0);
+        assert_eq!(space.sectors_for(1), 1);
+        assert_eq!(space.sectors_for(4096), 1);
+        assert_eq!(space.sectors_for(4097), 2);
+        assert_eq!(space.free_sectors(), 24);
+        assert_eq!(space.sector_size(), 8192);
+        assert_eq!(space.length_for(0), 0);
+        assert_eq!(space.length_for(2), 8192);
+        assert_eq!(space.sectors_for(space.length_for(24)), 24);
+        assert_eq!(space.shortfall(24, 0), None);
+        assert_eq!(space.shortfall(25, 0), Some(1));
+        assert_eq!(space.shortfall(30, 6), None);
+        assert_eq!(space.shortfall(40, 6), Some(10));
+
+        let full = SampleSpaceDump {
+            all_sector_size: 1024,
+            used_sector_size: 1100,
+        };
+        assert_eq!(full.free_sectors(), 0);
+        assert_eq!(full.shortfall(1, 0), Some(1));
+        assert_eq!(full.shortfall(0, 0), None);
+    }
 }
@@ -1,5 +1,6 @@
 //! Utility messages.
 
+use std::fmt;
 use std::io;
 
 use arrayref::{array_ref, array_refs};
@@ -7,20 +8,44 @@ use thiserror::Error;
 
 use crate::seven_bit::U7;
 
-use super::header::{ExtendedKorgSysEx, KorgSysEx};
-use super::{Incoming, Message, Outgoing, ParseError, Version, VOLCA_SAMPLE_2_ID};
+use super::header::{ExtendedKorgSysEx, KorgSysEx, UniversalSysEx};
+use super::{Incoming, Message, Outgoing, ParseError, Version, KORG_ID, VOLCA_SAMPLE_2_ID};
 
 /// Acknowledge status magic.
 pub const ACK_STATUS: u8 = 0x23;
 /// Not-Acknowledge status.
-#[derive(Debug, Error, Clone, Copy)]
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
 pub enum NakStatus {
     #[error("device is busy")]
-    Busy = 0x24,
+    Busy,
     #[error("sample memory is full")]
-    SampleFull = 0x25,
+    SampleFull,
     #[error("invalid data format")]
-    DataFormat = 0x26,
+    DataFormat,
+    /// Status byte this version does not know, e.g. added by newer firmware.
+    #[error("unknown status {0:#04X}")]
+    Unknown(u8),
+}
+
+impl NakStatus {
+    pub fn from_byte(status: u8) -> Self {
+        match status {
+            0x24 => Self::Busy,
+            0x25 => Self::SampleFull,
+            0x26 => Self::DataFormat,
+            status => Self::Unknown(status),
+        }
+    }
+
+    /// Status byte as sent by the device.
+    pub fn byte(self) -> u8 {
+        match self {
+            Self::Busy => 0x24,
+            Self::SampleFull => 0x25,
+            Self::DataFormat => 0x26,
+            Self::Unknown(status) => status,
+        }
+    }
 }
 
 /// Message representing result of an operation.
@@ -37,19 +62,25 @@ impl Message for Status {
 impl Incoming for Status {
     fn parse_data(slice: &[u8]) -> Result<Self, ParseError> {
         let (status, _) = slice.split_first().ok_or(ParseError::NotEnoughData)?;
-        let status = match *status {
+        Ok(match *status {
             ACK_STATUS => Ok(()),
-            x if x == NakStatus::Busy as u8 => Err(NakStatus::Busy),
-            x if x == NakStatus::SampleFull as u8 => Err(NakStatus::SampleFull),
-            x if x == NakStatus::DataFormat as u8 => Err(NakStatus::DataFormat),
-            _ => return Err(ParseError::NotEnoughData),
+            status => Err(NakStatus::from_byte(status)),
+        })
+    }
+}
+
+impl Outgoing for Status {
+    fn encode_data(&self, mut dest: impl io::Write) -> io::Result<()> {
+        let status = match self {
+            Ok(()) => ACK_STATUS,
+            Err(status) => status.byte(),
         };
-        Ok(status)
+        dest.write_all(&[status])
     }
 }
 
 /// Discovery request.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SearchDeviceRequest {
     pub echo: U7,
 }
@@ -59,7 +90,7 @@ impl Message for SearchDeviceRequest {
     type Id = [u8; 2];
 
     const ID: [u8; 2] = [0x50, 0x00];
-    const LEN: Option<usize> = Some(2);
+    const LEN: Option<usize> = Some(1);
 }
 
 impl Outgoing for SearchDeviceRequest {
@@ -68,8 +99,16 @@ impl Outgoing for SearchDeviceRequest {
     }
 }
 
+impl Incoming for SearchDeviceRequest {
+    fn parse_data(slice: &[u8]) -> Result<Self, ParseError> {
+        Ok(Self {
+            echo: U7::try_from(slice[0])?,
+        })
+    }
+}
+
 /// Discovery response.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SearchDeviceReply {
     pub echo: U7,
     pub device_id: U7,
@@ -89,17 +128,220 @@ impl Incoming for SearchDeviceReply {
         let slice = array_ref!(slice, 0, 10);
         let (channel, echo, model_id, minor, major) = array_refs![slice, 1, 1, 4, 2, 2];
         if model_id != &VOLCA_SAMPLE_2_ID {
-            return Err(ParseError::IvanlidId {
+            let err = ParseError::IvanlidId {
                 expected: VOLCA_SAMPLE_2_ID.to_vec().into_boxed_slice(),
                 received: model_id.to_vec().into_boxed_slice(),
-            });
+            };
+            return Err(err.at(2));
         }
         let version = Version(u16::from_le_bytes(*major), u16::from_le_bytes(*minor));
 
         Ok(Self {
-            device_id: U7::new_checked(channel[0]).ok_or(ParseError::InvalidData)?,
-            echo: U7::new_checked(echo[0]).ok_or(ParseError::InvalidData)?,
+            device_id: U7::try_from(channel[0])?,
+            echo: U7::try_from(echo[0]).map_err(|err| ParseError::from(err).at(1))?,
             version,
         })
     }
 }
+
+impl Outgoing for SearchDeviceReply {
+    fn encode_data(&self, mut dest: impl io::Write) -> io::Result<()> {
+        let Version(major, minor) = self.version;
+        dest.write_all(&[self.device_id.as_u8(), self.echo.as_u8()])?;
+        dest.write_all(&VOLCA_SAMPLE_2_ID)?;
+        dest.write_all(&minor.to_le_bytes())?;
+        dest.write_all(&major.to_le_bytes())
+    }
+}
+
+/// Universal identity request. Unlike [`SearchDeviceRequest`], any MIDI device may answer it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdentityRequest;
+
+impl Message for IdentityRequest {
+    type Header = UniversalSysEx;
+    type Id = [u8; 2];
+
+    const ID: [u8; 2] = [0x06, 0x01];
+    const LEN: Option<usize> = Some(0);
+}
+
+impl Outgoing for IdentityRequest {
+    fn encode_data(&self, _: impl io::Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Incoming for IdentityRequest {
+    fn parse_data(_: &[u8]) -> Result<Self, ParseError> {
+        Ok(Self)
+    }
+}
+
+/// Manufacturer ID in an [`IdentityReply`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Manufacturer {
+    /// One byte ID, like [`KORG_ID`].
+    Short(u8),
+    /// Three byte ID, without the leading zero.
+    Extended([u8; 2]),
+}
+
+impl fmt::Display for Manufacturer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Short(KORG_ID) => write!(f, "Korg ({KORG_ID:02X})"),
+            Self::Short(id) => write!(f, "{id:02X}"),
+            Self::Extended([high, low]) => write!(f, "00 {high:02X} {low:02X}"),
+        }
+    }
+}
+
+/// Universal identity reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityReply {
+    pub manufacturer: Manufacturer,
+    pub family: [u8; 2],
+    pub model: [u8; 2],
+    pub version: [u8; 4],
+}
+
+impl IdentityReply {
+    pub fn is_volca_sample_2(&self) -> bool {
+        self.manufacturer == Manufacturer::Short(KORG_ID)
+            && [self.family, self.model].concat() == VOLCA_SAMPLE_2_ID
+    }
+
+    /// Firmware version, encoded as in [`SearchDeviceReply`]. Other manufacturers encode it
+    /// differently.
+    pub fn korg_version(&self) -> Version {
+        let (minor, major) = array_refs![&self.version, 2, 2];
+        Version(u16::from_le_bytes(*major), u16::from_le_bytes(*minor))
+    }
+}
+
+impl fmt::Display for IdentityReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "manufacturer {}, family {:02X?}, model {:02X?}, version {:02X?}",
+            self.manufacturer, self.family, self.model, self.version
+        )
+    }
+}
+
+impl Message for IdentityReply {
+    type Header = UniversalSysEx;
+    type Id = [u8; 2];
+
+    const ID: [u8; 2] = [0x06, 0x02];
+}
+
+impl Incoming for IdentityReply {
+    fn parse_data(slice: &[u8]) -> Result<Self, ParseError> {
+        let (manufacturer, rest) = match slice {
+            [0, high, low, rest @ ..] => (Manufacturer::Extended([*high, *low]), rest),
+            [id, rest @ ..] if *id != 0 => (Manufacturer::Short(*id), rest),
+            _ => return Err(ParseError::NotEnoughData),
+        };
+        // Some devices append more data, which is ignored
+        if rest.len() < 8 {
+            return Err(ParseError::NotEnoughData.at(slice.len()));
+        }
+        let (family, model, version) = array_refs![array_ref!(rest, 0, 8), 2, 2, 4];
+        Ok(Self {
+            manufacturer,
+            family: *family,
+            model: *model,
+            version: *version,
+        })
+    }
+}
+
+impl Outgoing for IdentityReply {
+    fn encode_data(&self, mut dest: impl io::Write) -> io::Result<()> {
+        match self.manufacturer {
+            Manufacturer::Short(id) => dest.write_all(&[id])?,
+            Manufacturer::Extended([high, low]) => dest.write_all(&[0, high, low])?,
+        }
+        dest.write_all(&self.family)?;
+        dest.write_all(&self.model)?;
+        dest.write_all(&self.version)
+    }
+}
+
+#[test]
+fn status_bytes() {
+    use super::Header;
+    use hex_literal::hex;
+
+    let parse = |status: u8| {
+        let header = ExtendedKorgSysEx::from_channel(U7::new(0)).encode();
+        let raw = [header.as_ref(), &[status], &hex!("F7")].concat();
+        Status::parse(&raw).unwrap().1
+    };
+    assert_eq!(parse(0x23), Ok(()));
+    assert_eq!(parse(0x24), Err(NakStatus::Busy));
+    assert_eq!(parse(0x25), Err(NakStatus::SampleFull));
+    assert_eq!(parse(0x26), Err(NakStatus::DataFormat));
+    assert_eq!(parse(0x31), Err(NakStatus::Unknown(0x31)));
+    assert_eq!(NakStatus::Unknown(0x31).to_string(), "unknown status 0x31");
+    for status in [0x24, 0x25, 0x26, 0x31] {
+        assert_eq!(NakStatus::from_byte(status).byte(), status);
+    }
+}
+
+#[test]
+fn search_reply_bounds() {
+    let parse = |device_id: u8, echo: u8| {
+        let raw = [
+            &[0xF0, KORG_ID, 0x50, 0x01, device_id, echo][..],
+            &VOLCA_SAMPLE_2_ID,
+            &[0x02, 0x00, 0x01, 0x00, 0xF7],
+        ]
+        .concat();
+        SearchDeviceReply::parse(&raw).map(|(_, reply)| reply)
+    };
+    for value in [0, 126, 127] {
+        let reply = parse(value, value).unwrap();
+        assert_eq!(reply.device_id.as_u8(), value);
+        assert_eq!(reply.echo.as_u8(), value);
+        assert_eq!(reply.version, Version(1, 2));
+    }
+    assert!(matches!(parse(128, 0), Err(ParseError::Not7Bit(_))));
+    assert!(matches!(parse(0, 128), Err(ParseError::Not7Bit(_))));
+}
+
+#[test]
+fn identity() {
+    use super::Header;
+    use hex_literal::hex;
+
+    let mut request = Vec::new();
+    IdentityRequest
+        .encode(UniversalSysEx::from_channel(U7::new(3)), &mut request)
+        .unwrap();
+    assert_eq!(request, hex!("F0 7E 7F 06 01 F7"));
+
+    let (header, reply) =
+        IdentityReply::parse(&hex!("F0 7E 03 06 02 42 2D 01 08 00 01 00 02 00 F7")).unwrap();
+    assert_eq!(header.device_id, U7::new(3));
+    assert!(reply.is_volca_sample_2());
+    assert_eq!(reply.korg_version().to_string(), "2.1");
+
+    // Other devices do not fail the parsing
+    let (_, reply) =
+        IdentityReply::parse(&hex!("F0 7E 7F 06 02 00 20 29 01 02 03 04 01 02 03 04 F7")).unwrap();
+    assert_eq!(reply.manufacturer, Manufacturer::Extended([0x20, 0x29]));
+    assert!(!reply.is_volca_sample_2());
+    assert_eq!(
+        reply.to_string(),
+        "manufacturer 00 20 29, family [01, 02], model [03, 04], version [01, 02, 03, 04]"
+    );
+
+    assert!(matches!(
+        IdentityReply::parse(&hex!("F0 7E 7F 06 02 42 2D 01 F7")),
+        Err(ParseError::NotEnoughData)
+    ));
+    assert!(IdentityReply::parse(&hex!("F0 42 30 06 02 42 2D 01 08 00 01 00 02 00 F7")).is_err());
+}
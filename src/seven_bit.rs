@@ -1,5 +1,8 @@
+use std::{iter, slice};
+
 use bytemuck::{Pod, TransparentWrapper, Zeroable};
 use derive_more::{Display, Into};
+use thiserror::Error;
 
 use crate::util::Array;
 
@@ -21,7 +24,7 @@ impl U7 {
     }
 
     pub fn new_checked(byte: u8) -> Option<Self> {
-        (byte < Self::MAX.0).then_some(Self(byte))
+        (byte <= Self::MAX.0).then_some(Self(byte))
     }
 
     pub const fn split_u8(num: u8) -> (u8, U7) {
@@ -43,9 +46,51 @@ impl U7 {
     }
 }
 
+/// A byte with the high bit set where a 7-bit value was expected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("{0:#04X} is not a 7-bit value")]
+pub struct InvalidU7(pub u8);
+
+impl TryFrom<u8> for U7 {
+    type Error = InvalidU7;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Self::new_checked(byte).ok_or(InvalidU7(byte))
+    }
+}
+
+impl From<U7> for usize {
+    fn from(value: U7) -> Self {
+        value.0.into()
+    }
+}
+
+/// Reinterprets received bytes as 7-bit values, checking the whole slice at once.
+pub fn cast_checked(bytes: &[u8]) -> Result<&[U7], InvalidU7> {
+    // A branchless OR over fixed-size blocks is vectorized, the offending byte is looked up only
+    // once a block fails.
+    for block in bytes.chunks(64) {
+        if block.iter().fold(0, |acc, byte| acc | byte) & 0b1000_0000 != 0 {
+            let byte = block
+                .iter()
+                .find(|byte| **byte & 0b1000_0000 != 0)
+                .expect("block has a high bit set");
+            return Err(InvalidU7(*byte));
+        }
+    }
+    Ok(U7::wrap_slice(bytes))
+}
+
 pub type FromKorgData<I> = Converter<I, U7ToU8>;
 pub type IntoKorgData<I> = Converter<I, U8ToU7>;
 
+impl<'a> FromKorgData<iter::Copied<slice::Iter<'a, U7>>> {
+    /// Decodes 7-bit data viewed with [`cast_checked`].
+    pub fn from_slice(data: &'a [U7]) -> Self {
+        Self::new(data.iter().copied())
+    }
+}
+
 pub trait Convert {
     type Input: Sized;
     type InputBuffer: Array<ArrayItem = Self::Input>;
@@ -63,13 +108,31 @@ pub struct U8ToU7;
 impl U8ToU7 {
     pub fn convert_len(len: usize) -> usize {
         let mut msbs = len / 7;
-        if len % 7 != 0 {
+        if !len.is_multiple_of(7) {
             msbs += 1;
         }
         len + msbs
         // let bits = len * 8;
         // let num_octets = bits / 7 + u8::from(bits % 7 != 0) as usize;
     }
+
+    /// Converts the whole `input` at once and appends the result to `output`.
+    ///
+    /// Output is identical to [`IntoKorgData`], but large payloads skip the per-item iterator
+    /// bookkeeping.
+    pub fn convert_slice(input: &[u8], output: &mut Vec<U7>) {
+        let start = output.len();
+        output.resize(start + Self::convert_len(input.len()), U7(0));
+
+        let dest = &mut output[start..];
+        for (chunk, octet) in input.chunks(7).zip(dest.chunks_mut(8)) {
+            for (idx, &byte) in chunk.iter().enumerate() {
+                let (msb, byte7) = U7::split_u8(byte);
+                octet[0].0 |= msb << idx;
+                octet[idx + 1] = byte7;
+            }
+        }
+    }
 }
 impl Convert for U8ToU7 {
     type Input = u8;
@@ -110,12 +173,29 @@ impl U7ToU8 {
         } else {
             // Number of bytes that hold MSBs for rest in the octet.
             let mut msbs = len / 8;
-            if len % 8 != 0 {
+            if !len.is_multiple_of(8) {
                 msbs += 1;
             }
             len - msbs
         }
     }
+
+    /// Converts the whole `input` at once and appends the result to `output`.
+    ///
+    /// Output is identical to [`FromKorgData`], but large payloads skip the per-item iterator
+    /// bookkeeping.
+    pub fn convert_slice(input: &[U7], output: &mut Vec<u8>) {
+        let start = output.len();
+        output.resize(start + Self::convert_len(input.len()), 0);
+
+        let dest = &mut output[start..];
+        for (octet, chunk) in input.chunks(8).zip(dest.chunks_mut(7)) {
+            let (msbs, bytes) = octet.split_first().expect("chunks are never empty");
+            for (idx, byte) in bytes.iter().enumerate() {
+                chunk[idx] = byte.0 | msbs.take_nth_msb(idx);
+            }
+        }
+    }
 }
 
 impl Convert for U7ToU8 {
@@ -226,6 +306,19 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn checked_range() {
+        assert_eq!(U7::new_checked(0x7F), Some(U7::MAX));
+        assert_eq!(U7::new_checked(0x80), None);
+
+        assert_eq!(U7::try_from(126), Ok(U7(126)));
+        assert_eq!(U7::try_from(127), Ok(U7::MAX));
+        assert_eq!(U7::try_from(128), Err(InvalidU7(128)));
+        assert_eq!(U7::try_from(255), Err(InvalidU7(255)));
+        assert_eq!(u8::from(U7::MAX), 127);
+        assert_eq!(usize::from(U7::MAX), 127);
+    }
+
     #[test]
     fn extract_msb() {
         assert_eq!(U7(0b0000_0001).take_nth_msb(0), 0b1000_0000);
@@ -250,7 +343,7 @@ mod tests {
     }
 
     prop_compose! {
-        fn u7_full_range()(raw in U7::MIN.as_u8()..U7::MAX.as_u8()) -> U7 {
+        fn u7_full_range()(raw in U7::MIN.as_u8()..=U7::MAX.as_u8()) -> U7 {
             U7::new_checked(raw).expect("overflow")
         }
     }
@@ -343,9 +436,29 @@ mod tests {
         assert_eq!(converted_data, converted_data_expected);
     }
 
+    /// Tests that bulk conversion matches the iterator byte for byte
+    fn test_slice<T: Copy, U: Debug + PartialEq>(
+        data: Vec<T>,
+        iter: impl FnOnce(std::vec::IntoIter<T>) -> Vec<U>,
+        slice: impl Fn(&[T], &mut Vec<U>),
+    ) {
+        let expected = iter(data.clone().into_iter());
+
+        let mut converted = Vec::new();
+        slice(&data, &mut converted);
+        assert_eq!(converted, expected);
+
+        // Output is appended, existing contents are left untouched.
+        let mut appended = Vec::new();
+        slice(&data[..data.len() / 2], &mut appended);
+        let prefix_len = appended.len();
+        slice(&data, &mut appended);
+        assert_eq!(appended[prefix_len..], expected[..]);
+    }
+
     proptest! {
         #[test]
-        fn u8_to_u7_and_back(data in vec(u8::MIN..u8::MAX, 0..(1024 * 100))) {
+        fn u8_to_u7_and_back(data in vec(any::<u8>(), 0..(1024 * 100))) {
             test_two_way::<U8ToU7, U7ToU8>(data)
         }
 
@@ -360,7 +473,7 @@ mod tests {
         }
 
         #[test]
-        fn converter_u8_to_u7(data in vec(u8::MIN..u8::MAX, 0..(1024 * 100))) {
+        fn converter_u8_to_u7(data in vec(any::<u8>(), 0..(1024 * 100))) {
             test_converter::<U8ToU7>(data)
         }
 
@@ -374,6 +487,59 @@ mod tests {
             test_converter::<U7ToU8>(data)
         }
 
+        #[test]
+        fn slice_u8_to_u7(data in vec(any::<u8>(), 0..(1024 * 100))) {
+            test_slice(
+                data,
+                |iter| IntoKorgData::new(iter).collect(),
+                U8ToU7::convert_slice,
+            )
+        }
+
+        #[test]
+        fn slice_u7_to_u8(
+            data in vec(u7_full_range(), 0..(1024 * 100)).prop_filter_map(
+                "U7 array cannot be of len 8n + 1",
+                filter_map_u7_vec
+            )
+        ) {
+            test_slice(
+                data,
+                |iter| FromKorgData::new(iter).collect(),
+                U7ToU8::convert_slice,
+            )
+        }
+
+        #[test]
+        fn checked_cast(mut data in vec(u7_full_range(), 0..1024), stray in any::<(usize, u8)>()) {
+            let bytes: Vec<u8> = data.iter().map(|byte| byte.as_u8()).collect();
+            assert_eq!(cast_checked(&bytes), Ok(&data[..]));
+
+            if !data.is_empty() {
+                let (idx, byte) = (stray.0 % data.len(), stray.1 | 0b1000_0000);
+                let mut bytes = bytes;
+                bytes[idx] = byte;
+                assert_eq!(cast_checked(&bytes), Err(InvalidU7(byte)));
+                data.truncate(idx);
+                assert_eq!(cast_checked(&bytes[..idx]), Ok(&data[..]));
+            }
+        }
+
+        #[test]
+        fn u7_conversions(byte in any::<u8>()) {
+            match U7::try_from(byte) {
+                Ok(num) => {
+                    assert!(byte <= 127);
+                    assert_eq!(u8::from(num), byte);
+                    assert_eq!(usize::from(num), usize::from(byte));
+                }
+                Err(err) => {
+                    assert!(byte >= 128);
+                    assert_eq!(err, InvalidU7(byte));
+                }
+            }
+        }
+
         #[test]
         fn take_msb(nth in 0..7usize, is_one in any::<bool>()) {
             let mut num = 0u8;
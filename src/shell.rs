@@ -0,0 +1,66 @@
+//! Interactive shell that keeps the device connection open between commands.
+
+use anyhow::Result;
+use clap::Parser;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use crate::config::Config;
+use crate::opt::{Operation, ShellCommand};
+use crate::{execute, App};
+
+/// Reads and executes commands until `quit` or end of input.
+///
+/// Errors are printed and do not stop the shell.
+pub fn run(app: &mut App, config: &Config, prompt: &str) -> Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    println!("Type `help` to list commands, `quit` to exit");
+
+    loop {
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line)?;
+        if matches!(line, "quit" | "exit") {
+            break;
+        }
+
+        let args = match shell_words::split(line) {
+            Ok(args) => args,
+            Err(err) => {
+                eprintln!("Error: {err}");
+                continue;
+            }
+        };
+        let cmd = match ShellCommand::try_parse_from(args) {
+            Ok(ShellCommand {
+                cmd: Operation::Shell,
+            }) => {
+                eprintln!("Error: already in shell");
+                continue;
+            }
+            Ok(ShellCommand { cmd }) => cmd,
+            Err(err) => {
+                // Also prints help and version
+                err.print()?;
+                continue;
+            }
+        };
+
+        if let Err(err) = execute(app, config, cmd) {
+            match err.downcast_ref::<clap::Error>() {
+                Some(err) => err.print()?,
+                None => eprintln!("Error: {err:?}"),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,141 @@
+//! Reading sample dumps from `.syx` files.
+
+use thiserror::Error;
+
+use crate::proto::{Incoming, ParseError, SampleData, SampleHeader, SampleNo, EOX, EST};
+
+/// Errors reading sample dumps from a SysEx file. Offsets are in bytes from the file start.
+#[derive(Debug, Error)]
+pub enum SyxError {
+    #[error("expected start of SysEx message at offset {0:#x}")]
+    MissingStart(usize),
+    #[error("SysEx message at offset {0:#x} has no end byte")]
+    Unterminated(usize),
+    #[error("message at offset {offset:#x} is not a Volca Sample 2 {expected}: {source}")]
+    UnexpectedMessage {
+        offset: usize,
+        expected: &'static str,
+        #[source]
+        source: ParseError,
+    },
+    #[error("sample header at offset {0:#x} is not followed by sample data")]
+    MissingData(usize),
+    #[error(
+        "sample data at offset {offset:#x} is for slot {data}, but its header is for slot {header}"
+    )]
+    SlotMismatch {
+        offset: usize,
+        header: SampleNo,
+        data: SampleNo,
+    },
+    #[error("file contains no samples")]
+    Empty,
+}
+
+/// A sample header and data dump pair.
+#[derive(Debug)]
+pub struct SampleDump {
+    /// Offset of the header message in the file.
+    pub offset: usize,
+    pub header: SampleHeader,
+    pub data: SampleData,
+}
+
+/// Splits concatenated SysEx messages. Returns each message together with its offset.
+fn split_messages(buf: &[u8]) -> Result<Vec<(usize, &[u8])>, SyxError> {
+    let mut messages = Vec::new();
+    let mut offset = 0;
+    while offset < buf.len() {
+        if buf[offset] != EST {
+            return Err(SyxError::MissingStart(offset));
+        }
+        let len = buf[offset..]
+            .iter()
+            .position(|&byte| byte == EOX)
+            .ok_or(SyxError::Unterminated(offset))?
+            + 1;
+        messages.push((offset, &buf[offset..offset + len]));
+        offset += len;
+    }
+    Ok(messages)
+}
+
+/// Parses sample header and data dump pairs, in the order they appear in the file.
+pub fn parse_samples(buf: &[u8]) -> Result<Vec<SampleDump>, SyxError> {
+    let mut messages = split_messages(buf)?.into_iter();
+    let mut samples = Vec::new();
+    while let Some((offset, message)) = messages.next() {
+        let (_, header) =
+            SampleHeader::parse(message).map_err(|source| SyxError::UnexpectedMessage {
+                offset,
+                expected: "sample header",
+                source,
+            })?;
+        let (data_offset, message) = messages.next().ok_or(SyxError::MissingData(offset))?;
+        let (_, data) =
+            SampleData::parse(message).map_err(|source| SyxError::UnexpectedMessage {
+                offset: data_offset,
+                expected: "sample data",
+                source,
+            })?;
+        if header.sample_no != data.sample_no {
+            return Err(SyxError::SlotMismatch {
+                offset: data_offset,
+                header: header.sample_no,
+                data: data.sample_no,
+            });
+        }
+        samples.push(SampleDump {
+            offset,
+            header,
+            data,
+        });
+    }
+
+    if samples.is_empty() {
+        return Err(SyxError::Empty);
+    }
+    Ok(samples)
+}
+
+#[test]
+fn parse_sample_dumps() {
+    use crate::proto::{Header, Outgoing};
+    use crate::seven_bit::U7;
+
+    let mut buf = Vec::new();
+    for (sample_no, name) in [(3u8, "kick"), (4, "snare")] {
+        let sample_no = SampleNo::try_from(sample_no).unwrap();
+        let (header, data) = SampleData::new(sample_no, name, vec![0, 1, -1, i16::MAX]);
+        header
+            .encode(Header::from_channel(U7::new(0)), &mut buf)
+            .unwrap();
+        data.encode(Header::from_channel(U7::new(0)), &mut buf)
+            .unwrap();
+    }
+    let header_len = buf.iter().position(|&byte| byte == EOX).unwrap() + 1;
+
+    let samples = parse_samples(&buf).unwrap();
+    assert_eq!(samples.len(), 2);
+    assert_eq!(samples[1].header.name, "snare");
+    assert_eq!(samples[1].data.sample_no.get(), 4);
+    assert_eq!(samples[1].data.data, [0, 1, -1, i16::MAX]);
+
+    // Another Korg device
+    let mut foreign = buf.clone();
+    foreign[header_len + 5] = 0x2E;
+    assert!(matches!(
+        parse_samples(&foreign),
+        Err(SyxError::UnexpectedMessage { offset, .. }) if offset == header_len
+    ));
+
+    assert!(matches!(
+        parse_samples(&buf[..header_len]),
+        Err(SyxError::MissingData(0))
+    ));
+    assert!(matches!(
+        parse_samples(&buf[..buf.len() - 1]),
+        Err(SyxError::Unterminated(_))
+    ));
+    assert!(matches!(parse_samples(&[]), Err(SyxError::Empty)));
+}
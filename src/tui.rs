@@ -0,0 +1,266 @@
+//! Full-screen terminal browser for the sample memory.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::audio::{write_sample, OutputFormat, VOLCA_SAMPLERATE};
+use crate::device::VolcaDevice;
+use crate::proto::{SampleHeader, SampleNo};
+use crate::util::{format_duration, normalize_path, resolve_conflict, OnConflict};
+
+/// How long to wait for a key press when nothing is loading.
+const IDLE_POLL: Duration = Duration::from_millis(250);
+
+enum Mode {
+    Normal,
+    ConfirmDelete(SampleNo),
+    Rename { sample_no: SampleNo, input: String },
+}
+
+struct Browser {
+    headers: Vec<SampleHeader>,
+    table: TableState,
+    mode: Mode,
+    status: String,
+    output_dir: PathBuf,
+    quit: bool,
+}
+
+/// Runs the browser until the user quits. Downloaded samples are saved into `output_dir`.
+pub fn run(device: &impl VolcaDevice, output_dir: PathBuf) -> Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = Browser::new(output_dir).run(&mut terminal, device);
+    ratatui::try_restore()?;
+    result
+}
+
+impl Browser {
+    fn new(output_dir: PathBuf) -> Self {
+        Self {
+            headers: Vec::with_capacity(SampleNo::COUNT.into()),
+            table: TableState::default().with_selected(0),
+            mode: Mode::Normal,
+            status: "Loading sample headers...".into(),
+            output_dir,
+            quit: false,
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal, device: &impl VolcaDevice) -> Result<()> {
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            // Headers are loaded one per iteration, so the interface stays responsive
+            let loading = self.headers.len() < SampleNo::COUNT.into();
+            if loading {
+                self.load_next(device);
+            }
+
+            let timeout = if loading { Duration::ZERO } else { IDLE_POLL };
+            if event::poll(timeout)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key, device);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn load_next(&mut self, device: &impl VolcaDevice) {
+        let sample_no = SampleNo::try_from(self.headers.len()).expect("called while loading");
+        match device.get_sample_header(sample_no) {
+            Ok(header) => {
+                self.headers.push(header);
+                if self.headers.len() == SampleNo::COUNT.into() {
+                    self.status = "Loaded all sample headers".into();
+                }
+            }
+            Err(err) => {
+                // Keep the slot so loading moves on
+                self.headers.push(SampleHeader::empty(sample_no));
+                self.status = format!("Could not load slot {sample_no}: {err:#}");
+            }
+        }
+    }
+
+    fn selected(&self) -> Option<&SampleHeader> {
+        self.table.selected().and_then(|idx| self.headers.get(idx))
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, device: &impl VolcaDevice) {
+        match &mut self.mode {
+            Mode::Normal => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+                KeyCode::Down | KeyCode::Char('j') => self.table.scroll_down_by(1),
+                KeyCode::Up | KeyCode::Char('k') => self.table.scroll_up_by(1),
+                KeyCode::PageDown => self.table.scroll_down_by(20),
+                KeyCode::PageUp => self.table.scroll_up_by(20),
+                KeyCode::Home => self.table.select_first(),
+                KeyCode::End => self.table.select_last(),
+                KeyCode::Char(action @ ('d' | 'r' | 's')) => {
+                    let Some(header) = self.selected().filter(|h| !h.is_empty()).cloned() else {
+                        self.status = "Slot is empty".into();
+                        return;
+                    };
+                    match action {
+                        'd' => {
+                            self.status = format!("Delete {}? [y/n]", header.name);
+                            self.mode = Mode::ConfirmDelete(header.sample_no);
+                        }
+                        'r' => {
+                            self.mode = Mode::Rename {
+                                sample_no: header.sample_no,
+                                input: header.name,
+                            };
+                        }
+                        _ => {
+                            self.status = match self.download(device, &header) {
+                                Ok(path) => format!("Saved {} to {path:?}", header.name),
+                                Err(err) => format!("Could not download {}: {err:#}", header.name),
+                            };
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Mode::ConfirmDelete(sample_no) => {
+                let sample_no = *sample_no;
+                self.mode = Mode::Normal;
+                if key.code != KeyCode::Char('y') {
+                    self.status = "Cancelled".into();
+                    return;
+                }
+                self.status = match device.delete_sample(sample_no) {
+                    Ok(()) => {
+                        self.headers[usize::from(sample_no)] = SampleHeader::empty(sample_no);
+                        format!("Deleted slot {sample_no}")
+                    }
+                    Err(err) => format!("Could not delete slot {sample_no}: {err:#}"),
+                };
+            }
+            Mode::Rename { sample_no, input } => match key.code {
+                KeyCode::Char(c)
+                    if (c.is_ascii_graphic() || c == ' ')
+                        && input.len() < SampleHeader::NAME_LEN =>
+                {
+                    input.push(c)
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Esc => {
+                    self.mode = Mode::Normal;
+                    self.status = "Cancelled".into();
+                }
+                KeyCode::Enter => {
+                    let (sample_no, name) = (*sample_no, input.clone());
+                    self.mode = Mode::Normal;
+                    self.status = match self.rename(device, sample_no, &name) {
+                        Ok(()) => format!("Renamed slot {sample_no} to {name}"),
+                        Err(err) => format!("Could not rename slot {sample_no}: {err:#}"),
+                    };
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn download(&self, device: &impl VolcaDevice, header: &SampleHeader) -> Result<PathBuf> {
+        let data = device.get_sample(header.sample_no)?;
+        let path = normalize_path(
+            &self.output_dir,
+            &header.name,
+            OutputFormat::Wav.extension(),
+        )?;
+        let path = resolve_conflict(path, OnConflict::Rename)?;
+        write_sample(&data.data, &path, OutputFormat::Wav, VOLCA_SAMPLERATE)?;
+        Ok(path)
+    }
+
+    /// Renames a sample by uploading its data again with a new header. Speed and level are kept.
+    fn rename(&mut self, device: &impl VolcaDevice, sample_no: SampleNo, name: &str) -> Result<()> {
+        let current = self.headers[usize::from(sample_no)].clone();
+        let data = device.get_sample(sample_no)?;
+        let header = SampleHeader::builder(sample_no)
+            .name(name)
+            .length_of(&data.data)
+            .speed(current.speed())
+            .level(current.level())
+            .build()?;
+        device.send_sample(header.clone(), data)?;
+        self.headers[usize::from(sample_no)] = header;
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [title, table, status] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let occupied = self.headers.iter().filter(|h| !h.is_empty()).count();
+        frame.render_widget(
+            Paragraph::new(format!(
+                "Volca Sample 2 - {occupied} of {} loaded slots occupied ({}/{} loaded) \
+                 | d: delete, r: rename, s: save to {:?}, q: quit",
+                self.headers.len(),
+                self.headers.len(),
+                SampleNo::COUNT,
+                self.output_dir
+            ))
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+            title,
+        );
+
+        let rows = self.headers.iter().map(|header| {
+            if header.is_empty() {
+                Row::new([format!("{:3}", header.sample_no), "-".into()])
+            } else {
+                Row::new([
+                    format!("{:3}", header.sample_no),
+                    header.name.clone(),
+                    header.length.to_string(),
+                    format_duration(header.duration()),
+                    header.speed().to_string(),
+                    header.level.to_string(),
+                ])
+            }
+        });
+        let widths = [
+            Constraint::Length(4),
+            Constraint::Length(SampleHeader::NAME_LEN as u16 + 1),
+            Constraint::Length(9),
+            Constraint::Length(9),
+            Constraint::Length(6),
+            Constraint::Length(6),
+        ];
+        let header = Row::new(["Slot", "Name", "Length", "Duration", "Speed", "Level"])
+            .style(Style::new().add_modifier(Modifier::UNDERLINED));
+        frame.render_stateful_widget(
+            Table::new(rows, widths)
+                .header(header)
+                .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            table,
+            &mut self.table,
+        );
+
+        let status_line = match &self.mode {
+            Mode::Rename { sample_no, input } => {
+                format!("New name for slot {sample_no} (Enter to confirm, Esc to cancel): {input}")
+            }
+            _ => self.status.clone(),
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+    }
+}
@@ -1,11 +1,20 @@
 use std::borrow::Cow;
 use std::fmt;
-use std::io;
+use std::fs;
+use std::io::{self, IsTerminal};
 use std::ops;
-use std::path::{Path, PathBuf};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
 
 use anyhow::{anyhow, bail, Result};
 use bytemuck::{cast_slice, Pod, Zeroable};
+use clap::ValueEnum;
+use thiserror::Error;
+
+use crate::device::{Candidate, ProgressSink};
+use crate::exit;
+use crate::proto::SampleHeader;
 
 pub const DEBUG_TRESHOLD: usize = 16;
 
@@ -58,29 +67,671 @@ pub fn extract_file_name(path: &Path) -> Result<Cow<'_, str>> {
         .ok_or_else(|| anyhow!("could not extract filename"))
 }
 
-pub fn ask(question: &str) -> io::Result<bool> {
-    use io::Write;
+/// User declined a confirmation prompt.
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct Aborted(pub String);
+
+/// Ctrl-C was pressed while waiting.
+#[derive(Debug, Error)]
+#[error("interrupted while {0}")]
+pub struct Interrupted(pub &'static str);
+
+/// Number of live [`CatchInterrupt`] guards.
+static CATCH_INTERRUPT: AtomicUsize = AtomicUsize::new(0);
+/// Set when Ctrl-C is pressed while it is caught.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Catches Ctrl-C until dropped, so that a wait or a transfer can be stopped cleanly.
+///
+/// The handler can only be set once, so it is shared by all guards, which may be nested. Without a
+/// guard, or when Ctrl-C is pressed a second time, the process exits with [`exit::INTERRUPTED`]
+/// right away.
+pub struct CatchInterrupt(());
+
+impl CatchInterrupt {
+    pub fn new() -> Result<Self> {
+        if CATCH_INTERRUPT.fetch_add(1, Ordering::SeqCst) == 0 {
+            INTERRUPTED.store(false, Ordering::SeqCst);
+        }
+        // Dropped on error too, so the count stays right
+        let guard = Self(());
+        let handler = || {
+            if CATCH_INTERRUPT.load(Ordering::SeqCst) == 0
+                || INTERRUPTED.swap(true, Ordering::SeqCst)
+            {
+                std::process::exit(exit::INTERRUPTED.into());
+            }
+            eprintln!("Stopping, press Ctrl-C again to quit right away");
+        };
+        match ctrlc::set_handler(handler) {
+            Ok(()) | Err(ctrlc::Error::MultipleHandlers) => Ok(guard),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Whether Ctrl-C was pressed since the outermost guard was created.
+    pub fn interrupted(&self) -> bool {
+        interrupted()
+    }
+}
+
+impl Drop for CatchInterrupt {
+    fn drop(&mut self) {
+        CATCH_INTERRUPT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
+/// Whether Ctrl-C was pressed while it is caught by a [`CatchInterrupt`].
+pub fn interrupted() -> bool {
+    CATCH_INTERRUPT.load(Ordering::SeqCst) > 0 && INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Answer confirmation prompts without asking. Set by the `--yes` option.
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+pub fn set_assume_yes(yes: bool) {
+    ASSUME_YES.store(yes, Ordering::Relaxed);
+}
+
+/// Asks a yes/no question on the terminal.
+pub fn ask(question: &str) -> Result<bool> {
+    ask_with_default(question, None)
+}
+
+/// Asks a yes/no question on the terminal. An empty answer selects `default`, if there is one.
+///
+/// With `--yes`, returns `default` or `true` without asking. Fails if stdin is not a terminal.
+pub fn ask_with_default(question: &str, default: Option<bool>) -> Result<bool> {
+    if ASSUME_YES.load(Ordering::Relaxed) {
+        return Ok(default.unwrap_or(true));
+    }
+    if !io::stdin().is_terminal() {
+        bail!(Aborted(
+            "confirmation required, but stdin is not a terminal (pass --yes to confirm)".into()
+        ));
+    }
+    prompt(question, default, io::stdin().lock(), io::stdout())
+}
+
+fn prompt(
+    question: &str,
+    default: Option<bool>,
+    mut input: impl io::BufRead,
+    mut output: impl io::Write,
+) -> Result<bool> {
+    let choices = match default {
+        Some(true) => "Y/n",
+        Some(false) => "y/N",
+        None => "Y/N",
+    };
     let mut buf = String::new();
-    let stdin = io::stdin();
-    let stdout = io::stdout();
     loop {
-        print!("{question} [Y/N]: ");
-        stdout.lock().flush()?;
-        stdin.read_line(&mut buf)?;
-        match buf.as_str() {
-            "Y\n" | "y\n" => return Ok(true),
-            "N\n" | "n\n" => return Ok(false),
-            _ => buf.clear(),
+        write!(output, "{question} [{choices}]: ")?;
+        output.flush()?;
+        buf.clear();
+        if input.read_line(&mut buf)? == 0 {
+            bail!(Aborted(
+                "confirmation required, but input was closed".into()
+            ));
+        }
+        match buf.trim() {
+            "Y" | "y" | "yes" => return Ok(true),
+            "N" | "n" | "no" => return Ok(false),
+            "" if default.is_some() => return Ok(default.unwrap_or_default()),
+            _ => {}
         }
     }
 }
 
-pub fn normalize_path(path: &Path, filename: &str) -> Result<PathBuf> {
-    let mut path = path.canonicalize()?;
-    if path.is_dir() {
-        path.push(filename);
-        path.set_extension("wav");
+/// Asks to choose one of several devices on the terminal. Returns `None` without asking if stdin is
+/// not a terminal or `--yes` is given, or if the input is closed.
+pub fn choose_device(candidates: &[Candidate]) -> Option<usize> {
+    if ASSUME_YES.load(Ordering::Relaxed) || !io::stdin().is_terminal() {
+        return None;
+    }
+    choose(
+        "Found several devices",
+        candidates,
+        io::stdin().lock(),
+        io::stdout(),
+    )
+    .ok()
+    .flatten()
+}
+
+fn choose(
+    title: &str,
+    options: &[impl fmt::Display],
+    mut input: impl io::BufRead,
+    mut output: impl io::Write,
+) -> io::Result<Option<usize>> {
+    writeln!(output, "{title}:")?;
+    for (index, option) in options.iter().enumerate() {
+        writeln!(output, "  [{index}] {option}")?;
+    }
+    let mut buf = String::new();
+    loop {
+        write!(
+            output,
+            "Choose one [0-{}]: ",
+            options.len().saturating_sub(1)
+        )?;
+        output.flush()?;
+        buf.clear();
+        if input.read_line(&mut buf)? == 0 {
+            return Ok(None);
+        }
+        match buf.trim().parse::<usize>() {
+            Ok(index) if index < options.len() => return Ok(Some(index)),
+            _ => {}
+        }
+    }
+}
+
+/// Chooses the file to write a sample named `filename` to.
+///
+/// If `path` is an existing directory or ends with a path separator, the sanitized sample name is
+/// joined to it, with `extension` appended unless the name already ends with it in any case.
+/// Otherwise `path` is used as is, with `extension` appended only if it has none. Missing parent
+/// directories are created.
+pub fn normalize_path(path: &Path, filename: &str, extension: &str) -> Result<PathBuf> {
+    let is_dir = path.is_dir() || path.as_os_str().to_string_lossy().ends_with(MAIN_SEPARATOR);
+    let mut path = if is_dir {
+        path.join(sanitize_file_name(filename))
+    } else {
+        path.to_path_buf()
+    };
+    let append = match path.extension() {
+        // A sample name may well contain a dot
+        Some(ext) if is_dir => !ext.eq_ignore_ascii_case(extension),
+        Some(_) => false,
+        None => true,
+    };
+    if append {
+        path.as_mut_os_string().push(format!(".{extension}"));
+    }
+
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
     }
     Ok(path)
 }
+
+/// Converts a name into one the device can store and display: printable ASCII of at most
+/// [`SampleHeader::NAME_LEN`] bytes.
+///
+/// Accented latin letters are transliterated, other characters are dropped and whitespace runs
+/// are collapsed into a single space. Names with nothing left become `untitled`.
+pub fn sanitize_sample_name(name: &str) -> String {
+    let mut sanitized = String::with_capacity(name.len());
+    for word in name.split_whitespace() {
+        let start = sanitized.len();
+        if start > 0 {
+            sanitized.push(' ');
+        }
+        for c in word.chars() {
+            if c.is_ascii_graphic() {
+                sanitized.push(c);
+            } else {
+                sanitized.push_str(transliterate(c));
+            }
+        }
+        // Nothing left of the word, drop the separator too
+        if sanitized.trim_end().len() <= start {
+            sanitized.truncate(start);
+        }
+    }
+    // Only ASCII is left, so any byte is a char boundary
+    sanitized.truncate(SampleHeader::NAME_LEN.min(sanitized.len()));
+    sanitized.truncate(sanitized.trim_end().len());
+
+    if sanitized.is_empty() {
+        "untitled".into()
+    } else {
+        sanitized
+    }
+}
+
+/// ASCII replacement for a non-ASCII character. Empty if there is none.
+fn transliterate(c: char) -> &'static str {
+    match c {
+        'À'..='Å' => "A",
+        'à'..='å' => "a",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Ç' => "C",
+        'ç' => "c",
+        'È'..='Ë' => "E",
+        'è'..='ë' => "e",
+        'Ì'..='Ï' => "I",
+        'ì'..='ï' => "i",
+        'Ñ' => "N",
+        'ñ' => "n",
+        'Ò'..='Ö' | 'Ø' => "O",
+        'ò'..='ö' | 'ø' => "o",
+        'Ù'..='Ü' => "U",
+        'ù'..='ü' => "u",
+        'Ý' => "Y",
+        'ý' | 'ÿ' => "y",
+        'ß' => "ss",
+        'Œ' => "OE",
+        'œ' => "oe",
+        _ => "",
+    }
+}
+
+/// Replaces characters that can not appear in file names. Empty names become `untitled`.
+pub fn sanitize_file_name(name: &str) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    match name.as_str() {
+        "" | "." | ".." => "untitled".into(),
+        _ => name,
+    }
+}
+
+/// What to do when an output file already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+    /// Ask whether to overwrite the file.
+    Prompt,
+    /// Append a numeric suffix to the file name.
+    Rename,
+    /// Overwrite the file.
+    Overwrite,
+    /// Fail without writing anything.
+    Fail,
+}
+
+impl OnConflict {
+    /// Prompts if stdin is a terminal and fails otherwise.
+    pub fn default_for_stdin() -> Self {
+        if io::stdin().is_terminal() {
+            Self::Prompt
+        } else {
+            Self::Fail
+        }
+    }
+}
+
+/// Returns the path to write to according to the conflict policy.
+pub fn resolve_conflict(path: PathBuf, policy: OnConflict) -> Result<PathBuf> {
+    if !path.exists() {
+        return Ok(path);
+    }
+
+    match policy {
+        OnConflict::Overwrite => Ok(path),
+        OnConflict::Rename => Ok(numbered_path(&path)),
+        OnConflict::Fail => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("file already exists: {path:?}"),
+        )
+        .into()),
+        OnConflict::Prompt => {
+            if ask(&format!(
+                "File {path:?} already exists. Do you want to overwrite?"
+            ))? {
+                Ok(path)
+            } else {
+                bail!(Aborted(format!("file already exists: {path:?}")))
+            }
+        }
+    }
+}
+
+/// Finds the first path that does not exist among `name-1.ext`, `name-2.ext` and so on.
+fn numbered_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|ext| ext.to_string_lossy());
+    (1..)
+        .map(|idx| {
+            let name = match &extension {
+                Some(ext) => format!("{stem}-{idx}.{ext}"),
+                None => format!("{stem}-{idx}"),
+            };
+            path.with_file_name(name)
+        })
+        .find(|path| !path.exists())
+        .expect("ran out of file names")
+}
+
+/// Splits `0..len` into `parts` consecutive ranges of nearly equal length.
+pub fn split_evenly(len: usize, parts: usize) -> Vec<ops::Range<usize>> {
+    (0..parts)
+        .map(|idx| idx * len / parts..(idx + 1) * len / parts)
+        .collect()
+}
+
+/// Computes a short content hash of sample audio data.
+///
+/// The hash is the first 4 bytes of SHA-256 over little-endian sample bytes, formatted as 8 hex
+/// digits. It does not depend on the sample name, speed or level.
+pub fn sample_hash(data: &[i16]) -> String {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    for sample in data {
+        hasher.update(sample.to_le_bytes());
+    }
+    let digest = hasher.finalize();
+    digest[..4]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Formats sample duration as seconds, or milliseconds if it is shorter than a second.
+pub fn format_duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{}ms", duration.as_millis())
+    } else {
+        format!("{:.2}s", duration.as_secs_f64())
+    }
+}
+
+/// Prints transfer progress on a single line of stderr. Does nothing if stderr is not a terminal.
+pub struct TransferProgress {
+    label: String,
+    enabled: bool,
+}
+
+impl TransferProgress {
+    pub fn new(label: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            enabled: io::stderr().is_terminal(),
+        }
+    }
+}
+
+impl ProgressSink for TransferProgress {
+    fn progress(&mut self, done: usize, total: Option<usize>) {
+        if !self.enabled {
+            return;
+        }
+        match total {
+            Some(total) => eprint!("\r{}: {:3}%", self.label, done * 100 / total.max(1)),
+            None => eprint!("\r{}: {} KiB", self.label, done / 1024),
+        }
+    }
+
+    fn finished(&mut self) {
+        if self.enabled {
+            eprintln!();
+        }
+    }
+}
+
+/// Escapes characters that would break line and tab-separated output.
+pub fn escape_porcelain(value: &str) -> Cow<'_, str> {
+    if !value.contains(['\\', '\t', '\n', '\r']) {
+        return Cow::Borrowed(value);
+    }
+
+    let mut escaped = String::with_capacity(value.len() + 2);
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Checks if the string contains wildcard characters.
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Matches `value` against a pattern where `*` matches any sequence of characters and `?`
+/// matches a single character.
+pub fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let value = value.chars().collect::<Vec<_>>();
+
+    let (mut p, mut v) = (0, 0);
+    // Position of the last `*` in pattern and the value position it was tried at
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` consume one more character
+                Some((star, star_v)) => {
+                    backtrack = Some((star, star_v + 1));
+                    p = star + 1;
+                    v = star_v + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[test]
+fn duration_fmt() {
+    assert_eq!(format_duration(Duration::ZERO), "0ms");
+    assert_eq!(format_duration(Duration::from_millis(850)), "850ms");
+    assert_eq!(format_duration(Duration::from_millis(1320)), "1.32s");
+    assert_eq!(format_duration(Duration::from_secs(65)), "65.00s");
+}
+
+#[test]
+fn porcelain_escape() {
+    assert_eq!(escape_porcelain("kick 909"), "kick 909");
+    assert_eq!(escape_porcelain("a\tb\nc"), "a\\tb\\nc");
+    assert_eq!(escape_porcelain("back\\slash\r"), "back\\\\slash\\r");
+}
+
+#[test]
+fn glob() {
+    assert!(glob_match("loop_*", "loop_"));
+    assert!(glob_match("loop_*", "loop_amen"));
+    assert!(!glob_match("loop_*", "Loop_amen"));
+    assert!(glob_match("*_*", "hat_closed"));
+    assert!(!glob_match("*_*", "kick"));
+    assert!(glob_match("hat_?", "hat_1"));
+    assert!(!glob_match("hat_?", "hat_10"));
+    assert!(glob_match("*a*b", "xaxxab"));
+    assert!(glob_match("*", ""));
+    assert!(!glob_match("?", ""));
+}
+
+#[test]
+fn sample_hash_is_stable() {
+    assert_eq!(sample_hash(&[]), "e3b0c442");
+    assert_eq!(sample_hash(&[1, -1]), "16b8cb1f");
+    assert_ne!(sample_hash(&[1, -1]), sample_hash(&[-1, 1]));
+}
+
+#[test]
+fn prompt_answers() {
+    let answer = |default, input: &[u8]| {
+        let mut output = Vec::new();
+        let answer = prompt("Overwrite?", default, input, &mut output);
+        (answer.ok(), String::from_utf8(output).unwrap())
+    };
+
+    assert_eq!(
+        answer(None, b"y\n"),
+        (Some(true), "Overwrite? [Y/N]: ".into())
+    );
+    assert_eq!(answer(None, b"n\r\n").0, Some(false));
+    assert_eq!(answer(None, b"  yes").0, Some(true));
+    assert_eq!(
+        answer(None, b"maybe\nN\n"),
+        (Some(false), "Overwrite? [Y/N]: Overwrite? [Y/N]: ".into())
+    );
+    assert_eq!(
+        answer(Some(true), b"\n"),
+        (Some(true), "Overwrite? [Y/n]: ".into())
+    );
+    assert_eq!(answer(Some(false), b"\n").0, Some(false));
+
+    // Closed input must not loop forever
+    assert_eq!(answer(None, b"").0, None);
+    assert_eq!(answer(None, b"\n").0, None);
+}
+
+#[test]
+fn choose_answers() {
+    let answer = |input: &[u8]| {
+        let mut output = Vec::new();
+        let answer = choose("Pick", &["a", "b"], input, &mut output).unwrap();
+        (answer, String::from_utf8(output).unwrap())
+    };
+
+    assert_eq!(
+        answer(b"1\n"),
+        (
+            Some(1),
+            "Pick:\n  [0] a\n  [1] b\nChoose one [0-1]: ".into()
+        )
+    );
+    assert_eq!(answer(b"2\nx\n 0 \n").0, Some(0));
+    assert_eq!(answer(b"").0, None);
+}
+
+#[test]
+fn sample_names() {
+    for (name, expected) in [
+        ("kick909", "kick909"),
+        ("snare éclair", "snare eclair"),
+        ("Straße  \t loop", "Strasse loop"),
+        ("  padded  ", "padded"),
+        ("bass 🎸 line", "bass line"),
+        ("🎸", "untitled"),
+        ("", "untitled"),
+        ("ÆØÅ æøå Ñ", "AEOA aeoa N"),
+        ("snare_éclair_superlong_take2", "snare_eclair_superlong_t"),
+        // Truncation right before a space must not leave it trailing
+        ("aaaaaaaaaaaaaaaaaaaaaaa bbb", "aaaaaaaaaaaaaaaaaaaaaaa"),
+    ] {
+        let sanitized = sanitize_sample_name(name);
+        assert_eq!(sanitized, expected, "{name:?}");
+        assert!(sanitized.len() <= SampleHeader::NAME_LEN);
+    }
+}
+
+#[test]
+fn output_paths() {
+    let dir = std::env::temp_dir().join(format!("volsa2-paths-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // Directory target
+    assert_eq!(
+        normalize_path(&dir, "kick.909", "wav").unwrap(),
+        dir.join("kick.909.wav")
+    );
+    assert_eq!(
+        normalize_path(&dir, "kick.wav", "wav").unwrap(),
+        dir.join("kick.wav")
+    );
+    assert_eq!(
+        normalize_path(&dir, "kick.WAV", "wav").unwrap(),
+        dir.join("kick.WAV")
+    );
+    assert_eq!(
+        normalize_path(&dir, "hats/open", "flac").unwrap(),
+        dir.join("hats_open.flac")
+    );
+    assert_eq!(
+        normalize_path(&dir, "", "wav").unwrap(),
+        dir.join("untitled.wav")
+    );
+
+    // File target, with missing parent directories
+    let file = dir.join("new").join("snare.wav");
+    assert_eq!(normalize_path(&file, "kick", "wav").unwrap(), file);
+    assert!(dir.join("new").is_dir());
+    assert_eq!(
+        normalize_path(&dir.join("snare"), "kick", "wav").unwrap(),
+        dir.join("snare.wav")
+    );
+    // The extension given by the user is kept, whatever the format
+    for name in ["out.WAV", "take.aif"] {
+        assert_eq!(
+            normalize_path(&dir.join(name), "kick", "wav").unwrap(),
+            dir.join(name)
+        );
+    }
+
+    // Not yet existing directory target
+    let new_dir = format!("{}{MAIN_SEPARATOR}", dir.join("out").display());
+    assert_eq!(
+        normalize_path(Path::new(&new_dir), "kick", "wav").unwrap(),
+        dir.join("out").join("kick.wav")
+    );
+    assert!(dir.join("out").is_dir());
+
+    // Relative paths stay relative
+    assert_eq!(
+        normalize_path(Path::new("snare.wav"), "kick", "wav").unwrap(),
+        Path::new("snare.wav")
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn output_conflict() {
+    let dir = std::env::temp_dir().join(format!("volsa2-conflict-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("kick.wav");
+
+    // No conflict, the path is kept regardless of the policy
+    assert_eq!(
+        resolve_conflict(path.clone(), OnConflict::Fail).unwrap(),
+        path
+    );
+
+    std::fs::write(&path, b"").unwrap();
+    assert_eq!(
+        resolve_conflict(path.clone(), OnConflict::Overwrite).unwrap(),
+        path
+    );
+    assert!(resolve_conflict(path.clone(), OnConflict::Fail).is_err());
+    assert_eq!(
+        resolve_conflict(path.clone(), OnConflict::Rename).unwrap(),
+        dir.join("kick-1.wav")
+    );
+
+    std::fs::write(dir.join("kick-1.wav"), b"").unwrap();
+    assert_eq!(
+        resolve_conflict(path.clone(), OnConflict::Rename).unwrap(),
+        dir.join("kick-2.wav")
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn even_split() {
+    assert_eq!(split_evenly(10, 2), [0..5, 5..10]);
+    assert_eq!(split_evenly(10, 3), [0..3, 3..6, 6..10]);
+    assert_eq!(split_evenly(2, 3), [0..0, 0..1, 1..2]);
+}
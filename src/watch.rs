@@ -0,0 +1,95 @@
+//! Filesystem watching helpers.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{debug, trace, warn};
+
+/// Watches a single file for changes.
+///
+/// The parent directory is watched instead of the file itself, since a lot of audio software
+/// exports files by writing to a temporary file and then renaming it over the original.
+pub struct FileWatcher {
+    // Must be kept alive for the events to be delivered.
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    file_name: OsString,
+    debounce: Duration,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path, debounce: Duration) -> Result<Self> {
+        let path = path.canonicalize()?;
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("path must point to a file: {path:?}"))?
+            .to_owned();
+        let dir = path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        debug!(?dir, ?file_name, "watching for changes");
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            file_name,
+            debounce,
+        })
+    }
+
+    /// Waits up to `timeout` for the watched file to change, returning `false` if it did not.
+    ///
+    /// Successive events are merged together until no new events arrive for the debounce
+    /// interval, so a file written in several steps results in a single change.
+    pub fn wait_for_change(&self, timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(remaining) {
+                Ok(event) => {
+                    if self.is_relevant(event) {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return Ok(false),
+                Err(err @ RecvTimeoutError::Disconnected) => return Err(err.into()),
+            }
+        }
+
+        loop {
+            match self.events.recv_timeout(self.debounce) {
+                Ok(event) => {
+                    self.is_relevant(event);
+                }
+                Err(RecvTimeoutError::Timeout) => return Ok(true),
+                Err(err @ RecvTimeoutError::Disconnected) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn is_relevant(&self, event: notify::Result<Event>) -> bool {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                warn!(%err, "watch error");
+                return false;
+            }
+        };
+        trace!(?event, "fs event");
+
+        matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+            && event
+                .paths
+                .iter()
+                .any(|path| path.file_name() == Some(&self.file_name))
+    }
+}